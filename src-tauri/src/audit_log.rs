@@ -0,0 +1,558 @@
+use crate::crypto::CryptoService;
+use crate::database::{AuditEvent, AuditLogExport, Database, AUDIT_LOG_HEAD_KEY};
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Recorded actions
+pub const LOGIN: &str = "login";
+pub const LOGIN_FAILED: &str = "login_failed";
+pub const ENTRY_REVEALED: &str = "entry_revealed";
+pub const ENTRY_COPIED: &str = "entry_copied";
+pub const EXPORT: &str = "export";
+pub const IMPORT: &str = "import";
+pub const ACCOUNT_VALUE_REPLACED: &str = "account_value_replaced";
+pub const BREACH_DISMISSED: &str = "breach_dismissed";
+pub const READONLY_QUERY: &str = "readonly_query";
+pub const RECOVERY_DRILL: &str = "recovery_drill";
+pub const KDF_UPGRADED: &str = "kdf_upgraded";
+pub const METADATA_ENCRYPTED: &str = "metadata_encrypted";
+pub const ENTRIES_MERGED: &str = "entries_merged";
+pub const SOFTWARE_RENAMED: &str = "software_renamed";
+pub const ENTRY_SHARED: &str = "entry_shared";
+pub const ATTACHMENT_OPENED: &str = "attachment_opened";
+pub const RECOVERY_CODE_CREATED: &str = "recovery_code_created";
+pub const RECOVERY_CODE_USED: &str = "recovery_code_used";
+pub const SECURITY_QUESTIONS_CHANGED: &str = "security_questions_changed";
+
+// Rows returned by get_audit_log when the caller gives no limit
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+// Chained from when the stored head is missing or forged, so the next sealed row
+// can never verify and the break stays visible
+const BROKEN_CHAIN: &str = "broken";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub occurred_at: String,
+    pub action: String,
+    pub entry_id: Option<i64>,
+    pub detail: Option<String>,
+    pub segment_id: Option<i64>, // Imported segment the row belongs to, None for the local log
+    pub source_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditLogVerification {
+    pub valid: bool,
+    pub checked_count: usize,
+    // Rows recorded while locked, not yet sealed into the chain
+    pub pending_count: usize,
+    pub first_invalid_id: Option<i64>,
+    pub message: String,
+    // The local log first, then each imported segment; every one is chained on its own
+    pub segments: Vec<AuditSegmentVerification>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditSegmentVerification {
+    pub segment_id: Option<i64>,
+    pub imported_at: Option<String>,
+    pub first_id: Option<i64>,
+    pub last_id: Option<i64>,
+    pub checked_count: usize,
+    pub valid: bool,
+    pub first_invalid_id: Option<i64>,
+}
+
+// Where a chain stopped verifying
+enum ChainBreak {
+    Row { index: usize, id: i64 },
+    Head,
+}
+
+// Rows of another vault's log, verified under its key and re-sealed under the current one
+pub struct ImportedSegment {
+    tag: String,
+    head: String,
+    events: Vec<AuditEvent>,
+}
+
+// Append an event. With the vault key at hand the detail is encrypted and the row is
+// sealed into the chain at once; events recorded without it (failed logins, exports)
+// stay pending, detail in plaintext, until the next event that has the key.
+// Never pass secrets as the detail: pending details are not encrypted yet.
+pub fn record(database: &Database, action: &str, entry_id: Option<i64>, detail: Option<&str>, vault_key: Option<&[u8; 32]>) -> Result<()> {
+    database.append_audit_event(action, entry_id, detail)?;
+    if let Some(vault_key) = vault_key {
+        seal_pending(database, vault_key)?;
+    }
+    Ok(())
+}
+
+// Encrypt the details of pending rows and link them onto the chain. Returns the number sealed.
+pub fn seal_pending(database: &Database, vault_key: &[u8; 32]) -> Result<usize> {
+    let pending = database.get_unsealed_audit_events()?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let audit_key = audit_key(vault_key)?;
+    let mut previous = chain_tip(database, &audit_key)?;
+    let mut sealed = Vec::with_capacity(pending.len());
+    for mut event in pending {
+        if let Some(detail) = &event.detail {
+            let nonce = CryptoService::generate_nonce();
+            event.detail = Some(CryptoService::encrypt_data(detail, vault_key, &nonce)?);
+            event.detail_nonce = Some(nonce);
+        }
+        let mac = row_mac(&audit_key, &previous, &event);
+        event.mac = Some(mac.clone());
+        previous = mac;
+        sealed.push(event);
+    }
+
+    database.seal_audit_events(&sealed, &format!("{}:{}", previous, head_tag(&audit_key, &previous)))?;
+    Ok(sealed.len())
+}
+
+// A page of the log, newest first, with details decrypted. Pending rows are sealed first.
+pub fn get_audit_log(database: &Database, vault_key: &[u8; 32], offset: usize, limit: usize) -> Result<Vec<AuditRecord>> {
+    seal_pending(database, vault_key)?;
+
+    database.get_audit_events_page(offset, limit)?
+        .into_iter()
+        .map(|event| open_record(event, vault_key))
+        .collect()
+}
+
+// The whole log, oldest first, as CSV for auditors. Details are decrypted: they never
+// carry secrets. Nothing is sealed here, so a mistyped key cannot end up in the chain.
+pub fn export_csv(database: &Database, vault_key: &[u8; 32]) -> Result<(String, usize)> {
+    let mut csv = String::from("id,occurred_at,action,entry_id,detail,segment_id,source_id\n");
+    let events = database.get_all_audit_events()?;
+    let count = events.len();
+    for event in events {
+        let record = open_record(event, vault_key)?;
+        let fields = [
+            record.id.to_string(),
+            record.occurred_at,
+            record.action,
+            record.entry_id.map(|id| id.to_string()).unwrap_or_default(),
+            record.detail.unwrap_or_default(),
+            record.segment_id.map(|id| id.to_string()).unwrap_or_default(),
+            record.source_id.map(|id| id.to_string()).unwrap_or_default(),
+        ];
+        csv.push_str(&fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","));
+        csv.push('\n');
+    }
+    Ok((csv, count))
+}
+
+// Recompute every chain: the local log and each imported segment, independently. Editing
+// or deleting a sealed row breaks the link of the row after it; dropping rows off the end
+// leaves the head pointing at a MAC no row carries. Read-only.
+pub fn verify_audit_log(database: &Database, vault_key: &[u8; 32]) -> Result<AuditLogVerification> {
+    let audit_key = audit_key(vault_key)?;
+    let sealed = database.get_sealed_audit_events()?;
+    let pending_count = database.get_unsealed_audit_events()?.len();
+
+    let local_head = database.get_setting(AUDIT_LOG_HEAD_KEY)?;
+    let (local, message) = verify_chain(None, None, &audit_key, &sealed, local_head.as_deref());
+    let mut segments = vec![local];
+    let mut messages = vec![message];
+
+    let mut imported = database.get_imported_audit_events()?;
+    for segment in database.get_audit_segments()? {
+        let (events, rest): (Vec<_>, Vec<_>) = imported.into_iter().partition(|event| event.segment_id == Some(segment.id));
+        imported = rest;
+        let key = segment_key(&audit_key, &segment.tag)?;
+        let (verification, message) = verify_chain(Some(segment.id), Some(segment.imported_at), &key, &events, Some(&segment.head));
+        segments.push(verification);
+        messages.push(message);
+    }
+    // Rows left over point at a segment that is gone
+    if let Some(orphan) = imported.first() {
+        segments.push(AuditSegmentVerification {
+            segment_id: orphan.segment_id,
+            imported_at: None,
+            first_id: Some(orphan.id),
+            last_id: imported.last().map(|event| event.id),
+            checked_count: 0,
+            valid: false,
+            first_invalid_id: Some(orphan.id),
+        });
+        messages.push(Some("Audit events belong to an imported segment that was removed".to_string()));
+    }
+
+    let checked_count: usize = segments.iter().map(|segment| segment.checked_count).sum();
+    let (valid, first_invalid_id, message) = match segments.iter().zip(messages).find(|(segment, _)| !segment.valid) {
+        Some((segment, message)) => (false, segment.first_invalid_id, message.unwrap_or_default()),
+        None if segments.len() == 1 => (true, None, format!("{} audit events verified", checked_count)),
+        None => (
+            true,
+            None,
+            format!("{} audit events verified in the local log and {} imported segments", checked_count, segments.len() - 1),
+        ),
+    };
+    Ok(AuditLogVerification {
+        valid,
+        checked_count,
+        pending_count,
+        first_invalid_id,
+        message,
+        segments,
+    })
+}
+
+// Verify every chain of an exported log under the backup's vault key, then re-seal each
+// as a new segment under the current one. Earlier imported segments come first, the
+// backup's own log last. Fails without writing anything if any chain is broken.
+pub fn prepare_import(audit_log: &AuditLogExport, source_key: &[u8; 32], target_key: &[u8; 32]) -> Result<Vec<ImportedSegment>> {
+    let source_audit_key = audit_key(source_key)?;
+    let target_audit_key = audit_key(target_key)?;
+
+    let mut chains = Vec::new();
+    for segment in &audit_log.segments {
+        let events: Vec<_> = audit_log.events.iter().filter(|event| event.segment_id == Some(segment.id)).cloned().collect();
+        chains.push((segment_key(&source_audit_key, &segment.tag)?, events, Some(segment.head.clone())));
+    }
+    let local: Vec<_> = audit_log.events.iter().filter(|event| event.segment_id.is_none()).cloned().collect();
+    chains.push((source_audit_key, local, audit_log.head.clone()));
+
+    let chained_count: usize = chains.iter().map(|(_, events, _)| events.len()).sum();
+    if chained_count != audit_log.events.len() {
+        return Err(anyhow!("The backup's audit log has events outside any of its segments"));
+    }
+
+    let mut segments = Vec::new();
+    for (key, events, head) in chains {
+        if let Err(chain_break) = check_chain(&key, &events, head.as_deref()) {
+            let at = match chain_break {
+                ChainBreak::Row { id, .. } => format!("at event {}", id),
+                ChainBreak::Head => "at its end".to_string(),
+            };
+            return Err(anyhow!("The backup's audit log does not verify {}", at));
+        }
+        if events.is_empty() {
+            continue;
+        }
+
+        let tag = CryptoService::generate_salt();
+        let target_key_for_segment = segment_key(&target_audit_key, &tag)?;
+        let mut previous = String::new();
+        let mut resealed = Vec::with_capacity(events.len());
+        for event in events {
+            let (detail, detail_nonce) = match (&event.detail, &event.detail_nonce) {
+                (Some(detail), Some(nonce)) => {
+                    let detail = CryptoService::decrypt_data(detail, source_key, nonce)
+                        .map_err(|_| anyhow!("Audit event {} cannot be decrypted with the backup's vault key", event.id))?;
+                    let nonce = CryptoService::generate_nonce();
+                    (Some(CryptoService::encrypt_data(&detail, target_key, &nonce)?), Some(nonce))
+                }
+                _ => (event.detail.clone(), None),
+            };
+            let mut event = AuditEvent {
+                detail,
+                detail_nonce,
+                mac: None,
+                segment_id: None,
+                source_id: Some(event.id),
+                ..event
+            };
+            let mac = row_mac(&target_key_for_segment, &previous, &event);
+            event.mac = Some(mac.clone());
+            previous = mac;
+            resealed.push(event);
+        }
+
+        segments.push(ImportedSegment {
+            head: format!("{}:{}", previous, head_tag(&target_key_for_segment, &previous)),
+            tag,
+            events: resealed,
+        });
+    }
+    Ok(segments)
+}
+
+// Append prepared segments to the local log. Returns the number of events added.
+pub fn append_imported(database: &Database, segments: &[ImportedSegment]) -> Result<usize> {
+    let mut count = 0;
+    for segment in segments {
+        database.insert_audit_segment(&segment.tag, &segment.head, &segment.events)?;
+        count += segment.events.len();
+    }
+    Ok(count)
+}
+
+fn open_record(event: AuditEvent, vault_key: &[u8; 32]) -> Result<AuditRecord> {
+    let detail = match (&event.detail, &event.detail_nonce) {
+        (Some(detail), Some(nonce)) => Some(
+            CryptoService::decrypt_data(detail, vault_key, nonce)
+                .map_err(|_| anyhow!("Audit event {} cannot be decrypted with this vault key", event.id))?,
+        ),
+        _ => event.detail.clone(),
+    };
+    Ok(AuditRecord {
+        id: event.id,
+        occurred_at: event.occurred_at,
+        action: event.action,
+        entry_id: event.entry_id,
+        detail,
+        segment_id: event.segment_id,
+        source_id: event.source_id,
+    })
+}
+
+// Walk one chain from its first row and check its head
+fn check_chain(key: &HmacSha256, events: &[AuditEvent], head: Option<&str>) -> std::result::Result<(), ChainBreak> {
+    let mut previous = String::new();
+    for (index, event) in events.iter().enumerate() {
+        let expected = row_mac(key, &previous, event);
+        if event.mac.as_deref() != Some(expected.as_str()) {
+            return Err(ChainBreak::Row { index, id: event.id });
+        }
+        previous = expected;
+    }
+
+    let head_matches = match head {
+        Some(head) => head.split_once(':').is_some_and(|(mac, tag)| mac == previous && head_tag(key, mac) == tag),
+        None => events.is_empty(),
+    };
+    if head_matches { Ok(()) } else { Err(ChainBreak::Head) }
+}
+
+// Report on one chain, with the reason it failed if it did
+fn verify_chain(
+    segment_id: Option<i64>,
+    imported_at: Option<String>,
+    key: &HmacSha256,
+    events: &[AuditEvent],
+    head: Option<&str>,
+) -> (AuditSegmentVerification, Option<String>) {
+    let (checked_count, first_invalid_id, message) = match check_chain(key, events, head) {
+        Ok(()) => (events.len(), None, None),
+        Err(ChainBreak::Row { index, id }) => (index, Some(id), Some("An audit event was modified, removed or inserted")),
+        Err(ChainBreak::Head) => (events.len(), None, Some("The audit log was truncated or its head was tampered with")),
+    };
+    let verification = AuditSegmentVerification {
+        segment_id,
+        imported_at,
+        first_id: events.first().map(|event| event.id),
+        last_id: events.last().map(|event| event.id),
+        checked_count,
+        valid: message.is_none(),
+        first_invalid_id,
+    };
+    let message = message.map(|message| match segment_id {
+        Some(segment_id) => format!("Imported segment {}: {}", segment_id, message),
+        None => message.to_string(),
+    });
+    (verification, message)
+}
+
+// Separate key for the chain, so MACs reveal nothing about the vault key
+fn audit_key(vault_key: &[u8; 32]) -> Result<HmacSha256> {
+    let mut mac = HmacSha256::new_from_slice(vault_key).map_err(|e| anyhow!("Invalid vault key: {}", e))?;
+    mac.update(b"pwdbox-audit-log-v1");
+    let key = mac.finalize().into_bytes();
+    HmacSha256::new_from_slice(&key).map_err(|e| anyhow!("Invalid audit key: {}", e))
+}
+
+// MAC the chain is linked from: the head's, when its tag checks out
+fn chain_tip(database: &Database, audit_key: &HmacSha256) -> Result<String> {
+    match database.get_setting(AUDIT_LOG_HEAD_KEY)? {
+        Some(head) => Ok(match head.split_once(':') {
+            Some((mac, tag)) if head_tag(audit_key, mac) == tag => mac.to_string(),
+            _ => BROKEN_CHAIN.to_string(),
+        }),
+        None if database.get_sealed_audit_events()?.is_empty() => Ok(String::new()),
+        None => Ok(BROKEN_CHAIN.to_string()),
+    }
+}
+
+// Key for one imported segment, so its rows cannot be moved into another chain
+fn segment_key(audit_key: &HmacSha256, tag: &str) -> Result<HmacSha256> {
+    let mut key = audit_key.clone();
+    update_field(&mut key, b"segment");
+    update_field(&mut key, tag.as_bytes());
+    HmacSha256::new_from_slice(&key.finalize().into_bytes()).map_err(|e| anyhow!("Invalid segment key: {}", e))
+}
+
+fn head_tag(audit_key: &HmacSha256, mac: &str) -> String {
+    let mut tag = audit_key.clone();
+    update_field(&mut tag, b"head");
+    update_field(&mut tag, mac.as_bytes());
+    hex(&tag.finalize().into_bytes())
+}
+
+fn row_mac(audit_key: &HmacSha256, previous: &str, event: &AuditEvent) -> String {
+    let mut mac = audit_key.clone();
+    update_field(&mut mac, previous.as_bytes());
+    // Imported rows are chained by the id they had in the log they came from
+    update_field(&mut mac, event.source_id.unwrap_or(event.id).to_string().as_bytes());
+    update_field(&mut mac, event.occurred_at.as_bytes());
+    update_field(&mut mac, event.action.as_bytes());
+    update_field(&mut mac, event.entry_id.map(|id| id.to_string()).unwrap_or_default().as_bytes());
+    update_field(&mut mac, event.detail.as_deref().unwrap_or_default().as_bytes());
+    update_field(&mut mac, event.detail_nonce.as_deref().unwrap_or_default().as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+// Length-prefixed, so field boundaries cannot be shifted
+fn update_field(mac: &mut HmacSha256, field: &[u8]) {
+    mac.update(&(field.len() as u64).to_le_bytes());
+    mac.update(field);
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Quote when needed, and keep spreadsheet apps from reading a detail as a formula
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) { format!("'{}", field) } else { field.to_string() };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{Connection, params};
+
+    fn open_database(dir: &tempfile::TempDir) -> Database {
+        Database::new(dir.path().join("pwdbox.db")).unwrap()
+    }
+
+    fn raw(database: &Database) -> Connection {
+        Connection::open(database.path()).unwrap()
+    }
+
+    #[test]
+    fn test_pending_events_are_sealed_and_details_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_database(&dir);
+        let key = CryptoService::generate_vault_key();
+
+        record(&database, LOGIN_FAILED, None, None, None).unwrap();
+        record(&database, EXPORT, None, Some("3 entries"), None).unwrap();
+        assert_eq!(verify_audit_log(&database, &key).unwrap().pending_count, 2);
+
+        record(&database, LOGIN, None, None, Some(&key)).unwrap();
+        record(&database, ENTRY_REVEALED, Some(7), None, Some(&key)).unwrap();
+
+        let verification = verify_audit_log(&database, &key).unwrap();
+        assert!(verification.valid, "{}", verification.message);
+        assert_eq!((verification.checked_count, verification.pending_count), (4, 0));
+
+        let log = get_audit_log(&database, &key, 0, 10).unwrap();
+        let actions: Vec<_> = log.iter().map(|record| record.action.as_str()).collect();
+        assert_eq!(actions, vec![ENTRY_REVEALED, LOGIN, EXPORT, LOGIN_FAILED]);
+        assert_eq!(log[0].entry_id, Some(7));
+        assert_eq!(log[2].detail.as_deref(), Some("3 entries"));
+
+        // At rest the detail is ciphertext
+        let stored: String = raw(&database)
+            .query_row("SELECT detail FROM audit_log WHERE action = ?1", params![EXPORT], |row| row.get(0))
+            .unwrap();
+        assert_ne!(stored, "3 entries");
+
+        let other_key = CryptoService::generate_vault_key();
+        assert!(!verify_audit_log(&database, &other_key).unwrap().valid);
+    }
+
+    #[test]
+    fn test_tampering_and_truncation_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_database(&dir);
+        let key = CryptoService::generate_vault_key();
+        for id in 1..=4 {
+            record(&database, ENTRY_REVEALED, Some(id), None, Some(&key)).unwrap();
+        }
+        assert!(verify_audit_log(&database, &key).unwrap().valid);
+
+        // Removing a row from the middle breaks the next row's link
+        raw(&database).execute("DELETE FROM audit_log WHERE id = 2", []).unwrap();
+        let verification = verify_audit_log(&database, &key).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid_id, Some(3));
+
+        // Dropping the newest rows is caught by the head, and stays caught after new events
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_database(&dir);
+        for id in 1..=4 {
+            record(&database, ENTRY_REVEALED, Some(id), None, Some(&key)).unwrap();
+        }
+        raw(&database).execute("DELETE FROM audit_log WHERE id >= 3", []).unwrap();
+        let verification = verify_audit_log(&database, &key).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid_id, None);
+
+        record(&database, LOGIN, None, None, Some(&key)).unwrap();
+        assert!(!verify_audit_log(&database, &key).unwrap().valid);
+    }
+
+    #[test]
+    fn test_imported_segments_verify_independently() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = Database::new(dir.path().join("source.db")).unwrap();
+        let source_key = CryptoService::generate_vault_key();
+        record(&source, LOGIN, None, None, Some(&source_key)).unwrap();
+        record(&source, BREACH_DISMISSED, Some(4), Some("rotated elsewhere"), Some(&source_key)).unwrap();
+        record(&source, ENTRY_REVEALED, Some(4), None, Some(&source_key)).unwrap();
+        let export = source.export_audit_log().unwrap();
+
+        let database = open_database(&dir);
+        let key = CryptoService::generate_vault_key();
+        record(&database, LOGIN, None, None, Some(&key)).unwrap();
+        record(&database, ENTRY_REVEALED, Some(1), None, Some(&key)).unwrap();
+        assert!(prepare_import(&export, &key, &key).is_err());
+
+        let segments = prepare_import(&export, &source_key, &key).unwrap();
+        assert_eq!(append_imported(&database, &segments).unwrap(), 3);
+        // The local chain carries on past the imported rows
+        record(&database, LOGIN, None, None, Some(&key)).unwrap();
+
+        let verification = verify_audit_log(&database, &key).unwrap();
+        assert!(verification.valid, "{}", verification.message);
+        assert_eq!(verification.checked_count, 6);
+        let boundaries: Vec<_> = verification.segments.iter()
+            .map(|segment| (segment.segment_id.is_some(), segment.first_id, segment.last_id))
+            .collect();
+        assert_eq!(boundaries, vec![(false, Some(1), Some(6)), (true, Some(3), Some(5))]);
+
+        let dismissed = get_audit_log(&database, &key, 0, 10).unwrap().into_iter()
+            .find(|record| record.action == BREACH_DISMISSED)
+            .unwrap();
+        assert_eq!((dismissed.source_id, dismissed.detail.as_deref()), (Some(2), Some("rotated elsewhere")));
+
+        // Tampering with the segment flags it alone
+        raw(&database).execute("UPDATE audit_log SET entry_id = 9 WHERE source_id = 3", []).unwrap();
+        let verification = verify_audit_log(&database, &key).unwrap();
+        assert!(!verification.valid);
+        assert!(verification.segments[0].valid && !verification.segments[1].valid);
+        assert_eq!(verification.first_invalid_id, Some(5));
+    }
+
+    #[test]
+    fn test_csv_export_quotes_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_database(&dir);
+        let key = CryptoService::generate_vault_key();
+        record(&database, BREACH_DISMISSED, Some(3), Some("=not a formula, \"really\""), Some(&key)).unwrap();
+
+        let (csv, count) = export_csv(&database, &key).unwrap();
+        assert_eq!(count, 1);
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.starts_with("1,"));
+        assert!(row.ends_with(",breach_dismissed,3,\"'=not a formula, \"\"really\"\"\",,"), "{}", row);
+    }
+}
@@ -0,0 +1,8 @@
+// Native messaging host for the browser extension; see native_messaging.rs. Browsers
+// start it with stdin and stdout connected to the extension, so errors go to stderr.
+fn main() {
+    if let Err(e) = pwdbox_lib::run_native_host() {
+        eprintln!("pwdbox-native-host: {:#}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::time_utils;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+// Have I Been Pwned lookups by k-anonymity: only the first five hex characters of a
+// password's SHA-1 leave the machine, and the suffixes sent back are matched locally.
+const RANGE_API: &str = "https://api.pwnedpasswords.com/range/";
+const PREFIX_LENGTH: usize = 5;
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+// Range responses are reused for this long before being fetched again
+const CACHE_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreachCheckStatus {
+    Complete,
+    // The API could not be reached; entries whose range was not cached went unchecked
+    NetworkUnavailable,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BreachCheckReport {
+    pub status: BreachCheckStatus,
+    pub message: String,
+    pub checked_count: usize,
+    pub unchecked_count: usize,
+    pub unreadable_count: usize,
+    pub breaches: BTreeMap<i64, u64>, // Entry id -> times seen in breaches, breached entries only
+}
+
+// Entry id and password_digest of its password
+pub type EntryDigest = (i64, Zeroizing<String>);
+
+// SHA-1 of a password in uppercase hex, the form the range API uses
+pub fn password_digest(password: &str) -> Zeroizing<String> {
+    let digest = Sha1::digest(password.as_bytes());
+    Zeroizing::new(digest.iter().map(|byte| format!("{:02X}", byte)).collect())
+}
+
+// Fetch one range over HTTPS. Padding makes every response about the same size, so the
+// response length does not hint at the prefix either.
+pub fn fetch_range(prefix: &str) -> Result<String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("PwdBox/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let response = client
+        .get(format!("{}{}", RANGE_API, prefix))
+        .header("Add-Padding", "true")
+        .send()?
+        .error_for_status()?;
+    Ok(response.text()?)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedRange {
+    fetched_at: String, // UTC RFC3339
+    body: String,
+}
+
+// Range responses on disk, one file per prefix. They hold hash suffixes of other people's
+// passwords, none of ours.
+pub struct RangeCache {
+    dir: PathBuf,
+}
+
+impl RangeCache {
+    pub fn new(dir: PathBuf) -> Self {
+        RangeCache { dir }
+    }
+
+    fn path(&self, prefix: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", prefix))
+    }
+
+    fn get(&self, prefix: &str, now: &DateTime<Utc>) -> Option<String> {
+        let cached: CachedRange = serde_json::from_str(&fs::read_to_string(self.path(prefix)).ok()?).ok()?;
+        let fetched_at = time_utils::parse_rfc3339(&cached.fetched_at).ok()?;
+        (*now - fetched_at < Duration::hours(CACHE_TTL_HOURS) && fetched_at <= *now).then_some(cached.body)
+    }
+
+    fn put(&self, prefix: &str, body: &str, now: &DateTime<Utc>) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let cached = CachedRange { fetched_at: time_utils::to_rfc3339(now), body: body.to_string() };
+        fs::write(self.path(prefix), serde_json::to_string(&cached)?)?;
+        Ok(())
+    }
+}
+
+// Suffix -> count from a range response. Padding lines have a count of 0.
+fn parse_range(body: &str) -> HashMap<&str, u64> {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .filter_map(|(suffix, count)| Some((suffix, count.trim().parse().ok()?)))
+        .collect()
+}
+
+// Look up `digests`, one range per distinct prefix, from the cache where it is fresh and
+// through `fetch` otherwise. Once a fetch fails no more are tried; what was cached is
+// still checked.
+pub fn check(
+    digests: &[EntryDigest],
+    unreadable_count: usize,
+    cache: &RangeCache,
+    now: &DateTime<Utc>,
+    fetch: &mut dyn FnMut(&str) -> Result<String>,
+) -> BreachCheckReport {
+    let mut ranges: BTreeMap<&str, Vec<(i64, &str)>> = BTreeMap::new();
+    for (entry_id, digest) in digests {
+        let (prefix, suffix) = digest.split_at(PREFIX_LENGTH);
+        ranges.entry(prefix).or_default().push((*entry_id, suffix));
+    }
+
+    let (mut checked_count, mut unchecked_count) = (0, 0);
+    let mut breaches = BTreeMap::new();
+    let mut network_error = None;
+    for (prefix, entries) in ranges {
+        let body = match cache.get(prefix, now) {
+            Some(body) => body,
+            None if network_error.is_some() => {
+                unchecked_count += entries.len();
+                continue;
+            }
+            None => match fetch(prefix) {
+                Ok(body) => {
+                    if let Err(e) = cache.put(prefix, &body, now) {
+                        log::warn!("Failed to cache a breach range: {}", e);
+                    }
+                    body
+                }
+                Err(e) => {
+                    network_error = Some(e.to_string());
+                    unchecked_count += entries.len();
+                    continue;
+                }
+            },
+        };
+
+        let counts = parse_range(&body);
+        for (entry_id, suffix) in entries {
+            checked_count += 1;
+            if let Some(&count) = counts.get(suffix).filter(|&&count| count > 0) {
+                breaches.insert(entry_id, count);
+            }
+        }
+    }
+
+    let (status, message) = match network_error {
+        Some(e) => (
+            BreachCheckStatus::NetworkUnavailable,
+            format!("Network unavailable: {} of {} passwords could not be checked ({})", unchecked_count, digests.len(), e),
+        ),
+        None => (
+            BreachCheckStatus::Complete,
+            format!("Checked {} passwords, {} found in known breaches", checked_count, breaches.len()),
+        ),
+    };
+    BreachCheckReport { status, message, checked_count, unchecked_count, unreadable_count, breaches }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_password_digest_matches_the_api_form() {
+        assert_eq!(*password_digest("password"), "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8");
+    }
+
+    #[test]
+    fn test_check_sends_prefixes_only_and_caches_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RangeCache::new(dir.path().join("ranges"));
+        let now = time_utils::now();
+        let digests = vec![
+            (1, password_digest("password")),
+            (2, password_digest("correct horse battery staple")),
+            (3, password_digest("password")),
+        ];
+        let breached_suffix = &digests[0].1[PREFIX_LENGTH..];
+        let range = format!("{}:3861493\r\n0018A45C4D1DEF81644B54AB7F969B88D65:0\r\n", breached_suffix);
+
+        let mut requested = Vec::new();
+        let mut fetch = |prefix: &str| -> Result<String> {
+            requested.push(prefix.to_string());
+            Ok(if prefix == "5BAA6" { range.clone() } else { String::new() })
+        };
+        let report = check(&digests, 0, &cache, &now, &mut fetch);
+        assert_eq!(report.status, BreachCheckStatus::Complete);
+        assert_eq!(report.breaches, BTreeMap::from([(1, 3861493), (3, 3861493)]));
+        assert_eq!(report.checked_count, 3);
+        assert_eq!(requested.len(), 2);
+        assert!(requested.iter().all(|prefix| prefix.len() == PREFIX_LENGTH));
+
+        // Within a day the cache answers; after it the ranges are fetched again
+        let mut fetched = 0;
+        check(&digests, 0, &cache, &(now + Duration::hours(23)), &mut |_| {
+            fetched += 1;
+            Ok(String::new())
+        });
+        assert_eq!(fetched, 0);
+        check(&digests, 0, &cache, &(now + Duration::hours(25)), &mut |_| {
+            fetched += 1;
+            Ok(String::new())
+        });
+        assert_eq!(fetched, 2);
+    }
+
+    #[test]
+    fn test_offline_check_reports_network_unavailable() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RangeCache::new(dir.path().join("ranges"));
+        let now = time_utils::now();
+        let digests = vec![(1, password_digest("password")), (2, password_digest("hunter2"))];
+        let breached_suffix = digests[0].1[PREFIX_LENGTH..].to_string();
+        cache.put("5BAA6", &format!("{}:10", breached_suffix), &now).unwrap();
+
+        let mut attempts = 0;
+        let report = check(&digests, 1, &cache, &now, &mut |_| {
+            attempts += 1;
+            Err(anyhow!("error sending request"))
+        });
+        assert_eq!(report.status, BreachCheckStatus::NetworkUnavailable);
+        assert!(report.message.starts_with("Network unavailable"));
+        assert_eq!((report.checked_count, report.unchecked_count, report.unreadable_count), (1, 1, 1));
+        assert_eq!(report.breaches, BTreeMap::from([(1, 10)]));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Optional features, as reported by get_capabilities
+pub const QUERY_CONSOLE: &str = "query_console";
+pub const CLIPBOARD_PARSING: &str = "clipboard_parsing";
+pub const EXPIRY_REMINDERS: &str = "expiry_reminders";
+// Have I Been Pwned lookups; opt-in since it is a network call
+pub const BREACH_CHECK: &str = "breach_check";
+// Fetching favicons for entries with a URL; opt-in for the same reason
+pub const FAVICONS: &str = "favicons";
+// The database file itself is SQLCipher-encrypted; enabled once it has been
+pub const ENCRYPTED_DATABASE: &str = "encrypted_database";
+// Commands still accept a master key from the frontend; see session.rs
+pub const LEGACY_MASTER_KEY_IPC: &str = "legacy_master_key_ipc";
+// Global shortcut that locks the vault, and the tray icon; desktop only. Enabled while the
+// shortcut is registered.
+pub const LOCK_SHORTCUT: &str = "lock_shortcut";
+// Locking the vault along with the OS session, which only Linux (over D-Bus) reports
+pub const LOCK_ON_SCREEN_LOCK: &str = "lock_on_screen_lock";
+// The browser extension native host; desktop only. Enabled once an extension is allowed.
+pub const NATIVE_MESSAGING: &str = "native_messaging";
+// Unlocking with a secret kept in the OS keychain. Enabled while it is turned on.
+pub const OS_KEYCHAIN_UNLOCK: &str = "os_keychain_unlock";
+// Unlocking with a short PIN, whose pepper is kept in the OS keychain. Enabled while it is turned on.
+pub const PIN_UNLOCK: &str = "pin_unlock";
+
+// Commands that only work when their capability is available and enabled, so the
+// frontend can hide the UI that calls them. Keep in sync with generate_handler!.
+pub const OPTIONAL_COMMANDS: &[(&str, &str)] = &[
+    ("run_readonly_query", QUERY_CONSOLE),
+    ("parse_clipboard_for_credentials", CLIPBOARD_PARSING),
+    ("migrate_to_encrypted_db", ENCRYPTED_DATABASE),
+    ("check_breaches", BREACH_CHECK),
+    ("fetch_icon", FAVICONS),
+    ("get_lock_shortcut", LOCK_SHORTCUT),
+    ("set_lock_shortcut", LOCK_SHORTCUT),
+    ("install_native_host", NATIVE_MESSAGING),
+    ("answer_native_credential_request", NATIVE_MESSAGING),
+    ("enable_os_keychain_unlock", OS_KEYCHAIN_UNLOCK),
+    ("disable_os_keychain_unlock", OS_KEYCHAIN_UNLOCK),
+    ("unlock_with_os_keychain", OS_KEYCHAIN_UNLOCK),
+    ("enable_pin_unlock", PIN_UNLOCK),
+    ("disable_pin_unlock", PIN_UNLOCK),
+    ("unlock_with_pin", PIN_UNLOCK),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capability {
+    // Compiled in and supported on this platform
+    pub available: bool,
+    // Switched on by the user (always false when unavailable)
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_if_unavailable: Option<String>,
+}
+
+impl Capability {
+    pub fn available(enabled: bool) -> Self {
+        Capability { available: true, enabled, reason_if_unavailable: None }
+    }
+
+    pub fn unavailable(reason: impl Into<String>) -> Self {
+        Capability { available: false, enabled: false, reason_if_unavailable: Some(reason.into()) }
+    }
+}
+
+// Feature name -> capability. Each optional module registers its own entry; runtime
+// probes that need the app handle may then downgrade an entry to unavailable.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct CapabilityRegistry {
+    capabilities: BTreeMap<&'static str, Capability>,
+}
+
+impl CapabilityRegistry {
+    pub fn register(&mut self, feature: &'static str, capability: Capability) {
+        self.capabilities.insert(feature, capability);
+    }
+
+    pub fn mark_unavailable(&mut self, feature: &'static str, reason: impl Into<String>) {
+        self.capabilities.insert(feature, Capability::unavailable(reason));
+    }
+
+    pub fn get(&self, feature: &str) -> Option<&Capability> {
+        self.capabilities.get(feature)
+    }
+
+    // Features decided at compile time, whose modules may not be built at all
+    pub fn register_build_features(&mut self) {
+        if cfg!(feature = "query-console") {
+            self.register(QUERY_CONSOLE, Capability::available(true));
+        } else {
+            self.register(QUERY_CONSOLE, Capability::unavailable("Not included in this build"));
+        }
+        if !cfg!(feature = "sqlcipher") {
+            self.register(ENCRYPTED_DATABASE, Capability::unavailable("Not included in this build"));
+        }
+        if !cfg!(feature = "os-keychain") {
+            self.register(OS_KEYCHAIN_UNLOCK, Capability::unavailable("Not included in this build"));
+            self.register(PIN_UNLOCK, Capability::unavailable("Not included in this build"));
+        }
+        if cfg!(desktop) {
+            // Enabled by the app once the shortcut is registered
+            self.register(LOCK_SHORTCUT, Capability::available(false));
+        } else {
+            self.register(LOCK_SHORTCUT, Capability::unavailable("Not available on this platform"));
+        }
+        if crate::session::LEGACY_KEY_IPC {
+            self.register(LEGACY_MASTER_KEY_IPC, Capability::available(true));
+        } else {
+            self.register(LEGACY_MASTER_KEY_IPC, Capability::unavailable("Removed from this build"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault_coordinator::VaultCoordinator;
+
+    // Names in generate_handler! that sit behind a #[cfg(...)] attribute
+    fn feature_gated_commands() -> Vec<String> {
+        let source = include_str!("lib.rs");
+        let handler = source.split("generate_handler![").nth(1).unwrap().split("])").next().unwrap();
+        let mut gated = Vec::new();
+        let mut lines = handler.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            if line.starts_with("#[cfg(") {
+                gated.push(lines.next().unwrap().trim_end_matches(',').to_string());
+            }
+        }
+        gated
+    }
+
+    #[test]
+    fn test_every_optional_command_has_a_capability() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let registry = vault.capabilities().unwrap();
+
+        let source = include_str!("lib.rs");
+        for (command, feature) in OPTIONAL_COMMANDS {
+            assert!(source.contains(&format!("async fn {}(", command)), "{} is not a command", command);
+            assert!(registry.get(feature).is_some(), "{} has no capability entry", feature);
+        }
+        for command in feature_gated_commands() {
+            assert!(
+                OPTIONAL_COMMANDS.iter().any(|(optional, _)| *optional == command),
+                "{} is feature-gated but not listed in OPTIONAL_COMMANDS",
+                command,
+            );
+        }
+
+        assert_eq!(registry.get(CLIPBOARD_PARSING), Some(&Capability::available(true)));
+        vault.settings(|settings_service| settings_service.set_clipboard_parsing_enabled(false)).unwrap();
+        assert_eq!(vault.capabilities().unwrap().get(CLIPBOARD_PARSING), Some(&Capability::available(false)));
+    }
+}
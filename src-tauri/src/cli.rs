@@ -0,0 +1,343 @@
+use crate::crypto::{CryptoService, DEFAULT_GENERATED_PASSWORD_LENGTH};
+use crate::data_dir;
+use crate::database::{EntryType, SecretKind};
+use crate::export_service::{ExportRequest, ImportMode, ImportRequest};
+use crate::password_service::{AddPasswordRequest, DecryptPasswordRequest, GetPasswordsRequest, PasswordEntryResponse, PasswordResponse};
+use crate::user_service::LoginRequest;
+use crate::vault_coordinator::VaultCoordinator;
+use anyhow::{Result, anyhow};
+use clap::{CommandFactory, Parser, Subcommand};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+// Headless use from a terminal, e.g. `pwdbox get github` over SSH. Each run opens the vault
+// through the same coordinator as the app and asks for the master password; nothing
+// outlives the process. The app may have the same vault open meanwhile: SQLite arbitrates
+// between the two processes (see BUSY_TIMEOUT and write_transaction in database.rs).
+#[derive(Parser)]
+#[command(name = "pwdbox", version, about = "PwdBox password manager. Without a command the app opens.")]
+struct Cli {
+    #[arg(long, global = true, help = "Print results as JSON, for scripts")]
+    json: bool,
+    // Read by data_dir.rs from the arguments; declared so it is allowed here too
+    #[arg(long, global = true, help = "Use the vault next to the executable")]
+    portable: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "List entries, without their passwords")]
+    List {
+        #[arg(help = "Only entries whose software, account or notes contain this")]
+        query: Option<String>,
+        #[arg(long, help = "Only entries with this tag")]
+        tag: Option<String>,
+    },
+    #[command(about = "Print the password of the one entry matching the query")]
+    Get {
+        #[arg(required_unless_present = "id", help = "Software or account, in full or in part")]
+        query: Option<String>,
+        #[arg(long, conflicts_with = "query", help = "The entry's id, as shown by list")]
+        id: Option<i64>,
+    },
+    #[command(about = "Add an entry; the password is asked for unless generated or read from a file")]
+    Add {
+        #[arg(long)]
+        software: String,
+        #[arg(long)]
+        account: String,
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long)]
+        notes: Option<String>,
+        #[arg(long = "tag", help = "Tag the entry; may be repeated")]
+        tags: Vec<String>,
+        #[arg(long, help = "Generate the password and print it")]
+        generate: bool,
+        #[arg(long, conflicts_with = "generate", help = "Read the password from a file as is, e.g. a private key")]
+        secret_file: Option<PathBuf>,
+        #[arg(long, value_parser = parse_secret_kind, default_value = "password", help = "password, ssh_key or api_token")]
+        kind: SecretKind,
+        #[arg(long, default_value_t = DEFAULT_GENERATED_PASSWORD_LENGTH, requires = "generate")]
+        length: usize,
+        #[arg(long, requires = "generate", help = "Letters and digits only")]
+        no_symbols: bool,
+    },
+    #[command(about = "Print a random password; the vault is not opened")]
+    Generate {
+        #[arg(long, default_value_t = DEFAULT_GENERATED_PASSWORD_LENGTH)]
+        length: usize,
+        #[arg(long, help = "Letters and digits only")]
+        no_symbols: bool,
+    },
+    #[command(about = "Write an encrypted backup of the vault")]
+    Export {
+        path: String,
+        #[arg(long, help = "Include entries in the trash")]
+        include_trash: bool,
+        #[arg(long, help = "Include files attached to entries")]
+        include_attachments: bool,
+    },
+    #[command(about = "Add the entries of a backup to the vault")]
+    Import {
+        path: String,
+        #[arg(long, help = "Replace the vault with the backup instead, master password included")]
+        replace: bool,
+        #[arg(long, help = "Let the backup's version win over an existing entry")]
+        overwrite_duplicates: bool,
+        #[arg(long, help = "The backup is from another vault; asks for its master password")]
+        other_vault: bool,
+    },
+}
+
+// None when no subcommand was given and the app should open. Arguments the CLI does not
+// know may be the platform's (a macOS process serial number, say), so those open the app
+// too unless they came with a subcommand, --help or --version.
+pub fn run() -> Option<ExitCode> {
+    let cli = match Cli::try_parse() {
+        Ok(Cli { command: None, .. }) => return None,
+        Ok(cli) => cli,
+        Err(e) if names_a_command() => {
+            let _ = e.print();
+            return Some(if e.use_stderr() { ExitCode::from(2) } else { ExitCode::SUCCESS });
+        }
+        Err(_) => return None,
+    };
+    attach_console();
+
+    match execute(cli) {
+        Ok(()) => Some(ExitCode::SUCCESS),
+        Err(e) => {
+            eprintln!("pwdbox: {:#}", e);
+            Some(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn names_a_command() -> bool {
+    let command = Cli::command();
+    std::env::args().skip(1).any(|arg| {
+        matches!(arg.as_str(), "-h" | "--help" | "-V" | "--version") || command.get_subcommands().any(|sub| sub.get_name() == arg)
+    })
+}
+
+// Windows release builds are GUI programs and start without a console; write to the
+// terminal the CLI was run from instead
+#[cfg(all(windows, not(debug_assertions)))]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(all(windows, not(debug_assertions))))]
+fn attach_console() {}
+
+fn execute(cli: Cli) -> Result<()> {
+    let Some(command) = cli.command else {
+        return Ok(());
+    };
+    match command {
+        Command::Generate { length, no_symbols } => {
+            let password = CryptoService::generate_password(length, !no_symbols)?;
+            print(cli.json, &json!({"password": password}), || password.clone())
+        }
+        Command::List { query, tag } => {
+            let (vault, master_key) = unlock()?;
+            let request = GetPasswordsRequest { master_key, search_query: query, tag, ..Default::default() };
+            let data = response_data(vault.passwords(|password_service| password_service.get_all_passwords(request))?)?;
+            let entries: Vec<PasswordEntryResponse> = serde_json::from_value(data["entries"].clone())?;
+            print(cli.json, &json!(entries), || entries.iter().map(entry_line).collect::<Vec<_>>().join("\n"))
+        }
+        Command::Get { query, id } => {
+            let (vault, master_key) = unlock()?;
+            let id = match (id, query) {
+                (Some(id), _) => id,
+                (None, Some(query)) => find_one(&vault, &query, &master_key)?,
+                (None, None) => return Err(anyhow!("Give a query or --id")),
+            };
+            let request = DecryptPasswordRequest { id, master_key, skip_usage_tracking: false };
+            let data = response_data(vault.passwords(|password_service| password_service.get_password(request))?)?;
+            let entry: PasswordEntryResponse = serde_json::from_value(data)?;
+            print(cli.json, &json!(entry), || entry.password.clone().unwrap_or_default())
+        }
+        Command::Add { software, account, url, notes, tags, generate, secret_file, kind, length, no_symbols } => {
+            let (vault, master_key) = unlock()?;
+            let password = match (generate, secret_file) {
+                (true, _) => CryptoService::generate_password(length, !no_symbols)?,
+                (false, Some(path)) => std::fs::read_to_string(&path).map_err(|e| anyhow!("Could not read {}: {}", path.display(), e))?,
+                (false, None) => prompt_new_secret("Password for the entry")?,
+            };
+            let request = AddPasswordRequest {
+                software,
+                account,
+                password: password.clone(),
+                notes,
+                expires_at: None,
+                tags,
+                url,
+                entry_type: EntryType::Login,
+                secret_kind: kind,
+                master_key,
+            };
+            let response = vault.passwords(|password_service| password_service.add_password(request))?;
+            let message = response.message.clone();
+            let mut data = response_data(response)?;
+            if generate {
+                data["password"] = json!(password);
+            }
+            print(cli.json, &data, || match generate {
+                true => format!("{}\n{}", message, password),
+                false => message.clone(),
+            })
+        }
+        Command::Export { path, include_trash, include_attachments } => {
+            let (vault, master_key) = unlock()?;
+            let request = ExportRequest {
+                export_passphrase: prompt_new_secret("Passphrase for the backup")?,
+                file_path: path,
+                include_stats_history: true,
+                include_icons: true,
+                include_attachments,
+                include_trash,
+                include_audit_log: true,
+                entry_ids: None,
+                tag: None,
+                include_user_meta: true,
+                master_key: Some(master_key),
+            };
+            let response = vault.exports(|export_service| export_service.export_data(request))?;
+            if !response.success {
+                return Err(anyhow!(response.message));
+            }
+            print(cli.json, &json!(response), || response.message.clone())
+        }
+        Command::Import { path, replace, overwrite_duplicates, other_vault } => {
+            let (vault, master_key) = unlock()?;
+            let import_passphrase = rpassword::prompt_password("Passphrase of the backup: ")?;
+            let source_master_password = match other_vault {
+                true => Some(rpassword::prompt_password("Master password of the backup's vault: ")?),
+                false => None,
+            };
+            let request = ImportRequest {
+                import_passphrase,
+                file_path: path,
+                mode: if replace { ImportMode::Replace } else { ImportMode::Merge },
+                overwrite_duplicates,
+                target_master_key: source_master_password.as_ref().map(|_| master_key.clone()),
+                source_master_password,
+                session_key: Some(master_key),
+            };
+            let response = vault.import_data(request)?;
+            if !response.success {
+                return Err(anyhow!(response.message));
+            }
+            print(cli.json, &json!(response), || response.message.clone())
+        }
+    }
+}
+
+// Open the configured vault and log in with the master password from the terminal.
+// Returns the stamped vault key the services take.
+fn unlock() -> Result<(VaultCoordinator, String)> {
+    let (dir, _) = data_dir::resolve()?;
+    if !dir.exists() {
+        return Err(anyhow!("There is no vault in {}; set one up in the app first", dir.display()));
+    }
+    let vault = VaultCoordinator::open(&dir)?;
+    if !vault.users(|user_service| user_service.is_app_setup())? {
+        return Err(anyhow!("The vault in {} is not set up yet; set it up in the app first", dir.display()));
+    }
+
+    let request = LoginRequest { master_password: rpassword::prompt_password("Master password: ")? };
+    let response = vault.login_with_progress(request, &mut |_| {})?;
+    let Some(master_key) = response.master_key.filter(|_| response.success) else {
+        return Err(anyhow!(response.message));
+    };
+    // As in the app, a failed snapshot never keeps the user out
+    let _ = vault.snapshots(|snapshot_service| snapshot_service.take_snapshot_if_due(&master_key));
+    Ok((vault, master_key))
+}
+
+// The id of the entry `query` names. An entry whose software or account is the query
+// itself wins over entries merely containing it; several of either are an error listing them.
+fn find_one(vault: &VaultCoordinator, query: &str, master_key: &str) -> Result<i64> {
+    let data = response_data(vault.passwords(|password_service| password_service.search_passwords(query, master_key))?)?;
+    let matches: Vec<PasswordEntryResponse> = serde_json::from_value(data)?;
+    let exact: Vec<&PasswordEntryResponse> = matches
+        .iter()
+        .filter(|entry| entry.software.eq_ignore_ascii_case(query) || entry.account.eq_ignore_ascii_case(query))
+        .collect();
+    let candidates: Vec<&PasswordEntryResponse> = if exact.is_empty() { matches.iter().collect() } else { exact };
+    match candidates.as_slice() {
+        [] => Err(anyhow!("No entry matches {}", query)),
+        [entry] => Ok(entry.id),
+        several => {
+            let lines: Vec<String> = several.iter().map(|entry| entry_line(entry)).collect();
+            Err(anyhow!("{} entries match {}; pick one with --id:\n{}", several.len(), query, lines.join("\n")))
+        }
+    }
+}
+
+fn response_data(response: PasswordResponse) -> Result<Value> {
+    if !response.success {
+        return Err(anyhow!(response.message));
+    }
+    Ok(response.data.unwrap_or_default())
+}
+
+// Tab-separated, so the output can be cut and sorted
+fn entry_line(entry: &PasswordEntryResponse) -> String {
+    format!("{}\t{}\t{}\t{}", entry.id, entry.software, entry.account, entry.url.as_deref().unwrap_or_default())
+}
+
+fn print(json: bool, value: &Value, text: impl FnOnce() -> String) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        println!("{}", text());
+    }
+    Ok(())
+}
+
+fn parse_secret_kind(value: &str) -> Result<SecretKind, String> {
+    serde_json::from_value(json!(value)).map_err(|_| format!("unknown kind {}; use password, ssh_key or api_token", value))
+}
+
+// Asked twice, so a typo does not lock the user out of a backup or an entry
+fn prompt_new_secret(what: &str) -> Result<String> {
+    let secret = rpassword::prompt_password(format!("{}: ", what))?;
+    if secret.is_empty() {
+        return Err(anyhow!("{} cannot be empty", what));
+    }
+    if rpassword::prompt_password(format!("{} again: ", what))? != secret {
+        return Err(anyhow!("The two entries differ"));
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subcommands_parse_and_no_subcommand_opens_the_app() {
+        Cli::command().debug_assert();
+        let gui = Cli::try_parse_from(["pwdbox", "--portable"]).unwrap();
+        assert!(gui.command.is_none());
+
+        let cli = Cli::try_parse_from(["pwdbox", "get", "github", "--json"]).unwrap();
+        assert!(cli.json);
+        assert!(matches!(cli.command, Some(Command::Get { query: Some(query), id: None }) if query == "github"));
+        assert!(Cli::try_parse_from(["pwdbox", "get"]).is_err());
+        assert!(Cli::try_parse_from(["pwdbox", "add", "--software", "a", "--account", "b", "--length", "30"]).is_err());
+        let add = Cli::try_parse_from(["pwdbox", "add", "--software", "a", "--account", "b", "--secret-file", "id_ed25519", "--kind", "ssh_key"]).unwrap();
+        assert!(matches!(add.command, Some(Command::Add { kind: SecretKind::SshKey, secret_file: Some(_), .. })));
+        assert!(Cli::try_parse_from(["pwdbox", "add", "--software", "a", "--account", "b", "--kind", "pgp"]).is_err());
+        assert!(Cli::try_parse_from(["pwdbox", "add", "--software", "a", "--account", "b", "--secret-file", "key", "--generate"]).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::database::{EntryType, SecretKind};
+use crate::domains;
+use crate::password_service::AddPasswordRequest;
+
+// Labels that introduce a value, longest first so "username" wins over "user"
+const ACCOUNT_LABELS: &[&str] = &[
+    "email address", "user name", "username", "user id", "userid", "e-mail", "email",
+    "account", "login", "user", "用户名", "账号", "帐号", "邮箱",
+];
+const PASSWORD_LABELS: &[&str] = &[
+    "temporary password", "initial password", "password", "passcode", "passwd", "pwd", "pass", "密码",
+];
+const SERVICE_LABELS: &[&str] = &["website", "service", "site", "url", "网址", "网站"];
+
+// Build an unsaved entry draft from free text such as a welcome email. Returns None when
+// neither an account nor a password is found. The draft's master_key is left empty for
+// the frontend to fill in when the user saves it.
+pub fn parse_credentials(text: &str) -> Option<AddPasswordRequest> {
+    let password = find_labeled(text, PASSWORD_LABELS);
+    let account = find_labeled(text, ACCOUNT_LABELS).or_else(|| find_email(text));
+    if password.is_none() && account.is_none() {
+        return None;
+    }
+
+    let software = find_url_host(text)
+        .or_else(|| find_labeled(text, SERVICE_LABELS).map(|value| {
+            domains::host_from(&value).map_or(value, |host| strip_www(&host))
+        }))
+        .unwrap_or_default();
+
+    Some(AddPasswordRequest {
+        software,
+        account: account.unwrap_or_default(),
+        password: password.unwrap_or_default(),
+        notes: None,
+        expires_at: None,
+        tags: Vec::new(),
+        url: None,
+        entry_type: EntryType::Login,
+        secret_kind: SecretKind::Password,
+        master_key: String::new(),
+    })
+}
+
+// First value introduced by one of `labels`, as in "Username: alice", "password = x"
+// or "your temporary password is x". Labels only match at a word start.
+fn find_labeled(text: &str, labels: &[&str]) -> Option<String> {
+    for line in text.lines() {
+        for (start, _) in line.char_indices() {
+            let at_word_start = line[..start].chars().next_back().is_none_or(|c| !c.is_alphanumeric());
+            if !at_word_start {
+                continue;
+            }
+            for label in labels {
+                let matched = line[start..]
+                    .get(..label.len())
+                    .is_some_and(|candidate| candidate.eq_ignore_ascii_case(label));
+                if !matched {
+                    continue;
+                }
+                if let Some(value) = value_after_label(&line[start + label.len()..]) {
+                    return Some(value);
+                }
+            }
+        }
+    }
+    None
+}
+
+// The value following a label: a separator (":", "=", full-width colon, or "is") and
+// then the next whitespace-delimited token
+fn value_after_label(rest: &str) -> Option<String> {
+    let rest = rest.trim_start();
+    let rest = if let Some(after) = rest.strip_prefix(|c| matches!(c, ':' | '：' | '=')) {
+        after
+    } else {
+        let after = rest.strip_prefix("is")?;
+        after.strip_prefix([':', '：']).or_else(|| after.strip_prefix(char::is_whitespace))?
+    };
+
+    let token = rest.split_whitespace().next()?;
+    let value = clean_token(token);
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+// Drop quoting and the punctuation that ends a sentence around a token. A password that
+// genuinely ends in "." or "," loses it; drafts are always reviewed before saving.
+fn clean_token(token: &str) -> &str {
+    token
+        .trim_end_matches([',', ';', '.'])
+        .trim_matches(|c| matches!(c, '"' | '\'' | '`' | '“' | '”' | '「' | '」' | '<' | '>'))
+}
+
+fn find_email(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .map(clean_token)
+        .map(|token| token.trim_start_matches("mailto:"))
+        .find(|token| {
+            token.split_once('@').is_some_and(|(local, domain)| {
+                !local.is_empty() && domains::host_from(domain).is_some_and(|host| host == domain.to_lowercase())
+            })
+        })
+        .map(str::to_string)
+}
+
+fn find_url_host(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .map(clean_token)
+        .filter(|token| {
+            let lower = token.to_ascii_lowercase();
+            lower.starts_with("https://") || lower.starts_with("http://")
+        })
+        .find_map(domains::host_from)
+        .map(|host| strip_www(&host))
+}
+
+fn strip_www(host: &str) -> String {
+    host.strip_prefix("www.").unwrap_or(host).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(text: &str) -> (String, String, String) {
+        let draft = parse_credentials(text).unwrap();
+        assert!(draft.master_key.is_empty());
+        (draft.software, draft.account, draft.password)
+    }
+
+    fn triple(software: &str, account: &str, password: &str) -> (String, String, String) {
+        (software.to_string(), account.to_string(), password.to_string())
+    }
+
+    #[test]
+    fn test_welcome_email_formats() {
+        assert_eq!(
+            parsed("Welcome aboard!\n\nUsername: alice\nPassword: S3cure!pass\n\nSign in at https://www.example.com/login"),
+            triple("example.com", "alice", "S3cure!pass"),
+        );
+        assert_eq!(
+            parsed("username: bob / password: hunter2"),
+            triple("", "bob", "hunter2"),
+        );
+        assert_eq!(
+            parsed("Your account has been created.\nLogin = carol@corp.example\nYour temporary password is: \"Tmp-1234\".\nPortal: https://portal.corp.example"),
+            triple("portal.corp.example", "carol@corp.example", "Tmp-1234"),
+        );
+        assert_eq!(
+            parsed("Hi dave@mail.example.org, your new password is x9!Kq2z"),
+            triple("", "dave@mail.example.org", "x9!Kq2z"),
+        );
+        assert_eq!(
+            parsed("Website: shop.example.net\nUser ID: 100234\nPIN code follows separately\nPWD=abc:def"),
+            triple("shop.example.net", "100234", "abc:def"),
+        );
+        assert_eq!(
+            parsed("网站：https://mail.example.cn\n用户名：小明\n密码：Mima2024"),
+            triple("mail.example.cn", "小明", "Mima2024"),
+        );
+    }
+
+    #[test]
+    fn test_labels_need_word_starts_and_separators() {
+        // "passwords" and "bypass" are not labels; "user" inside "superuser" is not either
+        assert!(parse_credentials("Manage your passwords in one place. Bypass: none. superuser: root").is_none());
+        assert!(parse_credentials("Just a note about lunch").is_none());
+        assert!(parse_credentials("Password:").is_none());
+        assert_eq!(parsed("Password reset for you\npassword: correct-horse").2, "correct-horse");
+    }
+}
@@ -1,14 +1,242 @@
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, AeadInPlace, KeyInit, OsRng, Payload},
     Aes256Gcm, Key, Nonce,
 };
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::{rand_core::RngCore, SaltString}};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version, password_hash::{rand_core::RngCore, SaltString}};
 use base64::{Engine as _, engine::general_purpose};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::ops::Range;
+use zeroize::{Zeroize, Zeroizing};
 
+use crate::error::PwdBoxError;
+use crate::password_policy;
 use anyhow::{Result, anyhow};
 
+// A 256-bit key that is zeroed when dropped. Derived, unwrapped and decoded keys are all
+// handed out as this, so no copy outlives its use.
+pub type SecretKey = Zeroizing<[u8; 32]>;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Bounds accepted for KDF parameters, whether set by the user or read from an export file
+const MIN_KDF_M_COST: u32 = 8 * 1024; // 8 MiB
+pub const MAX_KDF_M_COST: u32 = 1024 * 1024; // 1 GiB
+const MAX_KDF_T_COST: u32 = 16;
+const MAX_KDF_P_COST: u32 = 16;
+
+// Recovery codes carry this many random bytes, written as groups of four hex digits
+const RECOVERY_CODE_BYTES: usize = 16;
+
+// AES-GCM nonces are 96 bits
+pub const NONCE_LENGTH: usize = 12;
+
+// Encrypted under the vault key at setup; see encrypt_canary
+const CANARY_PLAINTEXT: &str = "pwdbox-canary-v1";
+
+// First line of a v2 export file. v1 files are bare base64, which never starts with it.
+pub const EXPORT_MAGIC: &str = "PWDBOX";
+pub const EXPORT_FORMAT_VERSION: u32 = 2;
+
+// Why a file that is neither format is refused, binaries and text alike
+pub const NOT_AN_EXPORT: &str = "This file doesn't look like a PwdBox export";
+
+// Base64 decoded at a time when an export payload is decoded in place; a multiple of 4
+const BASE64_CHUNK: usize = 64 * 1024;
+
+// The message an export header's key_check tags
+const KEY_CHECK_INPUT: &[u8] = b"pwdbox-export-key-check";
+
+// Fragments of the most common passwords and keyboard walks; a password_strength match
+// counts as a single character
+const COMMON_PATTERNS: &[&str] = &[
+    "password", "passw0rd", "qwertyuiop", "qwerty", "asdfgh", "zxcvbn", "letmein", "welcome",
+    "iloveyou", "admin", "login", "dragon", "monkey", "master", "shadow", "sunshine",
+    "football", "baseball", "princess", "trustno1", "abc123", "111111", "123123",
+];
+
+// Estimated bits at which password_strength moves up to scores 1 to 4
+const STRENGTH_THRESHOLDS: [u32; 4] = [28, 40, 60, 80];
+
+// Latencies benchmark_kdf will tune for
+const MIN_KDF_TARGET_MS: u64 = 100;
+const MAX_KDF_TARGET_MS: u64 = 5_000;
+
+// Argon2id cost parameters. Recorded next to whatever they produced, since the same
+// password derives a different key under different parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub m_cost: u32, // Memory in KiB
+    pub t_cost: u32, // Iterations
+    pub p_cost: u32, // Lanes
+}
+
+// The argon2 crate's defaults, used for every hash and key made before parameters were recorded
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    pub fn validate(&self) -> Result<()> {
+        if !(MIN_KDF_M_COST..=MAX_KDF_M_COST).contains(&self.m_cost) {
+            return Err(anyhow!("KDF memory must be between {} and {} KiB", MIN_KDF_M_COST, MAX_KDF_M_COST));
+        }
+        if !(1..=MAX_KDF_T_COST).contains(&self.t_cost) {
+            return Err(anyhow!("KDF iterations must be between 1 and {}", MAX_KDF_T_COST));
+        }
+        if !(1..=MAX_KDF_P_COST).contains(&self.p_cost) {
+            return Err(anyhow!("KDF parallelism must be between 1 and {}", MAX_KDF_P_COST));
+        }
+        Ok(())
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+            .map_err(|e| anyhow!("Invalid KDF parameters: {}", e))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    // Parse the Display form. Bounded like user input, so a crafted file cannot ask for unbounded memory
+    fn from_header(header: &str) -> Result<Self> {
+        let mut params = KdfParams { m_cost: 0, t_cost: 0, p_cost: 0 };
+        for field in header.split(',') {
+            let (name, value) = field.split_once('=').ok_or_else(|| anyhow!("Invalid KDF header"))?;
+            let value = value.parse()?;
+            match name {
+                "m" => params.m_cost = value,
+                "t" => params.t_cost = value,
+                "p" => params.p_cost = value,
+                _ => return Err(anyhow!("Invalid KDF header")),
+            }
+        }
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+// "m=19456,t=2,p=1", as written in export file headers and audit details
+impl std::fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "m={},t={},p={}", self.m_cost, self.t_cost, self.p_cost)
+    }
+}
+
+// Plaintext second line of a v2 export file, readable without the passphrase. The magic
+// line and this line are the payload's associated data, so editing either makes the file
+// fail to decrypt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub format_version: u32,
+    pub kdf: String, // Always "argon2id" so far
+    pub kdf_params: KdfParams,
+    pub salt: String,
+    pub nonce: String,
+    pub created_at: String, // UTC RFC3339
+    pub app_version: String,
+    // Tag of a fixed string under the file's MAC key. It tells a wrong passphrase from a
+    // damaged file, and where it is present the file must end in a MAC line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_check: Option<String>,
+}
+
+// Why an export file would not open. Files without an integrity tag cannot tell the two
+// apart and always report WrongPassphrase.
+#[derive(Debug)]
+pub enum ExportOpenError {
+    WrongPassphrase,
+    Corrupted(String),
+}
+
+impl std::fmt::Display for ExportOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportOpenError::WrongPassphrase => write!(f, "Failed to decrypt import file. Please check your passphrase."),
+            ExportOpenError::Corrupted(reason) => write!(f, "The export file is damaged or incomplete: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ExportOpenError {}
+
+// An export file taken apart, ready for the key derived from its passphrase
+pub struct ExportEnvelope {
+    pub format_version: u32,
+    pub header: Option<ExportHeader>, // v2 files only
+    pub kdf_params: KdfParams,
+    pub salt: String,
+    nonce: String,
+    // The file as read. Its payload is decoded and decrypted where it lies, so opening
+    // a large export never holds a second copy of it.
+    contents: Vec<u8>,
+    payload: Range<usize>, // The base64 ciphertext within `contents`
+    aad: Vec<u8>,
+    mac: Option<String>, // Tag over the aad and the payload line
+}
+
+impl ExportEnvelope {
+    // Where the file has an integrity tag it is checked before anything is decrypted
+    pub fn open(self, key: &[u8; 32]) -> Result<String> {
+        let key_check = self.header.as_ref().and_then(|header| header.key_check.as_deref());
+        if let Some(key_check) = key_check {
+            let mac_key = CryptoService::export_mac_key(key)?;
+            if !CryptoService::verify_tag(&mac_key, &[KEY_CHECK_INPUT], key_check) {
+                return Err(ExportOpenError::WrongPassphrase.into());
+            }
+            let mac = self.mac.as_deref().ok_or_else(|| ExportOpenError::Corrupted("the integrity tag is missing".to_string()))?;
+            if !CryptoService::verify_tag(&mac_key, &[&self.aad, &self.contents[self.payload.clone()], b"\n"], mac) {
+                return Err(ExportOpenError::Corrupted("the integrity tag does not match".to_string()).into());
+            }
+        }
+        let checked = key_check.is_some();
+
+        let failed = || -> anyhow::Error {
+            match checked {
+                true => ExportOpenError::Corrupted("the payload does not decrypt".to_string()),
+                false => ExportOpenError::WrongPassphrase,
+            }
+            .into()
+        };
+        let ciphertext = CryptoService::decode_base64_in_place(self.contents, self.payload).map_err(|_| failed())?;
+        let plaintext = CryptoService::decrypt_in_place(ciphertext, key, &self.nonce, &self.aad).map_err(|_| failed())?;
+        String::from_utf8(plaintext).map_err(|e| {
+            let error = anyhow!("Failed to convert decrypted data to string: {}", e.utf8_error());
+            CryptoService::clear_sensitive_data(&mut e.into_bytes());
+            error
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfTiming {
+    pub params: KdfParams,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfBenchmark {
+    pub target_ms: u64,
+    pub recommended: KdfParams,
+    pub timings: Vec<KdfTiming>, // Every probe, in the order they ran
+}
+
 pub struct CryptoService;
 
+// Character sets and length bounds for generate_password
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b"!#$%&()*+,-./:;<=>?@[]^_{|}~";
+pub const DEFAULT_GENERATED_PASSWORD_LENGTH: usize = 20;
+const MIN_GENERATED_PASSWORD_LENGTH: usize = 8;
+const MAX_GENERATED_PASSWORD_LENGTH: usize = 128;
+
 impl CryptoService {
     // Generate a random salt for hashing
     pub fn generate_salt() -> String {
@@ -17,13 +245,13 @@ impl CryptoService {
         general_purpose::STANDARD.encode(salt)
     }
 
-    // Hash a password using Argon2 with salt
-    pub fn hash_password(password: &str, salt: &str) -> Result<String> {
+    // Hash a password using Argon2 with salt. The parameters are embedded in the hash.
+    pub fn hash_password(password: &str, salt: &str, params: &KdfParams) -> Result<String> {
         let salt_bytes = general_purpose::STANDARD.decode(salt)?;
         let salt_str = SaltString::encode_b64(&salt_bytes)
             .map_err(|e| anyhow!("Failed to encode salt: {}", e))?;
 
-        let argon2 = Argon2::default();
+        let argon2 = params.argon2()?;
         let password_hash = argon2
             .hash_password(password.as_bytes(), &salt_str)
             .map_err(|e| anyhow!("Failed to hash password: {}", e))?;
@@ -31,7 +259,7 @@ impl CryptoService {
         Ok(password_hash.to_string())
     }
 
-    // Verify a password against its hash
+    // Verify a password against its hash, under the parameters the hash records
     pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
         let parsed_hash = PasswordHash::new(hash)
             .map_err(|e| anyhow!("Failed to parse hash: {}", e))?;
@@ -44,21 +272,147 @@ impl CryptoService {
     }
 
     // Derive encryption key from master password
-    pub fn derive_key_from_password(password: &str, salt: &str) -> Result<[u8; 32]> {
+    pub fn derive_key_from_password(password: &str, salt: &str, params: &KdfParams) -> Result<SecretKey> {
         let salt_bytes = general_purpose::STANDARD.decode(salt)?;
         if salt_bytes.len() < 16 {
             return Err(anyhow!("Salt must be at least 16 bytes"));
         }
 
-        let argon2 = Argon2::default();
-        let mut key = [0u8; 32];
+        let argon2 = params.argon2()?;
+        let mut key = SecretKey::default();
         
-        argon2.hash_password_into(password.as_bytes(), &salt_bytes, &mut key)
+        argon2.hash_password_into(password.as_bytes(), &salt_bytes, key.as_mut_slice())
             .map_err(|e| anyhow!("Failed to derive key: {}", e))?;
 
         Ok(key)
     }
 
+    // Time Argon2 on this machine and recommend the costliest parameters that still derive
+    // within `target_ms`: memory doubles from the defaults up to MAX_KDF_M_COST, then
+    // iterations go up. Never recommends less than the defaults, however slow the machine.
+    // Takes up to about twice the target per probe, so run it off the async runtime.
+    pub fn benchmark_kdf(target_ms: u64) -> Result<KdfBenchmark> {
+        if !(MIN_KDF_TARGET_MS..=MAX_KDF_TARGET_MS).contains(&target_ms) {
+            return Err(anyhow!("KDF target must be between {} and {} ms", MIN_KDF_TARGET_MS, MAX_KDF_TARGET_MS));
+        }
+
+        let salt = Self::generate_salt();
+        let mut timings: Vec<KdfTiming> = Vec::new();
+        let mut recommended = KdfParams::default();
+        let mut params = recommended;
+        loop {
+            let started = std::time::Instant::now();
+            Self::derive_key_from_password("kdf benchmark", &salt, &params)?;
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            timings.push(KdfTiming { params, elapsed_ms });
+            if elapsed_ms > target_ms {
+                break;
+            }
+            recommended = params;
+
+            params = if params.m_cost < MAX_KDF_M_COST {
+                KdfParams { m_cost: (params.m_cost * 2).min(MAX_KDF_M_COST), ..params }
+            } else if params.t_cost < MAX_KDF_T_COST {
+                KdfParams { t_cost: params.t_cost + 1, ..params }
+            } else {
+                break;
+            };
+        }
+
+        Ok(KdfBenchmark { target_ms, recommended, timings })
+    }
+
+    // Generate a random 256-bit key used to encrypt vault entries
+    pub fn generate_vault_key() -> SecretKey {
+        let mut key = SecretKey::default();
+        OsRng.fill_bytes(key.as_mut_slice());
+        key
+    }
+
+    // Wrap a key under a key-encryption key, returning (wrapped_key, nonce)
+    pub fn wrap_key(key: &[u8; 32], kek: &[u8; 32]) -> Result<(String, String)> {
+        let nonce = Self::generate_nonce();
+        let encoded = Zeroizing::new(general_purpose::STANDARD.encode(key));
+        let wrapped = Self::encrypt_data(&encoded, kek, &nonce)?;
+        Ok((wrapped, nonce))
+    }
+
+    // Unwrap a key previously wrapped with `wrap_key`
+    pub fn unwrap_key(wrapped_key: &str, nonce: &str, kek: &[u8; 32]) -> Result<SecretKey> {
+        let encoded = Zeroizing::new(Self::decrypt_data(wrapped_key, kek, nonce)?);
+        Self::decode_key(&encoded).map_err(|_| anyhow!("Invalid wrapped key length"))
+    }
+
+    // Decode a base64 encoded 256-bit key
+    pub fn decode_key(key_b64: &str) -> Result<SecretKey> {
+        let key_bytes = Zeroizing::new(general_purpose::STANDARD.decode(key_b64)?);
+        if key_bytes.len() != 32 {
+            return Err(anyhow!("Invalid master key length"));
+        }
+        let mut key = SecretKey::default();
+        key.copy_from_slice(&key_bytes);
+        Ok(key)
+    }
+
+    // A fresh 128-bit recovery code ("3f9a-0c1e-..."). Shown to the user once; only its
+    // verifier and the vault key wrapped under it are stored.
+    pub fn generate_recovery_code() -> Zeroizing<String> {
+        use std::fmt::Write as _;
+        let mut bytes = Zeroizing::new([0u8; RECOVERY_CODE_BYTES]);
+        OsRng.fill_bytes(bytes.as_mut_slice());
+        let mut code = Zeroizing::new(String::with_capacity(RECOVERY_CODE_BYTES * 5 / 2));
+        for (i, byte) in bytes.iter().enumerate() {
+            if i > 0 && i % 2 == 0 {
+                code.push('-');
+            }
+            let _ = write!(code, "{:02x}", byte);
+        }
+        code
+    }
+
+    // The hex digits of a typed recovery code, ignoring dashes, spaces and case; None if it
+    // cannot be one
+    pub fn normalize_recovery_code(code: &str) -> Option<Zeroizing<String>> {
+        let digits = Zeroizing::new(
+            code.chars().filter(|c| !c.is_whitespace() && *c != '-').map(|c| c.to_ascii_lowercase()).collect::<String>(),
+        );
+        (digits.len() == RECOVERY_CODE_BYTES * 2 && digits.chars().all(|c| c.is_ascii_hexdigit())).then_some(digits)
+    }
+
+    // Recovery codes are full-entropy, so unlike passwords and answers they need no Argon2 to
+    // slow down guessing: the key and verifier are HMACs of the salt keyed by the code
+    fn recovery_code_mac(code: &str, salt: &str, purpose: &[u8]) -> Result<HmacSha256> {
+        let digits = Self::normalize_recovery_code(code).ok_or_else(|| anyhow!("Not a recovery code"))?;
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(digits.as_bytes()).map_err(|e| anyhow!("Invalid recovery code: {}", e))?;
+        mac.update(purpose);
+        mac.update(&general_purpose::STANDARD.decode(salt)?);
+        Ok(mac)
+    }
+
+    // Key the vault key is wrapped under for a recovery code
+    pub fn recovery_code_kek(code: &str, salt: &str) -> Result<SecretKey> {
+        let mac = Self::recovery_code_mac(code, salt, b"pwdbox-recovery-code-kek")?;
+        let mut kek = SecretKey::default();
+        kek.copy_from_slice(&mac.finalize().into_bytes());
+        Ok(kek)
+    }
+
+    pub fn recovery_code_verifier(code: &str, salt: &str) -> Result<String> {
+        let mac = Self::recovery_code_mac(code, salt, b"pwdbox-recovery-code-verifier")?;
+        Ok(general_purpose::STANDARD.encode(mac.finalize().into_bytes()))
+    }
+
+    // Constant-time check of a typed code against its stored verifier
+    pub fn verify_recovery_code(code: &str, salt: &str, verifier: &str) -> bool {
+        let (Ok(mac), Ok(verifier)) = (
+            Self::recovery_code_mac(code, salt, b"pwdbox-recovery-code-verifier"),
+            general_purpose::STANDARD.decode(verifier),
+        ) else {
+            return false;
+        };
+        mac.verify_slice(&verifier).is_ok()
+    }
+
     // Generate a random nonce for AES-GCM
     pub fn generate_nonce() -> String {
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
@@ -67,8 +421,25 @@ impl CryptoService {
 
     // Encrypt data using AES-GCM
     pub fn encrypt_data(data: &str, key: &[u8; 32], nonce_str: &str) -> Result<String> {
+        Self::encrypt_data_with_aad(data, key, nonce_str, &[])
+    }
+
+    // Encrypt data using AES-GCM, authenticating `aad` alongside it. Empty `aad` is the
+    // same as none, so encrypt_data output decrypts here with empty `aad` and vice versa.
+    pub fn encrypt_data_with_aad(data: &str, key: &[u8; 32], nonce_str: &str, aad: &[u8]) -> Result<String> {
+        let ciphertext = Self::encrypt_bytes_with_aad(data.as_bytes(), key, nonce_str, aad)?;
+        Ok(general_purpose::STANDARD.encode(ciphertext))
+    }
+
+    // Encrypt bytes that need not be UTF-8, returning the raw ciphertext; how it is stored
+    // or encoded is up to the caller. encrypt_data is this plus base64.
+    pub fn encrypt_bytes(data: &[u8], key: &[u8; 32], nonce_str: &str) -> Result<Vec<u8>> {
+        Self::encrypt_bytes_with_aad(data, key, nonce_str, &[])
+    }
+
+    pub fn encrypt_bytes_with_aad(data: &[u8], key: &[u8; 32], nonce_str: &str, aad: &[u8]) -> Result<Vec<u8>> {
         let nonce_bytes = general_purpose::STANDARD.decode(nonce_str)?;
-        if nonce_bytes.len() != 12 {
+        if nonce_bytes.len() != NONCE_LENGTH {
             return Err(anyhow!("Invalid nonce length"));
         }
 
@@ -77,79 +448,440 @@ impl CryptoService {
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         let ciphertext = cipher
-            .encrypt(nonce, data.as_bytes())
-            .map_err(|e| anyhow!("Encryption failed: {}", e))?;
-
-        Ok(general_purpose::STANDARD.encode(ciphertext))
+            .encrypt(nonce, Payload { msg: data, aad })
+            .map_err(|e| PwdBoxError::crypto(format!("Encryption failed: {}", e)))?;
+        Ok(ciphertext)
     }
 
     // Decrypt data using AES-GCM
     pub fn decrypt_data(encrypted_data: &str, key: &[u8; 32], nonce_str: &str) -> Result<String> {
+        Self::decrypt_data_with_aad(encrypted_data, key, nonce_str, &[])
+    }
+
+    // Decrypt data using AES-GCM; fails unless `aad` is what it was encrypted with
+    pub fn decrypt_data_with_aad(encrypted_data: &str, key: &[u8; 32], nonce_str: &str, aad: &[u8]) -> Result<String> {
+        let ciphertext = general_purpose::STANDARD.decode(encrypted_data)?;
+        let plaintext = Self::decrypt_bytes_with_aad(&ciphertext, key, nonce_str, aad)?;
+
+        // On success the buffer becomes the string; otherwise clear it here
+        String::from_utf8(plaintext).map_err(|e| {
+            let error = anyhow!("Failed to convert decrypted data to string: {}", e.utf8_error());
+            Self::clear_sensitive_data(&mut e.into_bytes());
+            error
+        })
+    }
+
+    // Decrypt raw ciphertext from encrypt_bytes. The caller clears the plaintext once done
+    // with it.
+    pub fn decrypt_bytes(ciphertext: &[u8], key: &[u8; 32], nonce_str: &str) -> Result<Vec<u8>> {
+        Self::decrypt_bytes_with_aad(ciphertext, key, nonce_str, &[])
+    }
+
+    pub fn decrypt_bytes_with_aad(ciphertext: &[u8], key: &[u8; 32], nonce_str: &str, aad: &[u8]) -> Result<Vec<u8>> {
         let nonce_bytes = general_purpose::STANDARD.decode(nonce_str)?;
-        if nonce_bytes.len() != 12 {
+        if nonce_bytes.len() != NONCE_LENGTH {
             return Err(anyhow!("Invalid nonce length"));
         }
 
-        let ciphertext = general_purpose::STANDARD.decode(encrypted_data)?;
         let key = Key::<Aes256Gcm>::from_slice(key);
         let cipher = Aes256Gcm::new(key);
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         let plaintext = cipher
-            .decrypt(nonce, ciphertext.as_ref())
-            .map_err(|e| anyhow!("Decryption failed: {}", e))?;
+            .decrypt(nonce, Payload { msg: ciphertext, aad })
+            .map_err(|e| PwdBoxError::crypto(format!("Decryption failed: {}", e)))?;
+        Ok(plaintext)
+    }
+
+    // Random id an entry's ciphertexts are bound to. Kept for the life of the entry, across
+    // exports and re-encryption, unlike its row id.
+    pub fn generate_entry_uid() -> String {
+        let mut uid = [0u8; 16];
+        OsRng.fill_bytes(&mut uid);
+        general_purpose::STANDARD.encode(uid)
+    }
+
+    // Random password of `length` characters from letters and digits, and symbols when
+    // asked, with at least one character of each kind
+    pub fn generate_password(length: usize, symbols: bool) -> Result<String> {
+        if !(MIN_GENERATED_PASSWORD_LENGTH..=MAX_GENERATED_PASSWORD_LENGTH).contains(&length) {
+            return Err(PwdBoxError::invalid_field(
+                "length",
+                format!("Length must be between {} and {}", MIN_GENERATED_PASSWORD_LENGTH, MAX_GENERATED_PASSWORD_LENGTH),
+            )
+            .into());
+        }
+        let mut classes = vec![LOWERCASE, UPPERCASE, DIGITS];
+        if symbols {
+            classes.push(SYMBOLS);
+        }
+        let alphabet: Vec<u8> = classes.concat();
+        loop {
+            let password: String = (0..length).map(|_| alphabet[Self::random_index(alphabet.len())] as char).collect();
+            if classes.iter().all(|class| password.bytes().any(|byte| class.contains(&byte))) {
+                return Ok(password);
+            }
+        }
+    }
+
+    // Uniform in 0..len (len <= 256): bytes from the uneven tail of the range are drawn again
+    fn random_index(len: usize) -> usize {
+        let limit = 256 - 256 % len;
+        loop {
+            let mut byte = [0u8; 1];
+            OsRng.fill_bytes(&mut byte);
+            if (byte[0] as usize) < limit {
+                return byte[0] as usize % len;
+            }
+        }
+    }
 
-        String::from_utf8(plaintext)
-            .map_err(|e| anyhow!("Failed to convert decrypted data to string: {}", e))
+    // Associated data tying a ciphertext to the entry and field it was written for, so one
+    // copied onto another row, or from password to notes, fails authentication. Entries
+    // without a uid were encrypted before this binding and have none.
+    fn entry_aad(entry_uid: Option<&str>, field: &str) -> Vec<u8> {
+        match entry_uid {
+            Some(uid) => format!("pwdbox-entry:{}:{}", uid, field).into_bytes(),
+            None => Vec::new(),
+        }
     }
 
-    // Encrypt password entry
-    pub fn encrypt_password(password: &str, master_key: &[u8; 32]) -> Result<(String, String)> {
+    // Encrypt password entry. The password goes through whole as UTF-8, whatever its length
+    // or line breaks; see MAX_SECRET_BYTES in password_service.rs for the size limit.
+    pub fn encrypt_password(password: &str, master_key: &[u8; 32], entry_uid: Option<&str>) -> Result<(String, String)> {
         let nonce = Self::generate_nonce();
-        let encrypted = Self::encrypt_data(password, master_key, &nonce)?;
+        let encrypted = Self::encrypt_data_with_aad(password, master_key, &nonce, &Self::entry_aad(entry_uid, "password"))?;
         Ok((encrypted, nonce))
     }
 
     // Decrypt password entry
-    pub fn decrypt_password(encrypted_password: &str, nonce: &str, master_key: &[u8; 32]) -> Result<String> {
-        Self::decrypt_data(encrypted_password, master_key, nonce)
+    pub fn decrypt_password(encrypted_password: &str, nonce: &str, master_key: &[u8; 32], entry_uid: Option<&str>) -> Result<String> {
+        Self::decrypt_data_with_aad(encrypted_password, master_key, nonce, &Self::entry_aad(entry_uid, "password"))
+    }
+
+    // Encrypt optional entry notes under their own nonce, returning (encrypted_notes, notes_nonce)
+    pub fn encrypt_notes(notes: Option<&str>, master_key: &[u8; 32], entry_uid: Option<&str>) -> Result<(Option<String>, Option<String>)> {
+        match notes.filter(|notes| !notes.is_empty()) {
+            Some(notes) => {
+                let nonce = Self::generate_nonce();
+                let encrypted = Self::encrypt_data_with_aad(notes, master_key, &nonce, &Self::entry_aad(entry_uid, "notes"))?;
+                Ok((Some(encrypted), Some(nonce)))
+            }
+            None => Ok((None, None)),
+        }
+    }
+
+    // Encrypt a file attached to an entry under a fresh nonce, returning (ciphertext, nonce).
+    // Attachments are only added to entries that have a uid, so they are always bound.
+    pub fn encrypt_attachment(data: &[u8], master_key: &[u8; 32], entry_uid: &str) -> Result<(Vec<u8>, String)> {
+        let nonce = Self::generate_nonce();
+        let encrypted = Self::encrypt_bytes_with_aad(data, master_key, &nonce, &Self::entry_aad(Some(entry_uid), "attachment"))?;
+        Ok((encrypted, nonce))
+    }
+
+    pub fn decrypt_attachment(encrypted: &[u8], nonce: &str, master_key: &[u8; 32], entry_uid: &str) -> Result<Zeroizing<Vec<u8>>> {
+        Self::decrypt_bytes_with_aad(encrypted, master_key, nonce, &Self::entry_aad(Some(entry_uid), "attachment")).map(Zeroizing::new)
+    }
+
+    // Encrypt a custom field's value under a fresh nonce, returning (encrypted, nonce). Like
+    // attachments, fields are only added to entries that have a uid.
+    pub fn encrypt_custom_field(value: &str, master_key: &[u8; 32], entry_uid: &str) -> Result<(String, String)> {
+        let nonce = Self::generate_nonce();
+        let encrypted = Self::encrypt_data_with_aad(value, master_key, &nonce, &Self::entry_aad(Some(entry_uid), "custom_field"))?;
+        Ok((encrypted, nonce))
     }
 
-    // Encrypt export data with a user-provided passphrase
-    pub fn encrypt_export_data(data: &str, passphrase: &str) -> Result<String> {
+    pub fn decrypt_custom_field(encrypted: &str, nonce: &str, master_key: &[u8; 32], entry_uid: &str) -> Result<String> {
+        Self::decrypt_data_with_aad(encrypted, master_key, nonce, &Self::entry_aad(Some(entry_uid), "custom_field"))
+    }
+
+    // Decrypt entry notes. Notes stored without a nonce predate notes encryption and are plaintext.
+    pub fn decrypt_notes(notes: Option<&str>, notes_nonce: Option<&str>, master_key: &[u8; 32], entry_uid: Option<&str>) -> Result<Option<String>> {
+        match (notes, notes_nonce) {
+            (Some(notes), Some(nonce)) => Ok(Some(Self::decrypt_data_with_aad(notes, master_key, nonce, &Self::entry_aad(entry_uid, "notes"))?)),
+            (notes, None) => Ok(notes.map(str::to_string)),
+            (None, Some(_)) => Ok(None),
+        }
+    }
+
+    // Encrypt one of an entry's name fields ("software" or "account"), returning (encrypted, nonce).
+    // Always bound to the entry's uid: only entries that have one get their names encrypted.
+    pub fn encrypt_metadata(value: &str, master_key: &[u8; 32], entry_uid: &str, field: &str) -> Result<(String, String)> {
+        let nonce = Self::generate_nonce();
+        let encrypted = Self::encrypt_data_with_aad(value, master_key, &nonce, &Self::entry_aad(Some(entry_uid), field))?;
+        Ok((encrypted, nonce))
+    }
+
+    pub fn decrypt_metadata(encrypted: &str, nonce: &str, master_key: &[u8; 32], entry_uid: &str, field: &str) -> Result<String> {
+        Self::decrypt_data_with_aad(encrypted, master_key, nonce, &Self::entry_aad(Some(entry_uid), field))
+    }
+
+    // Encrypt the known canary plaintext, returning (encrypted, nonce). Stored with the user
+    // meta so a key can be checked without any entry to decrypt.
+    pub fn encrypt_canary(master_key: &[u8; 32]) -> Result<(String, String)> {
+        let nonce = Self::generate_nonce();
+        let encrypted = Self::encrypt_data_with_aad(CANARY_PLAINTEXT, master_key, &nonce, b"pwdbox-canary")?;
+        Ok((encrypted, nonce))
+    }
+
+    // Whether `master_key` opens the canary
+    pub fn verify_canary(encrypted: &str, nonce: &str, master_key: &[u8; 32]) -> bool {
+        Self::decrypt_data_with_aad(encrypted, master_key, nonce, b"pwdbox-canary")
+            .is_ok_and(|plaintext| plaintext == CANARY_PLAINTEXT)
+    }
+
+    // Encrypt export data into the v1 format, base64 of params:salt:nonce:payload. New
+    // exports are written as v2; v1 files are still read.
+    pub fn encrypt_export_data(data: &str, passphrase: &str, params: &KdfParams) -> Result<String> {
         let salt = Self::generate_salt();
-        let key = Self::derive_key_from_password(passphrase, &salt)?;
+        let key = Self::derive_key_from_password(passphrase, &salt, params)?;
+        Self::seal_export_data(data, params, &salt, &key)
+    }
+
+    // Encrypt v1 export data under a key already derived from the passphrase, `params` and `salt`
+    pub fn seal_export_data(data: &str, params: &KdfParams, salt: &str, key: &[u8; 32]) -> Result<String> {
         let nonce = Self::generate_nonce();
-        let encrypted = Self::encrypt_data(data, &key, &nonce)?;
+        let encrypted = Self::encrypt_data(data, key, &nonce)?;
 
-        // Create export format: salt:nonce:encrypted_data
-        let export_data = format!("{}:{}:{}", salt, nonce, encrypted);
+        // Create export format: kdf_params:salt:nonce:encrypted_data
+        let export_data = format!("{}:{}:{}:{}", params, salt, nonce, encrypted);
         Ok(general_purpose::STANDARD.encode(export_data))
     }
 
-    // Decrypt export data with a user-provided passphrase
+    // Encrypt export data into the v2 format: the magic line, a JSON header line, the base64
+    // payload, then an HMAC-SHA256 over all of those lines
+    pub fn encrypt_export_data_v2(data: &str, passphrase: &str, params: &KdfParams) -> Result<String> {
+        let salt = Self::generate_salt();
+        let key = Self::derive_key_from_password(passphrase, &salt, params)?;
+        Self::seal_export_data_v2(data, params, &salt, &key)
+    }
+
+    // Encrypt v2 export data under a key already derived from the passphrase, `params` and `salt`
+    pub fn seal_export_data_v2(data: &str, params: &KdfParams, salt: &str, key: &[u8; 32]) -> Result<String> {
+        let header = ExportHeader {
+            format_version: EXPORT_FORMAT_VERSION,
+            kdf: "argon2id".to_string(),
+            kdf_params: *params,
+            salt: salt.to_string(),
+            nonce: Self::generate_nonce(),
+            created_at: crate::time_utils::now_rfc3339(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            key_check: None,
+        };
+        let mac_key = Self::export_mac_key(key)?;
+        let header = ExportHeader { key_check: Some(Self::tag(&mac_key, KEY_CHECK_INPUT)), ..header };
+        let preamble = format!("{}\n{}\n", EXPORT_MAGIC, serde_json::to_string(&header)?);
+        let encrypted = Self::encrypt_data_with_aad(data, key, &header.nonce, preamble.as_bytes())?;
+        let body = format!("{}{}\n", preamble, encrypted);
+        let mac = Self::tag(&mac_key, body.as_bytes());
+        Ok(format!("{}{}\n", body, mac))
+    }
+
+    // MAC key for export files, derived from the same passphrase key as the encryption key
+    fn export_mac_key(key: &[u8; 32]) -> Result<HmacSha256> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).map_err(|e| anyhow!("Invalid export key: {}", e))?;
+        mac.update(b"pwdbox-export-mac-v1");
+        let mac_key = mac.finalize().into_bytes();
+        <HmacSha256 as Mac>::new_from_slice(&mac_key).map_err(|e| anyhow!("Invalid export MAC key: {}", e))
+    }
+
+    fn tag(mac_key: &HmacSha256, data: &[u8]) -> String {
+        let mut mac = mac_key.clone();
+        mac.update(data);
+        general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+    }
+
+    // Constant-time comparison with a base64 tag over `parts` run together
+    fn verify_tag(mac_key: &HmacSha256, parts: &[&[u8]], tag: &str) -> bool {
+        let Ok(tag) = general_purpose::STANDARD.decode(tag) else {
+            return false;
+        };
+        let mut mac = mac_key.clone();
+        for part in parts {
+            mac.update(part);
+        }
+        mac.verify_slice(&tag).is_ok()
+    }
+
+    // Decrypt export data of either format with a user-provided passphrase
     pub fn decrypt_export_data(encrypted_export: &str, passphrase: &str) -> Result<String> {
-        let decoded = general_purpose::STANDARD.decode(encrypted_export)?;
-        let export_str = String::from_utf8(decoded)?;
-        
-        let parts: Vec<&str> = export_str.splitn(3, ':').collect();
-        if parts.len() != 3 {
-            return Err(anyhow!("Invalid export data format"));
+        let envelope = Self::parse_export_data(encrypted_export)?;
+        let key = Self::derive_key_from_password(passphrase, &envelope.salt, &envelope.kdf_params)?;
+        envelope.open(&key)
+    }
+
+    // Take an export file apart without decrypting it, telling the formats apart by the
+    // magic line. Anything else, a binary included, is refused as NOT_AN_EXPORT.
+    pub fn parse_export_data(contents: impl Into<Vec<u8>>) -> Result<ExportEnvelope> {
+        let contents = contents.into();
+        let magic_len = EXPORT_MAGIC.len() + 1;
+        if !contents.starts_with(EXPORT_MAGIC.as_bytes()) || contents.get(EXPORT_MAGIC.len()) != Some(&b'\n') {
+            return Self::parse_export_data_v1(contents);
+        }
+
+        // Past the magic line a file that will not parse has been damaged
+        let invalid_header = || ExportOpenError::Corrupted("the header is unreadable".to_string());
+        let header_end = contents[magic_len..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map(|position| magic_len + position)
+            .ok_or_else(invalid_header)?;
+        let header_line = std::str::from_utf8(&contents[magic_len..header_end]).map_err(|_| invalid_header())?;
+        let header: ExportHeader = serde_json::from_str(header_line).map_err(|_| invalid_header())?;
+        if header.format_version != EXPORT_FORMAT_VERSION {
+            return Err(anyhow!("Export format version {} is not supported by this version of PwdBox", header.format_version));
+        }
+        if header.kdf != "argon2id" {
+            return Err(anyhow!("Unsupported export key derivation {}", header.kdf));
         }
+        header.kdf_params.validate()?;
+        // Files written before the integrity tag end after the payload
+        let lines: Vec<Range<usize>> = Self::line_ranges(&contents, header_end + 1).take(3).collect();
+        if lines.len() > 2 {
+            return Err(ExportOpenError::Corrupted("unexpected data after the integrity tag".to_string()).into());
+        }
+        let payload = lines.first().cloned().unwrap_or(contents.len()..contents.len());
+        let mac = lines.get(1).map(|mac| String::from_utf8_lossy(&contents[mac.clone()]).into_owned());
+        let aad = contents[..header_end + 1].to_vec();
+        Ok(ExportEnvelope {
+            format_version: header.format_version,
+            kdf_params: header.kdf_params,
+            salt: header.salt.clone(),
+            nonce: header.nonce.clone(),
+            contents,
+            payload,
+            aad,
+            mac,
+            header: Some(header),
+        })
+    }
 
-        let salt = parts[0];
-        let nonce = parts[1];
-        let encrypted_data = parts[2];
+    // v1 files are base64 of params:salt:nonce:payload, the payload base64 in turn. Files
+    // written before the parameters were recorded have none and used the defaults.
+    fn parse_export_data_v1(contents: Vec<u8>) -> Result<ExportEnvelope> {
+        let not_an_export = || PwdBoxError::validation(NOT_AN_EXPORT);
+        let start = contents.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(contents.len());
+        let end = contents.iter().rposition(|byte| !byte.is_ascii_whitespace()).map_or(start, |last| last + 1);
+        let decoded = Self::decode_base64_in_place(contents, start..end).map_err(|_| not_an_export())?;
 
-        let key = Self::derive_key_from_password(passphrase, salt)?;
-        Self::decrypt_data(encrypted_data, &key, nonce)
+        let export_str = std::str::from_utf8(&decoded).map_err(|_| not_an_export())?;
+        let parts: Vec<&str> = export_str.splitn(4, ':').collect();
+        let (kdf_params, salt, nonce, payload) = match parts[..] {
+            [header, salt, nonce, payload] => (KdfParams::from_header(header).map_err(|_| not_an_export())?, salt, nonce, payload),
+            [salt, nonce, payload] => (KdfParams::default(), salt, nonce, payload),
+            _ => return Err(not_an_export().into()),
+        };
+        let (salt, nonce) = (salt.to_string(), nonce.to_string());
+        let payload = export_str.len() - payload.len()..export_str.len();
+        Ok(ExportEnvelope {
+            format_version: 1,
+            header: None,
+            kdf_params,
+            salt,
+            nonce,
+            contents: decoded,
+            payload,
+            aad: Vec::new(),
+            mac: None,
+        })
     }
 
-    // Securely clear sensitive data from memory
-    pub fn clear_sensitive_data(data: &mut [u8]) {
-        for byte in data.iter_mut() {
-            *byte = 0;
+    // The non-blank lines of `bytes` from `start` on, as ranges with the surrounding
+    // whitespace left out
+    fn line_ranges(bytes: &[u8], start: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut offset = start;
+        bytes[start..].split(|&byte| byte == b'\n').filter_map(move |line| {
+            let line_start = offset;
+            offset += line.len() + 1;
+            let leading = line.iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+            let trailing = line[leading..].iter().rev().take_while(|byte| byte.is_ascii_whitespace()).count();
+            (leading < line.len()).then(|| line_start + leading..line_start + line.len() - trailing)
+        })
+    }
+
+    // Decode the base64 at `range` of `buffer` to the front of it and drop the rest. The
+    // decoded bytes never reach past the base64 already read, so no second buffer the size
+    // of a large payload is needed.
+    fn decode_base64_in_place(mut buffer: Vec<u8>, range: Range<usize>) -> Result<Vec<u8>> {
+        let mut decoded = vec![0u8; BASE64_CHUNK / 4 * 3];
+        let mut written = 0;
+        for start in range.clone().step_by(BASE64_CHUNK) {
+            let end = (start + BASE64_CHUNK).min(range.end);
+            let count = general_purpose::STANDARD.decode_slice(&buffer[start..end], &mut decoded)?;
+            buffer[written..written + count].copy_from_slice(&decoded[..count]);
+            written += count;
+        }
+        buffer.truncate(written);
+        Ok(buffer)
+    }
+
+    // Decrypt `buffer` where it is; the plaintext replaces the ciphertext
+    fn decrypt_in_place(mut buffer: Vec<u8>, key: &[u8; 32], nonce_str: &str, aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce_bytes = general_purpose::STANDARD.decode(nonce_str)?;
+        if nonce_bytes.len() != NONCE_LENGTH {
+            return Err(anyhow!("Invalid nonce length"));
+        }
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        cipher
+            .decrypt_in_place(Nonce::from_slice(&nonce_bytes), aad, &mut buffer)
+            .map_err(|e| PwdBoxError::crypto(format!("Decryption failed: {}", e)))?;
+        Ok(buffer)
+    }
+
+    // Strength from 0 (trivial) to 4 (strong), by guessable_entropy_bits
+    pub fn password_strength(password: &str) -> u8 {
+        let bits = Self::guessable_entropy_bits(password);
+        STRENGTH_THRESHOLDS.iter().filter(|&&threshold| bits >= threshold).count() as u8
+    }
+
+    // The character-pool estimate of password_policy, counting only the characters that are
+    // not predictable: a common password or keyboard walk counts as one, and so does a run
+    // of repeated or consecutive characters (aaaa, abcd, 4321).
+    pub fn guessable_entropy_bits(password: &str) -> u32 {
+        let length = password.chars().count();
+        if length == 0 {
+            return 0;
         }
+        let bits_per_char = password_policy::estimate_entropy_bits(password) as f64 / length as f64;
+
+        // Cut the common patterns out, leaving the pieces between them. Passwords may hold
+        // any character, line breaks included, so no character can stand in for a cut.
+        let mut pieces = vec![password.to_lowercase()];
+        let mut pattern_count = 0;
+        for pattern in COMMON_PATTERNS {
+            let mut rest = Vec::with_capacity(pieces.len());
+            for piece in &pieces {
+                let parts: Vec<&str> = piece.split(pattern).collect();
+                pattern_count += parts.len() - 1;
+                rest.extend(parts.into_iter().filter(|part| !part.is_empty()).map(str::to_string));
+            }
+            pieces = rest;
+        }
+        let unpredictable: usize = pieces
+            .iter()
+            .map(|piece| {
+                let chars: Vec<char> = piece.chars().collect();
+                chars
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, c)| *index == 0 || (**c as i64 - chars[index - 1] as i64).abs() > 1)
+                    .count()
+            })
+            .sum();
+
+        (bits_per_char * (unpredictable + pattern_count) as f64) as u32
+    }
+
+    // Securely clear sensitive data from memory. Goes through zeroize, so the writes are
+    // not optimised away as dead stores.
+    pub fn clear_sensitive_data(data: &mut [u8]) {
+        data.zeroize();
+    }
+
+    // Clear a decrypted string before its buffer is freed, spare capacity included
+    pub fn clear_sensitive_string(mut value: String) {
+        value.zeroize();
     }
 }
 
@@ -162,22 +894,145 @@ mod tests {
         let password = "test_password_123";
         let salt = CryptoService::generate_salt();
         
-        let hash = CryptoService::hash_password(password, &salt).unwrap();
+        let hash = CryptoService::hash_password(password, &salt, &KdfParams::default()).unwrap();
         assert!(CryptoService::verify_password(password, &hash).unwrap());
         assert!(!CryptoService::verify_password("wrong_password", &hash).unwrap());
     }
 
+    #[test]
+    fn test_generated_passwords_use_every_character_kind() {
+        for symbols in [false, true] {
+            let password = CryptoService::generate_password(12, symbols).unwrap();
+            assert_eq!(password.len(), 12);
+            assert!(password.bytes().any(|byte| byte.is_ascii_lowercase()));
+            assert!(password.bytes().any(|byte| byte.is_ascii_uppercase()));
+            assert!(password.bytes().any(|byte| byte.is_ascii_digit()));
+            assert_eq!(password.bytes().any(|byte| SYMBOLS.contains(&byte)), symbols);
+        }
+        assert!(CryptoService::generate_password(7, true).is_err());
+        assert!(CryptoService::generate_password(129, true).is_err());
+    }
+
     #[test]
     fn test_encryption_decryption() {
         let data = "sensitive_password_data";
         let salt = CryptoService::generate_salt();
-        let key = CryptoService::derive_key_from_password("master_password", &salt).unwrap();
+        let key = CryptoService::derive_key_from_password("master_password", &salt, &KdfParams::default()).unwrap();
         let nonce = CryptoService::generate_nonce();
 
         let encrypted = CryptoService::encrypt_data(data, &key, &nonce).unwrap();
         let decrypted = CryptoService::decrypt_data(&encrypted, &key, &nonce).unwrap();
 
         assert_eq!(data, decrypted);
+
+    }
+
+    #[test]
+    fn test_string_ciphertexts_are_unchanged_by_the_byte_api() {
+        // Produced independently with AES-256-GCM, key 00..1f and nonce 00..0b
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce = general_purpose::STANDARD.encode(std::array::from_fn::<u8, 12, _>(|i| i as u8));
+        let stored = "NGe4aKyRq23oHufqwpoPAvGy2FCRDz5jq7R8oDoMhVQRWwRRq2Dt";
+        assert_eq!(CryptoService::encrypt_data("sensitive_password_data", &key, &nonce).unwrap(), stored);
+        assert_eq!(CryptoService::decrypt_data(stored, &key, &nonce).unwrap(), "sensitive_password_data");
+        assert_eq!(
+            general_purpose::STANDARD.encode(CryptoService::encrypt_bytes(b"sensitive_password_data", &key, &nonce).unwrap()),
+            stored
+        );
+        let bound = "NGe4aKyRq23oHufqwpoPAvGy2FCRDz7S0kZXOUv6hgULtlvts1KX";
+        assert_eq!(CryptoService::decrypt_password(bound, &nonce, &key, Some("uid")).unwrap(), "sensitive_password_data");
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_bytes_round_trip(data in proptest::collection::vec(proptest::num::u8::ANY, 0..2048), key in proptest::array::uniform32(proptest::num::u8::ANY)) {
+            let nonce = CryptoService::generate_nonce();
+            let encrypted = CryptoService::encrypt_bytes(&data, &key, &nonce).unwrap();
+            proptest::prop_assert_eq!(encrypted.len(), data.len() + 16);
+            proptest::prop_assert_eq!(CryptoService::decrypt_bytes(&encrypted, &key, &nonce).unwrap(), data.clone());
+            // Bytes that are not UTF-8 only fail the string variant, after authenticating
+            let encoded = general_purpose::STANDARD.encode(&encrypted);
+            proptest::prop_assert_eq!(CryptoService::decrypt_data(&encoded, &key, &nonce).is_ok(), std::str::from_utf8(&data).is_ok());
+        }
+
+        #[test]
+        fn prop_nul_and_invalid_utf8_survive(prefix in "[a-z\\x00]{0,64}", tail in proptest::collection::vec(0x80u8..=0xff, 1..64)) {
+            let key = CryptoService::generate_vault_key();
+            let nonce = CryptoService::generate_nonce();
+            let mut data = prefix.into_bytes();
+            data.push(0);
+            data.extend(tail);
+            let encrypted = CryptoService::encrypt_bytes_with_aad(&data, &key, &nonce, b"aad").unwrap();
+            proptest::prop_assert_eq!(CryptoService::decrypt_bytes_with_aad(&encrypted, &key, &nonce, b"aad").unwrap(), data);
+            proptest::prop_assert!(CryptoService::decrypt_bytes_with_aad(&encrypted, &key, &nonce, b"other").is_err());
+        }
+    }
+
+    #[test]
+    fn test_password_strength_scores() {
+        let score = CryptoService::password_strength;
+        assert_eq!(score(""), 0);
+        assert_eq!(score("password"), 0);
+        assert_eq!(score("Password1!"), 0);
+        assert_eq!(score("aaaaaaaaaaaaaaaa"), 0);
+        assert_eq!(score("12345678"), 0);
+        assert_eq!(score("qwerty123"), 0);
+        assert_eq!(score("blueberry"), 1);
+        assert_eq!(score("Tr0ub4dor&3"), 3);
+        assert_eq!(score("correct horse battery staple"), 4);
+        assert_eq!(score("x7#Qm9!vR2@kL5$w"), 4);
+        // A common password inside a longer one adds little
+        assert!(score("mypassword") < score("mypa55wqrd"));
+        // Line breaks count like any other character
+        assert_eq!(score("correct\nhorse\nbattery\nstaple"), 4);
+    }
+
+    #[test]
+    fn test_key_wrapping() {
+        let vault_key = CryptoService::generate_vault_key();
+        let salt = CryptoService::generate_salt();
+        let kek = CryptoService::derive_key_from_password("master_password", &salt, &KdfParams::default()).unwrap();
+        let other_kek = CryptoService::derive_key_from_password("other_password", &salt, &KdfParams::default()).unwrap();
+
+        let (wrapped, nonce) = CryptoService::wrap_key(&vault_key, &kek).unwrap();
+        assert_eq!(CryptoService::unwrap_key(&wrapped, &nonce, &kek).unwrap(), vault_key);
+        assert!(CryptoService::unwrap_key(&wrapped, &nonce, &other_kek).is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes_verify_however_they_are_typed() {
+        let code = CryptoService::generate_recovery_code();
+        assert_eq!(code.len(), 39);
+        assert_eq!(code.split('-').count(), 8);
+        assert_ne!(*code, *CryptoService::generate_recovery_code());
+
+        let salt = CryptoService::generate_salt();
+        let verifier = CryptoService::recovery_code_verifier(&code, &salt).unwrap();
+        let retyped = code.replace('-', " ").to_uppercase();
+        assert!(CryptoService::verify_recovery_code(&retyped, &salt, &verifier));
+        assert_eq!(CryptoService::recovery_code_kek(&retyped, &salt).unwrap(), CryptoService::recovery_code_kek(&code, &salt).unwrap());
+        assert!(!CryptoService::verify_recovery_code(&CryptoService::generate_recovery_code(), &salt, &verifier));
+        assert!(!CryptoService::verify_recovery_code(&code, &CryptoService::generate_salt(), &verifier));
+        assert!(!CryptoService::verify_recovery_code("not-a-code", &salt, &verifier));
+
+        // The stored verifier says nothing about the key
+        let kek = CryptoService::recovery_code_kek(&code, &salt).unwrap();
+        assert_ne!(general_purpose::STANDARD.encode(*kek), verifier);
+    }
+
+    #[test]
+    fn test_keys_and_buffers_are_zeroed() {
+        let mut buffer = *b"sensitive";
+        CryptoService::clear_sensitive_data(&mut buffer);
+        assert_eq!(buffer, [0u8; 9]);
+
+        // Run a derived key's destructor in place. A [u8; 32] stays readable afterwards,
+        // which shows what the drop leaves behind in memory.
+        let salt = CryptoService::generate_salt();
+        let mut key = std::mem::ManuallyDrop::new(CryptoService::derive_key_from_password("master_password", &salt, &KdfParams::default()).unwrap());
+        assert_ne!(**key, [0u8; 32]);
+        unsafe { std::mem::ManuallyDrop::drop(&mut key) };
+        assert_eq!(**key, [0u8; 32]);
     }
 
     #[test]
@@ -185,9 +1040,149 @@ mod tests {
         let data = r#"{"test": "data"}"#;
         let passphrase = "export_passphrase";
 
-        let encrypted = CryptoService::encrypt_export_data(data, passphrase).unwrap();
+        let encrypted = CryptoService::encrypt_export_data(data, passphrase, &KdfParams::default()).unwrap();
         let decrypted = CryptoService::decrypt_export_data(&encrypted, passphrase).unwrap();
 
         assert_eq!(data, decrypted);
     }
+
+    #[test]
+    fn test_kdf_benchmark_stays_within_bounds() {
+        assert!(CryptoService::benchmark_kdf(10).is_err());
+        assert!(CryptoService::benchmark_kdf(60_000).is_err());
+
+        let benchmark = CryptoService::benchmark_kdf(100).unwrap();
+        assert!(!benchmark.timings.is_empty());
+        assert!(benchmark.timings.iter().all(|timing| timing.params.validate().is_ok()));
+        assert!(benchmark.recommended.m_cost >= KdfParams::default().m_cost);
+        assert!(benchmark.recommended.t_cost >= KdfParams::default().t_cost);
+        // The recommendation is a probe that made the target, unless none did
+        assert!(benchmark.recommended == KdfParams::default() || benchmark.timings.iter()
+            .any(|timing| timing.params == benchmark.recommended && timing.elapsed_ms <= benchmark.target_ms));
+    }
+
+    #[test]
+    fn test_export_records_kdf_params() {
+        let data = r#"{"test": "data"}"#;
+        let passphrase = "export_passphrase";
+        let params = KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+
+        let encrypted = CryptoService::encrypt_export_data(data, passphrase, &params).unwrap();
+        assert_eq!(CryptoService::parse_export_data(encrypted.as_str()).unwrap().kdf_params, params);
+        assert_eq!(CryptoService::decrypt_export_data(&encrypted, passphrase).unwrap(), data);
+
+        // Files from before the header was written used the defaults
+        let salt = CryptoService::generate_salt();
+        let key = CryptoService::derive_key_from_password(passphrase, &salt, &KdfParams::default()).unwrap();
+        let nonce = CryptoService::generate_nonce();
+        let payload = CryptoService::encrypt_data(data, &key, &nonce).unwrap();
+        let legacy = general_purpose::STANDARD.encode(format!("{}:{}:{}", salt, nonce, payload));
+        assert_eq!(CryptoService::decrypt_export_data(&legacy, passphrase).unwrap(), data);
+
+        // A header asking for more than the bounds is refused before any derivation
+        let greedy = general_purpose::STANDARD.encode(format!("m=4194304,t=1,p=1:{}:{}:{}", salt, nonce, payload));
+        assert!(CryptoService::parse_export_data(greedy).is_err());
+    }
+
+    #[test]
+    fn test_export_v2_round_trip_and_v1_detection() {
+        let data = r#"{"test": "data"}"#;
+        let passphrase = "export_passphrase";
+        let params = KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+
+        let v2 = CryptoService::encrypt_export_data_v2(data, passphrase, &params).unwrap();
+        assert!(v2.starts_with("PWDBOX\n"));
+        let envelope = CryptoService::parse_export_data(v2.as_str()).unwrap();
+        let header = envelope.header.as_ref().unwrap();
+        assert_eq!((envelope.format_version, header.kdf_params, header.kdf.as_str()), (2, params, "argon2id"));
+        assert!(chrono::DateTime::parse_from_rfc3339(&header.created_at).is_ok());
+        assert_eq!(CryptoService::decrypt_export_data(&v2, passphrase).unwrap(), data);
+        assert!(CryptoService::decrypt_export_data(&v2, "wrong").is_err());
+
+        // The header is authenticated with the payload
+        let tampered = v2.replace(&header.created_at, "2000-01-01T00:00:00Z");
+        assert!(CryptoService::parse_export_data(tampered.as_str()).is_ok());
+        assert!(CryptoService::decrypt_export_data(&tampered, passphrase).is_err());
+        let newer = v2.replace("\"format_version\":2", "\"format_version\":3");
+        assert!(matches!(CryptoService::parse_export_data(newer.as_str()), Err(e) if e.to_string().contains("version 3")));
+
+        let v1 = CryptoService::encrypt_export_data(data, passphrase, &params).unwrap();
+        let envelope = CryptoService::parse_export_data(v1.as_str()).unwrap();
+        assert_eq!((envelope.format_version, envelope.header.is_none(), envelope.kdf_params), (1, true, params));
+        assert_eq!(CryptoService::decrypt_export_data(&v1, passphrase).unwrap(), data);
+
+        assert!(CryptoService::parse_export_data("name,url,username,password\n").is_err());
+        let binary = CryptoService::parse_export_data(vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff]).err().unwrap();
+        assert_eq!(binary.to_string(), NOT_AN_EXPORT);
+    }
+
+    #[test]
+    fn test_large_exports_open_in_place() {
+        let passphrase = "export_passphrase";
+        let params = KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+        // Several base64 chunks, the last of them partial
+        let data = format!("{{\"padding\": \"{}\"}}", "x".repeat(BASE64_CHUNK * 3 + 7));
+
+        let v2 = CryptoService::encrypt_export_data_v2(&data, passphrase, &params).unwrap();
+        assert_eq!(CryptoService::decrypt_export_data(&v2, passphrase).unwrap(), data);
+        let v1 = CryptoService::encrypt_export_data(&data, passphrase, &params).unwrap();
+        assert_eq!(CryptoService::decrypt_export_data(&v1, passphrase).unwrap(), data);
+
+        // A payload cut short inside a chunk, re-signed so the tag is not what catches it, fails
+        // in the in-place decode or decrypt and is reported as damage, not a wrong passphrase
+        let envelope = CryptoService::parse_export_data(v2.as_str()).unwrap();
+        let key = CryptoService::derive_key_from_password(passphrase, &envelope.salt, &envelope.kdf_params).unwrap();
+        let mac_key = CryptoService::export_mac_key(&key).unwrap();
+        let lines: Vec<&str> = v2.lines().collect();
+        let open_cut = |cut_by: usize, resign: bool| {
+            let payload = &lines[2][..lines[2].len() - cut_by];
+            let body = format!("{}\n{}\n{}\n", lines[0], lines[1], payload);
+            let mac = if resign { CryptoService::tag(&mac_key, body.as_bytes()) } else { lines[3].to_string() };
+            let error = CryptoService::decrypt_export_data(&format!("{}{}\n", body, mac), passphrase).err().unwrap();
+            error.downcast_ref::<ExportOpenError>().unwrap().to_string()
+        };
+        let does_not_decrypt = ExportOpenError::Corrupted("the payload does not decrypt".to_string()).to_string();
+        // Still base64, so the decrypt fails
+        assert_eq!(open_cut(BASE64_CHUNK / 2, true), does_not_decrypt);
+        // No longer base64, so the decode fails
+        assert_eq!(open_cut(3, true), does_not_decrypt);
+        assert_eq!(
+            open_cut(BASE64_CHUNK / 2, false),
+            ExportOpenError::Corrupted("the integrity tag does not match".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_export_integrity_tag_tells_damage_from_wrong_passphrase() {
+        let data = r#"{"test": "data"}"#;
+        let passphrase = "export_passphrase";
+        let params = KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+        let open_error = |contents: &str, passphrase: &str| {
+            let error = CryptoService::decrypt_export_data(contents, passphrase).err().unwrap();
+            match error.downcast_ref::<ExportOpenError>() {
+                Some(ExportOpenError::WrongPassphrase) => "wrong passphrase",
+                Some(ExportOpenError::Corrupted(_)) => "corrupted",
+                None => "other",
+            }
+        };
+
+        let v2 = CryptoService::encrypt_export_data_v2(data, passphrase, &params).unwrap();
+        assert_eq!(v2.lines().count(), 4);
+        assert_eq!(open_error(&v2, "wrong"), "wrong passphrase");
+
+        // A flipped payload character, a cut-off file and a missing MAC line are all damage,
+        // whichever passphrase is given
+        let lines: Vec<&str> = v2.lines().collect();
+        let flipped_char = if lines[2].starts_with('A') { "B" } else { "A" };
+        let flipped = format!("{}\n{}\n{}{}\n{}\n", lines[0], lines[1], flipped_char, &lines[2][1..], lines[3]);
+        assert_eq!(open_error(&flipped, passphrase), "corrupted");
+        assert_eq!(open_error(&v2[..v2.len() / 2], passphrase), "corrupted");
+        let without_mac = format!("{}\n{}\n{}\n", lines[0], lines[1], lines[2]);
+        assert_eq!(open_error(&without_mac, passphrase), "corrupted");
+        assert_eq!(open_error(&format!("{}\n{{", lines[0]), passphrase), "corrupted");
+
+        // Files without the tag still open, but cannot tell the two apart
+        let v1 = CryptoService::encrypt_export_data(data, passphrase, &params).unwrap();
+        assert_eq!(open_error(&v1, "wrong"), "wrong passphrase");
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,209 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Where the vault lives: `data` next to the executable in portable mode, else
+// PWDBOX_DATA_DIR when set, else the directory named in the pointer file, else the
+// platform data directory. The pointer file always stays in the default directory, so it
+// can be found before the vault is.
+pub const ENV_VAR: &str = "PWDBOX_DATA_DIR";
+const POINTER_FILE: &str = "data_dir";
+
+// Portable mode, for running from a USB stick: a portable.flag file next to the
+// executable, or --portable on the command line
+const PORTABLE_FLAG: &str = "portable.flag";
+const PORTABLE_ARG: &str = "--portable";
+const PORTABLE_DATA_DIR: &str = "data";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataDirSource {
+    Default,
+    Pointer,
+    Environment, // set_data_dir is refused while the variable decides
+    Portable,    // Likewise; backups default into the data directory as well
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirInfo {
+    pub path: String,
+    pub source: DataDirSource,
+    pub default_path: String,
+}
+
+pub fn default_dir() -> Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .ok_or_else(|| anyhow!("Could not determine app data directory"))?
+        .join("PwdBox"))
+}
+
+// The executable's data directory, in portable mode
+fn portable_dir() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    let portable = exe_dir.join(PORTABLE_FLAG).exists() || std::env::args().skip(1).any(|arg| arg == PORTABLE_ARG);
+    portable.then(|| exe_dir.join(PORTABLE_DATA_DIR))
+}
+
+pub fn resolve() -> Result<(PathBuf, DataDirSource)> {
+    resolve_in(&default_dir()?, portable_dir(), std::env::var_os(ENV_VAR))
+}
+
+fn resolve_in(default_dir: &Path, portable_dir: Option<PathBuf>, env_dir: Option<OsString>) -> Result<(PathBuf, DataDirSource)> {
+    if let Some(dir) = portable_dir {
+        return Ok((dir, DataDirSource::Portable));
+    }
+    if let Some(dir) = env_dir.filter(|dir| !dir.is_empty()) {
+        return Ok((PathBuf::from(dir), DataDirSource::Environment));
+    }
+    match fs::read_to_string(default_dir.join(POINTER_FILE)) {
+        Ok(contents) if !contents.trim().is_empty() => Ok((PathBuf::from(contents.trim()), DataDirSource::Pointer)),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok((default_dir.to_path_buf(), DataDirSource::Default)),
+    }
+}
+
+// `dir` is the directory in use, which the pointer or the variable may no longer name
+pub fn describe(dir: &Path) -> Result<DataDirInfo> {
+    let default_dir = default_dir()?;
+    let source = if portable_dir().is_some() {
+        DataDirSource::Portable
+    } else if std::env::var_os(ENV_VAR).is_some_and(|dir| !dir.is_empty()) {
+        DataDirSource::Environment
+    } else if dir == default_dir {
+        DataDirSource::Default
+    } else {
+        DataDirSource::Pointer
+    };
+    Ok(DataDirInfo {
+        path: dir.to_string_lossy().to_string(),
+        source,
+        default_path: default_dir.to_string_lossy().to_string(),
+    })
+}
+
+// Create `dir` and make sure files can be written there, so that read-only media (a
+// locked USB stick, a CD) fails with an explanation rather than an SQLite error
+pub fn ensure_writable(dir: &Path, source: DataDirSource) -> Result<()> {
+    let probe = dir.join(".pwdbox-write-test");
+    let writable = fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b"")).and_then(|()| fs::remove_file(&probe));
+    writable.map_err(|e| {
+        let hint = match source {
+            DataDirSource::Portable => "; portable mode needs writable media, or remove portable.flag to use the usual data directory",
+            _ => "",
+        };
+        std::io::Error::new(e.kind(), format!("Cannot write to the data directory {}: {}{}", dir.display(), e, hint)).into()
+    })
+}
+
+// Where backups go when no path is given: beside the vault in portable mode, so they
+// travel with it, else PwdBox_Backups in the home directory
+pub fn default_backup_dir(dir: &Path) -> PathBuf {
+    if portable_dir().is_some() {
+        return dir.join("backups");
+    }
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")).join("PwdBox_Backups")
+}
+
+// Open `dir` from the next start on. The default directory removes the pointer; anything
+// else replaces it atomically.
+pub fn set_pointer(default_dir: &Path, dir: &Path) -> Result<()> {
+    let pointer = default_dir.join(POINTER_FILE);
+    if dir == default_dir {
+        return match fs::remove_file(&pointer) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    let dir = dir.to_str().ok_or_else(|| anyhow!("The data directory path is not valid UTF-8"))?;
+    fs::create_dir_all(default_dir)?;
+    let temp_path = default_dir.join(format!(".{}.tmp", POINTER_FILE));
+    fs::write(&temp_path, dir)?;
+    fs::rename(&temp_path, &pointer)?;
+    Ok(())
+}
+
+// Returned when write_atomically fails. Whatever was at the destination before is left
+// untouched; the temporary file is removed when possible.
+#[derive(Debug)]
+pub struct AtomicWriteError {
+    pub temp_path: PathBuf,
+    pub source: std::io::Error,
+}
+
+impl std::fmt::Display for AtomicWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not write {}: {}", self.temp_path.display(), self.source)
+    }
+}
+
+impl std::error::Error for AtomicWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Write to a temporary file next to `path`, fsync it, then rename it over `path`, so a
+// crash, full disk or removed drive never leaves a truncated file in place of a good one.
+// On Unix the file is readable by its owner only.
+pub fn write_atomically(path: &Path, write: impl FnOnce(&mut fs::File) -> std::io::Result<()>) -> Result<(), AtomicWriteError> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("file");
+    let temp_path = parent.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = (|| -> std::io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp_path)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp_path, path)?;
+        // Persist the rename itself; not every platform can open a directory for this
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    })();
+
+    result.map_err(|source| {
+        let _ = fs::remove_file(&temp_path);
+        AtomicWriteError { temp_path, source }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portable_then_environment_then_pointer_then_default() {
+        let default = tempfile::tempdir().unwrap();
+        let moved = tempfile::tempdir().unwrap();
+        assert_eq!(resolve_in(default.path(), None, None).unwrap(), (default.path().to_path_buf(), DataDirSource::Default));
+
+        set_pointer(default.path(), moved.path()).unwrap();
+        assert_eq!(resolve_in(default.path(), None, None).unwrap(), (moved.path().to_path_buf(), DataDirSource::Pointer));
+        // An empty variable counts as unset
+        assert_eq!(resolve_in(default.path(), None, Some(OsString::new())).unwrap().1, DataDirSource::Pointer);
+        let env_dir = Some(OsString::from("/srv/pwdbox"));
+        assert_eq!(
+            resolve_in(default.path(), None, env_dir.clone()).unwrap(),
+            (PathBuf::from("/srv/pwdbox"), DataDirSource::Environment)
+        );
+        // Portable mode wins over both
+        let portable = PathBuf::from("/media/usb/PwdBox/data");
+        assert_eq!(
+            resolve_in(default.path(), Some(portable.clone()), env_dir).unwrap(),
+            (portable, DataDirSource::Portable)
+        );
+
+        // Moving back to the default directory drops the pointer
+        set_pointer(default.path(), default.path()).unwrap();
+        assert!(!default.path().join(POINTER_FILE).exists());
+        assert_eq!(resolve_in(default.path(), None, None).unwrap().1, DataDirSource::Default);
+    }
+}
@@ -1,55 +1,757 @@
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction, TransactionBehavior, named_params, params};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use zeroize::Zeroizing;
+use crate::crypto::{KdfParams, SecretKey, NONCE_LENGTH};
+use crate::domains;
+use crate::time_utils;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserMeta {
     pub id: Option<i64>,
     pub master_hash: String,
     pub master_salt: String,
+    #[serde(default)]
+    pub security_questions: Vec<SecurityQuestionRecord>, // In the order they are asked, which the answers are joined in
+    // Exports from before security_questions carry exactly three questions in flat fields.
+    // Only ever read; see UserMeta::questions.
+    #[serde(flatten, skip_serializing)]
+    pub legacy_questions: LegacyQuestions,
+    pub wrapped_vault_key: Option<String>,
+    pub vault_key_nonce: Option<String>,
+    pub recovery_wrapped_vault_key: Option<String>,
+    pub recovery_vault_key_nonce: Option<String>,
+    pub recovery_key_salt: Option<String>,
+    #[serde(default)]
+    pub generation: i64, // Bumped whenever existing sessions must stop working; see session.rs
+    #[serde(default)]
+    pub recovery_verified_at: Option<String>, // Last passed recovery drill; cleared when the recovery material changes
+    #[serde(default)]
+    pub kdf_params: KdfParams, // What the master hash and key were made with
+    #[serde(default)]
+    pub recovery_kdf_params: KdfParams, // What the recovery key was derived with; only changes with the answers
+    #[serde(default)]
+    pub canary_ciphertext: Option<String>, // A known plaintext under the vault key, so a key can be checked on an empty vault
+    #[serde(default)]
+    pub canary_nonce: Option<String>,
+    #[serde(default)]
+    pub recovery_code_salt: Option<String>, // The recovery code fields are set together while a code exists
+    #[serde(default)]
+    pub recovery_code_verifier: Option<String>, // HMAC of the salt under the code; the code itself is never stored
+    #[serde(default)]
+    pub recovery_code_wrapped_vault_key: Option<String>,
+    #[serde(default)]
+    pub recovery_code_vault_key_nonce: Option<String>,
+    #[serde(default)]
+    pub answers_normalized: bool, // Answers were hashed through normalize_answer; older vaults compare them exactly
+    #[serde(default)]
+    pub password_hint: Option<String>, // Shown at the login screen only after a failed attempt; never part of the password
+    #[serde(default)]
+    pub duress_hash: Option<String>, // Argon2 hash of the password that opens the decoy vault; see VaultCoordinator::setup_duress
+    #[serde(default)]
+    pub duress_salt: Option<String>,
+}
+
+// A security question with the Argon2 hash of its answer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SecurityQuestionRecord {
+    pub question: String,
+    pub answer_hash: String,
+    pub salt: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LegacyQuestions {
+    #[serde(default)]
     pub question1: Option<String>,
+    #[serde(default)]
     pub answer1_hash: Option<String>,
+    #[serde(default)]
     pub answer_salt1: Option<String>,
+    #[serde(default)]
     pub question2: Option<String>,
+    #[serde(default)]
     pub answer2_hash: Option<String>,
+    #[serde(default)]
     pub answer_salt2: Option<String>,
+    #[serde(default)]
     pub question3: Option<String>,
+    #[serde(default)]
     pub answer3_hash: Option<String>,
+    #[serde(default)]
     pub answer_salt3: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl UserMeta {
+    // The security questions in order, taken from the legacy fields when this came from an
+    // older export
+    pub fn questions(&self) -> Vec<SecurityQuestionRecord> {
+        if !self.security_questions.is_empty() {
+            return self.security_questions.clone();
+        }
+        let legacy = &self.legacy_questions;
+        [
+            (&legacy.question1, &legacy.answer1_hash, &legacy.answer_salt1),
+            (&legacy.question2, &legacy.answer2_hash, &legacy.answer_salt2),
+            (&legacy.question3, &legacy.answer3_hash, &legacy.answer_salt3),
+        ]
+        .into_iter()
+        .filter_map(|fields| match fields {
+            (Some(question), Some(answer_hash), Some(salt)) => Some(SecurityQuestionRecord {
+                question: question.clone(),
+                answer_hash: answer_hash.clone(),
+                salt: salt.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct PasswordEntry {
     pub id: Option<i64>,
     pub software: String,
     pub account: String,
     pub encrypted_password: String,
     pub nonce: String,
-    pub notes: Option<String>,
+    pub notes: Option<String>, // Encrypted when notes_nonce is set
+    #[serde(default)]
+    pub notes_nonce: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<String>, // UTC RFC3339, kept in plaintext so reminders can run without the key
+    #[serde(default)]
+    pub last_used_at: Option<String>, // Last time the password was revealed
+    #[serde(default)]
+    pub password_changed_at: Option<String>, // Last time the password itself (not just metadata) changed
+    #[serde(default)]
+    pub breach_acknowledged_at: Option<String>, // When a breach check flagged this password; cleared once rotated or dismissed
+    #[serde(default)]
+    pub strength_score: Option<u8>, // CryptoService::password_strength, in plaintext so lists can show and filter on it
+    #[serde(default)]
+    pub rotation_days: Option<u32>, // A password change moves expires_at this far on; see set_rotation_days
+    #[serde(default)]
+    pub entry_type: EntryType,
+    #[serde(default)]
+    pub secret_kind: SecretKind,
+    #[serde(default)]
+    pub created_at: Option<String>, // Entries created before timestamps were tracked have none
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    #[serde(default)]
+    pub is_favorite: bool,
+    #[serde(default)]
+    pub deleted_at: Option<String>, // Set while the entry sits in the trash
+    #[serde(default)]
+    pub url: Option<String>, // As entered; the normalized host is stored alongside for matching
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<EntryIcon>, // Stored in entry_icons; only loaded for exports that include icons
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>, // Stored in entry_tags; only loaded for exports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>, // Stored in attachments; only loaded for exports that include them and re-encryption
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_fields: Vec<CustomField>, // Stored in custom_fields; only loaded for exports and re-encryption
+    #[serde(default)]
+    pub entry_uid: Option<String>, // What the ciphertexts are bound to; None on entries encrypted before that
+    // With encrypt_metadata on, software and account are stored here and their plaintext
+    // columns are left empty; see PasswordService::open_metadata
+    #[serde(default)]
+    pub software_enc: Option<String>,
+    #[serde(default)]
+    pub software_nonce: Option<String>,
+    #[serde(default)]
+    pub account_enc: Option<String>,
+    #[serde(default)]
+    pub account_nonce: Option<String>,
+}
+
+// An entry whose ciphertexts could never be decrypted; see Database::find_corrupt_entries
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CorruptEntry {
+    pub id: i64,
+    pub defect: String, // e.g. "nonce is 8 bytes, not 12"
+}
+
+// Autosaved editor state, encrypted under the vault key. Never exported.
+#[derive(Debug, Clone)]
+pub struct EntryDraft {
+    pub slot: i64, // Entry id, or NEW_ENTRY_DRAFT_SLOT for an entry not saved yet
+    pub encrypted_payload: String,
+    pub nonce: String,
+    pub saved_at: String,
+}
+
+// Entry ids start at 1, so slot 0 never collides with an entry
+pub const NEW_ENTRY_DRAFT_SLOT: i64 = 0;
+
+// A row of the audit log. The detail is plaintext until the row is sealed into the
+// chain (detail_nonce and mac are set together when that happens).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub occurred_at: String,
+    pub action: String,
+    pub entry_id: Option<i64>,
+    pub detail: Option<String>,
+    pub detail_nonce: Option<String>,
+    pub mac: Option<String>,
+    #[serde(default)]
+    pub segment_id: Option<i64>, // Set on rows of an imported segment; NULL for the local chain
+    #[serde(default)]
+    pub source_id: Option<i64>, // The row's id in the log it was imported from
+}
+
+// Settings key holding the tagged MAC of the last sealed audit row
+pub const AUDIT_LOG_HEAD_KEY: &str = "audit_log_head";
+
+// An audit log carried over from another vault by an import. Its rows are chained on
+// their own, under a key bound to `tag`, so the local chain is left as it was.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditSegment {
+    pub id: i64,
+    pub tag: String,
+    pub imported_at: String,
+    pub head: String, // Tagged MAC of the segment's last row, like AUDIT_LOG_HEAD_KEY
+}
+
+// Sealed audit rows as carried by an export: the local chain with its head, followed
+// by the rows of any segments imported earlier
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditLogExport {
+    pub head: Option<String>,
+    pub events: Vec<AuditEvent>,
+    #[serde(default)]
+    pub segments: Vec<AuditSegment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub entry_count: usize,
+}
+
+// Live entries sharing a software name, ignoring ASCII case
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SoftwareGroup {
+    pub software: String, // As spelled by the most recently updated entry
+    pub entry_count: usize,
+    pub last_updated_at: Option<String>,
+}
+
+// Whole-vault counts for the statistics view, from one aggregate query
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VaultCounts {
+    pub entry_count: usize,
+    pub favorite_count: usize,
+    pub trash_count: usize,
+    pub oldest_created_at: Option<String>, // Over live entries
+    pub newest_created_at: Option<String>,
+}
+
+// What the SQL-paged entry list is narrowed to; None and false leave it unfiltered
+#[derive(Debug, Default)]
+pub struct EntryFilter<'a> {
+    pub tag: Option<&'a str>,
+    pub software: Option<&'a str>, // Whole name, ignoring ASCII case like the software groups
+    pub favorites_only: bool,
+    pub max_strength: Option<u8>, // Entries scoring at most this
+}
+
+// Columns the entry list can be sorted by. Deserializing anything else fails, so
+// caller input never reaches the ORDER BY clause.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntrySortColumn {
+    Software,
+    Account,
+    CreatedAt,
+    UpdatedAt,
+    ExpiresAt, // Soonest first, so expired entries lead; entries that never expire come last either way
+}
+
+impl EntrySortColumn {
+    fn order_expression(self) -> &'static str {
+        match self {
+            EntrySortColumn::Software => "software COLLATE NOCASE",
+            EntrySortColumn::Account => "account COLLATE NOCASE",
+            EntrySortColumn::CreatedAt => "created_at",
+            EntrySortColumn::UpdatedAt => "updated_at",
+            EntrySortColumn::ExpiresAt => "expires_at IS NULL, expires_at",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn keyword(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct EntryIcon {
+    pub mime_type: String,
+    pub data: String, // Base64 encoded image
+    pub content_hash: String, // SHA-256 of the image bytes, lets the frontend cache icons
+}
+
+// Login history shown after unlocking. Only master password logins count; recovery attempts
+// and logins refused by the throttle leave it alone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoginStats {
+    pub last_login_at: Option<String>,
+    pub last_failed_at: Option<String>,
+    pub failed_since_last_login: u32, // Reset by a successful login only
+}
+
+// A favicon cached for a host, or a record that the host had none when last tried
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostIcon {
+    pub icon: Option<EntryIcon>,
+    pub fetched_at: String,
+}
+
+// A file kept with an entry, encrypted under the vault key and bound to the entry's uid
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Attachment {
+    #[serde(default, skip_serializing)]
+    pub id: Option<i64>, // Kept when the entry is rewritten in place; imports get new ids
+    pub filename: String,
+    #[serde(with = "base64_bytes")]
+    pub encrypted_blob: Vec<u8>,
+    pub nonce: String,
+    pub size: u64, // Of the file itself, before encryption
+    pub added_at: String,
+}
+
+// What lists show of an attachment, without its contents
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AttachmentInfo {
+    pub id: i64,
+    pub entry_id: i64,
+    pub filename: String,
+    pub size: u64,
+    pub added_at: String,
+}
+
+// An extra labelled value kept with an entry, such as a card's CVV or an identity's phone
+// number. The value is encrypted under the vault key and bound to the entry's uid.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct CustomField {
+    #[serde(default, skip_serializing)]
+    pub id: Option<i64>, // Kept when the entry is rewritten in place; imports get new ids
+    pub label: String,
+    pub encrypted_value: String,
+    pub nonce: String,
+    #[serde(default)]
+    pub field_kind: CustomFieldKind,
+    #[serde(default)]
+    pub position: u32, // Fields are shown in ascending position, then in the order they were added
+}
+
+// What a custom field holds, so the UI can format and mask it. The card and identity kinds
+// make up the templates of those entry types; see PasswordService::entry_template.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomFieldKind {
+    #[default]
+    Text,
+    Hidden, // Shown masked until revealed, like a password
+    CardNumber,
+    CardExpiry,
+    Cvv,
+    Cardholder,
+    Name,
+    Address,
+    Phone,
+    Totp, // Base32 TOTP secret or otpauth:// URI; get_entry_qr turns it into a QR code
+}
+
+impl CustomFieldKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            CustomFieldKind::Text => "text",
+            CustomFieldKind::Hidden => "hidden",
+            CustomFieldKind::CardNumber => "card_number",
+            CustomFieldKind::CardExpiry => "card_expiry",
+            CustomFieldKind::Cvv => "cvv",
+            CustomFieldKind::Cardholder => "cardholder",
+            CustomFieldKind::Name => "name",
+            CustomFieldKind::Address => "address",
+            CustomFieldKind::Phone => "phone",
+            CustomFieldKind::Totp => "totp",
+        }
+    }
+
+    // Values this build doesn't know are read as text
+    fn from_db(value: &str) -> Self {
+        match value {
+            "hidden" => CustomFieldKind::Hidden,
+            "card_number" => CustomFieldKind::CardNumber,
+            "card_expiry" => CustomFieldKind::CardExpiry,
+            "cvv" => CustomFieldKind::Cvv,
+            "cardholder" => CustomFieldKind::Cardholder,
+            "name" => CustomFieldKind::Name,
+            "address" => CustomFieldKind::Address,
+            "phone" => CustomFieldKind::Phone,
+            "totp" => CustomFieldKind::Totp,
+            _ => CustomFieldKind::Text,
+        }
+    }
+}
+
+// Attachment contents go into export files as base64 rather than a JSON array of numbers
+mod base64_bytes {
+    use base64::{Engine as _, engine::general_purpose};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        general_purpose::STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatsSnapshot {
+    pub recorded_at: String,
+    pub entry_count: i64,
+    pub weak_count: i64,
+    pub reused_count: i64,
+    pub breached_count: Option<i64>,
+    pub average_password_age_days: Option<f64>,
+}
+
+// What an entry holds, so the UI can pick its fields and icon. Every type is stored,
+// searched and exported the same way; they differ in which fields a save requires, see
+// PasswordService::validate_entry_fields.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+    #[default]
+    Login,      // Account and password
+    SecureNote, // Wi-Fi keys, alarm codes, recovery phrases; the body is in the encrypted notes
+    Card,       // Number, expiry, CVV and cardholder are custom fields
+    Identity,   // The name goes in the account, the details in custom fields
+}
+
+impl EntryType {
+    fn as_str(self) -> &'static str {
+        match self {
+            EntryType::Login => "login",
+            EntryType::SecureNote => "secure_note",
+            EntryType::Card => "card",
+            EntryType::Identity => "identity",
+        }
+    }
+
+    // Values this build doesn't know are read as logins
+    fn from_db(value: &str) -> Self {
+        match value {
+            "secure_note" => EntryType::SecureNote,
+            "card" => EntryType::Card,
+            "identity" => EntryType::Identity,
+            _ => EntryType::Login,
+        }
+    }
+}
+
+// What the password field holds, as a hint for showing it: keys and tokens are usually
+// long, often span several lines, and read better in a monospace font
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretKind {
+    #[default]
+    Password,
+    SshKey,   // A private key, PEM or OpenSSH format
+    ApiToken, // Also JWTs and other bearer tokens
+}
+
+impl SecretKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SecretKind::Password => "password",
+            SecretKind::SshKey => "ssh_key",
+            SecretKind::ApiToken => "api_token",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "ssh_key" => SecretKind::SshKey,
+            "api_token" => SecretKind::ApiToken,
+            _ => SecretKind::Password,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyMode {
+    #[default]
+    Advisory, // Saves go through with a warning
+    Strict,   // Saves are refused
+}
+
+impl PolicyMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            PolicyMode::Advisory => "advisory",
+            PolicyMode::Strict => "strict",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        if value == "strict" { PolicyMode::Strict } else { PolicyMode::Advisory }
+    }
+}
+
+// Minimum password strength for entries carrying a tag; see password_policy.rs
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub tag: String,
+    pub min_entropy_bits: u32,
+    #[serde(default)]
+    pub mode: PolicyMode,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExportData {
-    pub user_meta: UserMeta,
+    // Left out of partial exports, whose entries are encrypted under transfer_key instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_meta: Option<UserMeta>,
     pub password_entries: Vec<PasswordEntry>,
+    #[serde(default)]
+    pub stats_history: Option<Vec<StatsSnapshot>>,
+    #[serde(default)]
+    pub password_policies: Vec<PasswordPolicy>,
+    #[serde(default)]
+    pub audit_log: Option<AuditLogExport>,
+    // The vault had encrypt_metadata on, so entries may carry their names encrypted only
+    #[serde(default)]
+    pub metadata_encrypted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_key: Option<String>, // Base64
+}
+
+// First bytes of every unencrypted SQLite file
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+// Entries find_corrupt_entries loads at a time
+const CORRUPT_SCAN_BATCH_SIZE: usize = 500;
+
+// How long a statement waits for another connection (a second app window, the query
+// console) to release the file before failing with `database is locked`
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The full-text index over password_entries; see search_entry_ids. External content, so it
+// stores only the index, and the triggers mirror every change to the indexed columns.
+const SEARCH_INDEX: &str = "entry_search";
+const SEARCH_INDEX_SCHEMA: &str = "
+    CREATE VIRTUAL TABLE entry_search USING fts5(
+        software, account, url, content = 'password_entries', content_rowid = 'id'
+    );
+    CREATE TRIGGER entry_search_insert AFTER INSERT ON password_entries BEGIN
+        INSERT INTO entry_search (rowid, software, account, url) VALUES (new.id, new.software, new.account, new.url);
+    END;
+    CREATE TRIGGER entry_search_delete AFTER DELETE ON password_entries BEGIN
+        INSERT INTO entry_search (entry_search, rowid, software, account, url) VALUES ('delete', old.id, old.software, old.account, old.url);
+    END;
+    CREATE TRIGGER entry_search_update AFTER UPDATE OF software, account, url ON password_entries BEGIN
+        INSERT INTO entry_search (entry_search, rowid, software, account, url) VALUES ('delete', old.id, old.software, old.account, old.url);
+        INSERT INTO entry_search (rowid, software, account, url) VALUES (new.id, new.software, new.account, new.url);
+    END;
+";
+
+// Transactions that write take the write lock up front. A deferred one that reads first
+// fails with `database is locked` straight away, without waiting out BUSY_TIMEOUT, when
+// another process (the CLI beside the app) has written since its read.
+fn write_transaction(connection: &Connection) -> Result<Transaction<'_>> {
+    Ok(Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?)
 }
 
+// Clones share one connection, so services handed clones of the same Database never
+// lock each other out and a transaction sees every service's writes.
+#[derive(Clone)]
 pub struct Database {
-    connection: Connection,
+    connection: Arc<Mutex<Connection>>,
+    path: PathBuf,
+    key: Option<SecretKey>, // SQLCipher key when the file is encrypted
 }
 
 impl Database {
     pub fn new(db_path: PathBuf) -> Result<Self> {
-        let connection = Connection::open(db_path)?;
-        let db = Database { connection };
+        Self::open(db_path, None)
+    }
+
+    // Open (or create) a SQLCipher-encrypted database file. Fails on a wrong key, and on
+    // builds without SQLCipher, where PRAGMA key would be ignored and the file left readable.
+    pub fn new_encrypted(db_path: PathBuf, key: &[u8; 32]) -> Result<Self> {
+        Self::open(db_path, Some(SecretKey::new(*key)))
+    }
+
+    fn open(db_path: PathBuf, key: Option<SecretKey>) -> Result<Self> {
+        let connection = Connection::open(&db_path)?;
+        if let Some(key) = &key {
+            Self::apply_key(&connection, key)?;
+        }
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // Readers no longer wait on a writer (in-memory databases keep their own journal)
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        let db = Database { connection: Arc::new(Mutex::new(connection)), path: db_path, key };
         db.create_tables()?;
         Ok(db)
     }
 
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.connection.lock().map_err(|_| anyhow!("Database connection is unavailable after an earlier failure"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Bytes on disk: the database file plus its WAL, which holds recent writes until the
+    // next checkpoint
+    pub fn file_size(&self) -> Result<u64> {
+        let mut size = std::fs::metadata(&self.path)?.len();
+        let mut wal_path = self.path.as_os_str().to_owned();
+        wal_path.push("-wal");
+        match std::fs::metadata(&wal_path) {
+            Ok(metadata) => size += metadata.len(),
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            Err(_) => {}
+        }
+        Ok(size)
+    }
+
+    // Another connection to the same file, keyed like this one
+    pub fn open_connection(&self, flags: OpenFlags) -> Result<Connection> {
+        let connection = Connection::open_with_flags(&self.path, flags)?;
+        if let Some(key) = &self.key {
+            Self::apply_key(&connection, key)?;
+        }
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(connection)
+    }
+
+    // SQLCipher's raw key form, so the key is used as is rather than run through its passphrase KDF
+    fn raw_key(key: &[u8; 32]) -> Zeroizing<String> {
+        let hex: Zeroizing<String> = Zeroizing::new(key.iter().map(|byte| format!("{:02x}", byte)).collect());
+        Zeroizing::new(format!("x'{}'", hex.as_str()))
+    }
+
+    fn apply_key(connection: &Connection, key: &[u8; 32]) -> Result<()> {
+        connection.pragma_update(None, "key", Self::raw_key(key).as_str())?;
+        let cipher_version: Option<String> = connection
+            .query_row("PRAGMA cipher_version", [], |row| row.get(0))
+            .optional()?;
+        if cipher_version.is_none() {
+            return Err(anyhow!("This build cannot open encrypted vault databases"));
+        }
+        // A wrong key only shows once something is read
+        connection
+            .query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))
+            .map_err(|_| anyhow!("The vault database key is wrong or the file is damaged"))?;
+        Ok(())
+    }
+
+    // Whether `path` holds an unencrypted SQLite database
+    pub fn is_plaintext_file(path: &Path) -> bool {
+        let mut header = [0u8; 16];
+        std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && &header == SQLITE_HEADER
+    }
+
+    // Copy every table, index and trigger into a new SQLCipher file at `target` encrypted
+    // under `key`, in one transaction so the copy is a consistent snapshot
+    pub fn export_encrypted_to(&self, target: &Path, key: &[u8; 32]) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            params![target.to_string_lossy(), Self::raw_key(key).as_str()],
+        )?;
+        let result = (|| -> Result<()> {
+            let tx = connection.unchecked_transaction()?; // Only reads the vault
+            tx.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+            // The export copies the search index's table but not necessarily its contents
+            if Self::table_exists(&tx, "encrypted", SEARCH_INDEX)? {
+                tx.execute("INSERT INTO encrypted.entry_search(entry_search) VALUES ('rebuild')", [])?;
+            }
+            tx.commit()?;
+            Ok(())
+        })();
+        connection.execute("DETACH DATABASE encrypted", [])?;
+        result
+    }
+
+    // Write a consistent copy of the whole database to `target`
+    pub fn backup_to(&self, target: &Path) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "VACUUM INTO ?1",
+            params![target.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    // Rows in every table, by table name, to check a copy against its source
+    pub fn row_counts(&self) -> Result<Vec<(String, i64)>> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )?;
+        let tables = statement.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        tables
+            .into_iter()
+            .map(|table| {
+                let count = connection.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| row.get(0))?;
+                Ok((table, count))
+            })
+            .collect()
+    }
+
+    // Copy the database into `dir_name` next to itself, named by `file_prefix` and the time,
+    // before a change that rewrites the vault
+    pub fn safety_copy(&self, dir_name: &str, file_prefix: &str) -> Result<PathBuf> {
+        let backup_dir = self
+            .path()
+            .parent()
+            .ok_or_else(|| anyhow!("Database path has no parent directory"))?
+            .join(dir_name);
+        std::fs::create_dir_all(&backup_dir)?;
+
+        let backup_path = backup_dir.join(format!("{}{}.db", file_prefix, time_utils::file_stamp(&time_utils::now())));
+        if backup_path.exists() {
+            std::fs::remove_file(&backup_path)?;
+        }
+        self.backup_to(&backup_path)?;
+        Ok(backup_path)
+    }
+
     fn create_tables(&self) -> Result<()> {
+        let connection = self.connection()?;
         // Create user_meta table
-        self.connection.execute(
+        connection.execute(
             "CREATE TABLE IF NOT EXISTS user_meta (
                 id INTEGER PRIMARY KEY,
                 master_hash TEXT NOT NULL,
@@ -62,165 +764,1940 @@ impl Database {
                 answer_salt2 TEXT,
                 question3 TEXT,
                 answer3_hash TEXT,
-                answer_salt3 TEXT
+                answer_salt3 TEXT,
+                wrapped_vault_key TEXT,
+                vault_key_nonce TEXT,
+                recovery_wrapped_vault_key TEXT,
+                recovery_vault_key_nonce TEXT,
+                recovery_key_salt TEXT
+            )",
+            [],
+        )?;
+
+        // Add vault key columns if they don't exist (for migration)
+        for column in [
+            "wrapped_vault_key",
+            "vault_key_nonce",
+            "recovery_wrapped_vault_key",
+            "recovery_vault_key_nonce",
+            "recovery_key_salt",
+            "canary_ciphertext",
+            "canary_nonce",
+            "recovery_code_salt",
+            "recovery_code_verifier",
+            "recovery_code_wrapped_vault_key",
+            "recovery_code_vault_key_nonce",
+        ] {
+            let _ = connection.execute(
+                &format!("ALTER TABLE user_meta ADD COLUMN {} TEXT", column),
+                [],
+            );
+        }
+
+        // Create security_questions table (2 to 5 questions, asked in position order). Older
+        // databases kept exactly three in user_meta columns, which are moved here and cleared.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS security_questions (
+                position INTEGER PRIMARY KEY,
+                question TEXT NOT NULL,
+                answer_hash TEXT NOT NULL,
+                salt TEXT NOT NULL
             )",
             [],
         )?;
+        {
+            let tx = write_transaction(&connection)?;
+            for position in 1..=3 {
+                tx.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO security_questions (position, question, answer_hash, salt)
+                         SELECT {0}, question{0}, answer{0}_hash, answer_salt{0} FROM user_meta
+                         WHERE id = 1 AND question{0} IS NOT NULL AND answer{0}_hash IS NOT NULL AND answer_salt{0} IS NOT NULL",
+                        position
+                    ),
+                    [],
+                )?;
+            }
+            tx.execute(
+                "UPDATE user_meta SET question1 = NULL, answer1_hash = NULL, answer_salt1 = NULL,
+                                      question2 = NULL, answer2_hash = NULL, answer_salt2 = NULL,
+                                      question3 = NULL, answer3_hash = NULL, answer_salt3 = NULL
+                 WHERE question1 IS NOT NULL OR question2 IS NOT NULL OR question3 IS NOT NULL",
+                [],
+            )?;
+            tx.commit()?;
+        }
 
         // Create password_entries table
-        self.connection.execute(
+        connection.execute(
             "CREATE TABLE IF NOT EXISTS password_entries (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 software TEXT NOT NULL,
                 account TEXT NOT NULL,
                 encrypted_password TEXT NOT NULL,
                 nonce TEXT NOT NULL,
-                notes TEXT
+                notes TEXT,
+                notes_nonce TEXT,
+                expires_at TEXT,
+                last_used_at TEXT,
+                password_changed_at TEXT,
+                breach_acknowledged_at TEXT,
+                created_at TEXT,
+                updated_at TEXT,
+                is_favorite INTEGER NOT NULL DEFAULT 0,
+                deleted_at TEXT
             )",
             [],
         )?;
 
-        // Add notes column if it doesn't exist (for migration)
-        let _ = self.connection.execute(
-            "ALTER TABLE password_entries ADD COLUMN notes TEXT",
+        // Add columns introduced after the first release if they don't exist (for migration)
+        for column in ["notes", "notes_nonce", "expires_at", "last_used_at", "password_changed_at", "breach_acknowledged_at", "created_at", "updated_at", "deleted_at", "url", "url_host", "entry_uid", "software_enc", "software_nonce", "account_enc", "account_nonce"] {
+            let _ = connection.execute(
+                &format!("ALTER TABLE password_entries ADD COLUMN {} TEXT", column),
+                [],
+            );
+        }
+        let _ = connection.execute(
+            "ALTER TABLE password_entries ADD COLUMN is_favorite INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        // Filled in for existing entries by the score_password_strength data migration
+        let _ = connection.execute(
+            "ALTER TABLE password_entries ADD COLUMN strength_score INTEGER",
+            [],
+        );
+        let _ = connection.execute(
+            "ALTER TABLE password_entries ADD COLUMN rotation_days INTEGER",
+            [],
+        );
+        let _ = connection.execute(
+            "ALTER TABLE password_entries ADD COLUMN entry_type TEXT NOT NULL DEFAULT 'login'",
+            [],
+        );
+        let _ = connection.execute(
+            "ALTER TABLE password_entries ADD COLUMN secret_kind TEXT NOT NULL DEFAULT 'password'",
             [],
         );
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS idx_password_entries_url_host ON password_entries (url_host)",
+            [],
+        )?;
+        // Serves the prefix LIKE in software_suggestions
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS idx_password_entries_software_nocase ON password_entries (software COLLATE NOCASE)",
+            [],
+        )?;
 
-        Ok(())
-    }
+        // Create settings table (simple key/value store)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-    // User Meta operations
-    pub fn insert_user_meta(&self, user_meta: &UserMeta) -> Result<()> {
-        self.connection.execute(
-            "INSERT OR REPLACE INTO user_meta (
-                id, master_hash, master_salt, 
-                question1, answer1_hash, answer_salt1,
-                question2, answer2_hash, answer_salt2,
-                question3, answer3_hash, answer_salt3
-            ) VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-            params![
-                user_meta.master_hash,
-                user_meta.master_salt,
-                user_meta.question1,
-                user_meta.answer1_hash,
-                user_meta.answer_salt1,
-                user_meta.question2,
-                user_meta.answer2_hash,
-                user_meta.answer_salt2,
-                user_meta.question3,
-                user_meta.answer3_hash,
-                user_meta.answer_salt3
-            ],
+        // Create data_migrations table (completed first-unlock data migrations)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS data_migrations (
+                id TEXT PRIMARY KEY,
+                completed_at TEXT NOT NULL
+            )",
+            [],
         )?;
-        Ok(())
-    }
 
-    pub fn get_user_meta(&self) -> Result<Option<UserMeta>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT id, master_hash, master_salt, 
-                    question1, answer1_hash, answer_salt1,
-                    question2, answer2_hash, answer_salt2,
-                    question3, answer3_hash, answer_salt3
-             FROM user_meta WHERE id = 1"
-        )?;
-
-        let user_meta_iter = stmt.query_map([], |row| {
-            Ok(UserMeta {
-                id: Some(row.get(0)?),
-                master_hash: row.get(1)?,
-                master_salt: row.get(2)?,
-                question1: row.get(3)?,
-                answer1_hash: row.get(4)?,
-                answer_salt1: row.get(5)?,
-                question2: row.get(6)?,
-                answer2_hash: row.get(7)?,
-                answer_salt2: row.get(8)?,
-                question3: row.get(9)?,
-                answer3_hash: row.get(10)?,
-                answer_salt3: row.get(11)?,
-            })
-        })?;
+        // Create stats_history table (at most one snapshot per day)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS stats_history (
+                recorded_on TEXT PRIMARY KEY,
+                recorded_at TEXT NOT NULL,
+                entry_count INTEGER NOT NULL,
+                weak_count INTEGER NOT NULL,
+                reused_count INTEGER NOT NULL,
+                breached_count INTEGER,
+                average_password_age_days REAL
+            )",
+            [],
+        )?;
 
-        for user_meta in user_meta_iter {
-            return Ok(Some(user_meta?));
-        }
-        Ok(None)
-    }
+        // Create entry_icons table (user-supplied icons, at most one per entry)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS entry_icons (
+                entry_id INTEGER PRIMARY KEY,
+                mime_type TEXT NOT NULL,
+                data TEXT NOT NULL,
+                content_hash TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn user_exists(&self) -> Result<bool> {
-        let mut stmt = self.connection.prepare("SELECT COUNT(*) FROM user_meta WHERE id = 1")?;
-        let count: i64 = stmt.query_row([], |row| row.get(0))?;
-        Ok(count > 0)
-    }
+        // Create host_icons table (fetched favicons, keyed by the url_host of the entries they
+        // stand for; a row without data marks a host that had no usable icon)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS host_icons (
+                host TEXT PRIMARY KEY,
+                mime_type TEXT,
+                data TEXT,
+                content_hash TEXT,
+                fetched_at TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-    // Password Entry operations
-    pub fn insert_password_entry(&self, entry: &PasswordEntry) -> Result<i64> {
-        self.connection.execute(
-            "INSERT INTO password_entries (software, account, encrypted_password, nonce, notes)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![entry.software, entry.account, entry.encrypted_password, entry.nonce, entry.notes],
+        // Create attachments table (encrypted files kept with an entry). Like icons and tags,
+        // they are removed explicitly along with their entry.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS attachments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entry_id INTEGER NOT NULL,
+                filename TEXT NOT NULL,
+                encrypted_blob BLOB NOT NULL,
+                nonce TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                added_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS idx_attachments_entry_id ON attachments (entry_id)",
+            [],
         )?;
-        Ok(self.connection.last_insert_rowid())
-    }
 
-    pub fn get_all_password_entries(&self) -> Result<Vec<PasswordEntry>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT id, software, account, encrypted_password, nonce, notes FROM password_entries"
-        )?;
-
-        let entry_iter = stmt.query_map([], |row| {
-            Ok(PasswordEntry {
-                id: Some(row.get(0)?),
-                software: row.get(1)?,
-                account: row.get(2)?,
-                encrypted_password: row.get(3)?,
-                nonce: row.get(4)?,
-                notes: row.get(5)?,
-            })
-        })?;
+        // Create custom_fields table (labelled values kept with an entry, encrypted). Removed
+        // explicitly along with their entry, like attachments.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS custom_fields (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entry_id INTEGER NOT NULL,
+                label TEXT NOT NULL,
+                encrypted_value TEXT NOT NULL,
+                nonce TEXT NOT NULL,
+                field_kind TEXT NOT NULL DEFAULT 'text',
+                position INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS idx_custom_fields_entry_id ON custom_fields (entry_id)",
+            [],
+        )?;
 
-        let mut entries = Vec::new();
-        for entry in entry_iter {
-            entries.push(entry?);
-        }
-        Ok(entries)
-    }
+        // Create tags and entry_tags tables. Tag names are unique ignoring case; deleting
+        // a tag or an entry removes its entry_tags rows explicitly.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE
+            )",
+            [],
+        )?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS entry_tags (
+                entry_id INTEGER NOT NULL,
+                tag_id INTEGER NOT NULL,
+                PRIMARY KEY (entry_id, tag_id)
+            )",
+            [],
+        )?;
 
-    pub fn update_password_entry(&self, entry: &PasswordEntry) -> Result<()> {
-        if let Some(id) = entry.id {
-            self.connection.execute(
-                "UPDATE password_entries SET software = ?1, account = ?2, encrypted_password = ?3, nonce = ?4, notes = ?5 WHERE id = ?6",
-                params![entry.software, entry.account, entry.encrypted_password, entry.nonce, entry.notes, id],
-            )?;
-        } else {
-            return Err(anyhow!("Password entry ID is required for update"));
-        }
-        Ok(())
-    }
+        // Create password_policies table (one policy per tag, matched ignoring case)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS password_policies (
+                tag TEXT PRIMARY KEY COLLATE NOCASE,
+                min_entropy_bits INTEGER NOT NULL,
+                mode TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn delete_password_entry(&self, id: i64) -> Result<()> {
-        self.connection.execute("DELETE FROM password_entries WHERE id = ?1", params![id])?;
-        Ok(())
-    }
+        // Create entry_drafts table (one autosaved editor draft per entry, plus the new-entry slot)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS entry_drafts (
+                slot INTEGER PRIMARY KEY,
+                encrypted_payload TEXT NOT NULL,
+                nonce TEXT NOT NULL,
+                saved_at TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn search_password_entries(&self, query: &str) -> Result<Vec<PasswordEntry>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT id, software, account, encrypted_password, nonce, notes 
-             FROM password_entries 
-             WHERE software LIKE ?1 OR account LIKE ?1 OR notes LIKE ?1"
+        // Create audit_log table (append-only record of sensitive operations)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                occurred_at TEXT NOT NULL,
+                action TEXT NOT NULL,
+                detail TEXT
+            )",
+            [],
         )?;
+        // Encrypted, hash-chained audit rows (see audit_log.rs); older rows gain NULLs
+        // and are sealed into the chain the next time the vault is unlocked
+        for column in ["entry_id", "segment_id", "source_id"] {
+            let _ = connection.execute(
+                &format!("ALTER TABLE audit_log ADD COLUMN {} INTEGER", column),
+                [],
+            );
+        }
+        for column in ["detail_nonce", "mac"] {
+            let _ = connection.execute(
+                &format!("ALTER TABLE audit_log ADD COLUMN {} TEXT", column),
+                [],
+            );
+        }
 
-        let search_pattern = format!("%{}%", query);
-        let entry_iter = stmt.query_map(params![search_pattern], |row| {
-            Ok(PasswordEntry {
-                id: Some(row.get(0)?),
-                software: row.get(1)?,
-                account: row.get(2)?,
-                encrypted_password: row.get(3)?,
-                nonce: row.get(4)?,
-                notes: row.get(5)?,
-            })
+        // Imported audit log segments, chained separately from the local log
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS audit_segments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                tag TEXT NOT NULL,
+                imported_at TEXT NOT NULL,
+                head TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        let _ = connection.execute(
+            "ALTER TABLE user_meta ADD COLUMN generation INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        let _ = connection.execute("ALTER TABLE user_meta ADD COLUMN recovery_verified_at TEXT", []);
+        let _ = connection.execute("ALTER TABLE user_meta ADD COLUMN answers_normalized INTEGER NOT NULL DEFAULT 0", []);
+        let _ = connection.execute("ALTER TABLE user_meta ADD COLUMN password_hint TEXT", []);
+        let _ = connection.execute("ALTER TABLE user_meta ADD COLUMN duress_hash TEXT", []);
+        let _ = connection.execute("ALTER TABLE user_meta ADD COLUMN duress_salt TEXT", []);
+
+        // Rows written before KDF parameters were recorded used the defaults
+        let defaults = KdfParams::default();
+        for (column, default) in [
+            ("kdf_m_cost", defaults.m_cost),
+            ("kdf_t_cost", defaults.t_cost),
+            ("kdf_p_cost", defaults.p_cost),
+            ("recovery_kdf_m_cost", defaults.m_cost),
+            ("recovery_kdf_t_cost", defaults.t_cost),
+            ("recovery_kdf_p_cost", defaults.p_cost),
+        ] {
+            let _ = connection.execute(
+                &format!("ALTER TABLE user_meta ADD COLUMN {} INTEGER NOT NULL DEFAULT {}", column, default),
+                [],
+            );
+        }
+
+        // Create login_attempts table (one row: consecutive failed logins and recovery attempts)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS login_attempts (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                count INTEGER NOT NULL,
+                last_attempt_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Create login_stats table (one row, see LoginStats)
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS login_stats (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                last_login_at TEXT,
+                last_failed_at TEXT,
+                failed_since_last_login INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+
+        // Full-text index of the plaintext names and URL, kept up to date by triggers. Built
+        // over the existing entries when first created; a SQLite without FTS5 goes without
+        // and search matches by substring alone.
+        if !Self::table_exists(&connection, "main", SEARCH_INDEX)? {
+            let created = (|| -> Result<()> {
+                let tx = write_transaction(&connection)?;
+                tx.execute_batch(SEARCH_INDEX_SCHEMA)?;
+                tx.execute("INSERT INTO entry_search(entry_search) VALUES ('rebuild')", [])?;
+                tx.commit()?;
+                Ok(())
+            })();
+            if let Err(e) = created {
+                log::info!("Full-text search is unavailable: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn table_exists(connection: &Connection, schema: &str, table: &str) -> Result<bool> {
+        let sql = format!("SELECT COUNT(*) FROM \"{}\".sqlite_master WHERE type = 'table' AND name = ?1", schema);
+        Ok(connection.query_row(&sql, params![table], |row| row.get::<_, i64>(0))? > 0)
+    }
+
+    // Live entries with a word starting with each word of `query` in their software, account
+    // or URL, best match (bm25) first. None when there is no index or the query has anything
+    // but letters, digits and spaces, which FTS5 could read as syntax; callers then match by
+    // substring alone.
+    pub fn search_entry_ids(&self, query: &str) -> Result<Option<Vec<i64>>> {
+        let words: Vec<&str> = query.split_whitespace().collect();
+        if words.is_empty() || !query.chars().all(|c| c.is_alphanumeric() || c.is_whitespace()) {
+            return Ok(None);
+        }
+        let connection = self.connection()?;
+        if !Self::table_exists(&connection, "main", SEARCH_INDEX)? {
+            return Ok(None);
+        }
+        let fts_query = words.iter().map(|word| format!("\"{}\"*", word)).collect::<Vec<_>>().join(" ");
+        let mut statement = connection.prepare_cached(
+            "SELECT e.id FROM entry_search JOIN password_entries e ON e.id = entry_search.rowid
+             WHERE entry_search MATCH ?1 AND e.deleted_at IS NULL
+             ORDER BY bm25(entry_search)",
+        )?;
+        let ids = statement.query_map(params![fts_query], |row| row.get(0))?.collect::<rusqlite::Result<Vec<i64>>>()?;
+        Ok(Some(ids))
+    }
+
+    // Distinct software names of live entries starting with `prefix`, ignoring ASCII case,
+    // with how many entries use each, most used first. Names stored encrypted are blank and
+    // left out.
+    pub fn software_suggestions(&self, prefix: &str, limit: usize) -> Result<Vec<(String, i64)>> {
+        let connection = self.connection()?;
+        let pattern = format!("{}%", prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        let mut statement = connection.prepare_cached(
+            "SELECT software, COUNT(*) AS uses FROM password_entries
+             WHERE deleted_at IS NULL AND software != '' AND software LIKE ?1 ESCAPE '\\'
+             GROUP BY software ORDER BY uses DESC, software COLLATE NOCASE LIMIT ?2",
+        )?;
+        let suggestions = statement
+            .query_map(params![pattern, limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+        Ok(suggestions)
+    }
+
+    // Rebuild the index from the entries as stored, so no words of names since encrypted
+    // linger in it
+    pub fn rebuild_search_index(&self) -> Result<()> {
+        let connection = self.connection()?;
+        if Self::table_exists(&connection, "main", SEARCH_INDEX)? {
+            connection.execute("INSERT INTO entry_search(entry_search) VALUES ('rebuild')", [])?;
+        }
+        Ok(())
+    }
+
+    // User Meta operations. The row is replaced whole, so the legacy question columns end up
+    // empty; the questions are rewritten in security_questions. Callers pass a transaction
+    // or a connection outside one, which insert_user_meta wraps.
+    fn write_user_meta(connection: &Connection, user_meta: &UserMeta) -> Result<()> {
+        connection.execute("DELETE FROM security_questions", [])?;
+        for (position, question) in user_meta.questions().iter().enumerate() {
+            connection.execute(
+                "INSERT INTO security_questions (position, question, answer_hash, salt) VALUES (?1, ?2, ?3, ?4)",
+                params![position as i64 + 1, question.question, question.answer_hash, question.salt],
+            )?;
+        }
+        connection.execute(
+            "INSERT OR REPLACE INTO user_meta (
+                id, master_hash, master_salt,
+                wrapped_vault_key, vault_key_nonce,
+                recovery_wrapped_vault_key, recovery_vault_key_nonce, recovery_key_salt,
+                generation, recovery_verified_at,
+                kdf_m_cost, kdf_t_cost, kdf_p_cost,
+                recovery_kdf_m_cost, recovery_kdf_t_cost, recovery_kdf_p_cost,
+                canary_ciphertext, canary_nonce,
+                recovery_code_salt, recovery_code_verifier, recovery_code_wrapped_vault_key, recovery_code_vault_key_nonce,
+                answers_normalized, password_hint, duress_hash, duress_salt
+            ) VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25)",
+            params![
+                user_meta.master_hash,
+                user_meta.master_salt,
+                user_meta.wrapped_vault_key,
+                user_meta.vault_key_nonce,
+                user_meta.recovery_wrapped_vault_key,
+                user_meta.recovery_vault_key_nonce,
+                user_meta.recovery_key_salt,
+                user_meta.generation,
+                user_meta.recovery_verified_at,
+                user_meta.kdf_params.m_cost,
+                user_meta.kdf_params.t_cost,
+                user_meta.kdf_params.p_cost,
+                user_meta.recovery_kdf_params.m_cost,
+                user_meta.recovery_kdf_params.t_cost,
+                user_meta.recovery_kdf_params.p_cost,
+                user_meta.canary_ciphertext,
+                user_meta.canary_nonce,
+                user_meta.recovery_code_salt,
+                user_meta.recovery_code_verifier,
+                user_meta.recovery_code_wrapped_vault_key,
+                user_meta.recovery_code_vault_key_nonce,
+                user_meta.answers_normalized,
+                user_meta.password_hint,
+                user_meta.duress_hash,
+                user_meta.duress_salt,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_user_meta(&self, user_meta: &UserMeta) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        Self::write_user_meta(&tx, user_meta)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 0 before setup
+    pub fn get_user_meta_generation(&self) -> Result<i64> {
+        let connection = self.connection()?;
+        let generation = connection.query_row(
+            "SELECT generation FROM user_meta WHERE id = 1",
+            [],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(generation.unwrap_or(0))
+    }
+
+    pub fn get_user_meta(&self) -> Result<Option<UserMeta>> {
+        let connection = self.connection()?;
+        let user_meta = connection.query_row(
+            "SELECT id, master_hash, master_salt,
+                    wrapped_vault_key, vault_key_nonce,
+                    recovery_wrapped_vault_key, recovery_vault_key_nonce, recovery_key_salt,
+                    generation, recovery_verified_at,
+                    kdf_m_cost, kdf_t_cost, kdf_p_cost,
+                    recovery_kdf_m_cost, recovery_kdf_t_cost, recovery_kdf_p_cost,
+                    canary_ciphertext, canary_nonce,
+                    recovery_code_salt, recovery_code_verifier, recovery_code_wrapped_vault_key, recovery_code_vault_key_nonce,
+                    answers_normalized, password_hint, duress_hash, duress_salt
+             FROM user_meta WHERE id = 1",
+            [],
+            |row| {
+                Ok(UserMeta {
+                    id: Some(row.get(0)?),
+                    master_hash: row.get(1)?,
+                    master_salt: row.get(2)?,
+                    security_questions: Vec::new(),
+                    legacy_questions: LegacyQuestions::default(),
+                    wrapped_vault_key: row.get(3)?,
+                    vault_key_nonce: row.get(4)?,
+                    recovery_wrapped_vault_key: row.get(5)?,
+                    recovery_vault_key_nonce: row.get(6)?,
+                    recovery_key_salt: row.get(7)?,
+                    generation: row.get(8)?,
+                    recovery_verified_at: row.get(9)?,
+                    kdf_params: KdfParams { m_cost: row.get(10)?, t_cost: row.get(11)?, p_cost: row.get(12)? },
+                    recovery_kdf_params: KdfParams { m_cost: row.get(13)?, t_cost: row.get(14)?, p_cost: row.get(15)? },
+                    canary_ciphertext: row.get(16)?,
+                    canary_nonce: row.get(17)?,
+                    recovery_code_salt: row.get(18)?,
+                    recovery_code_verifier: row.get(19)?,
+                    recovery_code_wrapped_vault_key: row.get(20)?,
+                    recovery_code_vault_key_nonce: row.get(21)?,
+                    answers_normalized: row.get(22)?,
+                    password_hint: row.get(23)?,
+                    duress_hash: row.get(24)?,
+                    duress_salt: row.get(25)?,
+                })
+            },
+        ).optional()?;
+
+        let Some(mut user_meta) = user_meta else {
+            return Ok(None);
+        };
+        let mut stmt = connection.prepare("SELECT question, answer_hash, salt FROM security_questions ORDER BY position")?;
+        user_meta.security_questions = stmt
+            .query_map([], |row| Ok(SecurityQuestionRecord { question: row.get(0)?, answer_hash: row.get(1)?, salt: row.get(2)? }))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Some(user_meta))
+    }
+
+    // Only touches the drill timestamp, so it cannot race a password change rewriting the row
+    pub fn set_recovery_verified_at(&self, verified_at: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "UPDATE user_meta SET recovery_verified_at = ?1 WHERE id = 1",
+            params![verified_at],
+        )?;
+        Ok(())
+    }
+
+    // Only touches the duress password; commits rewriting the whole row carry the current one over
+    pub fn set_duress(&self, duress_hash: Option<&str>, duress_salt: Option<&str>) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "UPDATE user_meta SET duress_hash = ?1, duress_salt = ?2 WHERE id = 1",
+            params![duress_hash, duress_salt],
+        )?;
+        Ok(())
+    }
+
+    // Only touches the hint; commits rewriting the whole row carry the current one over
+    pub fn set_password_hint(&self, hint: Option<&str>) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute("UPDATE user_meta SET password_hint = ?1 WHERE id = 1", params![hint])?;
+        Ok(())
+    }
+
+    // Only touches the canary, for vaults set up before it existed
+    pub fn set_canary(&self, canary_ciphertext: &str, canary_nonce: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "UPDATE user_meta SET canary_ciphertext = ?1, canary_nonce = ?2 WHERE id = 1",
+            params![canary_ciphertext, canary_nonce],
+        )?;
+        Ok(())
+    }
+
+    // Consecutive failed attempts and when the last one was made; (0, None) after a success
+    pub fn get_login_attempts(&self) -> Result<(u32, Option<String>)> {
+        let connection = self.connection()?;
+        let attempts = connection.query_row(
+            "SELECT count, last_attempt_at FROM login_attempts WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, Some(row.get(1)?))),
+        ).optional()?;
+        Ok(attempts.unwrap_or((0, None)))
+    }
+
+    // Count one more failed attempt made at `attempted_at`; returns the new count
+    pub fn record_failed_login(&self, attempted_at: &str) -> Result<u32> {
+        let connection = self.connection()?;
+        Ok(connection.query_row(
+            "INSERT INTO login_attempts (id, count, last_attempt_at) VALUES (1, 1, ?1)
+             ON CONFLICT(id) DO UPDATE SET count = count + 1, last_attempt_at = excluded.last_attempt_at
+             RETURNING count",
+            params![attempted_at],
+            |row| row.get(0),
+        )?)
+    }
+
+    // Take back one failed attempt counted by record_failed_login
+    pub fn uncount_failed_login(&self) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute("UPDATE login_attempts SET count = MAX(count - 1, 0) WHERE id = 1", [])?;
+        Ok(())
+    }
+
+    pub fn reset_login_attempts(&self) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute("DELETE FROM login_attempts", [])?;
+        Ok(())
+    }
+
+    fn read_login_stats(connection: &Connection) -> Result<LoginStats> {
+        let stats = connection.query_row(
+            "SELECT last_login_at, last_failed_at, failed_since_last_login FROM login_stats WHERE id = 1",
+            [],
+            |row| Ok(LoginStats { last_login_at: row.get(0)?, last_failed_at: row.get(1)?, failed_since_last_login: row.get(2)? }),
+        ).optional()?;
+        Ok(stats.unwrap_or_default())
+    }
+
+    pub fn get_login_stats(&self) -> Result<LoginStats> {
+        let connection = self.connection()?;
+        Self::read_login_stats(&connection)
+    }
+
+    pub fn record_login_failure(&self, failed_at: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT INTO login_stats (id, last_failed_at, failed_since_last_login) VALUES (1, ?1, 1)
+             ON CONFLICT(id) DO UPDATE SET last_failed_at = excluded.last_failed_at, failed_since_last_login = failed_since_last_login + 1",
+            params![failed_at],
+        )?;
+        Ok(())
+    }
+
+    // Record a successful login and return the stats as they were before it
+    pub fn record_login_success(&self, logged_in_at: &str) -> Result<LoginStats> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        let previous = Self::read_login_stats(&tx)?;
+        tx.execute(
+            "INSERT INTO login_stats (id, last_login_at, failed_since_last_login) VALUES (1, ?1, 0)
+             ON CONFLICT(id) DO UPDATE SET last_login_at = excluded.last_login_at, failed_since_last_login = 0",
+            params![logged_in_at],
+        )?;
+        tx.commit()?;
+        Ok(previous)
+    }
+
+    pub fn user_exists(&self) -> Result<bool> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT COUNT(*) FROM user_meta WHERE id = 1")?;
+        let count: i64 = stmt.query_row([], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
+    // Password Entry operations
+    const ENTRY_COLUMNS: &'static str = "id, software, account, encrypted_password, nonce, notes, notes_nonce, expires_at, last_used_at, password_changed_at, breach_acknowledged_at, created_at, updated_at, is_favorite, deleted_at, url, entry_uid, software_enc, software_nonce, account_enc, account_nonce, strength_score, rotation_days, entry_type, secret_kind";
+
+    fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<PasswordEntry> {
+        Ok(PasswordEntry {
+            id: Some(row.get(0)?),
+            software: row.get(1)?,
+            account: row.get(2)?,
+            encrypted_password: row.get(3)?,
+            nonce: row.get(4)?,
+            notes: row.get(5)?,
+            notes_nonce: row.get(6)?,
+            expires_at: row.get(7)?,
+            last_used_at: row.get(8)?,
+            password_changed_at: row.get(9)?,
+            breach_acknowledged_at: row.get(10)?,
+            created_at: row.get(11)?,
+            updated_at: row.get(12)?,
+            is_favorite: row.get(13)?,
+            deleted_at: row.get(14)?,
+            url: row.get(15)?,
+            icon: None,
+            tags: Vec::new(),
+            attachments: Vec::new(),
+            custom_fields: Vec::new(),
+            entry_uid: row.get(16)?,
+            software_enc: row.get(17)?,
+            software_nonce: row.get(18)?,
+            account_enc: row.get(19)?,
+            account_nonce: row.get(20)?,
+            strength_score: row.get(21)?,
+            rotation_days: row.get(22)?,
+            entry_type: EntryType::from_db(&row.get::<_, String>(23)?),
+            secret_kind: SecretKind::from_db(&row.get::<_, String>(24)?),
+        })
+    }
+
+    // Lowercase host of the entry's URL, scheme, path and port stripped
+    fn url_host(entry: &PasswordEntry) -> Option<String> {
+        entry.url.as_deref().and_then(domains::host_from)
+    }
+
+    // What keeps the entry's ciphertexts from ever decrypting, e.g. "nonce is 8 bytes, not 12":
+    // a ciphertext that is not base64, or a nonce that is not 12 bytes of it. Plaintext notes
+    // (no notes_nonce) and names (no software_enc) are not checked. None when all is well.
+    pub fn entry_defect(entry: &PasswordEntry) -> Option<String> {
+        let sealed = [
+            ("encrypted_password", Some(&entry.encrypted_password), "nonce", Some(&entry.nonce)),
+            // Notes without a nonce are legacy plaintext, not a ciphertext missing its nonce
+            ("notes", entry.notes_nonce.as_ref().and(entry.notes.as_ref()), "notes_nonce", entry.notes_nonce.as_ref()),
+            ("software_enc", entry.software_enc.as_ref(), "software_nonce", entry.software_nonce.as_ref()),
+            ("account_enc", entry.account_enc.as_ref(), "account_nonce", entry.account_nonce.as_ref()),
+        ];
+        for (field, ciphertext, nonce_field, nonce) in sealed {
+            if ciphertext.is_none() && nonce.is_none() {
+                continue;
+            }
+            match nonce.map(|nonce| general_purpose::STANDARD.decode(nonce)) {
+                None => return Some(format!("{} has no {}", field, nonce_field)),
+                Some(Err(_)) => return Some(format!("{} is not valid base64", nonce_field)),
+                Some(Ok(bytes)) if bytes.len() != NONCE_LENGTH => {
+                    return Some(format!("{} is {} bytes, not {}", nonce_field, bytes.len(), NONCE_LENGTH));
+                }
+                Some(Ok(_)) => {}
+            }
+            match ciphertext.map(|ciphertext| general_purpose::STANDARD.decode(ciphertext)) {
+                None => return Some(format!("{} is missing beside {}", field, nonce_field)),
+                Some(Err(_)) => return Some(format!("{} is not valid base64", field)),
+                Some(Ok(_)) => {}
+            }
+        }
+        None
+    }
+
+    // Refuse to store an entry that could never be decrypted, so the fault shows up where
+    // it was made rather than as a decryption error later
+    fn check_entry_ciphertexts(entry: &PasswordEntry) -> Result<()> {
+        match Self::entry_defect(entry) {
+            Some(defect) => Err(anyhow!("Malformed password entry not stored: {}", defect)),
+            None => Ok(()),
+        }
+    }
+
+    // Insert the entry with its icon and tags; returns the new id. Statements are cached, so
+    // writing many entries in one transaction prepares them once.
+    fn write_password_entry(connection: &Connection, entry: &PasswordEntry) -> Result<i64> {
+        Self::check_entry_ciphertexts(entry)?;
+        connection.prepare_cached(
+            "INSERT INTO password_entries (software, account, encrypted_password, nonce, notes, notes_nonce, expires_at, last_used_at, password_changed_at, breach_acknowledged_at, created_at, updated_at, is_favorite, deleted_at, url, url_host, entry_uid,
+                 software_enc, software_nonce, account_enc, account_nonce, strength_score, rotation_days, entry_type, secret_kind)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25)",
+        )?.execute(params![
+                entry.software,
+                entry.account,
+                entry.encrypted_password,
+                entry.nonce,
+                entry.notes,
+                entry.notes_nonce,
+                entry.expires_at,
+                entry.last_used_at,
+                entry.password_changed_at,
+                entry.breach_acknowledged_at,
+                entry.created_at,
+                entry.updated_at,
+                entry.is_favorite,
+                entry.deleted_at,
+                entry.url,
+                Self::url_host(entry),
+                entry.entry_uid,
+                entry.software_enc,
+                entry.software_nonce,
+                entry.account_enc,
+                entry.account_nonce,
+                entry.strength_score,
+                entry.rotation_days,
+                entry.entry_type.as_str(),
+                entry.secret_kind.as_str(),
+        ])?;
+        let id = connection.last_insert_rowid();
+        if let Some(icon) = &entry.icon {
+            Self::write_entry_icon(connection, id, icon)?;
+        }
+        for tag in &entry.tags {
+            Self::write_entry_tag(connection, id, tag)?;
+        }
+        for attachment in &entry.attachments {
+            Self::write_attachment(connection, id, &Attachment { id: None, ..attachment.clone() })?;
+        }
+        for field in &entry.custom_fields {
+            Self::write_custom_field(connection, id, &CustomField { id: None, ..field.clone() })?;
+        }
+        Ok(id)
+    }
+
+    // Usage tracking (last_used_at), created_at and rotation_days are left alone; see
+    // touch_password_entry and set_rotation_days. Attachments and custom fields are only
+    // replaced when the entry carries some.
+    // Callers carry password_changed_at, breach_acknowledged_at, strength_score, updated_at, is_favorite, deleted_at, url, entry_type and secret_kind over from the stored entry.
+    fn rewrite_password_entry(connection: &Connection, entry: &PasswordEntry) -> Result<()> {
+        let id = entry.id.ok_or_else(|| anyhow!("Password entry ID is required for update"))?;
+        Self::check_entry_ciphertexts(entry)?;
+        connection.prepare_cached(
+            "UPDATE password_entries SET software = ?1, account = ?2, encrypted_password = ?3, nonce = ?4, notes = ?5, notes_nonce = ?6, expires_at = ?7,
+                 password_changed_at = ?8, breach_acknowledged_at = ?9, updated_at = ?10, is_favorite = ?11, deleted_at = ?12,
+                 url = ?13, url_host = ?14, entry_uid = ?15,
+                 software_enc = ?16, software_nonce = ?17, account_enc = ?18, account_nonce = ?19, strength_score = ?20, entry_type = ?21, secret_kind = ?22
+             WHERE id = ?23",
+        )?.execute(
+            params![
+                entry.software,
+                entry.account,
+                entry.encrypted_password,
+                entry.nonce,
+                entry.notes,
+                entry.notes_nonce,
+                entry.expires_at,
+                entry.password_changed_at,
+                entry.breach_acknowledged_at,
+                entry.updated_at,
+                entry.is_favorite,
+                entry.deleted_at,
+                entry.url,
+                Self::url_host(entry),
+                entry.entry_uid,
+                entry.software_enc,
+                entry.software_nonce,
+                entry.account_enc,
+                entry.account_nonce,
+                entry.strength_score,
+                entry.entry_type.as_str(),
+                entry.secret_kind.as_str(),
+                id,
+            ],
+        )?;
+        if let Some(icon) = &entry.icon {
+            Self::write_entry_icon(connection, id, icon)?;
+        }
+        for tag in &entry.tags {
+            Self::write_entry_tag(connection, id, tag)?;
+        }
+        if !entry.attachments.is_empty() {
+            connection.execute("DELETE FROM attachments WHERE entry_id = ?1", params![id])?;
+            for attachment in &entry.attachments {
+                Self::write_attachment(connection, id, attachment)?;
+            }
+        }
+        if !entry.custom_fields.is_empty() {
+            connection.execute("DELETE FROM custom_fields WHERE entry_id = ?1", params![id])?;
+            for field in &entry.custom_fields {
+                Self::write_custom_field(connection, id, field)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_custom_field(connection: &Connection, entry_id: i64, field: &CustomField) -> Result<i64> {
+        connection.prepare_cached(
+            "INSERT INTO custom_fields (id, entry_id, label, encrypted_value, nonce, field_kind, position) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?.execute(params![
+            field.id,
+            entry_id,
+            field.label,
+            field.encrypted_value,
+            field.nonce,
+            field.field_kind.as_str(),
+            field.position,
+        ])?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    fn write_attachment(connection: &Connection, entry_id: i64, attachment: &Attachment) -> Result<i64> {
+        connection.prepare_cached(
+            "INSERT INTO attachments (id, entry_id, filename, encrypted_blob, nonce, size, added_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?.execute(params![
+            attachment.id,
+            entry_id,
+            attachment.filename,
+            attachment.encrypted_blob,
+            attachment.nonce,
+            attachment.size,
+            attachment.added_at,
+        ])?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    fn write_entry_icon(connection: &Connection, entry_id: i64, icon: &EntryIcon) -> Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO entry_icons (entry_id, mime_type, data, content_hash) VALUES (?1, ?2, ?3, ?4)",
+            params![entry_id, icon.mime_type, icon.data, icon.content_hash],
+        )?;
+        Ok(())
+    }
+
+    // Create the tag if needed and attach it to the entry
+    fn write_entry_tag(connection: &Connection, entry_id: i64, name: &str) -> Result<()> {
+        connection.prepare_cached("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?.execute(params![name])?;
+        connection
+            .prepare_cached("INSERT OR IGNORE INTO entry_tags (entry_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2")?
+            .execute(params![entry_id, name])?;
+        Ok(())
+    }
+
+    pub fn insert_password_entry(&self, entry: &PasswordEntry) -> Result<i64> {
+        let connection = self.connection()?;
+        Self::write_password_entry(&connection, entry)
+    }
+
+    // All of `entries` or, on any error, none; returns their ids in order
+    pub fn insert_password_entries(&self, entries: &[PasswordEntry]) -> Result<Vec<i64>> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        let ids = entries
+            .iter()
+            .map(|entry| Self::write_password_entry(&tx, entry))
+            .collect::<Result<Vec<_>>>()?;
+        tx.commit()?;
+        Ok(ids)
+    }
+
+    // Every entry outside the trash
+    pub fn get_all_password_entries(&self) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            &format!("SELECT {} FROM password_entries WHERE deleted_at IS NULL", Self::ENTRY_COLUMNS)
+        )?;
+
+        let entry_iter = stmt.query_map([], Self::entry_from_row)?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    // Every stored entry, trashed ones included; for passes that must rewrite all rows
+    // (key changes, migrations) or copy the whole vault
+    pub fn get_all_password_entries_including_trash(&self) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            &format!("SELECT {} FROM password_entries", Self::ENTRY_COLUMNS)
+        )?;
+
+        let entry_iter = stmt.query_map([], Self::entry_from_row)?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    // The oldest live entry, used to check a key against the vault
+    pub fn get_first_password_entry(&self) -> Result<Option<PasswordEntry>> {
+        let connection = self.connection()?;
+        Ok(connection.query_row(
+            &format!("SELECT {} FROM password_entries WHERE deleted_at IS NULL ORDER BY id LIMIT 1", Self::ENTRY_COLUMNS),
+            [],
+            Self::entry_from_row,
+        ).optional()?)
+    }
+
+    // Walk entries in id order, handing `f` at most `batch_size` of them at a time, so
+    // vault-wide passes never hold the whole vault. Each batch is a fresh keyset query,
+    // so `f` may rewrite the rows it was given. Returns how many entries were visited.
+    pub fn for_each_password_entry_batch(
+        &self,
+        batch_size: usize,
+        include_trash: bool,
+        mut f: impl FnMut(Vec<PasswordEntry>) -> Result<()>,
+    ) -> Result<usize> {
+        let sql = format!(
+            "SELECT {} FROM password_entries
+             WHERE id > ?1 AND (?2 OR deleted_at IS NULL)
+             ORDER BY id LIMIT ?3",
+            Self::ENTRY_COLUMNS
+        );
+
+        let mut last_id = 0i64;
+        let mut visited = 0;
+        loop {
+            // The connection is released while `f` runs, which usually writes the batch back
+            let batch = {
+                let connection = self.connection()?;
+                let mut stmt = connection.prepare_cached(&sql)?;
+                let batch = stmt
+                    .query_map(params![last_id, include_trash, batch_size.max(1) as i64], Self::entry_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                batch
+            };
+            let Some(last) = batch.last().and_then(|entry| entry.id) else {
+                return Ok(visited);
+            };
+            last_id = last;
+            visited += batch.len();
+            f(batch)?;
+        }
+    }
+
+    // Rewrite every entry, trash included, in one transaction. `f` gets the entries a batch
+    // at a time, attachments and custom fields loaded, and returns them as they should be
+    // stored. An error from `f`, or a panic in it, rolls the whole pass back, so no entry is
+    // ever left rewritten while others are not. The connection stays locked until the end.
+    // Returns how many entries were rewritten.
+    pub fn rewrite_all_password_entries(
+        &self,
+        batch_size: usize,
+        mut f: impl FnMut(Vec<PasswordEntry>) -> Result<Vec<PasswordEntry>>,
+    ) -> Result<usize> {
+        let sql = format!("SELECT {} FROM password_entries WHERE id > ?1 ORDER BY id LIMIT ?2", Self::ENTRY_COLUMNS);
+
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        let mut last_id = 0i64;
+        let mut rewritten = 0;
+        loop {
+            let mut batch = tx
+                .prepare_cached(&sql)?
+                .query_map(params![last_id, batch_size.max(1) as i64], Self::entry_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            let Some(last) = batch.last().and_then(|entry| entry.id) else {
+                break;
+            };
+            last_id = last;
+            for entry in &mut batch {
+                if let Some(id) = entry.id {
+                    entry.attachments = Self::read_attachments(&tx, id)?;
+                    entry.custom_fields = Self::read_custom_fields(&tx, id)?;
+                }
+            }
+            for entry in f(batch)? {
+                Self::rewrite_password_entry(&tx, &entry)?;
+                rewritten += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(rewritten)
+    }
+
+    // Every entry, trash included, whose ciphertexts could never be decrypted, by id; see
+    // entry_defect. Rows written before writes were checked, or by hand, can be like that.
+    pub fn find_corrupt_entries(&self) -> Result<Vec<CorruptEntry>> {
+        let mut corrupt = Vec::new();
+        self.for_each_password_entry_batch(CORRUPT_SCAN_BATCH_SIZE, true, |batch| {
+            corrupt.extend(batch.iter().filter_map(|entry| {
+                Some(CorruptEntry { id: entry.id?, defect: Self::entry_defect(entry)? })
+            }));
+            Ok(())
+        })?;
+        Ok(corrupt)
+    }
+
+    // What PRAGMA integrity_check reports wrong with the file; empty when it is sound
+    pub fn integrity_errors(&self) -> Result<Vec<String>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("PRAGMA integrity_check")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut errors = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        errors.retain(|row| row != "ok");
+        Ok(errors)
+    }
+
+    // Live entries whose URL host is `domain` or one of its subdomains. Hosts only hold
+    // letters, digits, '-' and '.', so the LIKE pattern needs no escaping.
+    pub fn get_password_entries_for_domain(&self, domain: &str) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries
+             WHERE deleted_at IS NULL AND (url_host = ?1 OR url_host LIKE '%.' || ?1)
+             ORDER BY id",
+            Self::ENTRY_COLUMNS
+        ))?;
+        let entries = stmt.query_map(params![domain], Self::entry_from_row)?;
+        Ok(entries.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    // Shared WHERE clause of the entry list and its count. Entries not scored yet never
+    // match a max_strength.
+    const ENTRY_FILTER: &'static str = "deleted_at IS NULL
+             AND (:tag IS NULL OR id IN (SELECT entry_tags.entry_id FROM entry_tags JOIN tags ON tags.id = entry_tags.tag_id WHERE tags.name = :tag))
+             AND (:software IS NULL OR software = :software COLLATE NOCASE)
+             AND (NOT :favorites_only OR is_favorite = 1)
+             AND (:max_strength IS NULL OR strength_score <= :max_strength)";
+
+    // One page of entries in the requested order, optionally narrowed by `filter`. Without
+    // `sort_by`, favorites come first and then insertion order; ties always fall back to
+    // insertion order. A `limit` of None returns everything from `offset` on.
+    pub fn get_password_entries_page(
+        &self,
+        filter: &EntryFilter,
+        sort_by: Option<EntrySortColumn>,
+        sort_dir: SortDirection,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        // The ORDER BY parts come from fixed strings, never from caller input
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries WHERE {}
+             ORDER BY {} {}, id {} LIMIT :limit OFFSET :offset",
+            Self::ENTRY_COLUMNS,
+            Self::ENTRY_FILTER,
+            sort_by.map_or("is_favorite DESC, id", EntrySortColumn::order_expression),
+            sort_dir.keyword(),
+            sort_dir.keyword(),
+        ))?;
+
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let entry_iter = stmt.query_map(
+            named_params! {
+                ":limit": limit,
+                ":offset": offset as i64,
+                ":tag": filter.tag,
+                ":software": filter.software,
+                ":favorites_only": filter.favorites_only,
+                ":max_strength": filter.max_strength,
+            },
+            Self::entry_from_row,
+        )?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    pub fn count_filtered_entries(&self, filter: &EntryFilter) -> Result<usize> {
+        let connection = self.connection()?;
+        let count: i64 = connection.query_row(
+            &format!("SELECT COUNT(*) FROM password_entries WHERE {}", Self::ENTRY_FILTER),
+            named_params! {
+                ":tag": filter.tag,
+                ":software": filter.software,
+                ":favorites_only": filter.favorites_only,
+                ":max_strength": filter.max_strength,
+            },
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    // Lookups by id only see entries outside the trash
+    pub fn get_password_entry_by_id(&self, id: i64) -> Result<Option<PasswordEntry>> {
+        let connection = self.connection()?;
+        let entry = connection.query_row(
+            &format!("SELECT {} FROM password_entries WHERE id = ?1 AND deleted_at IS NULL", Self::ENTRY_COLUMNS),
+            params![id],
+            Self::entry_from_row,
+        ).optional()?;
+        Ok(entry)
+    }
+
+    // None when there is no such entry; Some(None) when it has no URL with a host
+    pub fn get_entry_url_host(&self, id: i64) -> Result<Option<Option<String>>> {
+        let connection = self.connection()?;
+        let host = connection.query_row(
+            "SELECT url_host FROM password_entries WHERE id = ?1 AND deleted_at IS NULL",
+            params![id],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(host)
+    }
+
+    pub fn entry_exists(&self, id: i64) -> Result<bool> {
+        let connection = self.connection()?;
+        let exists: bool = connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM password_entries WHERE id = ?1 AND deleted_at IS NULL)",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    pub fn update_password_entry(&self, entry: &PasswordEntry) -> Result<()> {
+        let connection = self.connection()?;
+        Self::rewrite_password_entry(&connection, entry)
+    }
+
+    // Flip the favorite flag; returns the new state, or None if the entry doesn't exist
+    pub fn toggle_favorite(&self, id: i64, updated_at: &str) -> Result<Option<bool>> {
+        let connection = self.connection()?;
+        let is_favorite = connection.query_row(
+            "UPDATE password_entries SET is_favorite = NOT is_favorite, updated_at = ?2 WHERE id = ?1 AND deleted_at IS NULL RETURNING is_favorite",
+            params![id, updated_at],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(is_favorite)
+    }
+
+    // Set or clear the entry's rotation interval along with its expiry; false if the entry
+    // doesn't exist
+    pub fn set_rotation_days(&self, id: i64, rotation_days: Option<u32>, expires_at: Option<&str>, updated_at: &str) -> Result<bool> {
+        let connection = self.connection()?;
+        let updated = connection.execute(
+            "UPDATE password_entries SET rotation_days = ?2, expires_at = ?3, updated_at = ?4 WHERE id = ?1 AND deleted_at IS NULL",
+            params![id, rotation_days, expires_at, updated_at],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn touch_password_entry(&self, id: i64, used_at: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "UPDATE password_entries SET last_used_at = ?1 WHERE id = ?2",
+            params![used_at, id],
+        )?;
+        Ok(())
+    }
+
+    // Flag entries found in a breach check; returns how many entries exist to be flagged
+    pub fn flag_breached_entries(&self, ids: &[i64], flagged_at: &str) -> Result<usize> {
+        let connection = self.connection()?;
+        let mut flagged = 0;
+        for id in ids {
+            flagged += connection.execute(
+                "UPDATE password_entries SET breach_acknowledged_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+                params![flagged_at, id],
+            )?;
+        }
+        Ok(flagged)
+    }
+
+    pub fn clear_breach_flag(&self, id: i64) -> Result<bool> {
+        let connection = self.connection()?;
+        let cleared = connection.execute(
+            "UPDATE password_entries SET breach_acknowledged_at = NULL WHERE id = ?1 AND breach_acknowledged_at IS NOT NULL",
+            params![id],
+        )?;
+        Ok(cleared > 0)
+    }
+
+    // Entries flagged as breached whose password has not changed since, oldest flag first
+    pub fn get_breached_unrotated_entries(&self) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries
+             WHERE deleted_at IS NULL AND breach_acknowledged_at IS NOT NULL
+               AND (password_changed_at IS NULL OR password_changed_at <= breach_acknowledged_at)
+             ORDER BY breach_acknowledged_at, id",
+            Self::ENTRY_COLUMNS
+        ))?;
+
+        let entry_iter = stmt.query_map([], Self::entry_from_row)?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    // Tags
+    pub fn add_tag_to_entry(&self, entry_id: i64, name: &str) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        Self::write_entry_tag(&tx, entry_id, name)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Attach the tag to every live entry of `ids` in one transaction; returns those entries
+    pub fn add_tag_to_entries(&self, ids: &[i64], name: &str) -> Result<Vec<i64>> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        let mut tagged = Vec::new();
+        for id in ids {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM password_entries WHERE id = ?1 AND deleted_at IS NULL)",
+                params![id],
+                |row| row.get(0),
+            )?;
+            if exists {
+                Self::write_entry_tag(&tx, *id, name)?;
+                tagged.push(*id);
+            }
+        }
+        tx.commit()?;
+        Ok(tagged)
+    }
+
+    pub fn remove_tag_from_entry(&self, entry_id: i64, name: &str) -> Result<bool> {
+        let connection = self.connection()?;
+        let removed = connection.execute(
+            "DELETE FROM entry_tags WHERE entry_id = ?1 AND tag_id = (SELECT id FROM tags WHERE name = ?2)",
+            params![entry_id, name],
+        )?;
+        Ok(removed > 0)
+    }
+
+    // Every tag with the number of entries outside the trash carrying it, by name
+    pub fn get_vault_counts(&self) -> Result<VaultCounts> {
+        let connection = self.connection()?;
+        let counts = connection.query_row(
+            "SELECT COUNT(*) FILTER (WHERE deleted_at IS NULL),
+                    COUNT(*) FILTER (WHERE deleted_at IS NULL AND is_favorite = 1),
+                    COUNT(*) FILTER (WHERE deleted_at IS NOT NULL),
+                    MIN(created_at) FILTER (WHERE deleted_at IS NULL),
+                    MAX(created_at) FILTER (WHERE deleted_at IS NULL)
+             FROM password_entries",
+            [],
+            |row| {
+                Ok(VaultCounts {
+                    entry_count: row.get::<_, i64>(0)? as usize,
+                    favorite_count: row.get::<_, i64>(1)? as usize,
+                    trash_count: row.get::<_, i64>(2)? as usize,
+                    oldest_created_at: row.get(3)?,
+                    newest_created_at: row.get(4)?,
+                })
+            },
+        )?;
+        Ok(counts)
+    }
+
+    // How many live entries have each strength score, None for those not scored yet
+    pub fn count_entries_by_strength(&self) -> Result<Vec<(Option<u8>, usize)>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT strength_score, COUNT(*) FROM password_entries WHERE deleted_at IS NULL GROUP BY strength_score",
+        )?;
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?
+            .collect::<rusqlite::Result<Vec<(Option<u8>, usize)>>>()?;
+        Ok(counts)
+    }
+
+    // Software names of live entries with how many entries each has, most entries first.
+    // The bare software column takes its value from the row holding MAX(updated_at).
+    pub fn get_software_groups(&self) -> Result<Vec<SoftwareGroup>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT software, COUNT(*), MAX(updated_at) FROM password_entries
+             WHERE deleted_at IS NULL
+             GROUP BY software COLLATE NOCASE
+             ORDER BY COUNT(*) DESC, software COLLATE NOCASE",
+        )?;
+        let group_iter = stmt.query_map([], |row| {
+            Ok(SoftwareGroup {
+                software: row.get(0)?,
+                entry_count: row.get::<_, i64>(1)? as usize,
+                last_updated_at: row.get(2)?,
+            })
+        })?;
+
+        let mut groups = Vec::new();
+        for group in group_iter {
+            groups.push(group?);
+        }
+        Ok(groups)
+    }
+
+    pub fn list_tags(&self) -> Result<Vec<Tag>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT tags.name, COUNT(password_entries.id) FROM tags
+             LEFT JOIN entry_tags ON entry_tags.tag_id = tags.id
+             LEFT JOIN password_entries ON password_entries.id = entry_tags.entry_id AND password_entries.deleted_at IS NULL
+             GROUP BY tags.id
+             ORDER BY tags.name COLLATE NOCASE",
+        )?;
+        let tag_iter = stmt.query_map([], |row| {
+            Ok(Tag {
+                name: row.get(0)?,
+                entry_count: row.get::<_, i64>(1)? as usize,
+            })
+        })?;
+
+        let mut tags = Vec::new();
+        for tag in tag_iter {
+            tags.push(tag?);
+        }
+        Ok(tags)
+    }
+
+    // Delete a tag and detach it from every entry
+    pub fn delete_tag(&self, name: &str) -> Result<bool> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        tx.execute(
+            "DELETE FROM entry_tags WHERE tag_id = (SELECT id FROM tags WHERE name = ?1)",
+            params![name],
+        )?;
+        let removed = tx.execute("DELETE FROM tags WHERE name = ?1", params![name])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    pub fn get_entries_by_tag(&self, name: &str) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries
+             WHERE deleted_at IS NULL
+               AND id IN (SELECT entry_tags.entry_id FROM entry_tags JOIN tags ON tags.id = entry_tags.tag_id WHERE tags.name = ?1)
+             ORDER BY id",
+            Self::ENTRY_COLUMNS
+        ))?;
+
+        let entry_iter = stmt.query_map(params![name], Self::entry_from_row)?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    // Tag names per entry id, each list sorted by name
+    pub fn get_entry_tags(&self) -> Result<HashMap<i64, Vec<String>>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT entry_tags.entry_id, tags.name FROM entry_tags
+             JOIN tags ON tags.id = entry_tags.tag_id
+             ORDER BY tags.name COLLATE NOCASE",
+        )?;
+        let tag_iter = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for tag in tag_iter {
+            let (entry_id, name) = tag?;
+            tags.entry(entry_id).or_default().push(name);
+        }
+        Ok(tags)
+    }
+
+    pub fn get_tags_for_entry(&self, entry_id: i64) -> Result<Vec<String>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT tags.name FROM entry_tags
+             JOIN tags ON tags.id = entry_tags.tag_id
+             WHERE entry_tags.entry_id = ?1
+             ORDER BY tags.name COLLATE NOCASE",
+        )?;
+        let name_iter = stmt.query_map(params![entry_id], |row| row.get(0))?;
+
+        let mut names = Vec::new();
+        for name in name_iter {
+            names.push(name?);
+        }
+        Ok(names)
+    }
+
+    // Entry icons
+    pub fn set_entry_icon(&self, entry_id: i64, icon: &EntryIcon) -> Result<()> {
+        let connection = self.connection()?;
+        Self::write_entry_icon(&connection, entry_id, icon)
+    }
+
+    pub fn clear_entry_icon(&self, entry_id: i64) -> Result<bool> {
+        let connection = self.connection()?;
+        let removed = connection.execute("DELETE FROM entry_icons WHERE entry_id = ?1", params![entry_id])?;
+        Ok(removed > 0)
+    }
+
+    pub fn get_entry_icon(&self, entry_id: i64) -> Result<Option<EntryIcon>> {
+        let connection = self.connection()?;
+        let icon = connection.query_row(
+            "SELECT mime_type, data, content_hash FROM entry_icons WHERE entry_id = ?1",
+            params![entry_id],
+            |row| Ok(EntryIcon {
+                mime_type: row.get(0)?,
+                data: row.get(1)?,
+                content_hash: row.get(2)?,
+            }),
+        ).optional()?;
+        Ok(icon)
+    }
+
+    pub fn get_entry_icons(&self) -> Result<HashMap<i64, EntryIcon>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT entry_id, mime_type, data, content_hash FROM entry_icons")?;
+        let icon_iter = stmt.query_map([], |row| {
+            Ok((row.get(0)?, EntryIcon {
+                mime_type: row.get(1)?,
+                data: row.get(2)?,
+                content_hash: row.get(3)?,
+            }))
+        })?;
+
+        let mut icons = HashMap::new();
+        for icon in icon_iter {
+            let (entry_id, icon) = icon?;
+            icons.insert(entry_id, icon);
+        }
+        Ok(icons)
+    }
+
+    // Host icons
+    pub fn get_host_icon(&self, host: &str) -> Result<Option<HostIcon>> {
+        let connection = self.connection()?;
+        let cached = connection.query_row(
+            "SELECT mime_type, data, content_hash, fetched_at FROM host_icons WHERE host = ?1",
+            params![host],
+            |row| {
+                let icon = match (row.get::<_, Option<String>>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?) {
+                    (Some(mime_type), Some(data), Some(content_hash)) => Some(EntryIcon { mime_type, data, content_hash }),
+                    _ => None,
+                };
+                Ok(HostIcon { icon, fetched_at: row.get(3)? })
+            },
+        ).optional()?;
+        Ok(cached)
+    }
+
+    pub fn set_host_icon(&self, host: &str, cached: &HostIcon) -> Result<()> {
+        let connection = self.connection()?;
+        let icon = cached.icon.as_ref();
+        connection.execute(
+            "INSERT OR REPLACE INTO host_icons (host, mime_type, data, content_hash, fetched_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                host,
+                icon.map(|icon| &icon.mime_type),
+                icon.map(|icon| &icon.data),
+                icon.map(|icon| &icon.content_hash),
+                cached.fetched_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_host_icons(&self) -> Result<usize> {
+        let connection = self.connection()?;
+        Ok(connection.execute("DELETE FROM host_icons", [])?)
+    }
+
+    // Entry id -> the favicon cached for its host, for entries whose host has one
+    pub fn get_entry_host_icons(&self) -> Result<HashMap<i64, EntryIcon>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT e.id, h.mime_type, h.data, h.content_hash FROM password_entries e
+             JOIN host_icons h ON h.host = e.url_host
+             WHERE h.data IS NOT NULL",
+        )?;
+        let icon_iter = stmt.query_map([], |row| {
+            Ok((row.get(0)?, EntryIcon {
+                mime_type: row.get(1)?,
+                data: row.get(2)?,
+                content_hash: row.get(3)?,
+            }))
+        })?;
+
+        let mut icons = HashMap::new();
+        for icon in icon_iter {
+            let (entry_id, icon) = icon?;
+            icons.insert(entry_id, icon);
+        }
+        Ok(icons)
+    }
+
+    // Attachments
+    pub fn insert_attachment(&self, entry_id: i64, attachment: &Attachment) -> Result<i64> {
+        let connection = self.connection()?;
+        Self::write_attachment(&connection, entry_id, attachment)
+    }
+
+    // The attachment with the id of the entry it belongs to
+    pub fn get_attachment(&self, id: i64) -> Result<Option<(i64, Attachment)>> {
+        let connection = self.connection()?;
+        let attachment = connection.query_row(
+            "SELECT entry_id, id, filename, encrypted_blob, nonce, size, added_at FROM attachments WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, Self::attachment_from_row(row, 1)?)),
+        ).optional()?;
+        Ok(attachment)
+    }
+
+    // Every attachment of the entry, contents included, oldest first
+    pub fn get_attachments_for_entry(&self, entry_id: i64) -> Result<Vec<Attachment>> {
+        let connection = self.connection()?;
+        Self::read_attachments(&connection, entry_id)
+    }
+
+    fn read_attachments(connection: &Connection, entry_id: i64) -> Result<Vec<Attachment>> {
+        let mut stmt = connection.prepare_cached(
+            "SELECT id, filename, encrypted_blob, nonce, size, added_at FROM attachments WHERE entry_id = ?1 ORDER BY id",
+        )?;
+        let attachments = stmt.query_map(params![entry_id], |row| Self::attachment_from_row(row, 0))?;
+        Ok(attachments.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Every attachment by entry id, contents included; for exports
+    pub fn get_entry_attachments(&self) -> Result<HashMap<i64, Vec<Attachment>>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT entry_id, id, filename, encrypted_blob, nonce, size, added_at FROM attachments ORDER BY id",
+        )?;
+        let attachment_iter = stmt.query_map([], |row| Ok((row.get(0)?, Self::attachment_from_row(row, 1)?)))?;
+
+        let mut attachments: HashMap<i64, Vec<Attachment>> = HashMap::new();
+        for attachment in attachment_iter {
+            let (entry_id, attachment) = attachment?;
+            attachments.entry(entry_id).or_default().push(attachment);
+        }
+        Ok(attachments)
+    }
+
+    // The entry's attachments without their contents, oldest first
+    pub fn list_attachments(&self, entry_id: i64) -> Result<Vec<AttachmentInfo>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT id, entry_id, filename, size, added_at FROM attachments WHERE entry_id = ?1 ORDER BY id",
+        )?;
+        let attachments = stmt.query_map(params![entry_id], |row| {
+            Ok(AttachmentInfo {
+                id: row.get(0)?,
+                entry_id: row.get(1)?,
+                filename: row.get(2)?,
+                size: row.get(3)?,
+                added_at: row.get(4)?,
+            })
         })?;
+        Ok(attachments.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Returns the id of the entry the attachment belonged to, None if there was no such attachment
+    pub fn delete_attachment(&self, id: i64) -> Result<Option<i64>> {
+        let connection = self.connection()?;
+        let entry_id = connection
+            .query_row("DELETE FROM attachments WHERE id = ?1 RETURNING entry_id", params![id], |row| row.get(0))
+            .optional()?;
+        Ok(entry_id)
+    }
+
+    fn attachment_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Attachment> {
+        Ok(Attachment {
+            id: row.get(first)?,
+            filename: row.get(first + 1)?,
+            encrypted_blob: row.get(first + 2)?,
+            nonce: row.get(first + 3)?,
+            size: row.get(first + 4)?,
+            added_at: row.get(first + 5)?,
+        })
+    }
+
+    // Custom fields
+    pub fn insert_custom_field(&self, entry_id: i64, field: &CustomField) -> Result<i64> {
+        let connection = self.connection()?;
+        Self::write_custom_field(&connection, entry_id, field)
+    }
+
+    // Returns false if there is no such field
+    pub fn update_custom_field(&self, id: i64, field: &CustomField) -> Result<bool> {
+        let connection = self.connection()?;
+        let updated = connection.execute(
+            "UPDATE custom_fields SET label = ?1, encrypted_value = ?2, nonce = ?3, field_kind = ?4, position = ?5 WHERE id = ?6",
+            params![field.label, field.encrypted_value, field.nonce, field.field_kind.as_str(), field.position, id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    // The custom field with the id of the entry it belongs to
+    pub fn get_custom_field(&self, id: i64) -> Result<Option<(i64, CustomField)>> {
+        let connection = self.connection()?;
+        let field = connection.query_row(
+            "SELECT entry_id, id, label, encrypted_value, nonce, field_kind, position FROM custom_fields WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, Self::custom_field_from_row(row, 1)?)),
+        ).optional()?;
+        Ok(field)
+    }
+
+    // The entry's custom fields in display order
+    pub fn get_custom_fields_for_entry(&self, entry_id: i64) -> Result<Vec<CustomField>> {
+        let connection = self.connection()?;
+        Self::read_custom_fields(&connection, entry_id)
+    }
+
+    fn read_custom_fields(connection: &Connection, entry_id: i64) -> Result<Vec<CustomField>> {
+        let mut stmt = connection.prepare_cached(
+            "SELECT id, label, encrypted_value, nonce, field_kind, position FROM custom_fields WHERE entry_id = ?1 ORDER BY position, id",
+        )?;
+        let fields = stmt.query_map(params![entry_id], |row| Self::custom_field_from_row(row, 0))?;
+        Ok(fields.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Every custom field by entry id, in display order; for lists and exports
+    pub fn get_entry_custom_fields(&self) -> Result<HashMap<i64, Vec<CustomField>>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT entry_id, id, label, encrypted_value, nonce, field_kind, position FROM custom_fields ORDER BY position, id",
+        )?;
+        let field_iter = stmt.query_map([], |row| Ok((row.get(0)?, Self::custom_field_from_row(row, 1)?)))?;
+
+        let mut fields: HashMap<i64, Vec<CustomField>> = HashMap::new();
+        for field in field_iter {
+            let (entry_id, field) = field?;
+            fields.entry(entry_id).or_default().push(field);
+        }
+        Ok(fields)
+    }
+
+    // Returns the id of the entry the field belonged to, None if there was no such field
+    pub fn delete_custom_field(&self, id: i64) -> Result<Option<i64>> {
+        let connection = self.connection()?;
+        let entry_id = connection
+            .query_row("DELETE FROM custom_fields WHERE id = ?1 RETURNING entry_id", params![id], |row| row.get(0))
+            .optional()?;
+        Ok(entry_id)
+    }
+
+    fn custom_field_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<CustomField> {
+        Ok(CustomField {
+            id: row.get(first)?,
+            label: row.get(first + 1)?,
+            encrypted_value: row.get(first + 2)?,
+            nonce: row.get(first + 3)?,
+            field_kind: CustomFieldKind::from_db(&row.get::<_, String>(first + 4)?),
+            position: row.get(first + 5)?,
+        })
+    }
+
+    // Entries not revealed since `cutoff` (UTC RFC3339), never-used entries first, then oldest first
+    pub fn get_entries_unused_since(&self, cutoff: &str) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries
+             WHERE deleted_at IS NULL AND (last_used_at IS NULL OR last_used_at < ?1)
+             ORDER BY last_used_at, id",
+            Self::ENTRY_COLUMNS
+        ))?;
+
+        let entry_iter = stmt.query_map(params![cutoff], Self::entry_from_row)?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    // Trash
+    pub fn trash_password_entry(&self, id: i64, deleted_at: &str) -> Result<bool> {
+        let connection = self.connection()?;
+        let trashed = connection.execute(
+            "UPDATE password_entries SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+            params![id, deleted_at],
+        )?;
+        connection.execute("DELETE FROM entry_drafts WHERE slot = ?1", params![id])?;
+        Ok(trashed > 0)
+    }
+
+    // Trash every live entry of `ids` in one transaction; returns those that were trashed
+    pub fn trash_password_entries(&self, ids: &[i64], deleted_at: &str) -> Result<Vec<i64>> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        let mut trashed = Vec::new();
+        for id in ids {
+            if tx.execute("UPDATE password_entries SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL", params![id, deleted_at])? > 0 {
+                tx.execute("DELETE FROM entry_drafts WHERE slot = ?1", params![id])?;
+                trashed.push(*id);
+            }
+        }
+        tx.commit()?;
+        Ok(trashed)
+    }
+
+    pub fn restore_password_entry(&self, id: i64) -> Result<bool> {
+        let connection = self.connection()?;
+        let restored = connection.execute(
+            "UPDATE password_entries SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+            params![id],
+        )?;
+        Ok(restored > 0)
+    }
+
+    // Write `kept` (tags and icon are added to its own, see rewrite_password_entry) with its
+    // created_at and last_used_at, and move `remove_ids` to the trash, in one transaction.
+    // Nothing changes if any of them is not a live entry.
+    pub fn fold_password_entries(&self, kept: &PasswordEntry, remove_ids: &[i64], deleted_at: &str) -> Result<()> {
+        let id = kept.id.ok_or_else(|| anyhow!("Password entry ID is required for update"))?;
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        Self::rewrite_password_entry(&tx, kept)?;
+        tx.execute(
+            "UPDATE password_entries SET created_at = ?2, last_used_at = ?3 WHERE id = ?1",
+            params![id, kept.created_at, kept.last_used_at],
+        )?;
+        for remove_id in remove_ids {
+            let trashed = tx.execute(
+                "UPDATE password_entries SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+                params![remove_id, deleted_at],
+            )?;
+            if trashed == 0 {
+                return Err(anyhow!("Password entry {} not found", remove_id));
+            }
+            tx.execute("DELETE FROM entry_drafts WHERE slot = ?1", params![remove_id])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Trashed entries, most recently deleted first
+    pub fn get_trashed_entries(&self) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC",
+            Self::ENTRY_COLUMNS
+        ))?;
+
+        let entry_iter = stmt.query_map([], Self::entry_from_row)?;
+
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    // Permanently delete trashed entries deleted at or before `cutoff` (every trashed
+    // entry when None), with their icons and tag links; returns how many were deleted
+    pub fn purge_trashed_entries(&self, cutoff: Option<&str>) -> Result<usize> {
+        let connection = self.connection()?;
+        const PURGED: &str = "SELECT id FROM password_entries WHERE deleted_at IS NOT NULL AND (?1 IS NULL OR deleted_at <= ?1)";
+        let tx = write_transaction(&connection)?;
+        tx.execute(&format!("DELETE FROM entry_icons WHERE entry_id IN ({})", PURGED), params![cutoff])?;
+        tx.execute(&format!("DELETE FROM attachments WHERE entry_id IN ({})", PURGED), params![cutoff])?;
+        tx.execute(&format!("DELETE FROM custom_fields WHERE entry_id IN ({})", PURGED), params![cutoff])?;
+        tx.execute(&format!("DELETE FROM entry_tags WHERE entry_id IN ({})", PURGED), params![cutoff])?;
+        let purged = tx.execute(&format!("DELETE FROM password_entries WHERE id IN ({})", PURGED), params![cutoff])?;
+        tx.commit()?;
+        Ok(purged)
+    }
+
+    pub fn delete_password_entry(&self, id: i64) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute("DELETE FROM password_entries WHERE id = ?1", params![id])?;
+        connection.execute("DELETE FROM entry_icons WHERE entry_id = ?1", params![id])?;
+        connection.execute("DELETE FROM attachments WHERE entry_id = ?1", params![id])?;
+        connection.execute("DELETE FROM custom_fields WHERE entry_id = ?1", params![id])?;
+        connection.execute("DELETE FROM entry_tags WHERE entry_id = ?1", params![id])?;
+        connection.execute("DELETE FROM entry_drafts WHERE slot = ?1", params![id])?;
+        Ok(())
+    }
+
+    // Entry drafts
+    pub fn save_entry_draft(&self, draft: &EntryDraft) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO entry_drafts (slot, encrypted_payload, nonce, saved_at) VALUES (?1, ?2, ?3, ?4)",
+            params![draft.slot, draft.encrypted_payload, draft.nonce, draft.saved_at],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_entry_draft(&self, slot: i64) -> Result<Option<EntryDraft>> {
+        let connection = self.connection()?;
+        let draft = connection.query_row(
+            "SELECT slot, encrypted_payload, nonce, saved_at FROM entry_drafts WHERE slot = ?1",
+            params![slot],
+            |row| Ok(EntryDraft {
+                slot: row.get(0)?,
+                encrypted_payload: row.get(1)?,
+                nonce: row.get(2)?,
+                saved_at: row.get(3)?,
+            }),
+        ).optional()?;
+        Ok(draft)
+    }
+
+    pub fn delete_entry_draft(&self, slot: i64) -> Result<bool> {
+        let connection = self.connection()?;
+        Ok(connection.execute("DELETE FROM entry_drafts WHERE slot = ?1", params![slot])? > 0)
+    }
+
+    // Drop drafts last saved at or before `cutoff` (UTC RFC3339); returns how many were dropped
+    pub fn purge_entry_drafts_saved_before(&self, cutoff: &str) -> Result<usize> {
+        let connection = self.connection()?;
+        Ok(connection.execute("DELETE FROM entry_drafts WHERE saved_at <= ?1", params![cutoff])?)
+    }
+
+    pub fn count_password_entries(&self) -> Result<usize> {
+        let connection = self.connection()?;
+        let count: i64 = connection.query_row("SELECT COUNT(*) FROM password_entries WHERE deleted_at IS NULL", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn count_password_entries_including_trash(&self) -> Result<usize> {
+        let connection = self.connection()?;
+        let count: i64 = connection.query_row("SELECT COUNT(*) FROM password_entries", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    // Replace the user meta and drop every entry in one transaction
+    pub fn reset_user_meta_and_wipe_entries(&self, user_meta: &UserMeta) -> Result<usize> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        let wiped = tx.execute("DELETE FROM password_entries", [])?;
+        tx.execute("DELETE FROM entry_icons", [])?;
+        tx.execute("DELETE FROM host_icons", [])?;
+        tx.execute("DELETE FROM attachments", [])?;
+        tx.execute("DELETE FROM custom_fields", [])?;
+        tx.execute("DELETE FROM entry_tags", [])?;
+        tx.execute("DELETE FROM entry_drafts", [])?;
+        // The audit log is sealed under the discarded vault key, so it starts over
+        tx.execute("DELETE FROM audit_log", [])?;
+        tx.execute("DELETE FROM audit_segments", [])?;
+        tx.execute("DELETE FROM settings WHERE key = ?1", params![AUDIT_LOG_HEAD_KEY])?;
+        Self::write_user_meta(&tx, user_meta)?;
+        tx.commit()?;
+        Ok(wiped)
+    }
+
+    // Store re-encrypted entries together with the user meta holding the new vault key
+    pub fn migrate_to_vault_key(&self, user_meta: &UserMeta, entries: &[PasswordEntry]) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        for entry in entries {
+            Self::rewrite_password_entry(&tx, entry)?;
+        }
+        Self::write_user_meta(&tx, user_meta)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Overwrite several entries (matched by id) in one transaction
+    pub fn update_password_entries(&self, entries: &[PasswordEntry]) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        for entry in entries {
+            Self::rewrite_password_entry(&tx, entry)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Entries whose expiry falls before `cutoff` (UTC RFC3339), soonest first
+    pub fn get_entries_expiring_before(&self, cutoff: &str) -> Result<Vec<PasswordEntry>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {} FROM password_entries
+             WHERE deleted_at IS NULL AND expires_at IS NOT NULL AND expires_at <= ?1
+             ORDER BY expires_at",
+            Self::ENTRY_COLUMNS
+        ))?;
+
+        let entry_iter = stmt.query_map(params![cutoff], Self::entry_from_row)?;
 
         let mut entries = Vec::new();
         for entry in entry_iter {
@@ -229,57 +2706,420 @@ impl Database {
         Ok(entries)
     }
 
-    // Export all data
-    pub fn export_all_data(&self) -> Result<ExportData> {
+    // Settings operations
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let connection = self.connection()?;
+        let value = connection.query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(value)
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_setting(&self, key: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute("DELETE FROM settings WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    // Replace all password entries, leaving user meta untouched
+    pub fn replace_password_entries(&self, entries: &[PasswordEntry]) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        tx.execute("DELETE FROM password_entries", [])?;
+        tx.execute("DELETE FROM entry_icons", [])?;
+        tx.execute("DELETE FROM attachments", [])?;
+        tx.execute("DELETE FROM custom_fields", [])?;
+        tx.execute("DELETE FROM entry_tags", [])?;
+        tx.execute("DELETE FROM entry_drafts", [])?;
+        for entry in entries {
+            Self::write_password_entry(&tx, entry)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Insert new entries and overwrite existing ones (matched by id) in one transaction
+    pub fn merge_password_entries(&self, inserts: &[PasswordEntry], updates: &[PasswordEntry]) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        for entry in inserts {
+            Self::write_password_entry(&tx, entry)?;
+        }
+        for entry in updates {
+            Self::rewrite_password_entry(&tx, entry)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Password policies
+    fn write_password_policy(connection: &Connection, policy: &PasswordPolicy) -> Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO password_policies (tag, min_entropy_bits, mode) VALUES (?1, ?2, ?3)",
+            params![policy.tag, policy.min_entropy_bits, policy.mode.as_str()],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_password_policies(&self) -> Result<Vec<PasswordPolicy>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT tag, min_entropy_bits, mode FROM password_policies ORDER BY tag COLLATE NOCASE",
+        )?;
+        let policies = stmt.query_map([], |row| Ok(PasswordPolicy {
+            tag: row.get(0)?,
+            min_entropy_bits: row.get(1)?,
+            mode: PolicyMode::from_db(&row.get::<_, String>(2)?),
+        }))?;
+        Ok(policies.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    // Insert or replace the policy for its tag
+    pub fn set_password_policy(&self, policy: &PasswordPolicy) -> Result<()> {
+        let connection = self.connection()?;
+        Self::write_password_policy(&connection, policy)
+    }
+
+    pub fn delete_password_policy(&self, tag: &str) -> Result<bool> {
+        let connection = self.connection()?;
+        Ok(connection.execute("DELETE FROM password_policies WHERE tag = ?1", params![tag])? > 0)
+    }
+
+    // Add policies for tags that have none yet, keeping the existing ones
+    pub fn merge_password_policies(&self, policies: &[PasswordPolicy]) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        for policy in policies {
+            tx.execute(
+                "INSERT OR IGNORE INTO password_policies (tag, min_entropy_bits, mode) VALUES (?1, ?2, ?3)",
+                params![policy.tag, policy.min_entropy_bits, policy.mode.as_str()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Audit trail. Use audit_log::record rather than calling these directly.
+    pub fn append_audit_event(&self, action: &str, entry_id: Option<i64>, detail: Option<&str>) -> Result<i64> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT INTO audit_log (occurred_at, action, entry_id, detail) VALUES (?1, ?2, ?3, ?4)",
+            params![time_utils::now_rfc3339(), action, entry_id, detail],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    fn query_audit_events(&self, filter: &str, params: impl rusqlite::Params) -> Result<Vec<AuditEvent>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(&format!(
+            "SELECT id, occurred_at, action, entry_id, detail, detail_nonce, mac, segment_id, source_id FROM audit_log {}",
+            filter
+        ))?;
+        let events = stmt.query_map(params, |row| Ok(AuditEvent {
+            id: row.get(0)?,
+            occurred_at: row.get(1)?,
+            action: row.get(2)?,
+            entry_id: row.get(3)?,
+            detail: row.get(4)?,
+            detail_nonce: row.get(5)?,
+            mac: row.get(6)?,
+            segment_id: row.get(7)?,
+            source_id: row.get(8)?,
+        }))?;
+        Ok(events.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    // Sealed rows of the local chain in chain order
+    pub fn get_sealed_audit_events(&self) -> Result<Vec<AuditEvent>> {
+        self.query_audit_events("WHERE mac IS NOT NULL AND segment_id IS NULL ORDER BY id", [])
+    }
+
+    pub fn get_unsealed_audit_events(&self) -> Result<Vec<AuditEvent>> {
+        self.query_audit_events("WHERE mac IS NULL AND segment_id IS NULL ORDER BY id", [])
+    }
+
+    // Rows of imported segments, grouped by segment and in chain order within each
+    pub fn get_imported_audit_events(&self) -> Result<Vec<AuditEvent>> {
+        self.query_audit_events("WHERE segment_id IS NOT NULL ORDER BY segment_id, id", [])
+    }
+
+    // Every row, oldest first
+    pub fn get_all_audit_events(&self) -> Result<Vec<AuditEvent>> {
+        self.query_audit_events("ORDER BY id", [])
+    }
+
+    pub fn get_audit_segments(&self) -> Result<Vec<AuditSegment>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT id, tag, imported_at, head FROM audit_segments ORDER BY id")?;
+        let segments = stmt.query_map([], |row| Ok(AuditSegment {
+            id: row.get(0)?,
+            tag: row.get(1)?,
+            imported_at: row.get(2)?,
+            head: row.get(3)?,
+        }))?;
+        Ok(segments.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    // Append an imported segment and its already sealed rows in one transaction; returns
+    // the segment id. The rows' own ids and segment_id are assigned here.
+    pub fn insert_audit_segment(&self, tag: &str, head: &str, events: &[AuditEvent]) -> Result<i64> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        tx.execute(
+            "INSERT INTO audit_segments (tag, imported_at, head) VALUES (?1, ?2, ?3)",
+            params![tag, time_utils::now_rfc3339(), head],
+        )?;
+        let segment_id = tx.last_insert_rowid();
+        for event in events {
+            tx.execute(
+                "INSERT INTO audit_log (occurred_at, action, entry_id, detail, detail_nonce, mac, segment_id, source_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![event.occurred_at, event.action, event.entry_id, event.detail, event.detail_nonce, event.mac, segment_id, event.source_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(segment_id)
+    }
+
+    // Sealed rows and chain heads for an export. Pending rows are left out until sealed.
+    pub fn export_audit_log(&self) -> Result<AuditLogExport> {
+        let mut events = self.get_sealed_audit_events()?;
+        events.extend(self.get_imported_audit_events()?);
+        Ok(AuditLogExport {
+            head: self.get_setting(AUDIT_LOG_HEAD_KEY)?,
+            events,
+            segments: self.get_audit_segments()?,
+        })
+    }
+
+    // Restore an exported log as it was, ids included, so its chains still verify under
+    // the vault key restored alongside it
+    fn write_audit_log(connection: &Connection, audit_log: &AuditLogExport) -> Result<()> {
+        for segment in &audit_log.segments {
+            connection.execute(
+                "INSERT INTO audit_segments (id, tag, imported_at, head) VALUES (?1, ?2, ?3, ?4)",
+                params![segment.id, segment.tag, segment.imported_at, segment.head],
+            )?;
+        }
+        for event in &audit_log.events {
+            connection.execute(
+                "INSERT INTO audit_log (id, occurred_at, action, entry_id, detail, detail_nonce, mac, segment_id, source_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    event.id,
+                    event.occurred_at,
+                    event.action,
+                    event.entry_id,
+                    event.detail,
+                    event.detail_nonce,
+                    event.mac,
+                    event.segment_id,
+                    event.source_id
+                ],
+            )?;
+        }
+        if let Some(head) = &audit_log.head {
+            connection.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![AUDIT_LOG_HEAD_KEY, head],
+            )?;
+        }
+        Ok(())
+    }
+
+    // Newest first
+    pub fn get_audit_events_page(&self, offset: usize, limit: usize) -> Result<Vec<AuditEvent>> {
+        self.query_audit_events("ORDER BY id DESC LIMIT ?1 OFFSET ?2", params![limit as i64, offset as i64])
+    }
+
+    // Store sealed rows and the new chain head in one transaction
+    pub fn seal_audit_events(&self, events: &[AuditEvent], head: &str) -> Result<()> {
+        let connection = self.connection()?;
+        let tx = write_transaction(&connection)?;
+        for event in events {
+            tx.execute(
+                "UPDATE audit_log SET detail = ?1, detail_nonce = ?2, mac = ?3 WHERE id = ?4",
+                params![event.detail, event.detail_nonce, event.mac, event.id],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![AUDIT_LOG_HEAD_KEY, head],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Data migration flags
+    pub fn is_migration_completed(&self, id: &str) -> Result<bool> {
+        let connection = self.connection()?;
+        let count: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM data_migrations WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    pub fn mark_migration_completed(&self, id: &str) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO data_migrations (id, completed_at) VALUES (?1, ?2)",
+            params![id, time_utils::now_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Stats history operations
+    fn write_stats_snapshot(connection: &Connection, snapshot: &StatsSnapshot) -> Result<()> {
+        let recorded_on = time_utils::utc_date(&time_utils::parse_rfc3339(&snapshot.recorded_at)?);
+        connection.execute(
+            "INSERT OR REPLACE INTO stats_history (
+                recorded_on, recorded_at, entry_count, weak_count, reused_count,
+                breached_count, average_password_age_days
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                recorded_on,
+                snapshot.recorded_at,
+                snapshot.entry_count,
+                snapshot.weak_count,
+                snapshot.reused_count,
+                snapshot.breached_count,
+                snapshot.average_password_age_days
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Record a snapshot, replacing any earlier snapshot from the same UTC day
+    pub fn record_stats_snapshot(&self, snapshot: &StatsSnapshot) -> Result<()> {
+        let connection = self.connection()?;
+        Self::write_stats_snapshot(&connection, snapshot)
+    }
+
+    // Snapshots recorded at or after `since` (all of them when None), oldest first
+    pub fn get_stats_history(&self, since: Option<&str>) -> Result<Vec<StatsSnapshot>> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT recorded_at, entry_count, weak_count, reused_count,
+                    breached_count, average_password_age_days
+             FROM stats_history
+             WHERE ?1 IS NULL OR recorded_at >= ?1
+             ORDER BY recorded_on ASC"
+        )?;
+
+        let snapshot_iter = stmt.query_map(params![since], |row| {
+            Ok(StatsSnapshot {
+                recorded_at: row.get(0)?,
+                entry_count: row.get(1)?,
+                weak_count: row.get(2)?,
+                reused_count: row.get(3)?,
+                breached_count: row.get(4)?,
+                average_password_age_days: row.get(5)?,
+            })
+        })?;
+
+        let mut snapshots = Vec::new();
+        for snapshot in snapshot_iter {
+            snapshots.push(snapshot?);
+        }
+        Ok(snapshots)
+    }
+
+    // Delete snapshots recorded before `cutoff`
+    pub fn prune_stats_history(&self, cutoff: &str) -> Result<usize> {
+        let connection = self.connection()?;
+        let pruned = connection.execute(
+            "DELETE FROM stats_history WHERE recorded_at < ?1",
+            params![cutoff],
+        )?;
+        Ok(pruned)
+    }
+
+    // Export all data, trashed entries only when asked for
+    pub fn export_all_data(&self, include_trash: bool) -> Result<ExportData> {
         let user_meta = self.get_user_meta()?
             .ok_or_else(|| anyhow!("No user data found"))?;
-        let password_entries = self.get_all_password_entries()?;
+        let mut password_entries = if include_trash {
+            self.get_all_password_entries_including_trash()?
+        } else {
+            self.get_all_password_entries()?
+        };
+        let mut tags = self.get_entry_tags()?;
+        let mut custom_fields = self.get_entry_custom_fields()?;
+        for entry in &mut password_entries {
+            entry.tags = entry.id.and_then(|id| tags.remove(&id)).unwrap_or_default();
+            entry.custom_fields = entry.id.and_then(|id| custom_fields.remove(&id)).unwrap_or_default();
+        }
 
         Ok(ExportData {
-            user_meta,
+            user_meta: Some(user_meta),
             password_entries,
+            stats_history: None,
+            password_policies: self.get_password_policies()?,
+            audit_log: None,
+            metadata_encrypted: false, // Filled in from the vault settings by ExportService
+            transfer_key: None,
         })
     }
 
     // Import all data (replaces existing data)
     pub fn import_all_data(&self, data: &ExportData) -> Result<()> {
+        let user_meta = data.user_meta.as_ref().ok_or_else(|| anyhow!("The export has no user data to restore"))?;
+        let connection = self.connection()?;
         // Start transaction
-        let tx = self.connection.unchecked_transaction()?;
+        let tx = write_transaction(&connection)?;
+
+        // Sessions opened on the replaced vault must not carry over
+        let generation: i64 = tx.query_row("SELECT COALESCE(MAX(generation), 0) FROM user_meta", [], |row| row.get(0))?;
 
         // Clear existing data
         tx.execute("DELETE FROM user_meta", [])?;
         tx.execute("DELETE FROM password_entries", [])?;
+        tx.execute("DELETE FROM entry_icons", [])?;
+        tx.execute("DELETE FROM attachments", [])?;
+        tx.execute("DELETE FROM custom_fields", [])?;
+        tx.execute("DELETE FROM entry_tags", [])?;
+        tx.execute("DELETE FROM entry_drafts", [])?;
+        // The audit log is sealed under the replaced vault key, so it starts over, from
+        // the backup's own log when the export carries it
+        tx.execute("DELETE FROM audit_log", [])?;
+        tx.execute("DELETE FROM audit_segments", [])?;
+        tx.execute("DELETE FROM settings WHERE key = ?1", params![AUDIT_LOG_HEAD_KEY])?;
+        if let Some(audit_log) = &data.audit_log {
+            Self::write_audit_log(&tx, audit_log)?;
+        }
 
         // Insert user meta
-        tx.execute(
-            "INSERT INTO user_meta (
-                id, master_hash, master_salt, 
-                question1, answer1_hash, answer_salt1,
-                question2, answer2_hash, answer_salt2,
-                question3, answer3_hash, answer_salt3
-            ) VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-            params![
-                data.user_meta.master_hash,
-                data.user_meta.master_salt,
-                data.user_meta.question1,
-                data.user_meta.answer1_hash,
-                data.user_meta.answer_salt1,
-                data.user_meta.question2,
-                data.user_meta.answer2_hash,
-                data.user_meta.answer_salt2,
-                data.user_meta.question3,
-                data.user_meta.answer3_hash,
-                data.user_meta.answer_salt3
-            ],
-        )?;
+        Self::write_user_meta(&tx, &UserMeta { generation: generation + 1, ..user_meta.clone() })?;
 
         // Insert password entries
         for entry in &data.password_entries {
-            tx.execute(
-                "INSERT INTO password_entries (software, account, encrypted_password, nonce, notes)
-                 VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![entry.software, entry.account, entry.encrypted_password, entry.nonce, entry.notes],
-            )?;
+            Self::write_password_entry(&tx, entry)?;
+        }
+
+        tx.execute("DELETE FROM password_policies", [])?;
+        for policy in &data.password_policies {
+            Self::write_password_policy(&tx, policy)?;
+        }
+
+        // Restore stats history when the export carries it
+        if let Some(stats_history) = &data.stats_history {
+            tx.execute("DELETE FROM stats_history", [])?;
+            for snapshot in stats_history {
+                Self::write_stats_snapshot(&tx, snapshot)?;
+            }
         }
 
         tx.commit()?;
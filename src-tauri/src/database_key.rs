@@ -0,0 +1,62 @@
+use crate::crypto::{CryptoService, SecretKey};
+use crate::data_dir;
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// Sits next to pwdbox.db when the database file is SQLCipher-encrypted and holds its key,
+// since the key is needed before anyone logs in (setup state, security questions, login
+// throttling all live in the database). So the database file on its own, e.g. copied out
+// by a sync tool or a backup of just that file, reads as random bytes, while anyone who can
+// read the whole app data directory can still open it. Entry secrets stay encrypted under
+// the vault key either way.
+pub const MARKER_FILE: &str = "pwdbox.db.key";
+
+const MARKER_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Marker {
+    version: u32,
+    cipher: String,
+    key: String, // Base64
+}
+
+// The database key, or None while the database file is plaintext
+pub fn read(app_data_dir: &Path) -> Result<Option<SecretKey>> {
+    let path = app_data_dir.join(MARKER_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let marker: Marker = serde_json::from_str(&Zeroizing::new(fs::read_to_string(&path)?))?;
+    if marker.version != MARKER_VERSION {
+        return Err(anyhow!("Unsupported database key file version {}", marker.version));
+    }
+    let key = CryptoService::decode_key(&marker.key);
+    CryptoService::clear_sensitive_string(marker.key);
+    Ok(Some(key?))
+}
+
+// Replace the key file atomically, readable by the owner only where the platform allows
+pub fn write(app_data_dir: &Path, key: &[u8; 32]) -> Result<()> {
+    let marker = Marker {
+        version: MARKER_VERSION,
+        cipher: "sqlcipher".to_string(),
+        key: general_purpose::STANDARD.encode(key),
+    };
+    let contents = Zeroizing::new(serde_json::to_string(&marker)?);
+    CryptoService::clear_sensitive_string(marker.key);
+
+    data_dir::write_atomically(&app_data_dir.join(MARKER_FILE), |file| file.write_all(contents.as_bytes()))?;
+    Ok(())
+}
+
+pub fn remove(app_data_dir: &Path) -> Result<()> {
+    match fs::remove_file(app_data_dir.join(MARKER_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
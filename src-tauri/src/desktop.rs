@@ -0,0 +1,144 @@
+use crate::{lock_now, AppState};
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::image::Image;
+use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+
+// Desktop-only ways to lock the vault without the main window: a global shortcut and a
+// tray icon. Both go through the same lock path as the lock_vault command, and the tray
+// follows the lock state through the session manager.
+const TRAY_ID: &str = "main";
+const MENU_LOCK: &str = "lock";
+const MENU_UNLOCK: &str = "unlock";
+const MENU_QUIT: &str = "quit";
+
+// The lock shortcut as registered at startup or by set_lock_shortcut. Registration fails
+// when the accelerator does not parse or another application holds it; the app then runs
+// without the shortcut and the settings screen shows the error.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LockShortcutStatus {
+    pub accelerator: String, // Empty when turned off
+    pub registered: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct LockShortcut(Mutex<LockShortcutStatus>);
+
+// Called from setup. Failures to register the shortcut are recorded, never returned.
+pub fn init(app: &AppHandle) -> tauri::Result<()> {
+    app.plugin(
+        tauri_plugin_global_shortcut::Builder::new()
+            .with_handler(|app, _shortcut, event| {
+                if event.state() == ShortcutState::Pressed {
+                    lock_now(app);
+                }
+            })
+            .build(),
+    )?;
+
+    let accelerator = app
+        .state::<AppState>()
+        .vault
+        .settings(|settings_service| settings_service.get_lock_shortcut())
+        .unwrap_or_default();
+    app.manage(LockShortcut(Mutex::new(register(app, &accelerator))));
+
+    init_tray(app)
+}
+
+// Bring the window forward from the tray, a minimized state or behind other windows
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+pub fn lock_shortcut_status(app: &AppHandle) -> LockShortcutStatus {
+    let shortcut = app.state::<LockShortcut>();
+    let status = shortcut.0.lock();
+    status.map(|status| status.clone()).unwrap_or_default()
+}
+
+// Swap the registered shortcut for `accelerator`, already saved in settings
+pub fn replace_lock_shortcut(app: &AppHandle, accelerator: &str) -> LockShortcutStatus {
+    let shortcut = app.state::<LockShortcut>();
+    let Ok(mut status) = shortcut.0.lock() else {
+        return LockShortcutStatus::default();
+    };
+    if status.registered {
+        if let Err(e) = app.global_shortcut().unregister(status.accelerator.as_str()) {
+            log::warn!("Failed to unregister the lock shortcut {}: {}", status.accelerator, e);
+        }
+    }
+    *status = register(app, accelerator);
+    status.clone()
+}
+
+fn register(app: &AppHandle, accelerator: &str) -> LockShortcutStatus {
+    if accelerator.is_empty() {
+        return LockShortcutStatus::default();
+    }
+    match app.global_shortcut().register(accelerator) {
+        Ok(()) => LockShortcutStatus { accelerator: accelerator.to_string(), registered: true, error: None },
+        Err(e) => {
+            log::warn!("Could not register the lock shortcut {}: {}", accelerator, e);
+            LockShortcutStatus {
+                accelerator: accelerator.to_string(),
+                registered: false,
+                error: Some(format!("Could not register {}: {}", accelerator, e)),
+            }
+        }
+    }
+}
+
+// Lock, Unlock (which brings the window forward for the login screen) and Quit. The icon
+// is the app icon while unlocked and a grayscale copy of it while locked.
+fn init_tray(app: &AppHandle) -> tauri::Result<()> {
+    let lock = MenuItem::with_id(app, MENU_LOCK, "Lock", false, None::<&str>)?;
+    let unlock = MenuItem::with_id(app, MENU_UNLOCK, "Unlock", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, MENU_QUIT, "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&lock, &unlock, &PredefinedMenuItem::separator(app)?, &quit])?;
+
+    let unlocked_icon = app.default_window_icon().cloned().map(Image::to_owned);
+    let locked_icon = unlocked_icon.as_ref().map(grayscale);
+    let mut tray = TrayIconBuilder::with_id(TRAY_ID)
+        .menu(&menu)
+        .tooltip("PwdBox (locked)")
+        .on_menu_event(|app, event| match event.id().as_ref() {
+            MENU_LOCK => lock_now(app),
+            MENU_UNLOCK => show_main_window(app),
+            MENU_QUIT => app.exit(0),
+            _ => {}
+        });
+    if let Some(icon) = locked_icon.clone() {
+        tray = tray.icon(icon);
+    }
+    tray.build(app)?;
+
+    let handle = app.clone();
+    app.state::<AppState>().session.set_on_change(move |unlocked| {
+        let _ = lock.set_enabled(unlocked);
+        let _ = unlock.set_enabled(!unlocked);
+        if let Some(tray) = handle.tray_by_id(TRAY_ID) {
+            let icon = if unlocked { unlocked_icon.clone() } else { locked_icon.clone() };
+            let _ = tray.set_icon(icon);
+            let _ = tray.set_tooltip(Some(if unlocked { "PwdBox (unlocked)" } else { "PwdBox (locked)" }));
+        }
+    });
+    Ok(())
+}
+
+fn grayscale(icon: &Image<'static>) -> Image<'static> {
+    let mut rgba = icon.rgba().to_vec();
+    for pixel in rgba.chunks_exact_mut(4) {
+        let luma = (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+        pixel[..3].fill(luma as u8);
+    }
+    Image::new_owned(rgba, icon.width(), icon.height())
+}
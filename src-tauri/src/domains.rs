@@ -0,0 +1,59 @@
+// Helpers for deriving service domains from free-text entry fields
+
+// Second-level labels that are registries rather than registrable names under common
+// country TLDs (e.g. "co.uk"). Without the full public suffix list this covers the
+// cases users actually hit; anything else falls back to the last two labels.
+const MULTI_PART_SUFFIXES: &[&str] = &["co", "com", "net", "org", "gov", "edu", "ac", "or", "ne", "go"];
+
+// Extract a lowercase host from a URL or bare host name ("https://Mail.Google.com/x" -> "mail.google.com")
+pub fn host_from(value: &str) -> Option<String> {
+    let value = value.trim().to_lowercase();
+    let without_scheme = value.split_once("://").map_or(value.as_str(), |(_, rest)| rest);
+    let authority = without_scheme.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?.trim_end_matches('.');
+
+    let labels: Vec<&str> = host.split('.').collect();
+    let valid = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels.last().is_some_and(|tld| tld.chars().all(|c| c.is_ascii_alphabetic()));
+    valid.then(|| host.to_string())
+}
+
+// Registrable domain of a host ("mail.google.com" -> "google.com", "www.bbc.co.uk" -> "bbc.co.uk")
+pub fn registrable_domain(host: &str) -> String {
+    let labels: Vec<&str> = host.split('.').collect();
+    let keep = match labels.as_slice() {
+        [.., second, tld] if tld.len() == 2 && MULTI_PART_SUFFIXES.contains(second) => 3,
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(keep)..].join(".")
+}
+
+// Key entries are grouped under: the registrable domain when the field holds a host or URL,
+// otherwise the software name lowercased with whitespace collapsed
+pub fn group_key(value: &str) -> String {
+    match host_from(value) {
+        Some(host) => registrable_domain(&host),
+        None => value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_keys() {
+        assert_eq!(group_key("https://Mail.Google.com/inbox?x=1"), "google.com");
+        assert_eq!(group_key("accounts.google.com"), "google.com");
+        assert_eq!(group_key("user@login.example.org:8443"), "example.org");
+        assert_eq!(group_key("www.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(group_key("shop.example.de"), "example.de");
+        assert_eq!(group_key("  Google   Mail "), "google mail");
+        assert_eq!(group_key("Version 2.0"), "version 2.0");
+        assert_eq!(group_key("192.168.0.1"), "192.168.0.1");
+    }
+}
@@ -0,0 +1,118 @@
+use crate::crypto::{CryptoService, KdfParams, EXPORT_FORMAT_VERSION};
+use crate::database::{EntryType, SecretKind};
+use crate::error::PwdBoxError;
+use crate::time_utils;
+use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+// One entry sent to someone else: its fields in the clear, sealed under a passphrase agreed
+// with them in the v2 export container, then base64 encoded so it travels as a single line
+// of text. Only the entry goes in, never anything of the vault it came from.
+const SHARE_FORMAT: &str = "pwdbox-shared-entry";
+
+const NOT_A_SHARE: &str = "This is not a shared PwdBox entry";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedEntry {
+    format: String, // SHARE_FORMAT, so a full export is never taken for a shared entry
+    pub created_at: String,
+    pub expires_at: Option<String>, // open refuses the blob from then on
+    pub software: String,
+    pub account: String,
+    pub password: String,
+    pub notes: Option<String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub entry_type: EntryType, // Blobs from before entry types open as logins
+    #[serde(default)]
+    pub secret_kind: SecretKind,
+}
+
+impl SharedEntry {
+    pub fn new(created_at: String, expires_at: Option<String>) -> Self {
+        SharedEntry {
+            format: SHARE_FORMAT.to_string(),
+            created_at,
+            expires_at,
+            software: String::new(),
+            account: String::new(),
+            password: String::new(),
+            notes: None,
+            url: None,
+            tags: Vec::new(),
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+        }
+    }
+}
+
+impl Drop for SharedEntry {
+    fn drop(&mut self) {
+        self.password.zeroize();
+        self.notes.zeroize();
+    }
+}
+
+pub fn seal(entry: &SharedEntry, passphrase: &str, params: &KdfParams) -> Result<String> {
+    let json = Zeroizing::new(serde_json::to_string(entry)?);
+    let container = CryptoService::encrypt_export_data_v2(&json, passphrase, params)?;
+    Ok(general_purpose::STANDARD.encode(container))
+}
+
+// A wrong passphrase fails like it does for an export file; an expired blob is refused
+// after decrypting, since its expiry is inside the sealed payload
+pub fn open(blob: &str, passphrase: &str, now: DateTime<Utc>) -> Result<SharedEntry> {
+    let container = general_purpose::STANDARD
+        .decode(blob.trim())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| PwdBoxError::invalid_field("blob", NOT_A_SHARE))?;
+    let envelope = CryptoService::parse_export_data(container)?;
+    if envelope.format_version != EXPORT_FORMAT_VERSION {
+        return Err(PwdBoxError::invalid_field("blob", NOT_A_SHARE).into());
+    }
+    let key = CryptoService::derive_key_from_password(passphrase, &envelope.salt, &envelope.kdf_params)?;
+    let json = Zeroizing::new(envelope.open(&key)?);
+    let entry: SharedEntry = serde_json::from_str(&json).map_err(|_| PwdBoxError::invalid_field("blob", NOT_A_SHARE))?;
+    if entry.format != SHARE_FORMAT {
+        return Err(PwdBoxError::invalid_field("blob", NOT_A_SHARE).into());
+    }
+    if let Some(expires_at) = &entry.expires_at {
+        if time_utils::parse_rfc3339(expires_at)? <= now {
+            return Err(PwdBoxError::validation(format!("This shared entry expired at {}", expires_at)).into());
+        }
+    }
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open_with_expiry() {
+        let params = KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+        let mut entry = SharedEntry::new("2030-01-01T00:00:00Z".to_string(), Some("2030-01-02T00:00:00Z".to_string()));
+        entry.software = "GitHub".to_string();
+        entry.password = "correct horse".to_string();
+        let blob = seal(&entry, "shared secret", &params).unwrap();
+        assert!(!blob.contains('\n'));
+
+        let before = time_utils::parse_rfc3339("2030-01-01T12:00:00Z").unwrap();
+        let opened = open(&blob, "shared secret", before).unwrap();
+        assert_eq!((opened.software.as_str(), opened.password.as_str()), ("GitHub", "correct horse"));
+        assert!(open(&blob, "wrong secret", before).is_err());
+
+        let after = time_utils::parse_rfc3339("2030-01-02T00:00:00Z").unwrap();
+        assert!(open(&blob, "shared secret", after).unwrap_err().to_string().contains("expired"));
+
+        // A full export under the same passphrase is not a shared entry
+        let export = general_purpose::STANDARD.encode(CryptoService::encrypt_export_data_v2("{}", "shared secret", &params).unwrap());
+        assert!(open(&export, "shared secret", before).unwrap_err().to_string().contains(NOT_A_SHARE));
+    }
+}
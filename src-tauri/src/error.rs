@@ -0,0 +1,196 @@
+use crate::crypto::ExportOpenError;
+use crate::password_policy::{MasterPasswordRejected, PolicyViolation};
+use crate::password_service::{ReEncryptFailed, VaultLimitError};
+use crate::session::{ExplicitKeyRefused, SessionInvalidated, VaultLocked};
+use serde::Serialize;
+use std::sync::PoisonError;
+
+// What commands return on failure. Serializes as {"code": "...", "message": "..."} (plus
+// "field" for validation errors); the frontend branches on the code, which stays the same
+// across releases, and shows the message.
+//
+// Services keep working with anyhow. They may return one of these through it where the
+// kind matters, and everything else is classified once, at the command boundary, by From.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PwdBoxError {
+    NotFound { message: String },
+    // A master key, session key or export passphrase that does not fit
+    InvalidKey { message: String },
+    // The vault auto-locked or lock_vault ran; log in again
+    Locked { message: String },
+    Crypto { message: String },
+    Database { message: String },
+    Io { message: String },
+    Validation {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        field: Option<String>, // The request field at fault, where there is one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        policy: Option<MasterPasswordRejected>, // The master password rule that failed
+    },
+    // Anything not classified above
+    Internal { message: String },
+}
+
+impl PwdBoxError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        PwdBoxError::NotFound { message: message.into() }
+    }
+
+    pub fn invalid_key(message: impl Into<String>) -> Self {
+        PwdBoxError::InvalidKey { message: message.into() }
+    }
+
+    pub fn crypto(message: impl Into<String>) -> Self {
+        PwdBoxError::Crypto { message: message.into() }
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        PwdBoxError::Io { message: message.into() }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        PwdBoxError::Validation { message: message.into(), field: None, policy: None }
+    }
+
+    pub fn invalid_field(field: &str, message: impl Into<String>) -> Self {
+        PwdBoxError::Validation { message: message.into(), field: Some(field.to_string()), policy: None }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        PwdBoxError::Internal { message: message.into() }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            PwdBoxError::NotFound { message }
+            | PwdBoxError::InvalidKey { message }
+            | PwdBoxError::Locked { message }
+            | PwdBoxError::Crypto { message }
+            | PwdBoxError::Database { message }
+            | PwdBoxError::Io { message }
+            | PwdBoxError::Validation { message, .. }
+            | PwdBoxError::Internal { message } => message,
+        }
+    }
+}
+
+impl std::fmt::Display for PwdBoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for PwdBoxError {}
+
+// Classify a service error by the first error in its chain that says what went wrong. The
+// message is the outermost one, context included, as the services wrote it.
+impl From<anyhow::Error> for PwdBoxError {
+    fn from(error: anyhow::Error) -> Self {
+        let message = error.to_string();
+        // Attached as context, which chain() does not hand out as its own type
+        if error.downcast_ref::<ReEncryptFailed>().is_some() {
+            return PwdBoxError::Crypto { message };
+        }
+        for cause in error.chain() {
+            if let Some(error) = cause.downcast_ref::<PwdBoxError>() {
+                return match error {
+                    PwdBoxError::Validation { field, policy, .. } => PwdBoxError::Validation { message, field: field.clone(), policy: policy.clone() },
+                    _ => error.clone(),
+                };
+            }
+            if cause.is::<VaultLocked>() {
+                return PwdBoxError::Locked { message };
+            }
+            if cause.is::<SessionInvalidated>() || cause.is::<ExplicitKeyRefused>() {
+                return PwdBoxError::InvalidKey { message };
+            }
+            match cause.downcast_ref::<ExportOpenError>() {
+                Some(ExportOpenError::WrongPassphrase) => return PwdBoxError::InvalidKey { message },
+                Some(ExportOpenError::Corrupted(_)) => return PwdBoxError::Crypto { message },
+                None => {}
+            }
+            if cause.is::<PolicyViolation>() {
+                return PwdBoxError::Validation { message, field: Some("password".to_string()), policy: None };
+            }
+            if let Some(rejected) = cause.downcast_ref::<MasterPasswordRejected>() {
+                return PwdBoxError::Validation { message, field: Some(rejected.field.to_string()), policy: Some(rejected.clone()) };
+            }
+            if cause.is::<VaultLimitError>() {
+                return PwdBoxError::Validation { message, field: None, policy: None };
+            }
+            match cause.downcast_ref::<rusqlite::Error>() {
+                Some(rusqlite::Error::QueryReturnedNoRows) => return PwdBoxError::NotFound { message },
+                Some(_) => return PwdBoxError::Database { message },
+                None => {}
+            }
+            if cause.is::<std::io::Error>() {
+                return PwdBoxError::Io { message };
+            }
+        }
+        PwdBoxError::Internal { message }
+    }
+}
+
+impl From<VaultLocked> for PwdBoxError {
+    fn from(error: VaultLocked) -> Self {
+        PwdBoxError::Locked { message: error.to_string() }
+    }
+}
+
+// A mutex poisoned by a panicking command
+impl<T> From<PoisonError<T>> for PwdBoxError {
+    fn from(error: PoisonError<T>) -> Self {
+        PwdBoxError::internal(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use serde_json::json;
+
+    fn serialized(error: anyhow::Error) -> serde_json::Value {
+        serde_json::to_value(PwdBoxError::from(error)).unwrap()
+    }
+
+    #[test]
+    fn test_service_errors_map_to_stable_codes() {
+        assert_eq!(serialized(anyhow::Error::new(VaultLocked)), json!({"code": "locked", "message": "locked"}));
+        assert_eq!(
+            serialized(PwdBoxError::not_found("Password entry not found").into()),
+            json!({"code": "not_found", "message": "Password entry not found"})
+        );
+        assert_eq!(serialized(ExportOpenError::WrongPassphrase.into())["code"], "invalid_key");
+        assert_eq!(serialized(ExportOpenError::Corrupted("truncated".to_string()).into())["code"], "crypto");
+        assert_eq!(serialized(anyhow::Error::new(rusqlite::Error::QueryReturnedNoRows))["code"], "not_found");
+        assert_eq!(serialized(anyhow::Error::new(rusqlite::Error::InvalidQuery))["code"], "database");
+        assert_eq!(serialized(anyhow!("Something unexpected")), json!({"code": "internal", "message": "Something unexpected"}));
+
+        // Found through context, which is kept in the message
+        let io_error = Err::<(), _>(std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"))
+            .context("Failed to read export file")
+            .unwrap_err();
+        assert_eq!(serialized(io_error), json!({"code": "io", "message": "Failed to read export file"}));
+    }
+
+    #[test]
+    fn test_validation_errors_name_the_field() {
+        let violation = PolicyViolation {
+            tag: "Infra".to_string(),
+            mode: crate::database::PolicyMode::Strict,
+            min_entropy_bits: 80,
+            entropy_bits: 40,
+        };
+        let error = serialized(violation.into());
+        assert_eq!((&error["code"], &error["field"]), (&json!("validation"), &json!("password")));
+
+        assert_eq!(
+            serde_json::to_value(PwdBoxError::invalid_field("clear_after_secs", "Out of range")).unwrap(),
+            json!({"code": "validation", "message": "Out of range", "field": "clear_after_secs"})
+        );
+        assert!(serde_json::to_value(PwdBoxError::validation("Bad request")).unwrap().get("field").is_none());
+    }
+}
@@ -0,0 +1,49 @@
+use serde_json::{Value, json};
+use std::sync::{Arc, OnceLock};
+
+// Change events, so an open list can update itself instead of polling get_all_passwords.
+// Single-entry changes carry {"id"}; batch operations send one ENTRIES_CHANGED with
+// {"ids"} rather than an event per entry.
+pub const ENTRY_ADDED: &str = "entry-added";
+pub const ENTRY_UPDATED: &str = "entry-updated";
+pub const ENTRY_DELETED: &str = "entry-deleted";
+pub const ENTRIES_CHANGED: &str = "entries-changed";
+pub const VAULT_IMPORTED: &str = "vault-imported";
+pub const BACKUP_CREATED: &str = "backup-created";
+
+// Where events end up: the app's AppHandle, or a recorder in tests
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &str, payload: Value);
+}
+
+// Given to the services when they are created, which is before the app has a handle. The
+// sink is set once the handle exists; events emitted until then are dropped. Clones share
+// the sink, so services recreated later still reach it.
+#[derive(Clone, Default)]
+pub struct EventEmitter {
+    sink: Arc<OnceLock<Arc<dyn EventSink>>>,
+}
+
+impl EventEmitter {
+    // Only the first sink set is kept
+    pub fn set_sink(&self, sink: Arc<dyn EventSink>) {
+        let _ = self.sink.set(sink);
+    }
+
+    pub fn emit(&self, event: &str, payload: Value) {
+        if let Some(sink) = self.sink.get() {
+            sink.emit(event, payload);
+        }
+    }
+
+    pub fn entry(&self, event: &str, id: i64) {
+        self.emit(event, json!({"id": id}));
+    }
+
+    // Nothing is sent when no entry changed
+    pub fn entries_changed(&self, ids: &[i64]) {
+        if !ids.is_empty() {
+            self.emit(ENTRIES_CHANGED, json!({"ids": ids}));
+        }
+    }
+}
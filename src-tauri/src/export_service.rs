@@ -1,20 +1,82 @@
-use crate::database::{Database, ExportData};
-use crate::crypto::CryptoService;
+use crate::audit_log;
+use crate::database::{CorruptEntry, Database, ExportData, PasswordEntry};
+use crate::crypto::{CryptoService, ExportEnvelope, ExportOpenError, SecretKey, NOT_AN_EXPORT};
+use crate::data_dir::{self, AtomicWriteError};
+use crate::domains;
+use crate::events::{self, EventEmitter};
+use crate::session;
+use crate::settings_service::SettingsService;
+use crate::password_service::{self, PasswordService};
+use crate::user_service::UserService;
+use crate::time_utils;
 use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExportRequest {
     pub export_passphrase: String,
     pub file_path: String,
+    #[serde(default)]
+    pub include_stats_history: bool,
+    #[serde(default)]
+    pub include_icons: bool,
+    #[serde(default)]
+    pub include_attachments: bool, // Off by default, as attached files can make the export much larger
+    #[serde(default)]
+    pub include_trash: bool, // Trashed entries are left out unless asked for
+    #[serde(default)]
+    pub include_audit_log: bool, // Sealed audit rows with their chain heads
+    // Only these entries, or only entries with this tag; both narrow the export together
+    #[serde(default)]
+    pub entry_ids: Option<Vec<i64>>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    // Without the user meta (master hash, security questions, wrapped keys) the entries are
+    // re-encrypted under a key of their own kept inside the file, so the export passphrase
+    // alone opens them. That needs the vault key, and leaves the audit log out.
+    #[serde(default = "include_user_meta_default")]
+    pub include_user_meta: bool,
+    #[serde(default)]
+    pub master_key: Option<String>,
+}
+
+fn include_user_meta_default() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    #[default]
+    Replace, // Wipe the current vault and restore the backup
+    Merge,   // Keep the current vault and add the backup's entries to it
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ImportRequest {
     pub import_passphrase: String,
     pub file_path: String,
+    #[serde(default)]
+    pub mode: ImportMode,
+    // In merge mode, let the backup's version win over an existing (software, account) match
+    #[serde(default)]
+    pub overwrite_duplicates: bool,
+    // When both are set, entries are re-encrypted from the backup's vault into the current
+    // one and the current user meta is kept instead of being replaced by the backup's
+    pub source_master_password: Option<String>,
+    pub target_master_key: Option<String>,
+    // The session's vault key, filled in by the command. Exports without user meta are
+    // always merged, under target_master_key or else this.
+    #[serde(skip)]
+    pub session_key: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +84,68 @@ pub struct ExportResponse {
     pub success: bool,
     pub message: String,
     pub file_path: Option<String>,
+    #[serde(default)]
+    pub timings: Option<OperationTimings>,
+    #[serde(default)]
+    pub failed_count: Option<usize>, // Entries left out of a plaintext export because they would not decrypt
+}
+
+// Where an import or export spent its time. Holds no secrets, so it is returned to the
+// UI and logged at debug level for bug reports.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OperationTimings {
+    pub file_io_ms: u64,
+    pub kdf_ms: u64,
+    pub crypto_ms: u64, // Encrypting an export, or decrypting (and re-encrypting) an import
+    pub db_ms: u64,
+    pub total_ms: u64,
+    pub rows: usize,
+    pub rows_per_sec: f64,
+    pub peak_memory_bytes: usize, // Estimate from the largest buffers held at the same time
+}
+
+impl OperationTimings {
+    fn finish(mut self, operation: &str, started: Instant, rows: usize) -> Self {
+        let elapsed = started.elapsed();
+        self.total_ms = elapsed.as_millis() as u64;
+        self.rows = rows;
+        self.rows_per_sec = if elapsed.is_zero() { 0.0 } else { rows as f64 / elapsed.as_secs_f64() };
+        log::debug!(target: "pwdbox::perf", "{} timings {}", operation, serde_json::to_string(&self).unwrap_or_default());
+        self
+    }
+}
+
+// Run `f`, adding its wall-clock time to `elapsed_ms`
+fn timed<T>(elapsed_ms: &mut u64, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    *elapsed_ms += started.elapsed().as_millis() as u64;
+    result
+}
+
+// Returned (through anyhow) when an export file could not be written. Whatever was at the
+// destination before is left untouched; the temporary file is removed when possible.
+#[derive(Debug)]
+pub struct ExportWriteError {
+    pub temp_path: PathBuf,
+    pub source: std::io::Error,
+}
+
+impl std::fmt::Display for ExportWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not write the export file (via {}): {}", self.temp_path.display(), self.source)
+    }
+}
+
+impl std::error::Error for ExportWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// See data_dir::write_atomically; failures name the export file
+fn write_atomically(path: &Path, write: impl FnOnce(&mut fs::File) -> std::io::Result<()>) -> Result<()> {
+    data_dir::write_atomically(path, write).map_err(|AtomicWriteError { temp_path, source }| ExportWriteError { temp_path, source }.into())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,6 +153,384 @@ pub struct ImportResponse {
     pub success: bool,
     pub message: String,
     pub imported_entries_count: Option<usize>,
+    pub reencrypted_count: Option<usize>,
+    pub skipped_count: Option<usize>, // Entries that failed to decrypt with the backup's key
+    pub inserted_count: Option<usize>,
+    pub updated_count: Option<usize>,
+    pub duplicates_skipped_count: Option<usize>,
+    // Audit events restored with the backup or appended as imported segments
+    #[serde(default)]
+    pub audit_events_imported: Option<usize>,
+    #[serde(default)]
+    pub timings: Option<OperationTimings>,
+    #[serde(default)]
+    pub row_errors: Option<Vec<CsvRowError>>, // CSV imports only: rows that could not be read
+    #[serde(default)]
+    pub malformed_entries: Option<Vec<CorruptEntry>>, // Export file imports only: entries left out, by their id in the backup
+    #[serde(default)]
+    pub file_status: Option<ExportFileStatus>, // Export file imports only
+}
+
+// What opening an export file with a passphrase found
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFileStatus {
+    Valid,
+    WrongPassphrase,
+    Corrupted, // Also files that are no PwdBox export
+}
+
+impl ExportFileStatus {
+    fn of(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<ExportOpenError>() {
+            Some(ExportOpenError::WrongPassphrase) => ExportFileStatus::WrongPassphrase,
+            _ => ExportFileStatus::Corrupted,
+        }
+    }
+}
+
+impl ImportResponse {
+    fn failure(message: &str) -> Self {
+        ImportResponse {
+            success: false,
+            message: message.to_string(),
+            imported_entries_count: None,
+            reencrypted_count: None,
+            skipped_count: None,
+            inserted_count: None,
+            updated_count: None,
+            duplicates_skipped_count: None,
+            audit_events_imported: None,
+            timings: None,
+            row_errors: None,
+            malformed_entries: None,
+            file_status: None,
+        }
+    }
+}
+
+// The decrypted contents of an export file. Files from before backup_info was added hold
+// the data alone, leaving `data` empty.
+#[derive(Deserialize)]
+struct ExportFile {
+    #[serde(default)]
+    backup_info: Option<serde_json::Value>,
+    #[serde(default)]
+    data: Option<ExportData>,
+}
+
+// Backup entries ready to be written to the current vault
+struct IncomingEntries {
+    entries: Vec<PasswordEntry>,
+    plaintexts: Option<Vec<Plaintext>>, // Present when re-encrypting
+    source_key: Option<SecretKey>,
+    target_key: Option<SecretKey>,
+    undecryptable_count: usize,
+}
+
+// Decrypted secrets of one incoming entry
+#[derive(PartialEq)]
+struct Plaintext {
+    password: String,
+    notes: Option<String>,
+}
+
+// Cleared as soon as the comparison or re-encryption that needed it is done
+impl Drop for Plaintext {
+    fn drop(&mut self) {
+        CryptoService::clear_sensitive_string(std::mem::take(&mut self.password));
+        if let Some(notes) = self.notes.take() {
+            CryptoService::clear_sensitive_string(notes);
+        }
+    }
+}
+
+fn decrypt_entry(entry: &PasswordEntry, key: &[u8; 32]) -> Result<Plaintext> {
+    Ok(Plaintext {
+        password: CryptoService::decrypt_password(&entry.encrypted_password, &entry.nonce, key, entry.entry_uid.as_deref())?,
+        notes: CryptoService::decrypt_notes(entry.notes.as_deref(), entry.notes_nonce.as_deref(), key, entry.entry_uid.as_deref())?,
+    })
+}
+
+impl IncomingEntries {
+    // Whether the incoming entry at `index` carries the same secret and notes as `current`
+    fn same_content(&self, current: &PasswordEntry, index: usize) -> bool {
+        let entry = &self.entries[index];
+        match (&self.plaintexts, &self.target_key) {
+            (Some(plaintexts), Some(target_key)) => {
+                decrypt_entry(current, target_key)
+                    .map(|plaintext| plaintext == plaintexts[index])
+                    .unwrap_or(false)
+            }
+            _ => {
+                current.encrypted_password == entry.encrypted_password
+                    && current.nonce == entry.nonce
+                    && current.notes == entry.notes
+                    && current.notes_nonce == entry.notes_nonce
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct MergePlan {
+    inserts: Vec<PasswordEntry>,
+    updates: Vec<PasswordEntry>,
+    skipped: Vec<PasswordEntry>,
+}
+
+// What import_csv does with a row whose (software, account) is already in the vault
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvImportMode {
+    #[default]
+    Skip,   // Keep the vault's entry
+    Update, // Replace its password (and notes or URL when the row has them)
+}
+
+// A CSV row that was left out of an import. `line` counts from 1 and includes the header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvRowError {
+    pub line: u64,
+    pub message: String,
+}
+
+// Entry field a CSV column is imported into
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvField {
+    Software,
+    Url,
+    Account,
+    Password,
+    Notes,
+    Tags,     // Comma-separated; several columns may feed it, e.g. a folder and a tag list
+    Favorite, // 1, true or yes
+    Ignore,   // Drops a column a preset would otherwise map
+}
+
+// Column layouts of other password managers' CSV exports
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CsvPreset {
+    #[serde(rename = "browser")]
+    Browser, // Chrome, Edge and Firefox
+    #[serde(rename = "lastpass")]
+    LastPass,
+    #[serde(rename = "1password")]
+    OnePassword,
+}
+
+impl CsvPreset {
+    // Source columns in order of preference. Chrome and Edge write name,url,username,password
+    // (newer versions add note); Firefox has no name and adds bookkeeping columns of its own.
+    fn columns(self) -> &'static [(&'static str, CsvField)] {
+        match self {
+            CsvPreset::Browser => &[
+                ("name", CsvField::Software),
+                ("url", CsvField::Url),
+                ("username", CsvField::Account),
+                ("password", CsvField::Password),
+                ("note", CsvField::Notes),
+                ("notes", CsvField::Notes),
+            ],
+            CsvPreset::LastPass => &[
+                ("name", CsvField::Software),
+                ("url", CsvField::Url),
+                ("username", CsvField::Account),
+                ("password", CsvField::Password),
+                ("extra", CsvField::Notes),
+                ("grouping", CsvField::Tags),
+                ("fav", CsvField::Favorite),
+            ],
+            CsvPreset::OnePassword => &[
+                ("title", CsvField::Software),
+                ("url", CsvField::Url),
+                ("website", CsvField::Url),
+                ("username", CsvField::Account),
+                ("password", CsvField::Password),
+                ("notes", CsvField::Notes),
+                ("notesPlain", CsvField::Notes),
+                ("tags", CsvField::Tags),
+                ("vault", CsvField::Tags),
+                ("favorite", CsvField::Favorite),
+            ],
+        }
+    }
+
+    fn detect(header: &csv::StringRecord) -> Self {
+        let has = |name: &str| header.iter().any(|column| clean_column(column).eq_ignore_ascii_case(name));
+        if has("grouping") || has("extra") {
+            CsvPreset::LastPass
+        } else if has("title") {
+            CsvPreset::OnePassword
+        } else {
+            CsvPreset::Browser
+        }
+    }
+}
+
+// How to read a CSV import. With neither a preset nor columns the preset is detected from
+// the header. Columns map source column names to fields and override what the preset maps
+// to the same field.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CsvImportOptions {
+    #[serde(default)]
+    pub preset: Option<CsvPreset>,
+    #[serde(default)]
+    pub columns: HashMap<String, CsvField>,
+    #[serde(default)]
+    pub strict: bool, // Import nothing when any row cannot be read
+}
+
+fn clean_column(column: &str) -> &str {
+    column.trim_start_matches('\u{feff}').trim()
+}
+
+// Which field each column of a particular file goes into
+struct CsvLayout {
+    format: &'static str,
+    columns: Vec<(usize, CsvField)>,
+    secure_note_url: Option<&'static str>, // LastPass files secure notes under a fake URL
+}
+
+impl CsvLayout {
+    fn resolve(header: &csv::StringRecord, options: &CsvImportOptions) -> Result<Self> {
+        let position = |name: &str| header.iter().position(|column| clean_column(column).eq_ignore_ascii_case(name.trim()));
+        let preset = options.preset.or_else(|| options.columns.is_empty().then(|| CsvPreset::detect(header)));
+
+        let mut columns: Vec<(usize, CsvField)> = Vec::new();
+        for &(name, field) in preset.map_or(&[][..], CsvPreset::columns) {
+            let taken = field != CsvField::Tags && columns.iter().any(|(_, mapped)| *mapped == field);
+            if let Some(index) = position(name).filter(|_| !taken) {
+                columns.push((index, field));
+            }
+        }
+        let mut explicit: Vec<_> = options.columns.iter().collect();
+        explicit.sort();
+        for (name, &field) in &explicit {
+            let index = position(name).ok_or_else(|| anyhow!("The file has no column named \"{}\"", name))?;
+            if !matches!(field, CsvField::Tags | CsvField::Ignore) && explicit.iter().filter(|(_, other)| **other == field).count() > 1 {
+                return Err(anyhow!("Only tags can be read from more than one column"));
+            }
+            columns.retain(|&(mapped_index, mapped)| mapped_index != index && (mapped != field || field == CsvField::Tags));
+            if field != CsvField::Ignore {
+                columns.push((index, field));
+            }
+        }
+        columns.sort();
+
+        let maps = |field| columns.iter().any(|(_, mapped)| *mapped == field);
+        if !maps(CsvField::Password) && !maps(CsvField::Notes) {
+            return Err(anyhow!("No column maps to the password or notes"));
+        }
+        if !maps(CsvField::Software) && !maps(CsvField::Url) {
+            return Err(anyhow!("No column maps to the software name or URL"));
+        }
+        let format = match preset {
+            Some(CsvPreset::Browser) if position("httpRealm").is_some() || position("guid").is_some() => "firefox",
+            Some(CsvPreset::Browser) => "chrome",
+            Some(CsvPreset::LastPass) => "lastpass",
+            Some(CsvPreset::OnePassword) => "1password",
+            None => "custom",
+        };
+        let secure_note_url = (preset == Some(CsvPreset::LastPass)).then_some("http://sn");
+        Ok(CsvLayout { format, columns, secure_note_url })
+    }
+
+    // The column → field pairs, for previews
+    fn describe(&self, header: &csv::StringRecord) -> serde_json::Value {
+        self.columns
+            .iter()
+            .map(|&(index, field)| serde_json::json!({ "column": header.get(index).map(clean_column), "field": field }))
+            .collect()
+    }
+
+    // The entry a record describes. Without a name the URL's host stands in for the software;
+    // rows with notes but no password (or LastPass's secure note URL) become secure notes,
+    // entries with no account or password.
+    fn parse(&self, record: &csv::StringRecord, line: u64) -> Result<CsvRow, CsvRowError> {
+        let values = |field| {
+            self.columns
+                .iter()
+                .filter(move |(_, mapped)| *mapped == field)
+                .filter_map(|&(index, _)| record.get(index))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let first = |field| values(field).next();
+        let error = |message: &str| CsvRowError { line, message: message.to_string() };
+
+        // Passwords are taken as they are, surrounding spaces included
+        let password = self.columns
+            .iter()
+            .find(|(_, mapped)| *mapped == CsvField::Password)
+            .and_then(|&(index, _)| record.get(index))
+            .unwrap_or_default();
+        let notes = first(CsvField::Notes).map(|notes| Zeroizing::new(notes.to_string()));
+        let mut url = first(CsvField::Url).map(str::to_string);
+        let secure_note = url.as_deref().is_some_and(|url| Some(url) == self.secure_note_url)
+            || (password.is_empty() && notes.is_some());
+        if secure_note {
+            url = None;
+        } else if password.is_empty() {
+            return Err(error("No password"));
+        }
+
+        let software = first(CsvField::Software)
+            .map(str::to_string)
+            .or_else(|| url.as_deref().map(|url| domains::host_from(url).unwrap_or_else(|| url.to_string())))
+            .ok_or_else(|| error("No name or URL"))?;
+        let mut tags = Vec::new();
+        for tag in values(CsvField::Tags).flat_map(|value| value.split(',')).filter(|tag| !tag.trim().is_empty()) {
+            let tag = password_service::normalize_tag(tag).ok_or_else(|| error("Tag names must be 1 to 64 characters"))?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let favorite = self.columns.iter().any(|(_, mapped)| *mapped == CsvField::Favorite).then(|| {
+            first(CsvField::Favorite).is_some_and(|value| ["1", "true", "yes"].iter().any(|yes| value.eq_ignore_ascii_case(yes)))
+        });
+
+        Ok(CsvRow {
+            line,
+            software,
+            account: if secure_note { String::new() } else { first(CsvField::Account).unwrap_or_default().to_string() },
+            url,
+            password: Zeroizing::new(password.to_string()),
+            notes,
+            tags,
+            favorite,
+        })
+    }
+}
+
+// One entry read from a CSV export
+struct CsvRow {
+    line: u64,
+    software: String,
+    account: String,
+    url: Option<String>,
+    password: Zeroizing<String>,
+    notes: Option<Zeroizing<String>>,
+    tags: Vec<String>,
+    favorite: Option<bool>, // None when no column maps to it
+}
+
+// What importing a CSV row would do
+enum CsvRowAction {
+    Insert,
+    Update(Box<PasswordEntry>), // The vault's entry, names opened
+    Skip,
+}
+
+impl CsvRowAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CsvRowAction::Insert => "insert",
+            CsvRowAction::Update(_) => "update",
+            CsvRowAction::Skip => "skip",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,28 +539,147 @@ pub struct BackupInfo {
     pub created_at: String,
     pub entry_count: usize,
     pub has_user_data: bool,
+    #[serde(default)]
+    pub partial: bool, // Chosen entries only, or no user meta
+}
+
+// One file in a backup directory. Files that are no export, or will not open, are listed
+// with the reason rather than failing the listing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupListing {
+    pub file_name: String,
+    pub file_path: String,
+    pub file_size: Option<u64>,
+    pub created_at: Option<String>, // From the v2 header, else the file's modification time
+    pub created_at_display: Option<String>,
+    pub is_pwdbox_export: bool,
+    pub format_version: Option<u32>,
+    pub entry_count: Option<usize>, // Only when listed with the passphrase
+    pub error: Option<String>,
+}
+
+// How long a prepared restore waits for confirm_restore
+pub const RESTORE_TOKEN_TTL: Duration = Duration::from_secs(5 * 60);
+
+// What prepare_restore found in a backup. Confirming with the token replaces the vault,
+// master password and security questions included, with the backup's.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreSummary {
+    pub token: String,
+    pub expires_in_secs: u64,
+    pub created_at: Option<String>,
+    pub entry_count: usize,
+    pub trash_count: usize,
+    pub audit_events: Option<usize>,
+    pub metadata_encrypted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreResponse {
+    pub success: bool,
+    pub message: String,
+    pub restored_entries_count: usize,
+    pub audit_events_restored: Option<usize>,
+    pub pre_restore_backup: String, // Copy of the database from just before the restore
+}
+
+// A verified backup, held in AppState until confirm_restore redeems its token once
+pub struct PendingRestore {
+    token: String,
+    data: ExportData,
+    expires_at: Instant,
+}
+
+impl PendingRestore {
+    fn new(data: ExportData, now: Instant) -> Self {
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        PendingRestore {
+            token: general_purpose::URL_SAFE_NO_PAD.encode(token),
+            data,
+            expires_at: now + RESTORE_TOKEN_TTL,
+        }
+    }
+
+    // Take the backup waiting in `slot` if `token` is its token. An expired one is dropped;
+    // another token leaves it waiting.
+    pub fn take(slot: &mut Option<PendingRestore>, token: &str, now: Instant) -> Result<ExportData> {
+        match slot.take() {
+            Some(pending) if now >= pending.expires_at => Err(anyhow!("The restore has expired; prepare it again")),
+            Some(pending) if pending.token == token => Ok(pending.data),
+            pending => {
+                *slot = pending;
+                Err(anyhow!("No restore is waiting for this token"))
+            }
+        }
+    }
 }
 
 pub struct ExportService {
     database: Database,
+    events: EventEmitter,
 }
 
 impl ExportService {
-    pub fn new(database: Database) -> Self {
-        ExportService { database }
+    pub fn new(database: Database, events: EventEmitter) -> Self {
+        ExportService { database, events }
     }
 
     // Export all data to an encrypted file
     pub fn export_data(&self, request: ExportRequest) -> Result<ExportResponse> {
+        let started = Instant::now();
+        let mut timings = OperationTimings::default();
+
+        // Without user meta the entries have to be decrypted, so check the key before anything else
+        let vault_key = match (request.include_user_meta, &request.master_key) {
+            (true, _) => None,
+            (false, Some(master_key)) => Some(session::open_key(&self.database, master_key)?),
+            (false, None) => return Err(anyhow!("Exporting without user data requires the master key")),
+        };
+        let partial = request.entry_ids.is_some() || request.tag.is_some() || !request.include_user_meta;
+
         // Get all data from database
-        let export_data = self.database.export_all_data()?;
+        let mut export_data = timed(&mut timings.db_ms, || -> Result<ExportData> {
+            let mut export_data = self.database.export_all_data(request.include_trash)?;
+            if let Some(entry_ids) = &request.entry_ids {
+                export_data.password_entries.retain(|entry| entry.id.is_some_and(|id| entry_ids.contains(&id)));
+            }
+            if let Some(tag) = &request.tag {
+                export_data.password_entries.retain(|entry| entry.tags.iter().any(|entry_tag| entry_tag.eq_ignore_ascii_case(tag)));
+            }
+            if request.include_stats_history {
+                export_data.stats_history = Some(self.database.get_stats_history(None)?);
+            }
+            if request.include_icons {
+                let mut icons = self.database.get_entry_icons()?;
+                for entry in &mut export_data.password_entries {
+                    entry.icon = entry.id.and_then(|id| icons.remove(&id));
+                }
+            }
+            if request.include_attachments {
+                let mut attachments = self.database.get_entry_attachments()?;
+                for entry in &mut export_data.password_entries {
+                    entry.attachments = entry.id.and_then(|id| attachments.remove(&id)).unwrap_or_default();
+                }
+            }
+            if request.include_audit_log && request.include_user_meta {
+                export_data.audit_log = Some(self.database.export_audit_log()?);
+            }
+            export_data.metadata_encrypted = SettingsService::encrypt_metadata_from(&self.database)?;
+            Ok(export_data)
+        })?;
+        let failed_count = vault_key
+            .map(|vault_key| timed(&mut timings.crypto_ms, || Self::seal_for_transfer(&mut export_data, &vault_key)))
+            .transpose()?;
+        let entry_count = export_data.password_entries.len();
 
         // Add metadata
         let backup_info = BackupInfo {
             version: "1.0".to_string(),
-            created_at: chrono::Utc::now().to_rfc3339(),
-            entry_count: export_data.password_entries.len(),
-            has_user_data: true,
+            created_at: time_utils::now_rfc3339(),
+            entry_count,
+            has_user_data: request.include_user_meta,
+            partial,
         };
 
         // Create complete export structure
@@ -67,194 +688,1105 @@ impl ExportService {
             "data": export_data
         });
 
-        // Serialize to JSON
-        let json_data = serde_json::to_string_pretty(&complete_export)?;
+        // Serialize to JSON. The export is sealed as one document, so only one copy of it
+        // is kept at a time: the entries go once they are serialized.
+        let json_data = Zeroizing::new(serde_json::to_string_pretty(&complete_export)?);
+        drop(complete_export);
 
-        // Encrypt the JSON data
-        let encrypted_data = CryptoService::encrypt_export_data(&json_data, &request.export_passphrase)?;
+        // Encrypt the JSON data. The parameters go in the file header for the import to read back.
+        let kdf_params = SettingsService::kdf_params_from(&self.database)?;
+        let salt = CryptoService::generate_salt();
+        let key = timed(&mut timings.kdf_ms, || CryptoService::derive_key_from_password(&request.export_passphrase, &salt, &kdf_params))?;
+        let encrypted_data = timed(&mut timings.crypto_ms, || CryptoService::seal_export_data_v2(&json_data, &kdf_params, &salt, &key))?;
+        timings.peak_memory_bytes = json_data.len() + encrypted_data.len();
+        drop(json_data);
 
         // Write to file
         let file_path = PathBuf::from(&request.file_path);
-        
-        // Ensure parent directory exists
-        if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        fs::write(&file_path, encrypted_data)?;
+        timed(&mut timings.file_io_ms, || -> Result<()> {
+            // Ensure parent directory exists
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_atomically(&file_path, |file| file.write_all(encrypted_data.as_bytes()))
+        })?;
+        let detail = if partial { format!("{} entries, partial", entry_count) } else { format!("{} entries", entry_count) };
+        audit_log::record(&self.database, audit_log::EXPORT, None, Some(&detail), None)?;
 
+        let message = match failed_count {
+            Some(failed) if failed > 0 => format!(
+                "Data exported successfully to {}; {} entries could not be decrypted and were left out",
+                request.file_path, failed
+            ),
+            _ => format!("Data exported successfully to {}", request.file_path),
+        };
         Ok(ExportResponse {
             success: true,
-            message: format!("Data exported successfully to {}", request.file_path),
+            message,
             file_path: Some(request.file_path),
+            timings: Some(timings.finish("export", started, entry_count)),
+            failed_count,
         })
     }
 
-    // Import data from an encrypted file
-    pub fn import_data(&self, request: ImportRequest) -> Result<ImportResponse> {
+    // Re-encrypt the entries under a new transfer key kept in the export and drop the user
+    // meta, so the file no longer depends on this vault. Names are opened on the way, as the
+    // importing vault seals them under its own key. Returns how many entries would not decrypt.
+    fn seal_for_transfer(export_data: &mut ExportData, vault_key: &[u8; 32]) -> Result<usize> {
+        let transfer_key = CryptoService::generate_vault_key();
+        let mut failed_count = 0;
+        let mut entries = Vec::with_capacity(export_data.password_entries.len());
+        for mut entry in std::mem::take(&mut export_data.password_entries) {
+            let opened = PasswordService::open_metadata(&mut entry, vault_key).and_then(|_| Self::decrypt_secrets(&entry, vault_key));
+            let Ok((password, notes)) = opened else {
+                failed_count += 1;
+                continue;
+            };
+            let entry_uid = entry.entry_uid.clone().unwrap_or_else(CryptoService::generate_entry_uid);
+            let reencrypted = PasswordService::reencrypt_attachments(&mut entry.attachments, vault_key, &transfer_key, &entry_uid)
+                .and_then(|_| PasswordService::reencrypt_custom_fields(&mut entry.custom_fields, vault_key, &transfer_key, &entry_uid));
+            if reencrypted.is_err() {
+                failed_count += 1;
+                continue;
+            }
+            let (encrypted_password, nonce) = CryptoService::encrypt_password(&password, &transfer_key, Some(&entry_uid))?;
+            let (notes, notes_nonce) = CryptoService::encrypt_notes(notes.as_deref().map(String::as_str), &transfer_key, Some(&entry_uid))?;
+            entries.push(PasswordEntry {
+                encrypted_password,
+                nonce,
+                notes,
+                notes_nonce,
+                entry_uid: Some(entry_uid),
+                ..entry
+            });
+        }
+        export_data.password_entries = entries;
+        export_data.user_meta = None;
+        export_data.metadata_encrypted = false;
+        export_data.transfer_key = Some(general_purpose::STANDARD.encode(*transfer_key));
+        Ok(failed_count)
+    }
+
+    // Read and decrypt an export file, returning its backup info and data
+    fn read_export_file(&self, request: &ImportRequest, timings: &mut OperationTimings) -> Result<(serde_json::Value, ExportData)> {
         // Read encrypted file
         let file_path = PathBuf::from(&request.file_path);
         
         if !file_path.exists() {
-            return Ok(ImportResponse {
-                success: false,
-                message: "Import file does not exist".to_string(),
-                imported_entries_count: None,
-            });
+            return Err(anyhow!("Import file does not exist"));
         }
 
-        let encrypted_data = fs::read_to_string(&file_path)?;
+        let contents = timed(&mut timings.file_io_ms, || fs::read(&file_path))?;
+        let file_size = contents.len();
 
-        // Decrypt the data
-        let json_data = CryptoService::decrypt_export_data(&encrypted_data, &request.import_passphrase)
-            .map_err(|_| anyhow!("Failed to decrypt import file. Please check your passphrase."))?;
+        // Decrypt the data. Files that are no export, are damaged or are of an unknown format
+        // version say so rather than blaming the passphrase.
+        let envelope = CryptoService::parse_export_data(contents)?;
+        Self::open_export(envelope, &request.import_passphrase, file_size, timings)
+    }
 
-        // Parse JSON
-        let import_json: serde_json::Value = serde_json::from_str(&json_data)?;
+    // Decrypt a parsed export file of `file_size` bytes into its backup info and data
+    fn open_export(envelope: ExportEnvelope, passphrase: &str, file_size: usize, timings: &mut OperationTimings) -> Result<(serde_json::Value, ExportData)> {
+        let key = timed(&mut timings.kdf_ms, || CryptoService::derive_key_from_password(passphrase, &envelope.salt, &envelope.kdf_params))?;
+        let json_data = Zeroizing::new(timed(&mut timings.crypto_ms, || envelope.open(&key))?);
+        // The file is decoded and decrypted in its own buffer, which becomes the JSON text;
+        // the parsed data comes to about as much again
+        timings.peak_memory_bytes = file_size + json_data.len();
 
-        // Extract export data
-        let export_data: ExportData = if import_json.get("data").is_some() {
+        // Parse JSON straight into the data, without a tree of the whole file in between
+        let export_file: ExportFile = serde_json::from_str(&json_data)?;
+        match export_file.data {
             // New format with metadata
-            serde_json::from_value(import_json["data"].clone())?
-        } else {
-            // Legacy format (direct export data)
-            serde_json::from_value(import_json)?
+            Some(export_data) => Ok((export_file.backup_info.unwrap_or_else(|| serde_json::json!({})), export_data)),
+            None => {
+                // Legacy format (direct export data)
+                let export_data: ExportData = serde_json::from_str(&json_data)?;
+                let backup_info = serde_json::json!({
+                    "version": "legacy",
+                    "entry_count": export_data.password_entries.len(),
+                    "has_user_data": true
+                });
+                Ok((backup_info, export_data))
+            }
+        }
+    }
+
+    // Take the backup's entries as stored, or re-encrypted under the current vault key
+    // when the request carries both keys or the export has its own transfer key
+    fn resolve_incoming(&self, export_data: &ExportData, request: &ImportRequest, timings: &mut OperationTimings) -> Result<IncomingEntries> {
+        if let Some(transfer_key) = &export_data.transfer_key {
+            let target_master_key = request.target_master_key.as_ref().or(request.session_key.as_ref())
+                .ok_or_else(|| anyhow!("Importing a partial export requires the current master key"))?;
+            let target_key = session::open_key(&self.database, target_master_key)?;
+            return Self::reencrypt_incoming(export_data, CryptoService::decode_key(transfer_key)?, target_key, timings);
+        }
+
+        let (source_master_password, target_master_key) = match (&request.source_master_password, &request.target_master_key) {
+            (Some(source_master_password), Some(target_master_key)) => (source_master_password, target_master_key),
+            (None, None) => {
+                if request.mode == ImportMode::Merge || export_data.user_meta.is_none() {
+                    self.check_from_this_vault(export_data, request)?;
+                }
+                return Ok(IncomingEntries {
+                    entries: export_data.password_entries.clone(),
+                    plaintexts: None,
+                    source_key: None,
+                    target_key: None,
+                    undecryptable_count: 0,
+                });
+            }
+            _ => return Err(anyhow!("Re-encrypting an import requires both the backup's master password and the current master key")),
+        };
+
+        let target_key = session::open_key(&self.database, target_master_key)?;
+        let user_meta = export_data.user_meta.as_ref().ok_or_else(|| anyhow!("Invalid import data: missing user information"))?;
+        let source_key = timed(&mut timings.kdf_ms, || UserService::recover_entry_key(user_meta, source_master_password))?
+            .ok_or_else(|| anyhow!("The master password does not match the backup"))?;
+        Self::reencrypt_incoming(export_data, source_key, target_key, timings)
+    }
+
+    // Entries merged as stored only decrypt if the backup was made under this vault's key, so
+    // its canary, or where it has none its first entry, has to open under the session's key
+    fn check_from_this_vault(&self, export_data: &ExportData, request: &ImportRequest) -> Result<()> {
+        let session_key = request.session_key.as_ref()
+            .ok_or_else(|| anyhow!("Merging a backup without its master password requires the current master key"))?;
+        let key = session::open_key(&self.database, session_key)?;
+        let canary = export_data.user_meta.as_ref()
+            .and_then(|user_meta| user_meta.canary_ciphertext.as_ref().zip(user_meta.canary_nonce.as_ref()));
+        let opens = match (canary, export_data.password_entries.first()) {
+            (Some((ciphertext, nonce)), _) => CryptoService::verify_canary(ciphertext, nonce, &key),
+            (None, Some(entry)) => CryptoService::decrypt_password(&entry.encrypted_password, &entry.nonce, &key, entry.entry_uid.as_deref())
+                .map(CryptoService::clear_sensitive_string)
+                .is_ok(),
+            (None, None) => true,
+        };
+        if !opens {
+            return Err(anyhow!("This backup is from another vault; give its master password"));
+        }
+        Ok(())
+    }
+
+    // Decrypt the backup's entries with `source_key` and encrypt them again under `target_key`
+    fn reencrypt_incoming(export_data: &ExportData, source_key: SecretKey, target_key: SecretKey, timings: &mut OperationTimings) -> Result<IncomingEntries> {
+        let mut entries = Vec::new();
+        let mut plaintexts = Vec::new();
+        let mut undecryptable_count = 0;
+        timed(&mut timings.crypto_ms, || -> Result<()> {
+            for entry in &export_data.password_entries {
+                // Names come out in plaintext; seal_for_target encrypts them again on the way in
+                let mut entry = entry.clone();
+                let entry_uid = entry.entry_uid.clone().unwrap_or_else(CryptoService::generate_entry_uid);
+                let opened = PasswordService::open_metadata(&mut entry, &source_key)
+                    .and_then(|_| PasswordService::reencrypt_attachments(&mut entry.attachments, &source_key, &target_key, &entry_uid))
+                    .and_then(|_| PasswordService::reencrypt_custom_fields(&mut entry.custom_fields, &source_key, &target_key, &entry_uid))
+                    .and_then(|_| decrypt_entry(&entry, &source_key));
+                match opened {
+                    Ok(plaintext) => {
+                        let (encrypted_password, nonce) = CryptoService::encrypt_password(&plaintext.password, &target_key, Some(&entry_uid))?;
+                        let (notes, notes_nonce) = CryptoService::encrypt_notes(plaintext.notes.as_deref(), &target_key, Some(&entry_uid))?;
+                        // Files written before scores were stored carry none
+                        let strength_score = Some(CryptoService::password_strength(&plaintext.password));
+                        entries.push(PasswordEntry {
+                            id: None,
+                            encrypted_password,
+                            nonce,
+                            notes,
+                            notes_nonce,
+                            strength_score,
+                            entry_uid: Some(entry_uid),
+                            ..entry
+                        });
+                        plaintexts.push(plaintext);
+                    }
+                    Err(_) => undecryptable_count += 1,
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(IncomingEntries {
+            entries,
+            plaintexts: Some(plaintexts),
+            source_key: Some(source_key),
+            target_key: Some(target_key),
+            undecryptable_count,
+        })
+    }
+
+    // Decide what merging the incoming entries would do, deduplicating on (software, account).
+    // That needs every name in plaintext: re-encrypted incoming entries already are, and the
+    // current vault's are decrypted with the target key.
+    fn plan_merge(&self, incoming: &IncomingEntries, overwrite_duplicates: bool) -> Result<MergePlan> {
+        let mut current = self.database.get_all_password_entries()?;
+        match &incoming.target_key {
+            Some(target_key) => {
+                for entry in &mut current {
+                    PasswordService::open_metadata(entry, target_key)?;
+                }
+            }
+            None if current.iter().chain(&incoming.entries).any(|entry| entry.software_enc.is_some()) => {
+                return Err(anyhow!("Merging entries with encrypted names requires the backup's master password"));
+            }
+            None => {}
+        }
+        let existing: HashMap<(String, String), PasswordEntry> = current
+            .into_iter()
+            .map(|entry| ((entry.software.clone(), entry.account.clone()), entry))
+            .collect();
+
+        let mut plan = MergePlan::default();
+        let mut seen = HashSet::new();
+        for (index, entry) in incoming.entries.iter().enumerate() {
+            let pair = (entry.software.clone(), entry.account.clone());
+            if !seen.insert(pair.clone()) {
+                // Repeated within the backup itself; the first occurrence wins
+                plan.skipped.push(entry.clone());
+                continue;
+            }
+
+            match existing.get(&pair) {
+                None => plan.inserts.push(entry.clone()),
+                Some(current) if overwrite_duplicates && !incoming.same_content(current, index) => {
+                    plan.updates.push(PasswordEntry {
+                        id: current.id,
+                        ..entry.clone()
+                    });
+                }
+                Some(_) => plan.skipped.push(entry.clone()),
+            }
+        }
+
+        Ok(plan)
+    }
+
+    // Re-encrypted entries carry plaintext names until they are written; encrypt them under
+    // the target key when the current vault keeps its names encrypted
+    fn seal_for_target(&self, entries: &mut [PasswordEntry], target_key: Option<&[u8; 32]>) -> Result<()> {
+        if let Some(target_key) = target_key {
+            if SettingsService::encrypt_metadata_from(&self.database)? {
+                for entry in entries {
+                    PasswordService::seal_metadata(entry, target_key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Take the backup's entries whose ciphertexts could never be decrypted out of it, so an
+    // import reports them instead of writing them. They are named by their id in the backup.
+    fn take_malformed(export_data: &mut ExportData) -> Vec<CorruptEntry> {
+        let mut malformed = Vec::new();
+        export_data.password_entries.retain(|entry| match Database::entry_defect(entry) {
+            Some(defect) => {
+                malformed.push(CorruptEntry { id: entry.id.unwrap_or_default(), defect });
+                false
+            }
+            None => true,
+        });
+        malformed
+    }
+
+    fn note_malformed(message: &mut String, malformed: &[CorruptEntry]) {
+        if !malformed.is_empty() {
+            message.push_str(&format!(" {} malformed entries were left out.", malformed.len()));
+        }
+    }
+
+    // Import data from an encrypted file
+    pub fn import_data(&self, request: ImportRequest) -> Result<ImportResponse> {
+        if !PathBuf::from(&request.file_path).exists() {
+            return Ok(ImportResponse::failure("Import file does not exist"));
+        }
+
+        let started = Instant::now();
+        let mut timings = OperationTimings::default();
+        let (_, mut export_data) = match self.read_export_file(&request, &mut timings) {
+            Ok(read) => read,
+            Err(e) if e.is::<ExportOpenError>() => {
+                return Ok(ImportResponse { file_status: Some(ExportFileStatus::of(&e)), ..ImportResponse::failure(&e.to_string()) });
+            }
+            Err(e) => return Err(e),
         };
 
         // Validate import data
-        if export_data.user_meta.master_hash.is_empty() {
-            return Ok(ImportResponse {
-                success: false,
-                message: "Invalid import data: missing user information".to_string(),
-                imported_entries_count: None,
-            });
+        let valid = match &export_data.user_meta {
+            Some(user_meta) => !user_meta.master_hash.is_empty(),
+            None => export_data.transfer_key.is_some(),
+        };
+        if !valid {
+            return Ok(ImportResponse::failure("Invalid import data: missing user information"));
         }
+        let malformed_entries = Self::take_malformed(&mut export_data);
 
-        let entry_count = export_data.password_entries.len();
+        let mut incoming = match self.resolve_incoming(&export_data, &request, &mut timings) {
+            Ok(incoming) => incoming,
+            Err(e) => return Ok(ImportResponse::failure(&e.to_string())),
+        };
+        let reencrypted_count = incoming.target_key.as_ref().map(|_| incoming.entries.len());
+        let skipped_count = incoming.target_key.as_ref().map(|_| incoming.undecryptable_count);
 
-        // Import data to database (this will replace existing data)
-        self.database.import_all_data(&export_data)?;
+        // Re-encrypting brings the backup's audit log over as separately chained segments.
+        // Checked before anything is written, so a tampered log leaves the vault untouched.
+        let audit_segments = match (&export_data.audit_log, &incoming.source_key, &incoming.target_key) {
+            (Some(audit_log), Some(source_key), Some(target_key)) => match audit_log::prepare_import(audit_log, source_key, target_key) {
+                Ok(segments) => Some(segments),
+                Err(e) => return Ok(ImportResponse::failure(&e.to_string())),
+            },
+            _ => None,
+        };
 
-        Ok(ImportResponse {
-            success: true,
-            message: format!("Data imported successfully. {} password entries restored.", entry_count),
-            imported_entries_count: Some(entry_count),
-        })
+        // Without user meta there is no vault to restore, only entries to add to this one
+        let mode = if export_data.user_meta.is_none() { ImportMode::Merge } else { request.mode };
+        match mode {
+            ImportMode::Replace => {
+                let entry_count = incoming.entries.len();
+                PasswordService::ensure_capacity(entry_count)?;
+                // A backup made with encrypted names keeps them encrypted, whichever way it comes in
+                if export_data.metadata_encrypted {
+                    SettingsService::enable_encrypt_metadata(&self.database)?;
+                }
+                self.seal_for_target(&mut incoming.entries, incoming.target_key.as_deref())?;
+                let audit_events_imported = timed(&mut timings.db_ms, || -> Result<Option<usize>> {
+                    if incoming.target_key.is_some() {
+                        // Keep the current user meta and policies, replace only the entries
+                        self.database.replace_password_entries(&incoming.entries)?;
+                        self.database.merge_password_policies(&export_data.password_policies)?;
+                        audit_segments.as_deref().map(|segments| audit_log::append_imported(&self.database, segments)).transpose()
+                    } else {
+                        // Import data to database (this will replace existing data). The
+                        // backup's vault key comes with it, so its audit log is restored as is.
+                        self.database.import_all_data(&export_data)?;
+                        Ok(export_data.audit_log.as_ref().map(|audit_log| audit_log.events.len()))
+                    }
+                })?;
+                audit_log::record(
+                    &self.database,
+                    audit_log::IMPORT,
+                    None,
+                    Some(&format!("replace, {} entries, {} audit events", entry_count, audit_events_imported.unwrap_or(0))),
+                    None,
+                )?;
+
+                let mut message = match skipped_count {
+                    Some(skipped) => format!(
+                        "Data imported successfully. {} password entries re-encrypted, {} skipped.",
+                        entry_count, skipped
+                    ),
+                    None => format!("Data imported successfully. {} password entries restored.", entry_count),
+                };
+                Self::note_malformed(&mut message, &malformed_entries);
+                self.vault_imported("replace", entry_count);
+
+                Ok(ImportResponse {
+                    success: true,
+                    message,
+                    imported_entries_count: Some(entry_count),
+                    reencrypted_count,
+                    skipped_count,
+                    inserted_count: None,
+                    updated_count: None,
+                    duplicates_skipped_count: None,
+                    audit_events_imported,
+                    timings: Some(timings.finish("import", started, entry_count)),
+                    row_errors: None,
+                    malformed_entries: Some(malformed_entries),
+                    file_status: Some(ExportFileStatus::Valid),
+                })
+            }
+            ImportMode::Merge => {
+                // Without both keys the backup's audit log cannot be verified, so it is left out
+                let (plan, audit_events_imported) = timed(&mut timings.db_ms, || -> Result<(MergePlan, Option<usize>)> {
+                    let mut plan = self.plan_merge(&incoming, request.overwrite_duplicates)?;
+                    PasswordService::ensure_capacity(self.database.count_password_entries()? + plan.inserts.len())?;
+                    if export_data.metadata_encrypted {
+                        SettingsService::enable_encrypt_metadata(&self.database)?;
+                    }
+                    self.seal_for_target(&mut plan.inserts, incoming.target_key.as_deref())?;
+                    self.seal_for_target(&mut plan.updates, incoming.target_key.as_deref())?;
+                    self.database.merge_password_entries(&plan.inserts, &plan.updates)?;
+                    self.database.merge_password_policies(&export_data.password_policies)?;
+                    let audit_events_imported = audit_segments
+                        .as_deref()
+                        .map(|segments| audit_log::append_imported(&self.database, segments))
+                        .transpose()?;
+                    Ok((plan, audit_events_imported))
+                })?;
+                let imported_count = plan.inserts.len() + plan.updates.len();
+                audit_log::record(
+                    &self.database,
+                    audit_log::IMPORT,
+                    None,
+                    Some(&format!(
+                        "merge, {} inserted, {} updated, {} audit events",
+                        plan.inserts.len(),
+                        plan.updates.len(),
+                        audit_events_imported.unwrap_or(0)
+                    )),
+                    None,
+                )?;
+                self.vault_imported("merge", imported_count);
+
+                let mut message = format!(
+                    "Data merged successfully. {} inserted, {} updated, {} duplicates skipped.",
+                    plan.inserts.len(),
+                    plan.updates.len(),
+                    plan.skipped.len()
+                );
+                Self::note_malformed(&mut message, &malformed_entries);
+                Ok(ImportResponse {
+                    success: true,
+                    message,
+                    imported_entries_count: Some(imported_count),
+                    reencrypted_count,
+                    skipped_count,
+                    inserted_count: Some(plan.inserts.len()),
+                    updated_count: Some(plan.updates.len()),
+                    duplicates_skipped_count: Some(plan.skipped.len()),
+                    audit_events_imported,
+                    timings: Some(timings.finish("import", started, imported_count)),
+                    row_errors: None,
+                    malformed_entries: Some(malformed_entries),
+                    file_status: Some(ExportFileStatus::Valid),
+                })
+            }
+        }
     }
 
     // Preview import file without actually importing
     pub fn preview_import(&self, request: ImportRequest) -> Result<serde_json::Value> {
-        // Read encrypted file
-        let file_path = PathBuf::from(&request.file_path);
-        
-        if !file_path.exists() {
-            return Err(anyhow!("Import file does not exist"));
-        }
+        let mut timings = OperationTimings::default();
+        let (mut backup_info, mut export_data) = self.read_export_file(&request, &mut timings)?;
+        let malformed_entries = Self::take_malformed(&mut export_data);
+        let partial = backup_info.get("partial").and_then(serde_json::Value::as_bool).unwrap_or(false) || export_data.user_meta.is_none();
+        let mode = if export_data.user_meta.is_none() { ImportMode::Merge } else { request.mode };
 
-        let encrypted_data = fs::read_to_string(&file_path)?;
-
-        // Decrypt the data
-        let json_data = CryptoService::decrypt_export_data(&encrypted_data, &request.import_passphrase)
-            .map_err(|_| anyhow!("Failed to decrypt import file. Please check your passphrase."))?;
+        // Render the backup creation time for display
+        if let Some(created_at) = backup_info.get("created_at").and_then(|v| v.as_str()).map(str::to_string) {
+            let locale = SettingsService::locale_from(&self.database)?;
+            backup_info["created_at_display"] = serde_json::json!(time_utils::display_rfc3339(&created_at, &locale));
+        }
 
-        // Parse JSON
-        let import_json: serde_json::Value = serde_json::from_str(&json_data)?;
+        let sample = |entries: &[PasswordEntry]| {
+            entries
+                .iter()
+                .take(5)
+                .map(|entry| serde_json::json!({
+                    "software": entry.software,
+                    "account": entry.account
+                }))
+                .collect::<Vec<_>>()
+        };
 
-        // Extract metadata or create it
-        let (backup_info, export_data) = if let Some(data) = import_json.get("data") {
-            // New format with metadata
-            let backup_info = import_json.get("backup_info").cloned()
-                .unwrap_or_else(|| serde_json::json!({}));
-            let export_data: ExportData = serde_json::from_value(data.clone())?;
-            (backup_info, export_data)
+        // Predict the merge result so the UI can confirm before committing
+        let merge_preview = if mode == ImportMode::Merge {
+            let incoming = self.resolve_incoming(&export_data, &request, &mut timings)?;
+            let plan = self.plan_merge(&incoming, request.overwrite_duplicates)?;
+            serde_json::json!({
+                "inserted_count": plan.inserts.len(),
+                "updated_count": plan.updates.len(),
+                "duplicates_skipped_count": plan.skipped.len(),
+                "undecryptable_count": incoming.undecryptable_count,
+                "inserted_sample": sample(&plan.inserts),
+                "updated_sample": sample(&plan.updates),
+                "skipped_sample": sample(&plan.skipped)
+            })
         } else {
-            // Legacy format
-            let export_data: ExportData = serde_json::from_value(import_json)?;
-            let backup_info = serde_json::json!({
-                "version": "legacy",
-                "entry_count": export_data.password_entries.len(),
-                "has_user_data": true
-            });
-            (backup_info, export_data)
+            serde_json::Value::Null
         };
 
         // Create preview
         let preview = serde_json::json!({
             "backup_info": backup_info,
+            "mode": mode,
             "preview": {
                 "entry_count": export_data.password_entries.len(),
-                "has_security_questions": export_data.user_meta.question1.is_some(),
-                "entries_sample": export_data.password_entries
-                    .iter()
-                    .take(5)
-                    .map(|entry| serde_json::json!({
-                        "software": entry.software,
-                        "account": entry.account
-                    }))
-                    .collect::<Vec<_>>()
-            }
+                // A partial export holds chosen entries only; without user data it always merges
+                "partial": partial,
+                "has_user_data": export_data.user_meta.is_some(),
+                "has_security_questions": export_data.user_meta.as_ref().is_some_and(|user_meta| !user_meta.questions().is_empty()),
+                "audit_event_count": export_data.audit_log.as_ref().map_or(0, |audit_log| audit_log.events.len()),
+                // Names of entries from such a backup only show in merge previews that re-encrypt
+                "metadata_encrypted": export_data.metadata_encrypted,
+                "entries_sample": sample(&export_data.password_entries),
+                // Left out of entry_count, and of the import
+                "malformed_entries": malformed_entries
+            },
+            "merge_preview": merge_preview
         });
 
         Ok(preview)
     }
 
-    // Create a backup with specified path or default filename
-    pub fn create_backup(&self, export_passphrase: &str, backup_path: Option<&str>) -> Result<ExportResponse> {
+    // Create a backup with specified path or a default filename in `backup_dir`
+    pub fn create_backup(&self, export_passphrase: &str, backup_path: Option<&str>, backup_dir: &Path) -> Result<ExportResponse> {
         let final_path = if let Some(path) = backup_path {
             // Use provided path directly
             PathBuf::from(path)
         } else {
             // Create default backup filename with timestamp
-            let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
+            let timestamp = time_utils::file_stamp(&time_utils::now());
             let filename = format!("pwdbox_backup_{}.enc", timestamp);
-            
-            // Use a default backup directory
-            let home_dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-            home_dir.join("PwdBox_Backups").join(filename)
+            backup_dir.join(filename)
         };
 
         let request = ExportRequest {
             export_passphrase: export_passphrase.to_string(),
             file_path: final_path.to_string_lossy().to_string(),
+            include_stats_history: true,
+            include_icons: true,
+            include_attachments: true, // A backup should bring back everything
+            include_trash: true,
+            include_audit_log: true,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: true,
+            master_key: None,
         };
 
-        self.export_data(request)
+        let response = self.export_data(request)?;
+        if response.success {
+            SettingsService::record_backup(&self.database, &time_utils::now_rfc3339())?;
+            self.events.emit(events::BACKUP_CREATED, serde_json::json!({"file_path": response.file_path}));
+        }
+        Ok(response)
     }
 
-    // Validate an export file without importing
-    pub fn validate_export_file(&self, file_path: &str, passphrase: &str) -> Result<bool> {
-        let request = ImportRequest {
-            import_passphrase: passphrase.to_string(),
-            file_path: file_path.to_string(),
-        };
-
-        match self.preview_import(request) {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
-        }
+    // Entries were brought in from a file; `mode` is how, "replace", "merge", "csv" or "restore"
+    fn vault_imported(&self, mode: &str, imported_entries_count: usize) {
+        self.events.emit(
+            events::VAULT_IMPORTED,
+            serde_json::json!({"mode": mode, "imported_entries_count": imported_entries_count}),
+        );
     }
 
-    // Get export file info
-    pub fn get_export_info(&self, file_path: &str) -> Result<serde_json::Value> {
+    // Write the audit log as plaintext CSV for auditors. It holds no secrets, so unlike
+    // export_data the file is not encrypted.
+    pub fn export_audit_log_csv(&self, master_key: &str, file_path: &str) -> Result<ExportResponse> {
+        let started = Instant::now();
+        let mut timings = OperationTimings::default();
+
+        let vault_key = session::open_key(&self.database, master_key)?;
+        let (csv, event_count) = timed(&mut timings.db_ms, || audit_log::export_csv(&self.database, &vault_key))?;
+        timings.peak_memory_bytes = csv.len();
+
         let path = PathBuf::from(file_path);
-        
+        timed(&mut timings.file_io_ms, || -> Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_atomically(&path, |file| file.write_all(csv.as_bytes()))
+        })?;
+        audit_log::record(&self.database, audit_log::EXPORT, None, Some(&format!("audit log csv, {} events", event_count)), None)?;
+
+        Ok(ExportResponse {
+            success: true,
+            message: format!("Audit log exported successfully to {}", file_path),
+            file_path: Some(file_path.to_string()),
+            timings: Some(timings.finish("audit log export", started, event_count)),
+            failed_count: None,
+        })
+    }
+
+    // Decrypt every entry outside the trash into a CSV that Bitwarden and Chrome import
+    // (name,url,username,password,notes), for moving to another manager. The file holds
+    // every password in the clear, so the caller has to acknowledge that first. Entries
+    // that fail to decrypt are left out and counted. Rows are written a batch at a time.
+    pub fn export_csv(&self, master_key: &str, file_path: &str, acknowledge_plaintext: bool) -> Result<ExportResponse> {
+        if !acknowledge_plaintext {
+            return Err(anyhow!("A CSV export stores every password unencrypted; acknowledge this to continue"));
+        }
+        let started = Instant::now();
+        let mut timings = OperationTimings::default();
+
+        let vault_key = session::open_key(&self.database, master_key)?;
+        let batch_size = SettingsService::scan_batch_size_from(&self.database)?;
+        let (mut exported_count, mut failed_count) = (0, 0);
+
+        let path = PathBuf::from(file_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        timed(&mut timings.file_io_ms, || {
+            write_atomically(&path, |file| {
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(["name", "url", "username", "password", "notes"])?;
+                self.database.for_each_password_entry_batch(batch_size, false, |batch| {
+                    for mut entry in batch {
+                        let opened = PasswordService::open_metadata(&mut entry, &vault_key)
+                            .and_then(|_| Self::decrypt_secrets(&entry, &vault_key));
+                        let Ok((password, notes)) = opened else {
+                            failed_count += 1;
+                            continue;
+                        };
+                        writer.write_record([
+                            entry.software.as_str(),
+                            entry.url.as_deref().unwrap_or_default(),
+                            entry.account.as_str(),
+                            password.as_str(),
+                            notes.as_deref().map_or("", String::as_str),
+                        ])?;
+                        exported_count += 1;
+                    }
+                    Ok(())
+                }).map_err(std::io::Error::other)?;
+                writer.flush()
+            })
+        })?;
+        audit_log::record(
+            &self.database,
+            audit_log::EXPORT,
+            None,
+            Some(&format!("plaintext csv, {} entries, {} failed", exported_count, failed_count)),
+            None,
+        )?;
+
+        let message = if failed_count > 0 {
+            format!("Exported {} entries to {}; {} could not be decrypted and were left out", exported_count, file_path, failed_count)
+        } else {
+            format!("Exported {} entries to {}", exported_count, file_path)
+        };
+        Ok(ExportResponse {
+            success: true,
+            message,
+            file_path: Some(file_path.to_string()),
+            timings: Some(timings.finish("csv export", started, exported_count)),
+            failed_count: Some(failed_count),
+        })
+    }
+
+    // An entry's password and notes, cleared from memory when dropped
+    fn decrypt_secrets(entry: &PasswordEntry, vault_key: &[u8; 32]) -> Result<(Zeroizing<String>, Option<Zeroizing<String>>)> {
+        let password = CryptoService::decrypt_password(&entry.encrypted_password, &entry.nonce, vault_key, entry.entry_uid.as_deref())?;
+        let notes = CryptoService::decrypt_notes(entry.notes.as_deref(), entry.notes_nonce.as_deref(), vault_key, entry.entry_uid.as_deref())?;
+        Ok((Zeroizing::new(password), notes.map(Zeroizing::new)))
+    }
+
+    // Bring in a CSV export from a browser or another password manager, read per `options`.
+    // Every entry is written in one transaction. Rows that cannot be read are left out and
+    // reported (or, in strict mode, stop the import); rows matching a vault entry on
+    // (software, account) skip or update it per `mode`.
+    pub fn import_csv(&self, file_path: &str, master_key: &str, mode: CsvImportMode, options: &CsvImportOptions) -> Result<ImportResponse> {
+        if !PathBuf::from(file_path).exists() {
+            return Ok(ImportResponse::failure("Import file does not exist"));
+        }
+        let started = Instant::now();
+        let mut timings = OperationTimings::default();
+
+        let vault_key = session::open_key(&self.database, master_key)?;
+        let (_, layout, rows, row_errors) = match timed(&mut timings.file_io_ms, || Self::read_csv(file_path, options)) {
+            Ok(parsed) => parsed,
+            Err(e) => return Ok(ImportResponse::failure(&e.to_string())),
+        };
+        if options.strict && !row_errors.is_empty() {
+            return Ok(ImportResponse {
+                row_errors: Some(row_errors),
+                ..ImportResponse::failure("Nothing was imported: some rows could not be read")
+            });
+        }
+        let actions = timed(&mut timings.db_ms, || self.plan_csv_import(&rows, &vault_key, mode))?;
+
+        let encrypt_metadata = SettingsService::encrypt_metadata_from(&self.database)?;
+        let (mut inserts, mut updates, mut duplicates_skipped) = (Vec::new(), Vec::new(), 0);
+        timed(&mut timings.crypto_ms, || -> Result<()> {
+            let now = time_utils::now_rfc3339();
+            for (row, action) in rows.iter().zip(actions) {
+                let (current, entries) = match action {
+                    CsvRowAction::Insert => (None, &mut inserts),
+                    CsvRowAction::Update(current) => (Some(*current), &mut updates),
+                    CsvRowAction::Skip => {
+                        duplicates_skipped += 1;
+                        continue;
+                    }
+                };
+                let mut entry = Self::csv_entry(row, current, &vault_key, &now)?;
+                if encrypt_metadata {
+                    PasswordService::seal_metadata(&mut entry, &vault_key)?;
+                }
+                entries.push(entry);
+            }
+            Ok(())
+        })?;
+        timed(&mut timings.db_ms, || -> Result<()> {
+            PasswordService::ensure_capacity(self.database.count_password_entries()? + inserts.len())?;
+            self.database.merge_password_entries(&inserts, &updates)
+        })?;
+        audit_log::record(
+            &self.database,
+            audit_log::IMPORT,
+            None,
+            Some(&format!(
+                "{} csv, {} inserted, {} updated, {} rows failed",
+                layout.format,
+                inserts.len(),
+                updates.len(),
+                row_errors.len()
+            )),
+            None,
+        )?;
+
+        let imported_count = inserts.len() + updates.len();
+        let mut message = format!(
+            "Imported {} entries: {} added, {} updated, {} duplicates skipped.",
+            imported_count,
+            inserts.len(),
+            updates.len(),
+            duplicates_skipped
+        );
+        if !row_errors.is_empty() {
+            message.push_str(&format!(" {} rows could not be read.", row_errors.len()));
+        }
+        self.vault_imported("csv", imported_count);
+        Ok(ImportResponse {
+            success: true,
+            message,
+            imported_entries_count: Some(imported_count),
+            reencrypted_count: None,
+            skipped_count: None,
+            inserted_count: Some(inserts.len()),
+            updated_count: Some(updates.len()),
+            duplicates_skipped_count: Some(duplicates_skipped),
+            audit_events_imported: None,
+            timings: Some(timings.finish("csv import", started, imported_count)),
+            row_errors: Some(row_errors),
+            malformed_entries: None,
+            file_status: None,
+        })
+    }
+
+    // The column mapping and the first `limit` rows as import_csv would read them, each with
+    // what importing it would do. Passwords are left out.
+    pub fn preview_csv_import(&self, file_path: &str, master_key: &str, mode: CsvImportMode, options: &CsvImportOptions, limit: usize) -> Result<serde_json::Value> {
+        let vault_key = session::open_key(&self.database, master_key)?;
+        let (header, layout, rows, row_errors) = Self::read_csv(file_path, options)?;
+        let actions = self.plan_csv_import(&rows, &vault_key, mode)?;
+        let count = |name: &str| actions.iter().filter(|action| action.as_str() == name).count();
+
+        Ok(serde_json::json!({
+            "format": layout.format,
+            "columns": layout.describe(&header),
+            "mode": mode,
+            "strict": options.strict,
+            "row_count": rows.len(),
+            "inserted_count": count("insert"),
+            "updated_count": count("update"),
+            "duplicates_skipped_count": count("skip"),
+            "row_errors": row_errors,
+            "rows": rows.iter().zip(&actions).take(limit).map(|(row, action)| serde_json::json!({
+                "line": row.line,
+                "software": row.software,
+                "account": row.account,
+                "url": row.url,
+                "has_notes": row.notes.is_some(),
+                "tags": row.tags,
+                "is_favorite": row.favorite,
+                "action": action.as_str()
+            })).collect::<Vec<_>>()
+        }))
+    }
+
+    // Parse a CSV export, collecting the rows that cannot be read
+    fn read_csv(file_path: &str, options: &CsvImportOptions) -> Result<(csv::StringRecord, CsvLayout, Vec<CsvRow>, Vec<CsvRowError>)> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(file_path)?;
+        let header = reader.headers()?.clone();
+        let layout = CsvLayout::resolve(&header, options)?;
+        let (mut rows, mut row_errors) = (Vec::new(), Vec::new());
+        let mut record = csv::StringRecord::new();
+        loop {
+            match reader.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => {
+                    let line = record.position().map_or(0, |position| position.line());
+                    match layout.parse(&record, line) {
+                        Ok(row) => rows.push(row),
+                        Err(row_error) => row_errors.push(row_error),
+                    }
+                }
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                Err(e) => row_errors.push(CsvRowError {
+                    line: e.position().map_or(0, |position| position.line()),
+                    message: e.to_string(),
+                }),
+            }
+        }
+        Ok((header, layout, rows, row_errors))
+    }
+
+    // Decide what each CSV row does, matching on (software, account) against the vault's
+    // entries with their names opened. A pair repeated within the file keeps its first row.
+    fn plan_csv_import(&self, rows: &[CsvRow], vault_key: &[u8; 32], mode: CsvImportMode) -> Result<Vec<CsvRowAction>> {
+        let mut existing = HashMap::new();
+        for mut entry in self.database.get_all_password_entries()? {
+            PasswordService::open_metadata(&mut entry, vault_key)?;
+            existing.insert((entry.software.clone(), entry.account.clone()), entry);
+        }
+
+        let mut seen = HashSet::new();
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let pair = (row.software.clone(), row.account.clone());
+                if !seen.insert(pair.clone()) {
+                    return CsvRowAction::Skip;
+                }
+                match existing.remove(&pair) {
+                    None => CsvRowAction::Insert,
+                    Some(current) if mode == CsvImportMode::Update && !Self::csv_row_matches(row, &current, vault_key) => {
+                        CsvRowAction::Update(Box::new(current))
+                    }
+                    Some(_) => CsvRowAction::Skip,
+                }
+            })
+            .collect())
+    }
+
+    // Whether the vault entry already holds everything the row would write
+    fn csv_row_matches(row: &CsvRow, entry: &PasswordEntry, vault_key: &[u8; 32]) -> bool {
+        let Ok((password, notes)) = Self::decrypt_secrets(entry, vault_key) else {
+            return false;
+        };
+        password == row.password
+            && row.notes.as_ref().is_none_or(|row_notes| notes.as_ref() == Some(row_notes))
+            && row.url.as_ref().is_none_or(|url| entry.url.as_ref() == Some(url))
+            && row.favorite.is_none_or(|favorite| favorite == entry.is_favorite)
+    }
+
+    // The entry to write for a CSV row: a new one, or `current` with the row's password and,
+    // where the row has them, its notes, URL and favorite flag. Tags are added to the entry's own.
+    fn csv_entry(row: &CsvRow, current: Option<PasswordEntry>, vault_key: &[u8; 32], now: &str) -> Result<PasswordEntry> {
+        let current = current.unwrap_or_else(|| PasswordEntry {
+            software: row.software.clone(),
+            account: row.account.clone(),
+            created_at: Some(now.to_string()),
+            ..Default::default()
+        });
+        let (current_password, current_notes) = Self::decrypt_secrets(&current, vault_key)
+            .map_or((None, None), |(password, notes)| (Some(password), notes));
+        let password_changed = current_password.as_ref() != Some(&row.password);
+
+        let entry_uid = current.entry_uid.clone().unwrap_or_else(CryptoService::generate_entry_uid);
+        let (encrypted_password, nonce) = CryptoService::encrypt_password(&row.password, vault_key, Some(&entry_uid))?;
+        let notes = row.notes.as_ref().or(current_notes.as_ref()).map(|notes| notes.as_str());
+        let (notes, notes_nonce) = CryptoService::encrypt_notes(notes, vault_key, Some(&entry_uid))?;
+        Ok(PasswordEntry {
+            encrypted_password,
+            nonce,
+            notes,
+            notes_nonce,
+            password_changed_at: if password_changed { Some(now.to_string()) } else { current.password_changed_at.clone() },
+            breach_acknowledged_at: if password_changed { None } else { current.breach_acknowledged_at.clone() },
+            strength_score: Some(CryptoService::password_strength(&row.password)),
+            updated_at: Some(now.to_string()),
+            url: row.url.clone().or_else(|| current.url.clone()),
+            is_favorite: row.favorite.unwrap_or(current.is_favorite),
+            tags: row.tags.clone(),
+            entry_uid: Some(entry_uid),
+            ..current
+        })
+    }
+
+    // First step of a restore: decrypt and check the backup without touching the vault.
+    // A damaged file, a wrong passphrase or a backup without user data is an error, so no
+    // token is issued for anything confirm_restore could not restore.
+    pub fn prepare_restore(&self, file_path: &str, passphrase: &str, now: Instant) -> Result<(PendingRestore, RestoreSummary)> {
+        let (backup_info, export_data) = self.read_export_file(&Self::read_request(file_path, passphrase), &mut OperationTimings::default())?;
+        if export_data.user_meta.as_ref().is_none_or(|user_meta| user_meta.master_hash.is_empty()) {
+            return Err(anyhow!("The backup has no user data to restore; import it into this vault instead"));
+        }
+        // A restore brings the vault back as it was, so it does not leave entries out
+        for entry in &export_data.password_entries {
+            if let Some(defect) = Database::entry_defect(entry) {
+                return Err(anyhow!("The backup cannot be restored: entry {} is malformed ({})", entry.id.unwrap_or_default(), defect));
+            }
+        }
+        PasswordService::ensure_capacity(export_data.password_entries.len())?;
+
+        let pending = PendingRestore::new(export_data, now);
+        let summary = RestoreSummary {
+            token: pending.token.clone(),
+            expires_in_secs: RESTORE_TOKEN_TTL.as_secs(),
+            created_at: backup_info.get("created_at").and_then(serde_json::Value::as_str).map(str::to_string),
+            entry_count: pending.data.password_entries.len(),
+            trash_count: pending.data.password_entries.iter().filter(|entry| entry.deleted_at.is_some()).count(),
+            audit_events: pending.data.audit_log.as_ref().map(|audit_log| audit_log.events.len()),
+            metadata_encrypted: pending.data.metadata_encrypted,
+        };
+        Ok((pending, summary))
+    }
+
+    // Second step: copy the database aside, then replace the vault with the backup in one
+    // transaction
+    pub fn confirm_restore(&self, export_data: &ExportData) -> Result<RestoreResponse> {
+        let pre_restore_backup = self.database.safety_copy("restore_backups", "pwdbox_pre_restore_")?;
+        self.database.import_all_data(export_data)?;
+        if export_data.metadata_encrypted {
+            SettingsService::enable_encrypt_metadata(&self.database)?;
+        }
+
+        let entry_count = export_data.password_entries.len();
+        let audit_events_restored = export_data.audit_log.as_ref().map(|audit_log| audit_log.events.len());
+        audit_log::record(
+            &self.database,
+            audit_log::IMPORT,
+            None,
+            Some(&format!("restore, {} entries, {} audit events", entry_count, audit_events_restored.unwrap_or(0))),
+            None,
+        )?;
+        self.vault_imported("restore", entry_count);
+
+        Ok(RestoreResponse {
+            success: true,
+            message: format!("Backup restored. {} password entries restored; log in with the backup's master password.", entry_count),
+            restored_entries_count: entry_count,
+            audit_events_restored,
+            pre_restore_backup: pre_restore_backup.to_string_lossy().to_string(),
+        })
+    }
+
+    // Validate an export file without importing
+    pub fn validate_export_file(&self, file_path: &str, passphrase: &str) -> Result<ExportFileStatus> {
+        match self.preview_import(Self::read_request(file_path, passphrase)) {
+            Ok(_) => Ok(ExportFileStatus::Valid),
+            Err(e) => Ok(ExportFileStatus::of(&e)),
+        }
+    }
+
+    // A request that only reads the file
+    fn read_request(file_path: &str, passphrase: &str) -> ImportRequest {
+        ImportRequest {
+            import_passphrase: passphrase.to_string(),
+            file_path: file_path.to_string(),
+            mode: ImportMode::Replace,
+            overwrite_duplicates: false,
+            source_master_password: None,
+            target_master_key: None,
+            session_key: None,
+        }
+    }
+
+    // Get export file info. v2 files carry their format version and creation details in a
+    // plaintext header; v1 files only show that they parse. What the export holds is inside
+    // the encryption, so whether it is full or partial is only reported with the passphrase.
+    pub fn get_export_info(&self, file_path: &str, passphrase: Option<&str>) -> Result<serde_json::Value> {
+        let (mut info, contents) = self.inspect_export_file(Path::new(file_path), passphrase)?;
+        if let Some(contents) = contents {
+            info["contents"] = contents?;
+        }
+        Ok(info)
+    }
+
+    // The file's details for get_export_info, with "contents" left null, and, given the
+    // passphrase, what the export holds or why it would not open. The file is read once.
+    fn inspect_export_file(&self, path: &Path, passphrase: Option<&str>) -> Result<(serde_json::Value, Option<Result<serde_json::Value>>)> {
         if !path.exists() {
             return Err(anyhow!("File does not exist"));
         }
 
-        let metadata = fs::metadata(&path)?;
+        let metadata = fs::metadata(path)?;
         let modified = metadata.modified()?
             .duration_since(std::time::UNIX_EPOCH)?
             .as_secs();
+        let modified_at = time_utils::from_unix_secs(modified as i64);
+        let locale = SettingsService::locale_from(&self.database)?;
 
-        Ok(serde_json::json!({
-            "file_path": file_path,
+        let contents = fs::read(path)?;
+        let file_size = contents.len();
+        let envelope = CryptoService::parse_export_data(contents);
+        let parsed = envelope.as_ref().ok();
+        let header = parsed.and_then(|envelope| envelope.header.as_ref());
+
+        let info = serde_json::json!({
+            "file_path": path.to_string_lossy(),
             "file_size": metadata.len(),
-            "modified_at": chrono::DateTime::<chrono::Utc>::from_timestamp(modified as i64, 0)
-                .map(|dt| dt.to_rfc3339())
+            "modified_at": modified_at
+                .map(|dt| time_utils::to_rfc3339(&dt))
                 .unwrap_or_else(|| "unknown".to_string()),
-            "exists": true
-        }))
+            "modified_at_display": modified_at
+                .map(|dt| time_utils::format_for_locale(&dt, &locale))
+                .unwrap_or_else(|| "unknown".to_string()),
+            "exists": true,
+            "is_pwdbox_export": parsed.is_some(),
+            "format_version": parsed.map(|envelope| envelope.format_version),
+            "kdf_params": parsed.map(|envelope| envelope.kdf_params),
+            "created_at": header.map(|header| &header.created_at),
+            "created_at_display": header.map(|header| time_utils::display_rfc3339(&header.created_at, &locale)),
+            "app_version": header.map(|header| &header.app_version),
+            "contents": serde_json::Value::Null
+        });
+
+        let contents = passphrase.map(|passphrase| {
+            let (backup_info, export_data) = Self::open_export(envelope?, passphrase, file_size, &mut OperationTimings::default())?;
+            let partial = backup_info.get("partial").and_then(serde_json::Value::as_bool).unwrap_or(false) || export_data.user_meta.is_none();
+            Ok(serde_json::json!({
+                "backup_info": backup_info,
+                "partial": partial,
+                "has_user_data": export_data.user_meta.is_some()
+            }))
+        });
+        Ok((info, contents))
+    }
+
+    // Every file in a backup directory, newest first. Entry counts are inside the encryption,
+    // so they are only read when the passphrase is given.
+    pub fn list_backups(&self, backup_dir: &str, passphrase: Option<&str>) -> Result<Vec<BackupListing>> {
+        let dir_path = PathBuf::from(backup_dir);
+        if !dir_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(&dir_path)? {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(_) => continue,
+            };
+            if path.is_file() {
+                backups.push(self.backup_listing(&path, passphrase));
+            }
+        }
+
+        backups.sort_by_key(|backup| {
+            std::cmp::Reverse(backup.created_at.as_deref().and_then(|created_at| time_utils::parse_rfc3339(created_at).ok()))
+        });
+        Ok(backups)
+    }
+
+    fn backup_listing(&self, path: &Path, passphrase: Option<&str>) -> BackupListing {
+        let mut listing = BackupListing {
+            file_name: path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+            file_path: path.to_string_lossy().to_string(),
+            file_size: None,
+            created_at: None,
+            created_at_display: None,
+            is_pwdbox_export: false,
+            format_version: None,
+            entry_count: None,
+            error: None,
+        };
+        let (info, contents) = match self.inspect_export_file(path, passphrase) {
+            Ok(inspected) => inspected,
+            Err(e) => {
+                listing.error = Some(e.to_string());
+                return listing;
+            }
+        };
+
+        let known = |key: &str| info[key].as_str().filter(|value| *value != "unknown").map(str::to_string);
+        listing.file_size = info["file_size"].as_u64();
+        listing.created_at = known("created_at").or_else(|| known("modified_at"));
+        listing.created_at_display = known("created_at_display").or_else(|| known("modified_at_display"));
+        listing.is_pwdbox_export = info["is_pwdbox_export"].as_bool().unwrap_or(false);
+        listing.format_version = info["format_version"].as_u64().map(|version| version as u32);
+
+        if !listing.is_pwdbox_export {
+            listing.error = Some(NOT_AN_EXPORT.to_string());
+        } else {
+            match contents {
+                Some(Ok(contents)) => listing.entry_count = contents["backup_info"]["entry_count"].as_u64().map(|count| count as usize),
+                Some(Err(e)) => listing.error = Some(e.to_string()),
+                None => {}
+            }
+        }
+        listing
     }
 
     // Clean up old backup files
@@ -276,6 +1808,7 @@ impl ExportService {
             
             if path.is_file() {
                 if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
+                    // Vault snapshots (pwdbox_snapshot_*.snap) have their own ring and never match
                     if filename.starts_with("pwdbox_backup_") && filename.ends_with(".enc") {
                         let metadata = entry.metadata()?;
                         backup_files.push((path, metadata.modified()?));
@@ -285,7 +1818,7 @@ impl ExportService {
         }
 
         // Sort by modification time (newest first)
-        backup_files.sort_by(|a, b| b.1.cmp(&a.1));
+        backup_files.sort_by_key(|b| std::cmp::Reverse(b.1));
 
         // Remove old backups (keep only the specified count)
         let mut cleaned_count = 0;
@@ -301,4 +1834,1001 @@ impl ExportService {
             "message": format!("Cleaned up {} old backup files", cleaned_count)
         }))
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{CustomFieldKind, EntryType, SecretKind};
+    use crate::password_service::{AddPasswordRequest, DecryptPasswordRequest, DeletePasswordRequest, GetPasswordsRequest, SetCustomFieldRequest};
+    use crate::crypto::KdfParams;
+    use crate::error::PwdBoxError;
+    use crate::user_service::{LoginRequest, SetupRequest};
+
+    struct Vault {
+        user_service: UserService,
+        password_service: PasswordService,
+        export_service: ExportService,
+        master_key: String,
+    }
+
+    fn vault(path: &Path, master_password: &str) -> Vault {
+        let user_service = UserService::new(Database::new(path.to_path_buf()).unwrap());
+        let master_key = user_service.setup_app(SetupRequest {
+            master_password: master_password.to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+
+        Vault {
+            user_service,
+            password_service: PasswordService::new(Database::new(path.to_path_buf()).unwrap(), EventEmitter::default()),
+            export_service: ExportService::new(Database::new(path.to_path_buf()).unwrap(), EventEmitter::default()),
+            master_key,
+        }
+    }
+
+    fn add(vault: &Vault, software: &str, password: &str, master_key: &str) -> i64 {
+        let response = vault.password_service.add_password(AddPasswordRequest {
+            software: software.to_string(),
+            account: "me@example.com".to_string(),
+            password: password.to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.to_string(),
+        }).unwrap();
+        response.data.unwrap()["id"].as_i64().unwrap()
+    }
+
+    fn entry_ids(vault: &Vault) -> Vec<i64> {
+        let response = vault.password_service.get_all_passwords(GetPasswordsRequest {
+            master_key: vault.master_key.clone(),
+            ..Default::default()
+        }).unwrap();
+        response.data.unwrap()["entries"].as_array().unwrap().iter().map(|e| e["id"].as_i64().unwrap()).collect()
+    }
+
+    fn export(vault: &Vault, file_path: &Path) {
+        vault.export_service.export_data(ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments: false,
+            include_trash: false,
+            include_audit_log: false,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: true,
+            master_key: None,
+        }).unwrap();
+    }
+
+    fn import_request(file_path: &Path, source_master_password: Option<&str>, target_master_key: Option<&str>) -> ImportRequest {
+        ImportRequest {
+            import_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            mode: ImportMode::Replace,
+            overwrite_duplicates: false,
+            source_master_password: source_master_password.map(str::to_string),
+            target_master_key: target_master_key.map(str::to_string),
+            session_key: None,
+        }
+    }
+
+    #[test]
+    fn test_import_reencrypts_into_current_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        let mail = add(&source, "mail", "mail-secret", &source.master_key);
+        add(&source, "bank", "bank-secret", &source.master_key);
+        source.password_service.add_tag_to_entry(mail, "Work").unwrap();
+        source.password_service.toggle_favorite(mail).unwrap();
+        // An entry the backup's key cannot decrypt
+        let foreign_key = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, CryptoService::generate_vault_key());
+        add(&source, "broken", "lost", &foreign_key);
+
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let response = target.export_service
+            .import_data(import_request(&file_path, Some("source_master"), Some(&target.master_key)))
+            .unwrap();
+        assert!(response.success);
+        assert_eq!(response.reencrypted_count, Some(2));
+        assert_eq!(response.skipped_count, Some(1));
+        let timings = response.timings.unwrap();
+        assert_eq!(timings.rows, 2);
+        assert!(timings.kdf_ms > 0 && timings.peak_memory_bytes > 0);
+
+        // The target keeps its own login and can read the imported entries
+        let login = target.user_service.login(LoginRequest { master_password: "target_master".to_string() }).unwrap();
+        assert!(login.success);
+        assert!(!target.user_service.login(LoginRequest { master_password: "source_master".to_string() }).unwrap().success);
+
+        let entries = entry_ids(&target);
+        assert_eq!(entries.len(), 2);
+        for (id, (expected, tags, is_favorite)) in entries.iter().zip([("mail-secret", vec!["Work"], true), ("bank-secret", vec![], false)]) {
+            let entry = target.password_service.get_password(DecryptPasswordRequest {
+                id: *id,
+                master_key: login.master_key.clone().unwrap(),
+                skip_usage_tracking: false,
+            }).unwrap().data.unwrap();
+            assert_eq!(entry["password"], expected);
+            assert_eq!(entry["tags"], serde_json::json!(tags));
+            assert_eq!(entry["is_favorite"], is_favorite);
+        }
+    }
+
+    #[test]
+    fn test_import_leaves_out_malformed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        let broken = add(&source, "broken", "lost", &source.master_key);
+        // Written around the checks in Database, as by an older build
+        rusqlite::Connection::open(dir.path().join("source.db")).unwrap()
+            .execute("UPDATE password_entries SET nonce = 'AAAA' WHERE id = ?1", [broken])
+            .unwrap();
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+        let malformed = vec![CorruptEntry { id: broken, defect: "nonce is 3 bytes, not 12".to_string() }];
+
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let preview = target.export_service.preview_import(import_request(&file_path, None, None)).unwrap();
+        assert_eq!(preview["preview"]["entry_count"], 1);
+        assert_eq!(preview["preview"]["malformed_entries"], serde_json::json!(malformed));
+
+        let response = target.export_service.import_data(import_request(&file_path, None, None)).unwrap();
+        assert!(response.success);
+        assert_eq!(response.imported_entries_count, Some(1));
+        assert_eq!(response.malformed_entries, Some(malformed));
+        assert!(response.message.ends_with("1 malformed entries were left out."));
+        assert_eq!(target.password_service.check_integrity().unwrap().data.unwrap()["corrupt_entries"], serde_json::json!([]));
+
+        // A restore cannot leave entries out, so it refuses the backup
+        let error = target.export_service.prepare_restore(file_path.to_str().unwrap(), "passphrase", Instant::now()).err().unwrap();
+        assert!(error.to_string().contains(&format!("entry {} is malformed", broken)));
+    }
+
+    #[test]
+    fn test_legacy_plaintext_notes_are_not_malformed() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        let legacy = add(&source, "mail", "mail-secret", &source.master_key);
+        // Notes from before they were encrypted carry no nonce
+        rusqlite::Connection::open(dir.path().join("source.db")).unwrap()
+            .execute("UPDATE password_entries SET notes = 'plain notes', notes_nonce = NULL WHERE id = ?1", [legacy])
+            .unwrap();
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let preview = target.export_service.preview_import(import_request(&file_path, None, None)).unwrap();
+        assert_eq!(preview["preview"]["malformed_entries"], serde_json::json!([]));
+        let response = target.export_service.import_data(import_request(&file_path, None, None)).unwrap();
+        assert_eq!(response.imported_entries_count, Some(1));
+        assert_eq!(response.malformed_entries, Some(Vec::new()));
+
+        let stored = target.export_service.database.get_all_password_entries().unwrap();
+        assert_eq!(stored[0].notes.as_deref(), Some("plain notes"));
+        assert_eq!(stored[0].notes_nonce, None);
+        assert_eq!(target.password_service.check_integrity().unwrap().data.unwrap()["corrupt_entries"], serde_json::json!([]));
+        assert!(target.export_service.prepare_restore(file_path.to_str().unwrap(), "passphrase", Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_encrypted_names_survive_export_and_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        source.password_service.encrypt_metadata(&source.master_key).unwrap();
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+
+        // Re-encrypted into a vault with plaintext names, which then keeps its names encrypted too
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        add(&target, "bank", "bank-secret", &target.master_key);
+        let request = ImportRequest { mode: ImportMode::Merge, ..import_request(&file_path, Some("source_master"), Some(&target.master_key)) };
+        assert_eq!(target.export_service.import_data(request).unwrap().inserted_count, Some(1));
+        assert!(SettingsService::encrypt_metadata_from(&target.export_service.database).unwrap());
+
+        let stored = target.export_service.database.get_all_password_entries().unwrap();
+        assert!(stored[1].software.is_empty() && stored[1].software_enc.is_some());
+        let page = target.password_service.get_all_passwords(GetPasswordsRequest {
+            master_key: target.master_key.clone(),
+            ..Default::default()
+        }).unwrap().data.unwrap();
+        let names: Vec<&str> = page["entries"].as_array().unwrap().iter().map(|entry| entry["software"].as_str().unwrap()).collect();
+        assert_eq!(names, ["bank", "mail"]);
+
+        // Without both keys the encrypted names cannot be deduplicated on, even in a backup
+        // of this vault; one of another vault is refused before that
+        let request = ImportRequest { mode: ImportMode::Merge, ..import_request(&file_path, None, None) };
+        assert!(!target.export_service.import_data(request).unwrap().success);
+        let own_backup = dir.path().join("own.enc");
+        export(&target, &own_backup);
+        let request = ImportRequest {
+            mode: ImportMode::Merge,
+            session_key: Some(target.master_key.clone()),
+            ..import_request(&own_backup, None, None)
+        };
+        assert!(target.export_service.import_data(request).is_err());
+    }
+
+    #[test]
+    fn test_export_skips_trash_unless_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        let bank = add(&source, "bank", "bank-secret", &source.master_key);
+        source.password_service.delete_password(DeletePasswordRequest { id: bank }).unwrap();
+        source.password_service.set_password_policy(crate::database::PasswordPolicy {
+            tag: "infra".to_string(),
+            min_entropy_bits: 64,
+            mode: crate::database::PolicyMode::Strict,
+        }).unwrap();
+
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+        let (_, data) = source.export_service.read_export_file(&import_request(&file_path, None, None), &mut OperationTimings::default()).unwrap();
+        assert_eq!(data.password_entries.len(), 1);
+        assert_eq!(data.password_policies.len(), 1);
+
+        source.export_service.export_data(ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments: false,
+            include_trash: true,
+            include_audit_log: false,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: true,
+            master_key: None,
+        }).unwrap();
+        let (_, data) = source.export_service.read_export_file(&import_request(&file_path, None, None), &mut OperationTimings::default()).unwrap();
+        let trashed: Vec<_> = data.password_entries.iter().filter(|entry| entry.deleted_at.is_some()).collect();
+        assert_eq!((data.password_entries.len(), trashed.len()), (2, 1));
+        assert_eq!(trashed[0].software, "bank");
+    }
+
+    #[test]
+    fn test_failed_export_write_keeps_the_previous_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("backup.enc");
+        fs::write(&file_path, "previous good backup").unwrap();
+
+        // The device fills up halfway through the write
+        let error = write_atomically(&file_path, |file| {
+            file.write_all(b"half of the new exp")?;
+            Err(std::io::Error::other("No space left on device"))
+        }).unwrap_err();
+        let error = error.downcast_ref::<ExportWriteError>().unwrap();
+        assert_eq!(error.temp_path.parent(), Some(dir.path()));
+        assert!(error.to_string().contains(&error.temp_path.display().to_string()));
+        assert!(!error.temp_path.exists());
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "previous good backup");
+
+        // A full export replaces the file once it is completely written
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        export(&source, &file_path);
+        assert_eq!(source.export_service.validate_export_file(&file_path.to_string_lossy(), "passphrase").unwrap(), ExportFileStatus::Valid);
+        let leftovers: Vec<_> = fs::read_dir(dir.path()).unwrap()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn test_csv_export_writes_plaintext_only_when_acknowledged() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        source.password_service.add_password(AddPasswordRequest {
+            software: "Mail, Inc.".to_string(),
+            account: "me@example.com".to_string(),
+            password: "pa\"ss,word".to_string(),
+            notes: Some("line one\nline \"two\"".to_string()),
+            expires_at: None,
+            tags: Vec::new(),
+            url: Some("https://mail.example.com".to_string()),
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: source.master_key.clone(),
+        }).unwrap();
+        // One entry whose ciphertext no longer opens
+        let broken = add(&source, "bank", "bank-secret", &source.master_key);
+        let mut entry = source.export_service.database.get_password_entry_by_id(broken).unwrap().unwrap();
+        entry.nonce = CryptoService::generate_nonce();
+        source.export_service.database.update_password_entries(&[entry]).unwrap();
+
+        let file_path = dir.path().join("export.csv");
+        let file_name = file_path.to_string_lossy().to_string();
+        assert!(source.export_service.export_csv(&source.master_key, &file_name, false).is_err());
+        assert!(!file_path.exists());
+
+        let response = source.export_service.export_csv(&source.master_key, &file_name, true).unwrap();
+        assert_eq!(response.failed_count, Some(1));
+        let mut reader = csv::Reader::from_path(&file_path).unwrap();
+        assert_eq!(reader.headers().unwrap(), vec!["name", "url", "username", "password", "notes"]);
+        let rows: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows, [vec!["Mail, Inc.", "https://mail.example.com", "me@example.com", "pa\"ss,word", "line one\nline \"two\""]]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&file_path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_browser_csv_import_skips_or_updates_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = vault(&dir.path().join("vault.db"), "vault_master");
+        let mail = add_for(&vault, "mail", "me@example.com", "old-mail-secret");
+
+        let chrome = dir.path().join("chrome.csv");
+        fs::write(&chrome, "\u{feff}name,url,username,password,note\n\
+            mail,https://mail.example.com,me@example.com,new-mail-secret,\n\
+            bank,https://bank.example.com,me@example.com,\"bank,secret\",pin is 1234\n\
+            bank,https://bank.example.com,me@example.com,other-bank-secret,\n\
+            forum,https://forum.example.com,me,,\n\
+            ,,me,orphan-secret,\n").unwrap();
+        let chrome = chrome.to_string_lossy().to_string();
+
+        let preview = vault.export_service.preview_csv_import(&chrome, &vault.master_key, CsvImportMode::Skip, &CsvImportOptions::default(), 2).unwrap();
+        assert_eq!(preview["format"], "chrome");
+        assert_eq!(preview["rows"].as_array().unwrap().len(), 2);
+        assert_eq!(preview["rows"][0]["action"], "skip");
+        assert_eq!(preview["rows"][1]["action"], "insert");
+        assert_eq!(entry_ids(&vault), vec![mail]);
+
+        let response = vault.export_service.import_csv(&chrome, &vault.master_key, CsvImportMode::Skip, &CsvImportOptions::default()).unwrap();
+        assert!(response.success);
+        assert_eq!((response.inserted_count, response.updated_count, response.duplicates_skipped_count), (Some(1), Some(0), Some(2)));
+        let lines: Vec<u64> = response.row_errors.unwrap().iter().map(|row_error| row_error.line).collect();
+        assert_eq!(lines, vec![5, 6]);
+        assert_eq!(reveal(&vault, mail), "old-mail-secret");
+        let bank = *entry_ids(&vault).iter().find(|id| **id != mail).unwrap();
+        assert_eq!(reveal(&vault, bank), "bank,secret");
+
+        // Firefox names no software, so the URL's host stands in for it
+        let firefox = dir.path().join("firefox.csv");
+        fs::write(&firefox, "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\"\n\
+            \"https://mail.example.com\",\"me@example.com\",\"new-mail-secret\",,\"\",\"{1}\"\n").unwrap();
+        let firefox = firefox.to_string_lossy().to_string();
+        let preview = vault.export_service.preview_csv_import(&firefox, &vault.master_key, CsvImportMode::Update, &CsvImportOptions::default(), 10).unwrap();
+        assert_eq!(preview["format"], "firefox");
+        assert_eq!(preview["rows"][0]["software"], "mail.example.com");
+
+        let response = vault.export_service.import_csv(&chrome, &vault.master_key, CsvImportMode::Update, &CsvImportOptions::default()).unwrap();
+        assert_eq!((response.inserted_count, response.updated_count, response.duplicates_skipped_count), (Some(0), Some(1), Some(2)));
+        assert_eq!(reveal(&vault, mail), "new-mail-secret");
+        assert_eq!(entry_ids(&vault).len(), 2);
+
+        let not_csv = dir.path().join("notes.csv");
+        fs::write(&not_csv, "title,body\nhello,world\n").unwrap();
+        assert!(!vault.export_service.import_csv(&not_csv.to_string_lossy(), &vault.master_key, CsvImportMode::Skip, &CsvImportOptions::default()).unwrap().success);
+    }
+
+    #[test]
+    fn test_password_manager_csv_presets_and_column_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = vault(&dir.path().join("vault.db"), "vault_master");
+        let entries = |vault: &Vault| -> Vec<serde_json::Value> {
+            let response = vault.password_service.get_all_passwords(GetPasswordsRequest {
+                master_key: vault.master_key.clone(),
+                ..Default::default()
+            }).unwrap();
+            response.data.unwrap()["entries"].as_array().unwrap().clone()
+        };
+
+        let lastpass = dir.path().join("lastpass.csv");
+        fs::write(&lastpass, "url,username,password,totp,extra,name,grouping,fav\n\
+            https://mail.example.com,me@example.com,mail-secret,,,mail,Work\\Email,1\n\
+            http://sn,,,,door code 4711,Office door,Work,0\n\
+            https://bank.example.com,me@example.com,,,,bank,,0\n").unwrap();
+        let lastpass = lastpass.to_string_lossy().to_string();
+
+        // Strict mode refuses the whole file over the row without a password
+        let strict = CsvImportOptions { strict: true, ..Default::default() };
+        let response = vault.export_service.import_csv(&lastpass, &vault.master_key, CsvImportMode::Skip, &strict).unwrap();
+        assert!(!response.success);
+        assert_eq!(response.row_errors.unwrap()[0].line, 4);
+        assert!(entries(&vault).is_empty());
+
+        let preview = vault.export_service.preview_csv_import(&lastpass, &vault.master_key, CsvImportMode::Skip, &strict, 10).unwrap();
+        assert_eq!(preview["format"], "lastpass");
+        assert!(preview["columns"].as_array().unwrap().contains(&serde_json::json!({ "column": "grouping", "field": "tags" })));
+        assert_eq!(preview["rows"][0]["tags"], serde_json::json!(["Work\\Email"]));
+
+        let response = vault.export_service.import_csv(&lastpass, &vault.master_key, CsvImportMode::Skip, &CsvImportOptions::default()).unwrap();
+        assert_eq!(response.inserted_count, Some(2));
+        let listed = entries(&vault);
+        let mail = listed.iter().find(|entry| entry["software"] == "mail").unwrap();
+        assert_eq!(mail["is_favorite"], true);
+        assert_eq!(mail["tags"], serde_json::json!(["Work\\Email"]));
+        let note = listed.iter().find(|entry| entry["software"] == "Office door").unwrap();
+        assert_eq!((&note["account"], &note["url"], note["is_favorite"].as_bool()), (&serde_json::json!(""), &serde_json::Value::Null, Some(false)));
+        let note = vault.password_service.get_password(DecryptPasswordRequest {
+            id: note["id"].as_i64().unwrap(),
+            master_key: vault.master_key.clone(),
+            skip_usage_tracking: false,
+        }).unwrap().data.unwrap();
+        assert_eq!(note["notes"], "door code 4711");
+
+        let onepassword = dir.path().join("1password.csv");
+        fs::write(&onepassword, "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes,Vault\n\
+            forum,https://forum.example.com,me,forum-secret,,false,false,\"hobby,social\",,Personal\n").unwrap();
+        let response = vault.export_service
+            .import_csv(&onepassword.to_string_lossy(), &vault.master_key, CsvImportMode::Skip, &CsvImportOptions::default())
+            .unwrap();
+        assert_eq!(response.inserted_count, Some(1));
+        let forum = entries(&vault).into_iter().find(|entry| entry["software"] == "forum").unwrap();
+        assert_eq!(forum["tags"], serde_json::json!(["hobby", "Personal", "social"]));
+
+        // A layout no preset knows, mapped by hand
+        let custom = dir.path().join("custom.csv");
+        fs::write(&custom, "Service,Login,Secret\nvpn,me,vpn-secret\n").unwrap();
+        let custom = custom.to_string_lossy().to_string();
+        let mapping = |columns: &[(&str, CsvField)]| CsvImportOptions {
+            columns: columns.iter().map(|(name, field)| (name.to_string(), *field)).collect(),
+            ..Default::default()
+        };
+        let missing = mapping(&[("Service", CsvField::Software), ("Pass", CsvField::Password)]);
+        assert!(vault.export_service.preview_csv_import(&custom, &vault.master_key, CsvImportMode::Skip, &missing, 10).is_err());
+        let options = mapping(&[("Service", CsvField::Software), ("Login", CsvField::Account), ("Secret", CsvField::Password)]);
+        let response = vault.export_service.import_csv(&custom, &vault.master_key, CsvImportMode::Skip, &options).unwrap();
+        assert_eq!(response.inserted_count, Some(1));
+        let vpn = entries(&vault).into_iter().find(|entry| entry["software"] == "vpn").unwrap();
+        assert_eq!(reveal(&vault, vpn["id"].as_i64().unwrap()), "vpn-secret");
+    }
+
+    #[test]
+    fn test_partial_export_without_user_meta_merges_into_any_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        let vpn = add(&source, "vpn", "vpn-secret", &source.master_key);
+        let git = add(&source, "git", "git-secret", &source.master_key);
+        let mail = add(&source, "mail", "mail-secret", &source.master_key);
+        source.password_service.add_tag_to_entry(vpn, "Shared").unwrap();
+        source.password_service.add_tag_to_entry(git, "shared").unwrap();
+
+        let file_path = dir.path().join("shared.enc");
+        let request = |master_key: Option<String>| ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments: false,
+            include_trash: false,
+            include_audit_log: true,
+            entry_ids: Some(vec![vpn, git, mail]),
+            tag: Some("shared".to_string()),
+            include_user_meta: false,
+            master_key,
+        };
+        assert!(source.export_service.export_data(request(None)).is_err());
+        let response = source.export_service.export_data(request(Some(source.master_key.clone()))).unwrap();
+        assert_eq!(response.failed_count, Some(0));
+
+        let plaintext = CryptoService::decrypt_export_data(&fs::read_to_string(&file_path).unwrap(), "passphrase").unwrap();
+        let exported: serde_json::Value = serde_json::from_str(&plaintext).unwrap();
+        assert!(exported["data"].get("user_meta").is_none() && exported["data"]["audit_log"].is_null());
+        assert!(!plaintext.contains("master_hash"));
+        let info = source.export_service.get_export_info(&file_path.to_string_lossy(), Some("passphrase")).unwrap();
+        assert_eq!((&info["contents"]["partial"], &info["contents"]["has_user_data"]), (&serde_json::json!(true), &serde_json::json!(false)));
+        assert!(source.export_service.get_export_info(&file_path.to_string_lossy(), None).unwrap()["contents"].is_null());
+
+        // A replace request still merges, under the session's key, and keeps the target's user
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let own = add(&target, "own", "own-secret", &target.master_key);
+        let with_session = || ImportRequest { session_key: Some(target.master_key.clone()), ..import_request(&file_path, None, None) };
+        let preview = target.export_service.preview_import(with_session()).unwrap();
+        assert_eq!(preview["mode"], "merge");
+        assert_eq!(preview["preview"]["partial"], true);
+        assert_eq!(preview["merge_preview"]["inserted_count"], 2);
+        assert!(!target.export_service.import_data(import_request(&file_path, None, None)).unwrap().success);
+
+        let response = target.export_service.import_data(with_session()).unwrap();
+        assert!(response.success, "{}", response.message);
+        assert_eq!(response.inserted_count, Some(2));
+        let ids = entry_ids(&target);
+        assert_eq!(ids.len(), 3);
+        assert!(ids.contains(&own));
+        let secrets: HashSet<String> = ids.iter().map(|id| reveal(&target, *id)).collect();
+        assert_eq!(secrets, HashSet::from(["own-secret", "vpn-secret", "git-secret"].map(str::to_string)));
+        assert!(target.user_service.login(LoginRequest { master_password: "target_master".to_string() }).unwrap().success);
+    }
+
+    #[test]
+    fn test_attachments_are_exported_only_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        let vpn = add(&source, "vpn", "vpn-secret", &source.master_key);
+        let config = dir.path().join("vpn.conf");
+        fs::write(&config, b"remote vpn.example.com 1194").unwrap();
+        source.password_service.add_attachment(vpn, config.to_str().unwrap(), &source.master_key).unwrap();
+
+        let file_path = dir.path().join("export.enc");
+        let request = |include_attachments: bool| ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments,
+            include_trash: false,
+            include_audit_log: false,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: false,
+            master_key: Some(source.master_key.clone()),
+        };
+        let exported = |include_attachments: bool| {
+            source.export_service.export_data(request(include_attachments)).unwrap();
+            CryptoService::decrypt_export_data(&fs::read_to_string(&file_path).unwrap(), "passphrase").unwrap()
+        };
+        assert!(!exported(false).contains("\"attachments\""));
+        assert!(exported(true).contains("\"attachments\""));
+
+        // Re-encrypted under the transfer key, the file opens in another vault
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let response = target.export_service
+            .import_data(ImportRequest { session_key: Some(target.master_key.clone()), ..import_request(&file_path, None, None) })
+            .unwrap();
+        assert!(response.success, "{}", response.message);
+        let imported = entry_ids(&target)[0];
+        let listed = target.password_service.list_attachments(imported).unwrap().data.unwrap();
+        assert_eq!(listed[0]["filename"], "vpn.conf");
+        let output = dir.path().join("vpn-out.conf");
+        target.password_service
+            .get_attachment(listed[0]["id"].as_i64().unwrap(), &target.master_key, output.to_str().unwrap())
+            .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"remote vpn.example.com 1194");
+    }
+
+    #[test]
+    fn test_custom_fields_travel_with_exports_and_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        let bank = add(&source, "bank", "bank-secret", &source.master_key);
+        source.password_service.set_custom_field(SetCustomFieldRequest {
+            entry_id: bank,
+            id: None,
+            label: "Security answer".to_string(),
+            value: "Rex".to_string(),
+            field_kind: CustomFieldKind::Hidden,
+            position: None,
+            master_key: source.master_key.clone(),
+        }).unwrap();
+
+        let file_path = dir.path().join("export.enc");
+        source.export_service.export_data(ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments: false,
+            include_trash: false,
+            include_audit_log: false,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: false,
+            master_key: Some(source.master_key.clone()),
+        }).unwrap();
+        let exported = CryptoService::decrypt_export_data(&fs::read_to_string(&file_path).unwrap(), "passphrase").unwrap();
+        assert!(exported.contains("\"custom_fields\"") && !exported.contains("Rex"));
+
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let response = target.export_service
+            .import_data(ImportRequest { session_key: Some(target.master_key.clone()), ..import_request(&file_path, None, None) })
+            .unwrap();
+        assert!(response.success, "{}", response.message);
+        let imported = entry_ids(&target)[0];
+        let entry = target.password_service
+            .get_password(DecryptPasswordRequest { id: imported, master_key: target.master_key.clone(), skip_usage_tracking: true })
+            .unwrap().data.unwrap();
+        assert_eq!(entry["custom_fields"][0]["label"], "Security answer");
+        assert_eq!(entry["custom_fields"][0]["value"], "Rex");
+        assert_eq!(entry["custom_fields"][0]["field_kind"], "hidden");
+    }
+
+    #[test]
+    fn test_export_info_reads_the_format_without_the_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        let v2_path = dir.path().join("backup.enc");
+        export(&source, &v2_path);
+
+        let info = source.export_service.get_export_info(&v2_path.to_string_lossy(), None).unwrap();
+        assert_eq!((&info["is_pwdbox_export"], &info["format_version"]), (&serde_json::json!(true), &serde_json::json!(2)));
+        assert_eq!(info["app_version"], env!("CARGO_PKG_VERSION"));
+        assert!(info["created_at"].is_string() && info["kdf_params"]["m_cost"].is_u64());
+
+        // A v1 file written before the header existed still imports, but has no creation details
+        let v2 = fs::read_to_string(&v2_path).unwrap();
+        let json_data = CryptoService::decrypt_export_data(&v2, "passphrase").unwrap();
+        let v1_path = dir.path().join("old.enc");
+        fs::write(&v1_path, CryptoService::encrypt_export_data(&json_data, "passphrase", &KdfParams::default()).unwrap()).unwrap();
+        let info = source.export_service.get_export_info(&v1_path.to_string_lossy(), None).unwrap();
+        assert_eq!((&info["format_version"], &info["created_at"]), (&serde_json::json!(1), &serde_json::Value::Null));
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let response = target.export_service.import_data(import_request(&v1_path, None, None)).unwrap();
+        assert_eq!(response.imported_entries_count, Some(1));
+
+        let other = dir.path().join("notes.txt");
+        fs::write(&other, "not an export").unwrap();
+        let info = source.export_service.get_export_info(&other.to_string_lossy(), None).unwrap();
+        assert_eq!((&info["is_pwdbox_export"], &info["format_version"]), (&serde_json::json!(false), &serde_json::Value::Null));
+        let error = target.export_service.import_data(import_request(&other, None, None)).unwrap_err();
+        assert_eq!(PwdBoxError::from(error), PwdBoxError::validation(NOT_AN_EXPORT));
+
+        // Nor is a binary, which is read without any UTF-8 error surfacing
+        let binary = dir.path().join("photo.jpg");
+        fs::write(&binary, [0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00, 0x80]).unwrap();
+        let error = target.export_service.preview_import(import_request(&binary, None, None)).unwrap_err();
+        assert_eq!(PwdBoxError::from(error), PwdBoxError::validation(NOT_AN_EXPORT));
+    }
+
+    #[test]
+    fn test_list_backups_flags_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        let backup_dir = dir.path().join("backups");
+        fs::create_dir_all(backup_dir.join("nested")).unwrap();
+        export(&source, &backup_dir.join("pwdbox_backup_old.enc"));
+        add(&source, "bank", "bank-secret", &source.master_key);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        export(&source, &backup_dir.join("pwdbox_backup_new.enc"));
+        fs::write(backup_dir.join("notes.txt"), "not an export").unwrap();
+        fs::write(backup_dir.join("binary.bin"), [0xff, 0xfe, 0x00]).unwrap();
+
+        let dir_path = backup_dir.to_string_lossy();
+        let listed = source.export_service.list_backups(&dir_path, None).unwrap();
+        assert_eq!(listed.len(), 4);
+        let exports: Vec<_> = listed.iter().filter(|backup| backup.is_pwdbox_export).collect();
+        assert_eq!(exports.iter().map(|backup| backup.file_name.as_str()).collect::<Vec<_>>(), ["pwdbox_backup_new.enc", "pwdbox_backup_old.enc"]);
+        assert!(exports.iter().all(|backup| backup.error.is_none() && backup.entry_count.is_none() && backup.format_version == Some(2)));
+        let foreign = listed.iter().find(|backup| backup.file_name == "notes.txt").unwrap();
+        assert_eq!((foreign.file_size, foreign.error.as_deref()), (Some(13), Some(NOT_AN_EXPORT)));
+
+        let listed = source.export_service.list_backups(&dir_path, Some("passphrase")).unwrap();
+        let counts: Vec<_> = listed.iter().filter(|backup| backup.is_pwdbox_export).map(|backup| backup.entry_count).collect();
+        assert_eq!(counts, [Some(2), Some(1)]);
+        let listed = source.export_service.list_backups(&dir_path, Some("wrong")).unwrap();
+        assert!(listed.iter().filter(|backup| backup.is_pwdbox_export).all(|backup| backup.entry_count.is_none() && backup.error.is_some()));
+
+        assert!(source.export_service.list_backups(&dir.path().join("missing").to_string_lossy(), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_restore_needs_a_verified_backup_and_its_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        add(&source, "bank", "bank-secret", &source.master_key);
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+        let path = file_path.to_string_lossy();
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        add(&target, "own", "own-secret", &target.master_key);
+        let now = Instant::now();
+
+        // Nothing that confirm_restore could not restore gets a token
+        assert!(target.export_service.prepare_restore(&path, "wrong", now).is_err());
+        let contents = fs::read_to_string(&file_path).unwrap();
+        let damaged_path = dir.path().join("damaged.enc");
+        fs::write(&damaged_path, &contents[..contents.len() - 20]).unwrap();
+        assert!(target.export_service.prepare_restore(&damaged_path.to_string_lossy(), "passphrase", now).is_err());
+        let partial_path = dir.path().join("partial.enc");
+        source.export_service.export_data(ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: partial_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments: false,
+            include_trash: false,
+            include_audit_log: false,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: false,
+            master_key: Some(source.master_key.clone()),
+        }).unwrap();
+        let error = target.export_service.prepare_restore(&partial_path.to_string_lossy(), "passphrase", now).err().unwrap();
+        assert!(error.to_string().contains("no user data"));
+
+        let (pending, summary) = target.export_service.prepare_restore(&path, "passphrase", now).unwrap();
+        assert_eq!((summary.entry_count, summary.trash_count, summary.expires_in_secs), (2, 0, RESTORE_TOKEN_TTL.as_secs()));
+        assert!(summary.created_at.is_some());
+        assert_eq!(entry_ids(&target).len(), 1);
+
+        // Another token leaves the restore waiting; an expired one drops it
+        let mut slot = Some(pending);
+        assert!(PendingRestore::take(&mut slot, "other", now).is_err());
+        assert!(slot.is_some());
+        let (expiring, _) = target.export_service.prepare_restore(&path, "passphrase", now).unwrap();
+        let expired_token = expiring.token.clone();
+        let mut expired_slot = Some(expiring);
+        assert!(PendingRestore::take(&mut expired_slot, &expired_token, now + RESTORE_TOKEN_TTL).is_err());
+        assert!(expired_slot.is_none());
+
+        let export_data = PendingRestore::take(&mut slot, &summary.token, now).unwrap();
+        assert!(PendingRestore::take(&mut slot, &summary.token, now).is_err());
+        let response = target.export_service.confirm_restore(&export_data).unwrap();
+        assert_eq!(response.restored_entries_count, 2);
+        assert!(Path::new(&response.pre_restore_backup).is_file());
+        assert!(!target.user_service.login(LoginRequest { master_password: "target_master".to_string() }).unwrap().success);
+        let login = target.user_service.login(LoginRequest { master_password: "source_master".to_string() }).unwrap();
+        let restored = Vault { master_key: login.master_key.unwrap(), ..target };
+        let mut secrets: Vec<String> = entry_ids(&restored).into_iter().map(|id| reveal(&restored, id)).collect();
+        secrets.sort();
+        assert_eq!(secrets, ["bank-secret", "mail-secret"]);
+
+        // The copy taken first still holds the vault as it was
+        let before = UserService::new(Database::new(PathBuf::from(&response.pre_restore_backup)).unwrap());
+        assert!(before.login(LoginRequest { master_password: "target_master".to_string() }).unwrap().success);
+    }
+
+    #[test]
+    fn test_damaged_export_is_told_apart_from_a_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+        let path = file_path.to_string_lossy();
+        let target = vault(&dir.path().join("target.db"), "target_master");
+
+        assert_eq!(source.export_service.validate_export_file(&path, "passphrase").unwrap(), ExportFileStatus::Valid);
+        assert_eq!(source.export_service.validate_export_file(&path, "wrong").unwrap(), ExportFileStatus::WrongPassphrase);
+        let response = target.export_service.import_data(ImportRequest {
+            import_passphrase: "wrong".to_string(),
+            ..import_request(&file_path, None, None)
+        }).unwrap();
+        assert!(!response.success);
+        assert_eq!(response.file_status, Some(ExportFileStatus::WrongPassphrase));
+
+        // Cut off before the integrity tag, as by an interrupted copy
+        let contents = fs::read_to_string(&file_path).unwrap();
+        let damaged_path = dir.path().join("damaged.enc");
+        fs::write(&damaged_path, &contents[..contents.len() - 20]).unwrap();
+        let damaged = damaged_path.to_string_lossy();
+        assert_eq!(source.export_service.validate_export_file(&damaged, "passphrase").unwrap(), ExportFileStatus::Corrupted);
+        let response = target.export_service.import_data(import_request(&damaged_path, None, None)).unwrap();
+        assert_eq!(response.file_status, Some(ExportFileStatus::Corrupted));
+        assert!(response.message.contains("damaged"));
+        assert_eq!(target.export_service.database.count_password_entries().unwrap(), 0);
+
+        let response = target.export_service.import_data(import_request(&file_path, None, None)).unwrap();
+        assert_eq!((response.success, response.file_status), (true, Some(ExportFileStatus::Valid)));
+    }
+
+    #[test]
+    fn test_import_reencrypt_rejects_wrong_source_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        add(&source, "mail", "mail-secret", &source.master_key);
+        let file_path = dir.path().join("backup.enc");
+        export(&source, &file_path);
+
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let existing = add(&target, "own", "own-secret", &target.master_key);
+
+        let response = target.export_service
+            .import_data(import_request(&file_path, Some("wrong"), Some(&target.master_key)))
+            .unwrap();
+        assert!(!response.success);
+
+        let half = target.export_service
+            .import_data(import_request(&file_path, None, Some(&target.master_key)))
+            .unwrap();
+        assert!(!half.success);
+
+        // The target vault is untouched
+        assert_eq!(entry_ids(&target), vec![existing]);
+    }
+
+    fn add_for(vault: &Vault, software: &str, account: &str, password: &str) -> i64 {
+        let response = vault.password_service.add_password(AddPasswordRequest {
+            software: software.to_string(),
+            account: account.to_string(),
+            password: password.to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: vault.master_key.clone(),
+        }).unwrap();
+        response.data.unwrap()["id"].as_i64().unwrap()
+    }
+
+    fn reveal(vault: &Vault, id: i64) -> String {
+        let entry = vault.password_service.get_password(DecryptPasswordRequest {
+            id,
+            master_key: vault.master_key.clone(),
+            skip_usage_tracking: false,
+        }).unwrap();
+        entry.data.unwrap()["password"].as_str().unwrap().to_string()
+    }
+
+    // A backup from another vault with one new account, one unchanged and one changed duplicate
+    fn merge_fixture(dir: &Path) -> (Vault, PathBuf, i64, i64) {
+        let source = vault(&dir.join("source.db"), "source_master");
+        add_for(&source, "mail", "me@example.com", "same-secret");
+        add_for(&source, "bank", "me@example.com", "old-bank-secret");
+        add_for(&source, "forum", "me", "forum-secret");
+        let file_path = dir.join("backup.enc");
+        export(&source, &file_path);
+
+        let target = vault(&dir.join("target.db"), "target_master");
+        let mail = add_for(&target, "mail", "me@example.com", "same-secret");
+        let bank = add_for(&target, "bank", "me@example.com", "new-bank-secret");
+        (target, file_path, mail, bank)
+    }
+
+    fn merge_request(file_path: &Path, target: &Vault, overwrite_duplicates: bool) -> ImportRequest {
+        ImportRequest {
+            mode: ImportMode::Merge,
+            overwrite_duplicates,
+            ..import_request(file_path, Some("source_master"), Some(&target.master_key))
+        }
+    }
+
+    #[test]
+    fn test_keyless_merge_only_takes_backups_of_this_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, file_path, mail, _) = merge_fixture(dir.path());
+        let keyless = |file_path: &Path| ImportRequest {
+            mode: ImportMode::Merge,
+            session_key: Some(target.master_key.clone()),
+            ..import_request(file_path, None, None)
+        };
+        let from_another_vault = "This backup is from another vault; give its master password";
+
+        // The other vault's canary does not open under this one's key
+        let response = target.export_service.import_data(keyless(&file_path)).unwrap();
+        assert!(!response.success);
+        assert_eq!(response.message, from_another_vault);
+        assert_eq!(target.export_service.preview_import(keyless(&file_path)).unwrap_err().to_string(), from_another_vault);
+        assert_eq!(entry_ids(&target).len(), 2);
+
+        // Without a canary, the first entry decides
+        let other = vault(&dir.path().join("other.db"), "other_master");
+        add(&other, "forum", "forum-secret", &other.master_key);
+        rusqlite::Connection::open(dir.path().join("other.db")).unwrap()
+            .execute("UPDATE user_meta SET canary_ciphertext = NULL, canary_nonce = NULL WHERE id = 1", [])
+            .unwrap();
+        let canaryless = dir.path().join("canaryless.enc");
+        export(&other, &canaryless);
+        assert_eq!(target.export_service.import_data(keyless(&canaryless)).unwrap().message, from_another_vault);
+
+        // A backup of this vault merges as stored
+        let own_backup = dir.path().join("own.enc");
+        export(&target, &own_backup);
+        target.export_service.database.delete_password_entry(mail).unwrap();
+        let response = target.export_service.import_data(keyless(&own_backup)).unwrap();
+        assert!(response.success, "{}", response.message);
+        assert_eq!(response.inserted_count, Some(1));
+        let secrets: Vec<String> = entry_ids(&target).into_iter().map(|id| reveal(&target, id)).collect();
+        assert!(secrets.contains(&"same-secret".to_string()));
+    }
+
+    #[test]
+    fn test_merge_keeps_existing_entries_and_user_meta() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, file_path, mail, bank) = merge_fixture(dir.path());
+
+        let response = target.export_service.import_data(merge_request(&file_path, &target, false)).unwrap();
+        assert!(response.success);
+        assert_eq!(response.inserted_count, Some(1));
+        assert_eq!(response.updated_count, Some(0));
+        assert_eq!(response.duplicates_skipped_count, Some(2));
+
+        assert_eq!(entry_ids(&target).len(), 3);
+        assert_eq!(reveal(&target, mail), "same-secret");
+        assert_eq!(reveal(&target, bank), "new-bank-secret");
+        assert!(target.user_service.login(LoginRequest { master_password: "target_master".to_string() }).unwrap().success);
+    }
+
+    #[test]
+    fn test_merge_overwrites_only_changed_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, file_path, mail, bank) = merge_fixture(dir.path());
+
+        let response = target.export_service.import_data(merge_request(&file_path, &target, true)).unwrap();
+        assert_eq!(response.inserted_count, Some(1));
+        assert_eq!(response.updated_count, Some(1));
+        assert_eq!(response.duplicates_skipped_count, Some(1));
+
+        assert_eq!(entry_ids(&target).len(), 3);
+        assert_eq!(reveal(&target, mail), "same-secret");
+        assert_eq!(reveal(&target, bank), "old-bank-secret");
+    }
+
+    #[test]
+    fn test_preview_predicts_merge_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, file_path, _, _) = merge_fixture(dir.path());
+
+        let preview = target.export_service.preview_import(merge_request(&file_path, &target, true)).unwrap();
+        assert_eq!(preview["mode"], "merge");
+        assert_eq!(preview["merge_preview"]["inserted_count"], 1);
+        assert_eq!(preview["merge_preview"]["updated_count"], 1);
+        assert_eq!(preview["merge_preview"]["duplicates_skipped_count"], 1);
+        assert_eq!(preview["merge_preview"]["inserted_sample"][0]["software"], "forum");
+
+        assert_eq!(entry_ids(&target).len(), 2);
+    }
+
+    #[test]
+    fn test_audit_log_is_carried_by_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = vault(&dir.path().join("source.db"), "source_master");
+        let mail = add(&source, "mail", "mail-secret", &source.master_key);
+        reveal(&source, mail);
+        reveal(&source, mail);
+        let file_path = dir.path().join("backup.enc");
+        source.export_service.export_data(ExportRequest {
+            export_passphrase: "passphrase".to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
+            include_stats_history: false,
+            include_icons: false,
+            include_attachments: false,
+            include_trash: false,
+            include_audit_log: true,
+            entry_ids: None,
+            tag: None,
+            include_user_meta: true,
+            master_key: None,
+        }).unwrap();
+
+        // Re-encrypting into another vault appends the log as its own segment
+        let target = vault(&dir.path().join("target.db"), "target_master");
+        let preview = target.export_service.preview_import(import_request(&file_path, None, None)).unwrap();
+        assert_eq!(preview["preview"]["audit_event_count"], 2);
+        let response = target.export_service
+            .import_data(import_request(&file_path, Some("source_master"), Some(&target.master_key)))
+            .unwrap();
+        assert!(response.success, "{}", response.message);
+        assert_eq!(response.audit_events_imported, Some(2));
+        let verification = target.password_service.verify_audit_log(&target.master_key).unwrap();
+        assert!(verification.valid, "{}", verification.message);
+        assert_eq!(verification.segments.len(), 2);
+        assert_eq!(verification.segments[1].checked_count, 2);
+
+        // A plain restore brings the backup's vault key along, so the log becomes the local one
+        let restored = vault(&dir.path().join("restored.db"), "other_master");
+        let response = restored.export_service.import_data(import_request(&file_path, None, None)).unwrap();
+        assert_eq!(response.audit_events_imported, Some(2));
+        let login = restored.user_service.login(LoginRequest { master_password: "source_master".to_string() }).unwrap();
+        let verification = restored.password_service.verify_audit_log(&login.master_key.unwrap()).unwrap();
+        assert!(verification.valid, "{}", verification.message);
+        assert_eq!(verification.segments.len(), 1);
+
+        let csv_path = dir.path().join("audit.csv");
+        target.export_service.export_audit_log_csv(&target.master_key, &csv_path.to_string_lossy()).unwrap();
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        assert!(csv.starts_with("id,occurred_at,action,entry_id,detail,segment_id,source_id\n"));
+        assert_eq!(csv.matches(audit_log::ENTRY_REVEALED).count(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::database::{EntryIcon, HostIcon};
+use crate::icons;
+use crate::time_utils;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::io::Read;
+
+// Favicons for entries with a URL, fetched by host and cached in host_icons so every entry
+// on the same host shares one download. Only the host leaves the machine, and only once
+// fetching is turned on in settings.
+
+// Where icons are fetched from unless settings name another service. "{host}" is replaced
+// by the entry's URL host.
+pub const DEFAULT_FAVICON_SOURCE: &str = "https://{host}/favicon.ico";
+const HOST_PLACEHOLDER: &str = "{host}";
+const MAX_SOURCE_LENGTH: usize = 256;
+
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Fetched icons are kept this long before being fetched again; hosts without one are retried sooner
+const REFRESH_DAYS: i64 = 30;
+const RETRY_FAILED_DAYS: i64 = 7;
+
+// From fetch_icon; host is None for entries without a URL, icon None when the host had none
+#[derive(Debug, Serialize)]
+pub struct FetchedIcon {
+    pub id: i64,
+    pub host: Option<String>,
+    pub icon: Option<EntryIcon>,
+}
+
+// A source must be HTTPS and name the host somewhere
+pub fn validate_source(source: &str) -> Result<()> {
+    let valid = source.len() <= MAX_SOURCE_LENGTH
+        && source.starts_with("https://")
+        && source.contains(HOST_PLACEHOLDER)
+        && !source.chars().any(char::is_whitespace);
+    if !valid {
+        return Err(anyhow!("Icon source must be an https:// URL containing {}", HOST_PLACEHOLDER));
+    }
+    Ok(())
+}
+
+// Hosts come from domains::host_from, so they hold nothing but letters, digits, '-' and '.'
+pub fn favicon_url(source: &str, host: &str) -> String {
+    source.replace(HOST_PLACEHOLDER, host)
+}
+
+pub fn is_fresh(cached: &HostIcon, now: &DateTime<Utc>) -> bool {
+    let Ok(fetched_at) = time_utils::parse_rfc3339(&cached.fetched_at) else {
+        return false;
+    };
+    let max_age = if cached.icon.is_some() { REFRESH_DAYS } else { RETRY_FAILED_DAYS };
+    fetched_at <= *now && *now - fetched_at < Duration::days(max_age)
+}
+
+// Fetch an icon over HTTPS. Anything that is not an image, or larger than an uploaded icon
+// may be, is refused before it is decoded.
+pub fn fetch(url: &str) -> Result<Vec<u8>> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("PwdBox/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let response = client.get(url).send()?.error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if !content_type.starts_with("image/") {
+        return Err(anyhow!("Not an image: {}", if content_type.is_empty() { "no content type" } else { &content_type }));
+    }
+    if response.content_length().is_some_and(|length| length > icons::MAX_ICON_BYTES as u64) {
+        return Err(anyhow!("Icon is larger than {} bytes", icons::MAX_ICON_BYTES));
+    }
+
+    // The declared length may be missing or wrong, so the body is capped as it is read
+    let mut bytes = Vec::new();
+    response.take(icons::MAX_ICON_BYTES as u64 + 1).read_to_end(&mut bytes)?;
+    if bytes.len() > icons::MAX_ICON_BYTES {
+        return Err(anyhow!("Icon is larger than {} bytes", icons::MAX_ICON_BYTES));
+    }
+    Ok(bytes)
+}
+
+// Fetch and prepare the icon for `host`. Network errors and unusable images both mean the
+// host has no icon; they are logged, not reported.
+pub fn download(source: &str, host: &str, fetch: &mut dyn FnMut(&str) -> Result<Vec<u8>>) -> Option<EntryIcon> {
+    let url = favicon_url(source, host);
+    match fetch(&url).and_then(|bytes| icons::prepare_favicon(&bytes)) {
+        Ok(icon) => Some(icon),
+        Err(e) => {
+            log::info!("No icon for {}: {}", host, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_and_cache_freshness() {
+        assert!(validate_source(DEFAULT_FAVICON_SOURCE).is_ok());
+        assert!(validate_source("https://icons.example.net/ip3/{host}.ico").is_ok());
+        for invalid in ["http://{host}/favicon.ico", "https://icons.example.net/", "https://{host}/a b.ico", "file:///{host}"] {
+            assert!(validate_source(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(favicon_url(DEFAULT_FAVICON_SOURCE, "github.com"), "https://github.com/favicon.ico");
+
+        let now = time_utils::parse_rfc3339("2030-03-01T00:00:00Z").unwrap();
+        let cached = |fetched_at: &str, icon: Option<EntryIcon>| HostIcon { icon, fetched_at: fetched_at.to_string() };
+        assert!(is_fresh(&cached("2030-02-01T00:00:00Z", Some(EntryIcon::default())), &now));
+        assert!(!is_fresh(&cached("2030-01-01T00:00:00Z", Some(EntryIcon::default())), &now));
+        assert!(!is_fresh(&cached("2030-02-01T00:00:00Z", None), &now));
+        assert!(is_fresh(&cached("2030-02-25T00:00:00Z", None), &now));
+        assert!(!is_fresh(&cached("2030-04-01T00:00:00Z", None), &now));
+        assert!(!is_fresh(&cached("not a date", None), &now));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::database::EntryIcon;
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use image::{ImageFormat, ImageReader, Limits, imageops::FilterType};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::path::Path;
+
+// Largest icon file accepted for upload
+pub const MAX_ICON_BYTES: usize = 256 * 1024;
+
+// Raster icons are downsized to fit in a square of this size
+pub const ICON_SIZE: u32 = 64;
+
+// Reject raster images whose header claims more than this before decoding them
+const MAX_SOURCE_DIMENSION: u32 = 4096;
+
+// SVG elements and attributes that survive sanitizing. Anything that can run script,
+// load external content or embed foreign markup is dropped.
+const SVG_ELEMENTS: &[&str] = &[
+    "svg", "g", "defs", "title", "desc", "path", "rect", "circle", "ellipse", "line",
+    "polyline", "polygon", "text", "tspan", "linearGradient", "radialGradient", "stop",
+    "clipPath", "mask", "use", "symbol",
+];
+const SVG_ATTRIBUTES: &[&str] = &[
+    "id", "viewBox", "width", "height", "x", "y", "x1", "y1", "x2", "y2", "cx", "cy", "r",
+    "rx", "ry", "d", "points", "transform", "fill", "fill-opacity", "fill-rule", "stroke",
+    "stroke-width", "stroke-opacity", "stroke-linecap", "stroke-linejoin", "stroke-dasharray",
+    "opacity", "offset", "stop-color", "stop-opacity", "gradientUnits", "gradientTransform",
+    "clip-path", "clip-rule", "mask", "font-family", "font-size", "font-weight", "text-anchor",
+    "preserveAspectRatio", "fx", "fy", "spreadMethod", "href",
+];
+
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+// Read an icon file, validate it and prepare it for storage
+pub fn load_icon(path: &Path) -> Result<EntryIcon> {
+    let size = std::fs::metadata(path)?.len();
+    if size > MAX_ICON_BYTES as u64 {
+        return Err(anyhow!("Icon is too large ({} bytes, the maximum is {} bytes)", size, MAX_ICON_BYTES));
+    }
+    prepare_icon(&std::fs::read(path)?)
+}
+
+// PNG and JPEG are re-encoded as a PNG no larger than ICON_SIZE (dropping any metadata);
+// SVG is rebuilt from an allowlist of elements and attributes
+pub fn prepare_icon(bytes: &[u8]) -> Result<EntryIcon> {
+    if bytes.len() > MAX_ICON_BYTES {
+        return Err(anyhow!("Icon is too large ({} bytes, the maximum is {} bytes)", bytes.len(), MAX_ICON_BYTES));
+    }
+
+    let (mime_type, data) = match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => ("image/png", downsize_raster(bytes, format)?),
+        _ if looks_like_svg(bytes) => ("image/svg+xml", sanitize_svg(bytes)?.into_bytes()),
+        _ => return Err(anyhow!("Unsupported icon format; use PNG, JPEG or SVG")),
+    };
+
+    Ok(EntryIcon {
+        mime_type: mime_type.to_string(),
+        content_hash: format!("{:x}", Sha256::digest(&data)),
+        data: general_purpose::STANDARD.encode(&data),
+    })
+}
+
+// Favicons are PNG, JPEG or ICO; all three are stored as a PNG like uploaded raster icons
+pub fn prepare_favicon(bytes: &[u8]) -> Result<EntryIcon> {
+    if bytes.len() > MAX_ICON_BYTES {
+        return Err(anyhow!("Icon is too large ({} bytes, the maximum is {} bytes)", bytes.len(), MAX_ICON_BYTES));
+    }
+
+    let data = match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Ico)) => downsize_raster(bytes, format)?,
+        _ => return Err(anyhow!("Unsupported favicon format; expected PNG, JPEG or ICO")),
+    };
+
+    Ok(EntryIcon {
+        mime_type: "image/png".to_string(),
+        content_hash: format!("{:x}", Sha256::digest(&data)),
+        data: general_purpose::STANDARD.encode(&data),
+    })
+}
+
+fn downsize_raster(bytes: &[u8], format: ImageFormat) -> Result<Vec<u8>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let mut image = reader.decode().map_err(|e| anyhow!("Invalid icon image: {}", e))?;
+    if image.width() > ICON_SIZE || image.height() > ICON_SIZE {
+        image = image.resize(ICON_SIZE, ICON_SIZE, FilterType::Lanczos3);
+    }
+
+    let mut output = Vec::new();
+    image.write_to(&mut Cursor::new(&mut output), ImageFormat::Png)?;
+    Ok(output)
+}
+
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).is_ok_and(|text| text.contains("<svg"))
+}
+
+fn sanitize_svg(bytes: &[u8]) -> Result<String> {
+    let text = std::str::from_utf8(bytes)?;
+    // DTDs (and with them entity expansion) are rejected by the parser's defaults
+    let document = roxmltree::Document::parse(text).map_err(|e| anyhow!("Invalid SVG icon: {}", e))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "svg" {
+        return Err(anyhow!("Invalid SVG icon: the root element must be <svg>"));
+    }
+
+    let mut output = String::new();
+    write_svg_node(root, &mut output);
+    Ok(output)
+}
+
+fn write_svg_node(node: roxmltree::Node, output: &mut String) {
+    if node.is_text() {
+        output.push_str(&escape_xml(node.text().unwrap_or_default()));
+        return;
+    }
+
+    let name = node.tag_name().name();
+    let in_svg_namespace = matches!(node.tag_name().namespace(), None | Some(SVG_NAMESPACE));
+    if !node.is_element() || !in_svg_namespace || !SVG_ELEMENTS.contains(&name) {
+        return;
+    }
+
+    output.push('<');
+    output.push_str(name);
+    if name == "svg" && node.parent_element().is_none() {
+        output.push_str(&format!(" xmlns=\"{}\"", SVG_NAMESPACE));
+    }
+    for attribute in node.attributes() {
+        let attribute_name = attribute.name();
+        if !SVG_ATTRIBUTES.contains(&attribute_name) {
+            continue;
+        }
+        // References may only point inside the document
+        let value = attribute.value();
+        let external_href = attribute_name == "href" && !value.starts_with('#');
+        let external_url = value.contains("url(") && !value.trim_start().starts_with("url(#");
+        if external_href || external_url {
+            continue;
+        }
+        output.push_str(&format!(" {}=\"{}\"", attribute_name, escape_xml(value)));
+    }
+    output.push('>');
+    for child in node.children() {
+        write_svg_node(child, output);
+    }
+    output.push_str(&format!("</{}>", name));
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::DynamicImage::new_rgba8(width, height);
+        let mut output = Vec::new();
+        image.write_to(&mut Cursor::new(&mut output), ImageFormat::Png).unwrap();
+        output
+    }
+
+    fn decoded_svg(icon: &EntryIcon) -> String {
+        String::from_utf8(general_purpose::STANDARD.decode(&icon.data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_raster_icons_are_downsized_to_png() {
+        let icon = prepare_icon(&png(300, 150)).unwrap();
+        assert_eq!(icon.mime_type, "image/png");
+
+        let data = general_purpose::STANDARD.decode(&icon.data).unwrap();
+        let image = image::load_from_memory(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (64, 32));
+        assert_eq!(icon.content_hash, format!("{:x}", Sha256::digest(&data)));
+
+        // Small icons keep their size; identical input hashes identically
+        let small = prepare_icon(&png(16, 16)).unwrap();
+        assert_eq!(small.content_hash, prepare_icon(&png(16, 16)).unwrap().content_hash);
+    }
+
+    #[test]
+    fn test_oversized_and_unknown_files_are_rejected() {
+        assert!(prepare_icon(&vec![0u8; MAX_ICON_BYTES + 1]).is_err());
+        assert!(prepare_icon(b"GIF89a not supported").is_err());
+        assert!(prepare_icon(&png(8, 8)[..20]).is_err());
+    }
+
+    #[test]
+    fn test_favicons_are_converted_to_png() {
+        let mut ico = Vec::new();
+        image::DynamicImage::new_rgba8(32, 32).write_to(&mut Cursor::new(&mut ico), ImageFormat::Ico).unwrap();
+        let icon = prepare_favicon(&ico).unwrap();
+        assert_eq!(icon.mime_type, "image/png");
+        let data = general_purpose::STANDARD.decode(&icon.data).unwrap();
+        assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::Png);
+
+        assert!(prepare_favicon(&png(128, 128)).is_ok());
+        // Favicons come from sites, so they are never taken as SVG
+        assert!(prepare_favicon(br#"<svg xmlns="http://www.w3.org/2000/svg"/>"#).is_err());
+        assert!(prepare_favicon(b"<html>Not found</html>").is_err());
+    }
+
+    #[test]
+    fn test_svg_is_sanitized() {
+        let icon = prepare_icon(br##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10" onload="alert(1)">
+            <script>alert(1)</script>
+            <foreignObject><div>hi</div></foreignObject>
+            <a href="javascript:alert(1)"><rect width="5" height="5"/></a>
+            <use href="https://evil.example/sprite.svg#x"/>
+            <use href="#shape"/>
+            <circle id="shape" r="4" fill="url(https://evil.example/x)" style="fill:red"/>
+        </svg>"##).unwrap();
+        assert_eq!(icon.mime_type, "image/svg+xml");
+
+        let svg = decoded_svg(&icon);
+        for forbidden in ["script", "onload", "foreignObject", "javascript", "evil.example", "style"] {
+            assert!(!svg.contains(forbidden), "{} survived: {}", forbidden, svg);
+        }
+        assert!(svg.contains("<use href=\"#shape\">"));
+        assert!(svg.contains("<circle id=\"shape\" r=\"4\">"));
+
+        assert!(prepare_icon(br#"<!DOCTYPE svg [<!ENTITY x "boom">]><svg>&x;</svg>"#).is_err());
+    }
+}
@@ -1,211 +1,1694 @@
 // Tauri library entrypoint for mobile platforms
+mod audit_log;
+mod breach_check;
+mod capabilities;
+#[cfg(desktop)]
+mod cli;
 mod database;
+mod database_key;
+mod data_dir;
+#[cfg(desktop)]
+mod desktop;
 mod crypto;
+mod error;
+mod events;
 mod user_service;
 mod password_service;
+mod password_policy;
 mod export_service;
+mod migrations;
+mod os_keychain;
+mod pin_unlock;
+#[cfg(desktop)]
+mod native_messaging;
+mod settings_service;
+mod time_utils;
+mod reminder_service;
+mod icons;
+mod favicons;
+mod qr_codes;
+mod domains;
+mod entry_share;
+mod credential_parser;
+mod snapshot_service;
+mod session;
+mod system_events;
+mod vault_coordinator;
+#[cfg(feature = "query-console")]
+mod query_console;
 
-use std::sync::Mutex;
-use tauri::State;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri::plugin::PermissionState;
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+use tauri_plugin_notification::NotificationExt;
+use zeroize::Zeroizing;
 
-use database::Database;
-use user_service::{UserService, SetupRequest, LoginRequest, AuthResponse, SecurityQuestion, RecoveryRequest, ResetPasswordRequest};
-use password_service::{PasswordService, AddPasswordRequest, PasswordResponse, GetPasswordsRequest, DecryptPasswordRequest, UpdatePasswordRequest, DeletePasswordRequest};
-use export_service::{ExportService, ExportRequest, ExportResponse, ImportRequest, ImportResponse};
+use user_service::{SetupRequest, LoginRequest, AuthResponse, SecurityQuestion, RecoveryRequest, ResetPasswordRequest, ResetWithCodeRequest, RecoveryCodeResponse, RecoveryDrillResult, RecoveryStatus};
+use password_service::{AddPasswordRequest, AddPasswordItem, BatchAddResponse, PasswordResponse, PasswordService, GetPasswordsRequest, DecryptPasswordRequest, UpdatePasswordRequest, DeletePasswordRequest, SaveEntryDraftRequest, ReplaceAccountRequest, SetCustomFieldRequest, TemplateField, VaultAuditReport, DuplicateReport};
+use export_service::{BackupListing, CsvImportMode, CsvImportOptions, ExportFileStatus, ExportRequest, ExportResponse, ImportRequest, ImportResponse, PendingRestore, RestoreResponse, RestoreSummary};
+use reminder_service::ReminderSettings;
+use snapshot_service::SnapshotInfo;
+#[cfg(feature = "query-console")]
+use query_console::QueryResult;
+use vault_coordinator::VaultCoordinator;
+use capabilities::CapabilityRegistry;
+use audit_log::{AuditLogVerification, AuditRecord};
+use breach_check::BreachCheckReport;
+use favicons::FetchedIcon;
+use qr_codes::QrKind;
+use database::{EntryType, PasswordPolicy};
+use crypto::{KdfBenchmark, KdfParams};
+use error::PwdBoxError;
+use session::{SessionManager, VaultLocked};
+use system_events::SystemEvent;
+use os_keychain::SecretStore;
+#[cfg(desktop)]
+use native_messaging::{Approvals, Browser, HostRequest, InstalledManifest};
 
-// Application state
+// How often the running app re-checks for expiring entries; reminders are still sent at most daily
+const REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Window for the "passwords-expiring" event sent when the vault is unlocked
+const EXPIRING_SOON_DAYS: u32 = 7;
+
+// Seconds a copied password stays on the clipboard when the caller gives no timeout, and the longest allowed
+const DEFAULT_CLIPBOARD_CLEAR_SECS: u64 = 30;
+const MAX_CLIPBOARD_CLEAR_SECS: u64 = 600;
+
+// How often the idle timer checks whether the vault should lock itself
+const AUTO_LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Application state. Commands reach services only through the coordinator, which owns
+// the lock order; see vault_coordinator.rs.
 struct AppState {
-    database: Mutex<Database>,
-    user_service: Mutex<UserService>,
-    password_service: Mutex<PasswordService>,
-    export_service: Mutex<ExportService>,
+    vault: VaultCoordinator,
+    session: SessionManager, // Vault key from login; zeroed on lock or after the idle timeout
+    pending_link: Mutex<Option<String>>, // Set when a notification is posted, consumed by the frontend on focus
+    pending_restore: Mutex<Option<PendingRestore>>, // Backup read by prepare_restore, waiting for confirm_restore
+    login_failed: AtomicBool, // A login failed since the app started or the last successful one; unlocks get_password_hint
 }
 
+// Why the services could not start. The app still opens then, without AppState, so the
+// user sees the cause and can pick another data directory; commands needing AppState are
+// refused by Tauri until retry_initialization succeeds. None once the services run.
+struct StartupFailure(Mutex<Option<String>>);
+
 // Mobile entry point
 #[tauri::mobile_entry_point]
 fn mobile_main() {
     run()
 }
 
-// Initialize application services
-fn initialize_services() -> Result<AppState, Box<dyn std::error::Error>> {
-    // Create app data directory
-    let app_data_dir = dirs::data_dir()
-        .ok_or("Could not determine app data directory")?
-        .join("PwdBox");
-    
-    std::fs::create_dir_all(&app_data_dir)?;
-    
-    // Database path
-    let db_path = app_data_dir.join("pwdbox.db");
-    
-    // Initialize database
-    let database = Database::new(db_path)?;
-    
-    // Initialize services
-    let user_service = UserService::new(Database::new(app_data_dir.join("pwdbox.db"))?);
-    let password_service = PasswordService::new(Database::new(app_data_dir.join("pwdbox.db"))?);
-    let export_service = ExportService::new(Database::new(app_data_dir.join("pwdbox.db"))?);
-    
+// Initialize application services, in `chosen_dir` or the configured data directory
+fn initialize_services(chosen_dir: Option<PathBuf>) -> anyhow::Result<AppState> {
+    let (app_data_dir, source) = match chosen_dir {
+        Some(chosen_dir) => (chosen_dir, data_dir::DataDirSource::Pointer),
+        None => data_dir::resolve()?,
+    };
+    // The startup error dialog shows what this says, read-only media included
+    data_dir::ensure_writable(&app_data_dir, source)?;
+
+    let vault = VaultCoordinator::open(&app_data_dir)?;
+    let auto_lock_minutes = vault.settings(|settings_service| settings_service.get_auto_lock_minutes())?;
     Ok(AppState {
-        database: Mutex::new(database),
-        user_service: Mutex::new(user_service),
-        password_service: Mutex::new(password_service),
-        export_service: Mutex::new(export_service),
+        vault,
+        session: SessionManager::new(Duration::from_secs(auto_lock_minutes as u64 * 60)),
+        pending_link: Mutex::new(None),
+        pending_restore: Mutex::new(None),
+        login_failed: AtomicBool::new(false),
     })
 }
 
+// Everything setup starts once AppState is managed: at launch, or from
+// retry_initialization after a failed start
+fn start_services(app: &AppHandle) -> tauri::Result<()> {
+    app.state::<AppState>().vault.set_event_sink(Arc::new(app.clone()));
+    #[cfg(desktop)]
+    desktop::init(app)?;
+    #[cfg(desktop)]
+    start_native_host_endpoint(app);
+    // Re-check for expiring entries while the app keeps running
+    let handle = app.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(REMINDER_CHECK_INTERVAL);
+        check_expiring_entries(&handle);
+    });
+    // Idle timer: lock once no command has run for the auto-lock timeout
+    let handle = app.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(AUTO_LOCK_CHECK_INTERVAL);
+        if handle.state::<AppState>().session.lock_if_idle(Instant::now()) {
+            on_vault_locked(&handle);
+        }
+    });
+    // Lock when the machine sleeps or the OS session locks
+    let handle = app.clone();
+    system_events::watch(move |event| on_system_event(&handle, event));
+    // Mobile apps are suspended when they go to the background
+    #[cfg(mobile)]
+    if let Some(window) = app.get_webview_window("main") {
+        let handle = app.clone();
+        window.on_window_event(move |event| {
+            if let tauri::WindowEvent::Suspended = event {
+                on_system_event(&handle, SystemEvent::Suspend);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Open the vault in `chosen_dir` (the configured directory when None) after a failed start.
+// A directory that works is kept for later starts. Does nothing once the services run; a
+// new failure replaces the recorded one.
+fn restart_services(app: &AppHandle, chosen_dir: Option<PathBuf>) -> Result<(), PwdBoxError> {
+    let startup_failure = app.state::<StartupFailure>();
+    let mut failure = startup_failure.0.lock()?;
+    if failure.is_none() {
+        return Ok(());
+    }
+    let app_state = initialize_services(chosen_dir.clone()).map_err(|e| {
+        let error = PwdBoxError::from(e);
+        *failure = Some(error.to_string());
+        error
+    })?;
+    if let Some(chosen_dir) = chosen_dir {
+        let remembered = data_dir::resolve().and_then(|(_, source)| match source {
+            data_dir::DataDirSource::Environment | data_dir::DataDirSource::Portable => Ok(()),
+            _ => data_dir::set_pointer(&data_dir::default_dir()?, &chosen_dir),
+        });
+        if let Err(e) = remembered {
+            log::warn!("Failed to remember the data directory {}: {}", chosen_dir.display(), e);
+        }
+    }
+    app.manage(app_state);
+    *failure = None;
+    start_services(app).map_err(|e| PwdBoxError::internal(e.to_string()))
+}
+
+// The native error dialog for a failed start, since the frontend may not get far enough
+// to show one (and on Windows there is no console). Offers another data directory where
+// a folder picker exists, or exiting.
+fn report_startup_failure(app: &AppHandle, error: &str) {
+    log::error!("Failed to initialize application services: {}", error);
+    let handle = app.clone();
+    let dialog = app
+        .dialog()
+        .message(format!("PwdBox could not open its vault.\n\n{}", error))
+        .title("PwdBox could not start")
+        .kind(MessageDialogKind::Error);
+    #[cfg(desktop)]
+    dialog
+        .buttons(MessageDialogButtons::OkCancelCustom("Choose another folder".to_string(), "Exit".to_string()))
+        .show(move |choose| if choose { choose_data_dir(handle) } else { handle.exit(1) });
+    #[cfg(mobile)]
+    dialog.buttons(MessageDialogButtons::OkCustom("Exit".to_string())).show(move |_| handle.exit(1));
+}
+
+// Closing the picker brings the error back; a folder that fails too shows its own error
+#[cfg(desktop)]
+fn choose_data_dir(app: AppHandle) {
+    let handle = app.clone();
+    app.dialog().file().set_title("Choose a folder for the PwdBox vault").pick_folder(move |folder| {
+        let Some(chosen_dir) = folder.and_then(|folder| folder.into_path().ok()) else {
+            let error = handle.state::<StartupFailure>().0.lock().ok().and_then(|failure| failure.clone());
+            report_startup_failure(&handle, error.as_deref().unwrap_or_default());
+            return;
+        };
+        match restart_services(&handle, Some(chosen_dir)) {
+            Ok(()) => {
+                // The frontend loaded without services; start it over
+                if let Some(window) = handle.get_webview_window("main") {
+                    let _ = window.eval("window.location.reload()");
+                }
+            }
+            Err(e) => report_startup_failure(&handle, e.message()),
+        }
+    });
+}
+
+// Change events from the services go to every window
+impl events::EventSink for AppHandle {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        let _ = Emitter::emit(self, event, payload);
+    }
+}
+
+// Post an OS notification for expiring entries. Never runs while the vault is locked.
+fn check_expiring_entries(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    if !state.session.is_unlocked() {
+        return;
+    }
+
+    let Ok(Some(reminder)) = state.vault.reminders(|reminder_service| reminder_service.check_expiring()) else {
+        return;
+    };
+
+    // Desktop notifications carry no click payload; activating the app brings the
+    // window forward and the frontend picks up the pending link on focus
+    if let Ok(mut pending_link) = state.pending_link.lock() {
+        *pending_link = Some(reminder.link.clone());
+    }
+    let _ = app
+        .notification()
+        .builder()
+        .title(&reminder.title)
+        .body(&reminder.body)
+        .show();
+    let _ = app.emit("expiry-reminder", &reminder);
+}
+
+// Tell the frontend how many entries are due within EXPIRING_SOON_DAYS, every time the
+// vault is unlocked; unlike the notification above this is not limited to once a day.
+// Expiry dates can't be read before the vault is open, so this runs at unlock rather
+// than at startup.
+fn announce_expiring_entries(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    if let Ok(count) = state.vault.passwords(|password_service| password_service.count_expiring(EXPIRING_SOON_DAYS)) {
+        if count > 0 {
+            let _ = app.emit("passwords-expiring", serde_json::json!({"count": count, "within_days": EXPIRING_SOON_DAYS}));
+        }
+    }
+}
+
+// Run a command that needs the vault unlocked. Refused with the "locked" error before it
+// touches the vault; a successful run restarts the idle timeout.
+fn unlocked<T>(state: &AppState, command: impl FnOnce() -> anyhow::Result<T>) -> Result<T, PwdBoxError> {
+    let result = unlocked_untouched(state, command)?;
+    let _ = state.session.touch(Instant::now());
+    Ok(result)
+}
+
+// As unlocked, but leaving the idle timeout running: for requests that do not come from
+// someone using the app, like the browser extension's
+fn unlocked_untouched<T>(state: &AppState, command: impl FnOnce() -> anyhow::Result<T>) -> Result<T, PwdBoxError> {
+    let epoch = state.session.epoch();
+    state.session.ensure_unlocked(Instant::now())?;
+    let result = command()?;
+    // The vault locked while the command ran (sleep, screen lock, the shortcut): drop what
+    // it decrypted rather than hand plaintext to a frontend that is showing the login screen
+    if state.session.epoch() != epoch {
+        return Err(VaultLocked.into());
+    }
+    Ok(result)
+}
+
+// Run a command on the blocking thread pool. For commands doing Argon2 work, which would
+// otherwise stall the async runtime (and with it the UI) for the whole derivation.
+async fn blocking<T: Send + 'static>(
+    app: &AppHandle,
+    command: impl FnOnce(&AppHandle, &AppState) -> Result<T, PwdBoxError> + Send + 'static,
+) -> Result<T, PwdBoxError> {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || command(&app, &app.state::<AppState>()))
+        .await
+        .map_err(|e| PwdBoxError::internal(e.to_string()))?
+}
+
+// The key a command needing the vault key runs with. It comes from the backend session;
+// a key sent by the frontend is only honoured with session::LEGACY_KEY_IPC.
+fn command_key(state: &AppState, explicit_key: &str) -> Result<String, PwdBoxError> {
+    state.session.key_for_command(explicit_key, Instant::now()).map_err(PwdBoxError::from)
+}
+
+// After a login or keychain unlock succeeded
+fn on_unlocked(app: &AppHandle, state: &AppState, response: &mut AuthResponse) -> Result<(), PwdBoxError> {
+    // A failed snapshot must never keep the user out of their vault
+    if let Some(master_key) = &response.master_key {
+        let _ = state.vault.snapshots(|snapshot_service| snapshot_service.take_snapshot_if_due(master_key));
+    }
+    start_session(state, response)?;
+    check_expiring_entries(app);
+    announce_expiring_entries(app);
+    Ok(())
+}
+
+// The OS keychain, in builds that include it
+#[cfg(feature = "os-keychain")]
+fn os_keychain() -> anyhow::Result<&'static dyn SecretStore> {
+    Ok(&os_keychain::OsKeychain)
+}
+
+#[cfg(not(feature = "os-keychain"))]
+fn os_keychain() -> anyhow::Result<&'static dyn SecretStore> {
+    Err(PwdBoxError::validation("This build cannot use the OS keychain").into())
+}
+
+// A new master password retires keychain and PIN unlock; the keys kept for them would still
+// open the vault. The password has already changed, so a failure here is only logged.
+fn retire_quick_unlock(state: &AppState) {
+    let Ok(store) = os_keychain() else {
+        return;
+    };
+    if let Err(e) = state.vault.disable_os_keychain_unlock(store) {
+        log::warn!("Failed to remove the OS keychain item: {}", e);
+    }
+    if let Err(e) = state.vault.disable_pin_unlock(store) {
+        log::warn!("Failed to remove the PIN unlock keychain item: {}", e);
+    }
+}
+
+// Start the session for a key the user just authenticated for. The frontend gets the
+// session token, and the key itself only from legacy builds.
+fn start_session(state: &AppState, response: &mut AuthResponse) -> Result<(), PwdBoxError> {
+    if let (true, Some(master_key)) = (response.success, &response.master_key) {
+        response.session_token = Some(state.session.unlock(master_key)?);
+    }
+    if !session::LEGACY_KEY_IPC {
+        response.master_key = None;
+    }
+    Ok(())
+}
+
+// Lock now, for the lock_vault command and, on desktop, the lock shortcut and tray menu
+fn lock_now(app: &AppHandle) {
+    if app.state::<AppState>().session.lock() {
+        on_vault_locked(app);
+    }
+}
+
+// Lock for a sleep or screen lock unless the user turned that off. Settings that cannot
+// be read count as on.
+fn on_system_event(app: &AppHandle, event: SystemEvent) {
+    let enabled = app.state::<AppState>().vault.settings(|settings_service| match event {
+        SystemEvent::Suspend => settings_service.get_lock_on_suspend(),
+        SystemEvent::ScreenLocked => settings_service.get_lock_on_screen_lock(),
+    });
+    if enabled.unwrap_or(true) {
+        lock_now(app);
+    }
+}
+
+// Tell every window the vault locked. Locking always succeeds; drafts left behind are
+// purged on a later lock. A restore waiting for confirmation is dropped, browser
+// extension requests waiting for approval are denied, and a decoy session ends.
+fn on_vault_locked(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let _ = state.vault.passwords(|password_service| password_service.purge_stale_drafts());
+    let _ = state.vault.leave_decoy();
+    if let Ok(mut pending_restore) = state.pending_restore.lock() {
+        *pending_restore = None;
+    }
+    #[cfg(desktop)]
+    if let Some(approvals) = app.try_state::<Approvals>() {
+        approvals.deny_all();
+    }
+    let _ = app.emit("vault-locked", ());
+}
+
+// Let the native host reach the app. Without the endpoint the extension only learns that
+// PwdBox is not running, so a failure is logged and the app carries on.
+#[cfg(desktop)]
+fn start_native_host_endpoint(app: &AppHandle) {
+    app.manage(Approvals::default());
+    let handle = app.clone();
+    let served = data_dir::default_dir().and_then(|endpoint_dir| {
+        native_messaging::serve(&endpoint_dir, Arc::new(move |extension_id: &str, request: HostRequest| {
+            answer_native_request(&handle, extension_id, request)
+        }))
+    });
+    if let Err(e) = served {
+        log::warn!("Browser extensions cannot reach PwdBox: {}", e);
+    }
+}
+
+// Answer a request relayed by the native host, on one of its connection threads. Only
+// extensions in the allowlist get anything but an error, and a password only leaves the
+// vault once the user approves it in the app.
+#[cfg(desktop)]
+fn answer_native_request(app: &AppHandle, extension_id: &str, request: HostRequest) -> Result<serde_json::Value, PwdBoxError> {
+    let state = app.state::<AppState>();
+    let allowed = state.vault.settings(|settings_service| settings_service.get_native_host_extensions())?;
+    if !allowed.iter().any(|allowed_id| allowed_id == extension_id) {
+        return Err(PwdBoxError::invalid_field("extension_id", format!("Extension {} is not allowed to use PwdBox", extension_id)));
+    }
+
+    match request {
+        HostRequest::Status => Ok(serde_json::json!({"running": true, "unlocked": state.session.is_unlocked()})),
+        HostRequest::QueryByUrl { url } => {
+            let master_key = command_key(&state, "")?;
+            let response = unlocked_untouched(&state, || {
+                state.vault.passwords(|password_service| password_service.find_entries_for_url(&url, &master_key))
+            })?;
+            if !response.success {
+                return Err(PwdBoxError::invalid_field("url", response.message));
+            }
+            Ok(response.data.unwrap_or_default())
+        }
+        HostRequest::GetCredential { id } => {
+            state.session.ensure_unlocked(Instant::now())?;
+            let approvals = app.state::<Approvals>();
+            let (request_id, answer) = approvals.request();
+            let _ = app.emit(
+                "native-credential-request",
+                serde_json::json!({"request_id": request_id, "extension_id": extension_id, "id": id}),
+            );
+            desktop::show_main_window(app);
+            if !approvals.wait(request_id, answer) {
+                return Err(PwdBoxError::validation("The request was not approved"));
+            }
+
+            let master_key = command_key(&state, "")?;
+            let request = DecryptPasswordRequest { id, master_key, skip_usage_tracking: false };
+            let response = unlocked_untouched(&state, || state.vault.passwords(|password_service| password_service.get_password(request)))?;
+            let entry = response.data.unwrap_or_default();
+            Ok(serde_json::json!({
+                "id": entry["id"],
+                "software": entry["software"],
+                "account": entry["account"],
+                "url": entry["url"],
+                "password": entry["password"],
+            }))
+        }
+    }
+}
+
 // User Management Commands
 #[tauri::command]
-async fn is_app_setup(state: State<'_, AppState>) -> Result<bool, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.is_app_setup().map_err(|e| e.to_string())
+async fn is_app_setup(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.users(|user_service| user_service.is_app_setup()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn setup_app(request: SetupRequest, app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let mut response = state.vault.setup_app(request)?;
+        start_session(state, &mut response)?;
+        Ok(response)
+    }).await
+}
+
+#[tauri::command]
+async fn login(request: LoginRequest, app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    blocking(&app, move |app, state| {
+        let mut response = state.vault
+            .login_with_progress(request, &mut |progress| {
+                let _ = app.emit("migration-progress", progress);
+            })?;
+
+        if response.success {
+            on_unlocked(app, state, &mut response)?;
+        }
+        state.login_failed.store(!response.success, Ordering::SeqCst);
+        Ok(response)
+    }).await
+}
+
+// Keychain unlock is turned on from an unlocked session and replaces any earlier item
+#[tauri::command]
+async fn enable_os_keychain_unlock(app: AppHandle) -> Result<(), PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let master_key = Zeroizing::new(command_key(state, "")?);
+        unlocked(state, || state.vault.enable_os_keychain_unlock(&master_key, os_keychain()?))
+    }).await
+}
+
+#[tauri::command]
+async fn disable_os_keychain_unlock(app: AppHandle) -> Result<(), PwdBoxError> {
+    blocking(&app, move |_, state| state.vault.disable_os_keychain_unlock(os_keychain()?).map_err(PwdBoxError::from)).await
+}
+
+// A failed keychain unlock is not a failed login: the password was never tried, so the
+// hint stays hidden and the frontend just falls back to the password form
+#[tauri::command]
+async fn unlock_with_os_keychain(app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    blocking(&app, move |app, state| {
+        let mut response = state.vault.unlock_with_os_keychain(os_keychain()?)?;
+        if response.success {
+            on_unlocked(app, state, &mut response)?;
+        }
+        Ok(response)
+    }).await
+}
+
+// A PIN for unlocking after the vault locks, with the master password still at hand for a
+// full login; see VaultCoordinator::enable_pin_unlock
+#[tauri::command]
+async fn enable_pin_unlock(pin: String, app: AppHandle) -> Result<(), PwdBoxError> {
+    let pin = Zeroizing::new(pin);
+    blocking(&app, move |_, state| {
+        let master_key = Zeroizing::new(command_key(state, "")?);
+        unlocked(state, || state.vault.enable_pin_unlock(&master_key, &pin, os_keychain()?))
+    }).await
+}
+
+#[tauri::command]
+async fn disable_pin_unlock(app: AppHandle) -> Result<(), PwdBoxError> {
+    blocking(&app, move |_, state| state.vault.disable_pin_unlock(os_keychain()?).map_err(PwdBoxError::from)).await
+}
+
+// Like a keychain unlock, a wrong PIN is not a failed login; it counts against the PIN's own cap
+#[tauri::command]
+async fn unlock_with_pin(pin: String, app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    let pin = Zeroizing::new(pin);
+    blocking(&app, move |app, state| {
+        let mut response = state.vault.unlock_with_pin(&pin, os_keychain()?)?;
+        if response.success {
+            on_unlocked(app, state, &mut response)?;
+        }
+        Ok(response)
+    }).await
+}
+
+#[tauri::command]
+async fn lock_vault(app: AppHandle) -> Result<(), PwdBoxError> {
+    lock_now(&app);
+    Ok(())
+}
+
+// Lets a reloaded frontend check whether the session it knows is still open
+#[tauri::command]
+async fn is_session_active(session_token: String, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    Ok(state.session.is_current(&session_token, Instant::now()))
+}
+
+// Called by the frontend on user input, so reading without running commands keeps the vault open
+#[tauri::command]
+async fn touch_activity(state: State<'_, AppState>) -> Result<(), PwdBoxError> {
+    state.session.touch(Instant::now()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn get_auto_lock_timeout(state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_auto_lock_minutes()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_auto_lock_timeout(minutes: u32, state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    let minutes = unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_auto_lock_minutes(minutes)))?;
+    state.session.set_idle_timeout(Duration::from_secs(minutes as u64 * 60));
+    Ok(minutes)
+}
+
+#[tauri::command]
+async fn get_security_questions(state: State<'_, AppState>) -> Result<Vec<SecurityQuestion>, PwdBoxError> {
+    state.vault.users(|user_service| user_service.get_security_questions()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn verify_recovery_answers(request: RecoveryRequest, app: AppHandle) -> Result<bool, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        state.vault.users(|user_service| user_service.verify_recovery_answers(request)).map_err(PwdBoxError::from)
+    }).await
+}
+
+// Checks the recovery answers and the recovery copy of the vault key without resetting
+// anything or starting a session
+#[tauri::command]
+async fn practice_recovery(request: RecoveryRequest, app: AppHandle) -> Result<RecoveryDrillResult, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        state.vault.users(|user_service| user_service.practice_recovery(request)).map_err(PwdBoxError::from)
+    }).await
+}
+
+#[tauri::command]
+async fn get_recovery_status(state: State<'_, AppState>) -> Result<RecoveryStatus, PwdBoxError> {
+    state.vault.users(|user_service| user_service.get_recovery_status()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn reset_master_password(request: ResetPasswordRequest, app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let mut response = state.vault.reset_master_password(request)?;
+        if response.success {
+            retire_quick_unlock(state);
+        }
+        start_session(state, &mut response)?;
+        Ok(response)
+    }).await
+}
+
+// The recovery code unwraps the vault key, so entries stay readable; the code is used up
+#[tauri::command]
+async fn reset_master_password_with_code(request: ResetWithCodeRequest, app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let mut response = state.vault.reset_master_password_with_code(request)?;
+        if response.success {
+            retire_quick_unlock(state);
+        }
+        start_session(state, &mut response)?;
+        Ok(response)
+    }).await
+}
+
+// Replaces any earlier recovery code. The new code is in the response and nowhere else.
+#[tauri::command]
+async fn generate_recovery_code(master_password: String, app: AppHandle) -> Result<RecoveryCodeResponse, PwdBoxError> {
+    let master_password = Zeroizing::new(master_password);
+    blocking(&app, move |_, state| {
+        unlocked(state, || state.vault.generate_recovery_code(&master_password))
+    }).await
 }
 
+// Replaces every question and answer at once. The recovery drill is due again afterwards,
+// which the returned status shows.
 #[tauri::command]
-async fn setup_app(request: SetupRequest, state: State<'_, AppState>) -> Result<AuthResponse, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.setup_app(request).map_err(|e| e.to_string())
+async fn change_security_questions(master_password: String, questions: Vec<(String, String)>, app: AppHandle) -> Result<RecoveryStatus, PwdBoxError> {
+    let (master_password, questions) = (Zeroizing::new(master_password), Zeroizing::new(questions));
+    blocking(&app, move |_, state| {
+        unlocked(state, || state.vault.change_security_questions(&master_password, &questions))
+    }).await
 }
 
+// Shown on the login screen, but only once a login has failed, so the hint is not on
+// screen for anyone looking over the shoulder of a user who knows their password
 #[tauri::command]
-async fn login(request: LoginRequest, state: State<'_, AppState>) -> Result<AuthResponse, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.login(request).map_err(|e| e.to_string())
+async fn get_password_hint(state: State<'_, AppState>) -> Result<Option<String>, PwdBoxError> {
+    if !state.login_failed.load(Ordering::SeqCst) {
+        return Ok(None);
+    }
+    state.vault.users(|user_service| user_service.get_password_hint()).map_err(PwdBoxError::from)
 }
 
+// A blank hint removes it. Returns the hint as saved.
 #[tauri::command]
-async fn get_security_questions(state: State<'_, AppState>) -> Result<Vec<SecurityQuestion>, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.get_security_questions().map_err(|e| e.to_string())
+async fn set_password_hint(master_password: String, hint: String, app: AppHandle) -> Result<Option<String>, PwdBoxError> {
+    let master_password = Zeroizing::new(master_password);
+    blocking(&app, move |_, state| {
+        unlocked(state, || state.vault.set_password_hint(&master_password, &hint))
+    }).await
 }
 
+// Logging in with the duress password opens a decoy vault; see VaultCoordinator::setup_duress
 #[tauri::command]
-async fn verify_recovery_answers(request: RecoveryRequest, state: State<'_, AppState>) -> Result<bool, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.verify_recovery_answers(request).map_err(|e| e.to_string())
+async fn setup_duress(master_password: String, duress_password: String, app: AppHandle) -> Result<(), PwdBoxError> {
+    let (master_password, duress_password) = (Zeroizing::new(master_password), Zeroizing::new(duress_password));
+    blocking(&app, move |_, state| {
+        unlocked(state, || state.vault.setup_duress(&master_password, &duress_password))
+    }).await
 }
 
+// None while the panic wipe is off. Only told to an unlocked session, so the login screen
+// gives nothing away.
 #[tauri::command]
-async fn reset_master_password(request: ResetPasswordRequest, state: State<'_, AppState>) -> Result<AuthResponse, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.reset_master_password(request).map_err(|e| e.to_string())
+async fn get_panic_wipe_threshold(state: State<'_, AppState>) -> Result<Option<u32>, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.get_panic_wipe_threshold()))
 }
 
+// See VaultCoordinator::set_panic_wipe_threshold; None turns it off
 #[tauri::command]
-async fn change_master_password(current_password: String, new_password: String, state: State<'_, AppState>) -> Result<AuthResponse, String> {
-    let user_service = state.user_service.lock().map_err(|e| e.to_string())?;
-    user_service.change_master_password(&current_password, &new_password).map_err(|e| e.to_string())
+async fn set_panic_wipe_threshold(master_password: String, threshold: Option<u32>, app: AppHandle) -> Result<Option<u32>, PwdBoxError> {
+    let master_password = Zeroizing::new(master_password);
+    blocking(&app, move |_, state| {
+        unlocked(state, || state.vault.set_panic_wipe_threshold(&master_password, threshold))
+    }).await
+}
+
+#[tauri::command]
+async fn change_master_password(current_password: String, new_password: String, app: AppHandle) -> Result<AuthResponse, PwdBoxError> {
+    let (current_password, new_password) = (Zeroizing::new(current_password), Zeroizing::new(new_password));
+    blocking(&app, move |_, state| {
+        let mut response = unlocked(state, || state.vault.change_master_password(&current_password, &new_password))?;
+        if response.success {
+            retire_quick_unlock(state);
+        }
+        // The old key was invalidated with the change
+        start_session(state, &mut response)?;
+        Ok(response)
+    }).await
 }
 
 // Password Management Commands
 #[tauri::command]
-async fn add_password(request: AddPasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.add_password(request).map_err(|e| e.to_string())
+async fn add_password(mut request: AddPasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    request.master_key = command_key(&state, &request.master_key)?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.add_password(request)))
+}
+
+// Many entries in one transaction; items that are refused are reported by index
+#[tauri::command]
+async fn add_passwords_batch(items: Vec<AddPasswordItem>, master_key: Option<String>, state: State<'_, AppState>) -> Result<BatchAddResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.add_passwords_batch(items, &master_key)))
+}
+
+// A search decrypts every entry's notes (and names or passwords when asked to), so it
+// runs off the async runtime
+#[tauri::command]
+async fn get_all_passwords(mut request: GetPasswordsRequest, app: AppHandle) -> Result<PasswordResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        request.master_key = command_key(state, &request.master_key)?;
+        unlocked(state, || state.vault.passwords(|password_service| password_service.get_all_passwords(request)))
+    }).await
+}
+
+#[tauri::command]
+async fn get_password(mut request: DecryptPasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    request.master_key = command_key(&state, &request.master_key)?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_password(request)))
+}
+
+// Put an entry's password on the system clipboard without handing it to the frontend, and
+// clear it after `clear_after_secs` unless something else has been copied since. Only a
+// digest of the password is kept around for that check.
+#[tauri::command]
+async fn copy_password_to_clipboard(
+    id: i64,
+    master_key: Option<String>,
+    clear_after_secs: Option<u64>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), PwdBoxError> {
+    let clear_after_secs = clear_after_secs.unwrap_or(DEFAULT_CLIPBOARD_CLEAR_SECS);
+    if clear_after_secs == 0 || clear_after_secs > MAX_CLIPBOARD_CLEAR_SECS {
+        return Err(PwdBoxError::invalid_field(
+            "clear_after_secs",
+            format!("Clipboard timeout must be between 1 and {} seconds", MAX_CLIPBOARD_CLEAR_SECS),
+        ));
+    }
+
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    let password = unlocked(&state, || state.vault.passwords(|password_service| password_service.password_for_clipboard(id, &master_key)))?;
+    let digest = Sha256::digest(password.as_bytes());
+    app.clipboard()
+        .write_text(password)
+        .map_err(|_| PwdBoxError::io("Could not write to the clipboard"))?;
+
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(clear_after_secs)).await;
+        let still_ours = app.clipboard().read_text().is_ok_and(|text| Sha256::digest(text.as_bytes()) == digest);
+        if still_ours && app.clipboard().clear().is_ok() {
+            let _ = app.emit("clipboard-cleared", serde_json::json!({"id": id}));
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+async fn update_password(mut request: UpdatePasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    request.master_key = command_key(&state, &request.master_key)?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.update_password(request)))
+}
+
+// Multi-select delete; entries go to the trash like delete_password
+#[tauri::command]
+async fn delete_passwords(ids: Vec<i64>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.delete_passwords(&ids)))
+}
+
+#[tauri::command]
+async fn delete_password(request: DeletePasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.delete_password(request)))
+}
+
+#[tauri::command]
+async fn search_passwords(query: String, master_key: Option<String>, app: AppHandle) -> Result<PasswordResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let master_key = command_key(state, master_key.as_deref().unwrap_or_default())?;
+        unlocked(state, || state.vault.passwords(|password_service| password_service.search_passwords(&query, &master_key)))
+    }).await
+}
+
+#[tauri::command]
+async fn get_password_count(state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_password_count()))
+}
+
+// Whether a cached key still opens the vault, e.g. after the machine resumes
+#[tauri::command]
+async fn validate_master_key(master_key: Option<String>, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.validate_master_key(&master_key)))
+}
+
+#[tauri::command]
+async fn get_health_report(master_key: Option<String>, app: AppHandle, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_health_report(&master_key, &mut |progress| {
+        let _ = app.emit("scan-progress", progress);
+    })))
+}
+
+// Have I Been Pwned lookup of every password by k-anonymity, when turned on in settings.
+// Breached entries are flagged for the action list. Offline, the report says so in its
+// status rather than failing.
+#[tauri::command]
+async fn check_breaches(master_key: Option<String>, app: AppHandle) -> Result<BreachCheckReport, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let master_key = command_key(state, master_key.as_deref().unwrap_or_default())?;
+        unlocked(state, || state.vault.check_breaches(&master_key, &mut breach_check::fetch_range))
+    }).await
+}
+
+// Weak, reused and old passwords by entry id. Runs off the async runtime, since large vaults
+// take a while to decrypt, and emits `audit-progress` after each batch.
+#[tauri::command]
+async fn audit_vault(master_key: Option<String>, app: AppHandle) -> Result<VaultAuditReport, PwdBoxError> {
+    blocking(&app, move |app, state| {
+        let master_key = command_key(state, master_key.as_deref().unwrap_or_default())?;
+        unlocked(state, || state.vault.passwords(|password_service| password_service.audit_vault(&master_key, &mut |progress| {
+            let _ = app.emit("audit-progress", progress);
+        })))
+    }).await
+}
+
+// Re-encrypts every entry under the vault key with fresh nonces, binding entries from before
+// ciphertexts were bound to their uid on the way. It is one transaction, so a failure leaves
+// the vault as it was; emits `reencrypt-progress` ({ done, total }) after each batch.
+#[tauri::command]
+async fn re_encrypt_vault(app: AppHandle) -> Result<PasswordResponse, PwdBoxError> {
+    blocking(&app, move |app, state| {
+        let master_key = Zeroizing::new(command_key(state, "")?);
+        unlocked(state, || state.vault.passwords(|password_service| password_service.re_encrypt_all_passwords(&master_key, &master_key, &mut |progress| {
+            let _ = app.emit("reencrypt-progress", serde_json::json!({"done": progress.processed, "total": progress.total}));
+        })))
+    }).await
+}
+
+// One-time and irreversible: from then on software and account are stored encrypted too
+#[tauri::command]
+async fn encrypt_metadata(master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.encrypt_metadata(&master_key)))
+}
+
+// One-time: moves pwdbox.db to a SQLCipher-encrypted file (encrypted_database capability)
+#[tauri::command]
+async fn migrate_to_encrypted_db(master_key: Option<String>, state: State<'_, AppState>) -> Result<(), PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.migrate_to_encrypted_db(&master_key))
+}
+
+#[tauri::command]
+async fn get_stats_history(range_days: Option<u32>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_stats_history(range_days)))
+}
+
+#[tauri::command]
+async fn get_entries_grouped_by_domain(offset: Option<usize>, limit: Option<usize>, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_entries_grouped_by_domain(offset, limit, &master_key)))
+}
+
+#[tauri::command]
+async fn set_entry_icon(id: i64, image_path: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.set_entry_icon(id, &image_path)))
+}
+
+#[tauri::command]
+async fn clear_entry_icon(id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.clear_entry_icon(id)))
+}
+
+// The entry's own icon, else the favicon fetched for its host
+#[tauri::command]
+async fn get_icon(entry_id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_icon(entry_id)))
+}
+
+// Favicon for the entry's URL host, when turned on in settings. Cached per host; a host that
+// cannot be reached or serves no usable image gives a null icon rather than an error.
+#[tauri::command]
+async fn fetch_icon(entry_id: i64, app: AppHandle) -> Result<FetchedIcon, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        unlocked(state, || state.vault.fetch_icon(entry_id, &mut favicons::fetch))
+    }).await
+}
+
+// A QR code of the entry's Wi-Fi network or TOTP secret, rendered in memory only
+#[tauri::command]
+async fn get_entry_qr(id: i64, kind: QrKind, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_entry_qr(id, &master_key, kind)))
+}
+
+#[tauri::command]
+async fn add_attachment(entry_id: i64, path: String, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.add_attachment(entry_id, &path, &master_key)))
+}
+
+#[tauri::command]
+async fn get_attachment(attachment_id: i64, output_path: String, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_attachment(attachment_id, &master_key, &output_path)))
+}
+
+#[tauri::command]
+async fn list_attachments(entry_id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.list_attachments(entry_id)))
+}
+
+#[tauri::command]
+async fn delete_attachment(id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.delete_attachment(id)))
+}
+
+#[tauri::command]
+async fn set_custom_field(mut request: SetCustomFieldRequest, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    request.master_key = command_key(&state, &request.master_key)?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.set_custom_field(request)))
+}
+
+#[tauri::command]
+async fn delete_custom_field(id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.delete_custom_field(id)))
+}
+
+// The fields the editor offers for a new entry of this type; needs no vault
+#[tauri::command]
+async fn get_entry_template(entry_type: EntryType) -> Result<Vec<TemplateField>, PwdBoxError> {
+    Ok(PasswordService::entry_template(entry_type))
+}
+
+#[tauri::command]
+async fn get_stale_passwords(days: u32, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_stale_passwords(days, &master_key)))
+}
+
+#[tauri::command]
+async fn tag_passwords(ids: Vec<i64>, tag: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.tag_passwords(&ids, &tag)))
+}
+
+#[tauri::command]
+async fn add_tag_to_entry(id: i64, tag: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.add_tag_to_entry(id, &tag)))
+}
+
+#[tauri::command]
+async fn remove_tag_from_entry(id: i64, tag: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.remove_tag_from_entry(id, &tag)))
+}
+
+#[tauri::command]
+async fn list_tags(state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.list_tags()))
+}
+
+#[tauri::command]
+async fn get_software_groups(master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_software_groups(&master_key)))
+}
+
+#[tauri::command]
+async fn replace_account_value(mut request: ReplaceAccountRequest, app: AppHandle, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    request.master_key = command_key(&state, &request.master_key)?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.replace_account_value(request, &mut |progress| {
+        let _ = app.emit("bulk-edit-progress", progress);
+    })))
+}
+
+#[tauri::command]
+async fn get_software_suggestions(prefix: String, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_software_suggestions(&prefix, &master_key)))
+}
+
+#[tauri::command]
+async fn normalize_software_names(mapping: HashMap<String, Vec<i64>>, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.normalize_software_names(&mapping, &master_key)))
+}
+
+#[tauri::command]
+async fn find_entries_for_url(url: String, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.find_entries_for_url(&url, &master_key)))
+}
+
+#[tauri::command]
+async fn duplicate_password(id: i64, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.duplicate_password(id, &master_key)))
+}
+
+// Both derive a key from the share passphrase, so they run off the async runtime
+#[tauri::command]
+async fn share_entry(id: i64, share_passphrase: String, expires_at: Option<String>, master_key: Option<String>, app: AppHandle) -> Result<PasswordResponse, PwdBoxError> {
+    let share_passphrase = Zeroizing::new(share_passphrase);
+    blocking(&app, move |_, state| {
+        let master_key = command_key(state, master_key.as_deref().unwrap_or_default())?;
+        unlocked(state, || state.vault.passwords(|password_service| {
+            password_service.share_entry(id, &master_key, &share_passphrase, expires_at.as_deref())
+        }))
+    }).await
+}
+
+#[tauri::command]
+async fn import_shared_entry(blob: String, passphrase: String, master_key: Option<String>, app: AppHandle) -> Result<PasswordResponse, PwdBoxError> {
+    let passphrase = Zeroizing::new(passphrase);
+    blocking(&app, move |_, state| {
+        let master_key = command_key(state, master_key.as_deref().unwrap_or_default())?;
+        unlocked(state, || state.vault.passwords(|password_service| password_service.import_shared_entry(&blob, &passphrase, &master_key)))
+    }).await
+}
+
+#[tauri::command]
+async fn find_duplicates(master_key: Option<String>, state: State<'_, AppState>) -> Result<DuplicateReport, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.find_duplicates(&master_key)))
+}
+
+// Fold duplicates into the entry kept; the others go to the trash
+#[tauri::command]
+async fn merge_entries(keep_id: i64, remove_ids: Vec<i64>, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.merge_entries(keep_id, &remove_ids, &master_key)))
+}
+
+#[tauri::command]
+async fn list_trash(master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.list_trash(&master_key)))
+}
+
+#[tauri::command]
+async fn restore_password(id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.restore_password(id)))
+}
+
+#[tauri::command]
+async fn list_password_policies(state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.list_password_policies()))
+}
+
+#[tauri::command]
+async fn set_password_policy(policy: PasswordPolicy, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.set_password_policy(policy)))
+}
+
+#[tauri::command]
+async fn delete_password_policy(tag: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.delete_password_policy(&tag)))
+}
+
+#[tauri::command]
+async fn get_audit_log(master_key: Option<String>, limit: Option<usize>, offset: Option<usize>, state: State<'_, AppState>) -> Result<Vec<AuditRecord>, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| {
+        password_service.get_audit_log(&master_key, offset.unwrap_or(0), limit.unwrap_or(audit_log::DEFAULT_PAGE_SIZE))
+    }))
+}
+
+#[tauri::command]
+async fn verify_audit_log(master_key: Option<String>, state: State<'_, AppState>) -> Result<AuditLogVerification, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.verify_audit_log(&master_key)))
+}
+
+#[tauri::command]
+async fn check_integrity(state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.check_integrity()))
+}
+
+#[tauri::command]
+async fn purge_trash(older_than_days: Option<u32>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.purge_trash(older_than_days)))
+}
+
+#[tauri::command]
+async fn save_entry_draft(mut request: SaveEntryDraftRequest, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    request.master_key = command_key(&state, &request.master_key)?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.save_entry_draft(request)))
+}
+
+#[tauri::command]
+async fn get_entry_draft(entry_id: Option<i64>, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_entry_draft(entry_id, &master_key)))
+}
+
+#[tauri::command]
+async fn discard_entry_draft(entry_id: Option<i64>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.discard_entry_draft(entry_id)))
+}
+
+#[tauri::command]
+async fn toggle_favorite(id: i64, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.toggle_favorite(id)))
+}
+
+#[tauri::command]
+async fn set_rotation_interval(id: i64, days: Option<u32>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.set_rotation_interval(id, days)))
+}
+
+#[tauri::command]
+async fn get_expiring_passwords(within_days: u32, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_expiring_passwords(within_days, &master_key)))
+}
+
+#[tauri::command]
+async fn get_entries_by_tag(tag: String, master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_entries_by_tag(&tag, &master_key)))
 }
 
 #[tauri::command]
-async fn get_all_passwords(request: GetPasswordsRequest, state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.get_all_passwords(request).map_err(|e| e.to_string())
+async fn delete_tag(tag: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.delete_tag(&tag)))
 }
 
 #[tauri::command]
-async fn get_password(request: DecryptPasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.get_password(request).map_err(|e| e.to_string())
+async fn mark_entries_breached(ids: Vec<i64>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.mark_entries_breached(&ids)))
 }
 
 #[tauri::command]
-async fn update_password(request: UpdatePasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.update_password(request).map_err(|e| e.to_string())
+async fn get_action_items(master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_action_items(&master_key)))
 }
 
 #[tauri::command]
-async fn delete_password(request: DeletePasswordRequest, state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.delete_password(request).map_err(|e| e.to_string())
+async fn dismiss_breach(id: i64, reason: String, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.dismiss_breach(id, &reason)))
 }
 
+// Only ever run on an explicit user action. The clipboard text is parsed in memory and
+// never logged, stored or echoed back in error messages.
 #[tauri::command]
-async fn search_passwords(query: String, master_key: String, state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.search_passwords(&query, &master_key).map_err(|e| e.to_string())
+async fn parse_clipboard_for_credentials(app: AppHandle, state: State<'_, AppState>) -> Result<Option<AddPasswordRequest>, PwdBoxError> {
+    let enabled = state.vault
+        .settings(|settings_service| settings_service.get_clipboard_parsing_enabled())?;
+    if !enabled {
+        return Err(PwdBoxError::validation("Creating entries from the clipboard is disabled in settings"));
+    }
+
+    let text = app
+        .clipboard()
+        .read_text()
+        .map_err(|_| PwdBoxError::io("Could not read text from the clipboard"))?;
+    Ok(credential_parser::parse_credentials(&text))
 }
 
 #[tauri::command]
-async fn get_password_count(state: State<'_, AppState>) -> Result<PasswordResponse, String> {
-    let password_service = state.password_service.lock().map_err(|e| e.to_string())?;
-    password_service.get_password_count().map_err(|e| e.to_string())
+async fn get_vault_stats(master_key: Option<String>, state: State<'_, AppState>) -> Result<PasswordResponse, PwdBoxError> {
+    // Strength counts only when a key is passed; an empty one stands for the session's
+    let master_key = master_key.map(|master_key| command_key(&state, &master_key)).transpose()?;
+    unlocked(&state, || state.vault.passwords(|password_service| password_service.get_vault_stats(master_key.as_deref())))
 }
 
 // Export/Import Commands
 #[tauri::command]
-async fn export_data(request: ExportRequest, state: State<'_, AppState>) -> Result<ExportResponse, String> {
-    let export_service = state.export_service.lock().map_err(|e| e.to_string())?;
-    export_service.export_data(request).map_err(|e| e.to_string())
+async fn export_data(mut request: ExportRequest, app: AppHandle) -> Result<ExportResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        if !request.include_user_meta {
+            request.master_key = Some(command_key(state, request.master_key.as_deref().unwrap_or_default())?);
+        }
+        unlocked(state, || state.vault.exports(|export_service| export_service.export_data(request)))
+    }).await
+}
+
+#[tauri::command]
+async fn import_data(mut request: ImportRequest, app: AppHandle) -> Result<ImportResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        if request.source_master_password.is_some() {
+            request.target_master_key = Some(command_key(state, request.target_master_key.as_deref().unwrap_or_default())?);
+        }
+        request.session_key = command_key(state, "").ok();
+        unlocked(state, || state.vault.import_data(request))
+    }).await
+}
+
+#[tauri::command]
+async fn preview_import(mut request: ImportRequest, app: AppHandle) -> Result<serde_json::Value, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        if request.source_master_password.is_some() {
+            request.target_master_key = Some(command_key(state, request.target_master_key.as_deref().unwrap_or_default())?);
+        }
+        request.session_key = command_key(state, "").ok();
+        unlocked(state, || state.vault.exports(|export_service| export_service.preview_import(request)))
+    }).await
+}
+
+#[tauri::command]
+async fn create_backup(export_passphrase: String, backup_path: Option<String>, app: AppHandle) -> Result<ExportResponse, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let backup_dir = data_dir::default_backup_dir(&state.vault.data_dir());
+        unlocked(state, || state.vault.exports(|export_service| export_service.create_backup(&export_passphrase, backup_path.as_deref(), &backup_dir)))
+    }).await
+}
+
+// Restoring a backup takes two steps. prepare_restore reads and checks the whole backup and
+// returns its summary with a token; only confirm_restore with that token replaces the vault.
+#[tauri::command]
+async fn prepare_restore(file_path: String, passphrase: String, app: AppHandle) -> Result<RestoreSummary, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        let (pending, summary) = unlocked(state, || state.vault.exports(|export_service| export_service.prepare_restore(&file_path, &passphrase, Instant::now())))?;
+        *state.pending_restore.lock()? = Some(pending);
+        Ok(summary)
+    }).await
+}
+
+#[tauri::command]
+async fn confirm_restore(token: String, state: State<'_, AppState>) -> Result<RestoreResponse, PwdBoxError> {
+    let export_data = {
+        let mut pending_restore = state.pending_restore.lock()?;
+        PendingRestore::take(&mut pending_restore, &token, Instant::now())?
+    };
+    unlocked(&state, || state.vault.confirm_restore(&export_data))
+}
+
+#[tauri::command]
+async fn export_audit_log_csv(master_key: Option<String>, file_path: String, state: State<'_, AppState>) -> Result<ExportResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.exports(|export_service| export_service.export_audit_log_csv(&master_key, &file_path)))
+}
+
+// Every password in the clear, for moving to another manager; refused without the acknowledgement
+#[tauri::command]
+async fn export_csv(master_key: Option<String>, file_path: String, acknowledge_plaintext: bool, state: State<'_, AppState>) -> Result<ExportResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.exports(|export_service| export_service.export_csv(&master_key, &file_path, acknowledge_plaintext)))
+}
+
+// Passwords exported from a browser, LastPass, 1Password, or any CSV with a column mapping
+#[tauri::command]
+async fn import_csv(file_path: String, master_key: Option<String>, mode: Option<CsvImportMode>, options: Option<CsvImportOptions>, state: State<'_, AppState>) -> Result<ImportResponse, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    let options = options.unwrap_or_default();
+    unlocked(&state, || state.vault.exports(|export_service| export_service.import_csv(&file_path, &master_key, mode.unwrap_or_default(), &options)))
+}
+
+#[tauri::command]
+async fn preview_csv_import(file_path: String, master_key: Option<String>, mode: Option<CsvImportMode>, options: Option<CsvImportOptions>, limit: Option<usize>, state: State<'_, AppState>) -> Result<serde_json::Value, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    let options = options.unwrap_or_default();
+    unlocked(&state, || state.vault.exports(|export_service| {
+        export_service.preview_csv_import(&file_path, &master_key, mode.unwrap_or_default(), &options, limit.unwrap_or(10))
+    }))
+}
+
+#[tauri::command]
+async fn validate_export_file(file_path: String, passphrase: String, app: AppHandle) -> Result<ExportFileStatus, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        state.vault.exports(|export_service| export_service.validate_export_file(&file_path, &passphrase)).map_err(PwdBoxError::from)
+    }).await
+}
+
+#[tauri::command]
+async fn get_export_info(file_path: String, passphrase: Option<String>, app: AppHandle) -> Result<serde_json::Value, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        state.vault.exports(|export_service| export_service.get_export_info(&file_path, passphrase.as_deref())).map_err(PwdBoxError::from)
+    }).await
+}
+
+// Every file in a backup directory, newest first; with the passphrase entry counts are read too
+#[tauri::command]
+async fn list_backups(backup_dir: String, passphrase: Option<String>, app: AppHandle) -> Result<Vec<BackupListing>, PwdBoxError> {
+    blocking(&app, move |_, state| {
+        state.vault.exports(|export_service| export_service.list_backups(&backup_dir, passphrase.as_deref())).map_err(PwdBoxError::from)
+    }).await
+}
+
+// Deletes all but the newest keep_count pwdbox_backup_*.enc files in the directory
+#[tauri::command]
+async fn cleanup_old_backups(backup_dir: String, keep_count: usize, state: State<'_, AppState>) -> Result<serde_json::Value, PwdBoxError> {
+    unlocked(&state, || state.vault.exports(|export_service| export_service.cleanup_old_backups(&backup_dir, keep_count)))
+}
+
+// Vault snapshots
+#[tauri::command]
+async fn list_snapshots(state: State<'_, AppState>) -> Result<Vec<SnapshotInfo>, PwdBoxError> {
+    state.vault.snapshots(|snapshot_service| snapshot_service.list_snapshots()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn restore_snapshot(file_name: String, master_key: Option<String>, state: State<'_, AppState>) -> Result<usize, PwdBoxError> {
+    let master_key = command_key(&state, master_key.as_deref().unwrap_or_default())?;
+    unlocked(&state, || state.vault.restore_snapshot(&file_name, &master_key))
+}
+
+#[tauri::command]
+async fn get_snapshot_interval_days(state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    state.vault.snapshots(|snapshot_service| snapshot_service.get_interval_days()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_snapshot_interval_days(days: u32, state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    unlocked(&state, || state.vault.snapshots(|snapshot_service| snapshot_service.set_interval_days(days)))
+}
+
+// Query console (metadata only)
+#[cfg(feature = "query-console")]
+#[tauri::command]
+async fn run_readonly_query(sql: String, state: State<'_, AppState>) -> Result<QueryResult, PwdBoxError> {
+    unlocked(&state, || state.vault.query_console(|query_console| query_console.run_readonly_query(&sql)))
+}
+
+// Settings Commands
+#[tauri::command]
+async fn get_locale(state: State<'_, AppState>) -> Result<String, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_locale()).map_err(PwdBoxError::from)
 }
 
 #[tauri::command]
-async fn import_data(request: ImportRequest, state: State<'_, AppState>) -> Result<ImportResponse, String> {
-    let export_service = state.export_service.lock().map_err(|e| e.to_string())?;
-    export_service.import_data(request).map_err(|e| e.to_string())
+async fn set_locale(locale: String, state: State<'_, AppState>) -> Result<String, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.set_locale(&locale)).map_err(PwdBoxError::from)
 }
 
 #[tauri::command]
-async fn preview_import(request: ImportRequest, state: State<'_, AppState>) -> Result<serde_json::Value, String> {
-    let export_service = state.export_service.lock().map_err(|e| e.to_string())?;
-    export_service.preview_import(request).map_err(|e| e.to_string())
+async fn get_vault_size_warning_threshold(state: State<'_, AppState>) -> Result<usize, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_vault_size_warning_threshold()).map_err(PwdBoxError::from)
 }
 
 #[tauri::command]
-async fn create_backup(export_passphrase: String, backup_path: Option<String>, state: State<'_, AppState>) -> Result<ExportResponse, String> {
-    let export_service = state.export_service.lock().map_err(|e| e.to_string())?;
-    export_service.create_backup(&export_passphrase, backup_path.as_deref()).map_err(|e| e.to_string())
+async fn set_vault_size_warning_threshold(threshold: usize, state: State<'_, AppState>) -> Result<usize, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_vault_size_warning_threshold(threshold)))
 }
 
 #[tauri::command]
-async fn validate_export_file(file_path: String, passphrase: String, state: State<'_, AppState>) -> Result<bool, String> {
-    let export_service = state.export_service.lock().map_err(|e| e.to_string())?;
-    export_service.validate_export_file(&file_path, &passphrase).map_err(|e| e.to_string())
+async fn get_login_lockout_minutes(state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_login_lockout_minutes()).map_err(PwdBoxError::from)
 }
 
 #[tauri::command]
-async fn get_export_info(file_path: String, state: State<'_, AppState>) -> Result<serde_json::Value, String> {
-    let export_service = state.export_service.lock().map_err(|e| e.to_string())?;
-    export_service.get_export_info(&file_path).map_err(|e| e.to_string())
+async fn set_login_lockout_minutes(minutes: u32, state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_login_lockout_minutes(minutes)))
+}
+
+#[tauri::command]
+async fn get_encrypt_metadata(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_encrypt_metadata()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn get_kdf_params(state: State<'_, AppState>) -> Result<KdfParams, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_kdf_params()).map_err(PwdBoxError::from)
+}
+
+// Used by setup, which hashes with the recommendation, and by the settings screen, which
+// applies it with set_kdf_params
+#[tauri::command]
+async fn benchmark_kdf(target_ms: u64, app: AppHandle) -> Result<KdfBenchmark, PwdBoxError> {
+    blocking(&app, move |_, state| state.vault.benchmark_kdf(target_ms).map_err(PwdBoxError::from)).await
+}
+
+// Re-hashes the master password under the new parameters, so it is needed again here
+#[tauri::command]
+async fn set_kdf_params(master_password: String, params: KdfParams, app: AppHandle) -> Result<KdfParams, PwdBoxError> {
+    let master_password = Zeroizing::new(master_password);
+    blocking(&app, move |_, state| unlocked(state, || state.vault.set_kdf_params(&master_password, params))).await
+}
+
+#[tauri::command]
+async fn get_scan_batch_size(state: State<'_, AppState>) -> Result<usize, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_scan_batch_size()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_scan_batch_size(batch_size: usize, state: State<'_, AppState>) -> Result<usize, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_scan_batch_size(batch_size)))
+}
+
+#[tauri::command]
+async fn get_password_max_age_days(state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_password_max_age_days()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_password_max_age_days(days: u32, state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_password_max_age_days(days)))
+}
+
+#[tauri::command]
+async fn get_breach_check_enabled(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_breach_check_enabled()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_breach_check_enabled(enabled: bool, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_breach_check_enabled(enabled)))
+}
+
+#[tauri::command]
+async fn get_favicon_fetch_enabled(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_favicon_fetch_enabled()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_favicon_fetch_enabled(enabled: bool, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_favicon_fetch_enabled(enabled)))
+}
+
+#[tauri::command]
+async fn get_favicon_source(state: State<'_, AppState>) -> Result<String, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_favicon_source()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_favicon_source(source: String, state: State<'_, AppState>) -> Result<String, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_favicon_source(&source)))
+}
+
+// The lock shortcut and whether it could be registered
+#[cfg(desktop)]
+#[tauri::command]
+async fn get_lock_shortcut(app: AppHandle) -> Result<desktop::LockShortcutStatus, PwdBoxError> {
+    Ok(desktop::lock_shortcut_status(&app))
+}
+
+// Saved even when registration fails, e.g. because another application holds the
+// accelerator; the status says so. An empty accelerator turns the shortcut off.
+#[cfg(desktop)]
+#[tauri::command]
+async fn set_lock_shortcut(accelerator: String, app: AppHandle, state: State<'_, AppState>) -> Result<desktop::LockShortcutStatus, PwdBoxError> {
+    let accelerator = unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_lock_shortcut(&accelerator)))?;
+    Ok(desktop::replace_lock_shortcut(&app, &accelerator))
+}
+
+#[tauri::command]
+async fn get_lock_on_suspend(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_lock_on_suspend()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_lock_on_suspend(enabled: bool, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_lock_on_suspend(enabled)))
+}
+
+// Only takes effect where screen locks are detected; see the lock_on_screen_lock capability
+#[tauri::command]
+async fn get_lock_on_screen_lock(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_lock_on_screen_lock()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_lock_on_screen_lock(enabled: bool, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_lock_on_screen_lock(enabled)))
+}
+
+#[tauri::command]
+async fn get_native_host_extensions(state: State<'_, AppState>) -> Result<Vec<String>, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_native_host_extensions()).map_err(PwdBoxError::from)
+}
+
+// The app checks every request against this list. The browsers' own copy, in the host
+// manifests, is only updated by install_native_host.
+#[tauri::command]
+async fn set_native_host_extensions(extension_ids: Vec<String>, state: State<'_, AppState>) -> Result<Vec<String>, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_native_host_extensions(&extension_ids)))
+}
+
+// Write the native host manifest for each browser, allowing the extensions in the allowlist
+#[cfg(desktop)]
+#[tauri::command]
+async fn install_native_host(browsers: Vec<Browser>, state: State<'_, AppState>) -> Result<Vec<InstalledManifest>, PwdBoxError> {
+    unlocked(&state, || {
+        let extension_ids = state.vault.settings(|settings_service| settings_service.get_native_host_extensions())?;
+        if extension_ids.is_empty() {
+            return Err(PwdBoxError::invalid_field("extension_ids", "Allow at least one extension first").into());
+        }
+        native_messaging::install(&browsers, &extension_ids, &data_dir::default_dir()?)
+    })
+}
+
+// The user's answer to a native-credential-request event. False when the request is no
+// longer waiting.
+#[cfg(desktop)]
+#[tauri::command]
+async fn answer_native_credential_request(request_id: u64, approved: bool, app: AppHandle) -> Result<bool, PwdBoxError> {
+    Ok(app.state::<Approvals>().answer(request_id, approved))
+}
+
+#[tauri::command]
+async fn get_clipboard_parsing_enabled(state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_clipboard_parsing_enabled()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_clipboard_parsing_enabled(enabled: bool, state: State<'_, AppState>) -> Result<bool, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_clipboard_parsing_enabled(enabled)))
+}
+
+#[tauri::command]
+async fn get_draft_grace_minutes(state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_draft_grace_minutes()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_draft_grace_minutes(minutes: u32, state: State<'_, AppState>) -> Result<u32, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_draft_grace_minutes(minutes)))
+}
+
+#[tauri::command]
+async fn get_attachment_max_bytes(state: State<'_, AppState>) -> Result<u64, PwdBoxError> {
+    state.vault.settings(|settings_service| settings_service.get_attachment_max_bytes()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_attachment_max_bytes(bytes: u64, state: State<'_, AppState>) -> Result<u64, PwdBoxError> {
+    unlocked(&state, || state.vault.settings(|settings_service| settings_service.set_attachment_max_bytes(bytes)))
+}
+
+#[tauri::command]
+async fn get_reminder_settings(state: State<'_, AppState>) -> Result<ReminderSettings, PwdBoxError> {
+    state.vault.reminders(|reminder_service| reminder_service.get_settings()).map_err(PwdBoxError::from)
+}
+
+#[tauri::command]
+async fn set_reminder_settings(settings: ReminderSettings, state: State<'_, AppState>) -> Result<ReminderSettings, PwdBoxError> {
+    unlocked(&state, || state.vault.reminders(|reminder_service| reminder_service.set_settings(settings)))
+}
+
+#[tauri::command]
+async fn take_pending_link(state: State<'_, AppState>) -> Result<Option<String>, PwdBoxError> {
+    let mut pending_link = state.pending_link.lock()?;
+    Ok(pending_link.take())
+}
+
+// Feature availability for this build and platform
+#[tauri::command]
+async fn get_capabilities(app: AppHandle, state: State<'_, AppState>) -> Result<CapabilityRegistry, PwdBoxError> {
+    let mut registry = state.vault.capabilities()?;
+    #[cfg(desktop)]
+    if desktop::lock_shortcut_status(&app).registered {
+        registry.register(capabilities::LOCK_SHORTCUT, capabilities::Capability::available(true));
+    }
+    if let Ok(PermissionState::Denied) = app.notification().permission_state() {
+        registry.mark_unavailable(capabilities::EXPIRY_REMINDERS, "Notifications are blocked for PwdBox");
+    }
+    Ok(registry)
+}
+
+// Why the app started without its services, or None when it started normally
+#[tauri::command]
+async fn get_startup_error(startup_failure: State<'_, StartupFailure>) -> Result<Option<String>, PwdBoxError> {
+    Ok(startup_failure.0.lock()?.clone())
+}
+
+// Try opening the vault again after a failed start, in `path` or the default directory.
+// On success the services run as after a normal start; the frontend should reload.
+#[tauri::command]
+async fn retry_initialization(path: Option<String>, app: AppHandle) -> Result<(), PwdBoxError> {
+    let chosen_dir = path.map(PathBuf::from);
+    tauri::async_runtime::spawn_blocking(move || restart_services(&app, chosen_dir))
+        .await
+        .map_err(|e| PwdBoxError::internal(e.to_string()))?
 }
 
 // Utility Commands
 #[tauri::command]
-async fn get_app_data_dir() -> Result<String, String> {
-    let app_data_dir = dirs::data_dir()
-        .ok_or("Could not determine app data directory")?
-        .join("PwdBox");
-    
-    Ok(app_data_dir.to_string_lossy().to_string())
+async fn get_app_data_dir(state: State<'_, AppState>) -> Result<String, PwdBoxError> {
+    Ok(state.vault.data_dir().to_string_lossy().to_string())
+}
+
+// The data directory in use and what chose it
+#[tauri::command]
+async fn get_data_dir(state: State<'_, AppState>) -> Result<data_dir::DataDirInfo, PwdBoxError> {
+    data_dir::describe(&state.vault.data_dir()).map_err(PwdBoxError::from)
 }
 
+// Use `new_path` as the data directory from now on, moving the vault there with
+// `move_existing`; see VaultCoordinator::change_data_dir. Refused in portable mode and
+// while PWDBOX_DATA_DIR is set, since either would win again at the next start. Switching to another vault without
+// moving locks the session, whose key belongs to the old one.
 #[tauri::command]
-async fn get_default_backup_dir() -> Result<String, String> {
-    let home_dir = dirs::home_dir()
-        .ok_or("Could not determine home directory")?
-        .join("PwdBox_Backups");
-    
-    Ok(home_dir.to_string_lossy().to_string())
+async fn set_data_dir(
+    new_path: String,
+    move_existing: bool,
+    overwrite: Option<bool>,
+    app: AppHandle,
+) -> Result<data_dir::DataDirInfo, PwdBoxError> {
+    blocking(&app, move |app, state| {
+        let new_dir = PathBuf::from(&new_path);
+        if !new_dir.is_absolute() {
+            return Err(PwdBoxError::invalid_field("new_path", "The data directory must be an absolute path"));
+        }
+        match data_dir::resolve()?.1 {
+            data_dir::DataDirSource::Environment => {
+                return Err(PwdBoxError::validation(format!("The data directory is set by {}", data_dir::ENV_VAR)));
+            }
+            data_dir::DataDirSource::Portable => {
+                return Err(PwdBoxError::validation("Portable mode keeps the vault next to the executable"));
+            }
+            _ => {}
+        }
+        let default_dir = data_dir::default_dir()?;
+        unlocked(state, || {
+            state.vault.change_data_dir(&new_dir, move_existing, overwrite.unwrap_or(false), || {
+                data_dir::set_pointer(&default_dir, &new_dir)
+            })
+        })?;
+        if !move_existing {
+            lock_now(app);
+        }
+        data_dir::describe(&new_dir).map_err(PwdBoxError::from)
+    }).await
+}
+
+#[tauri::command]
+async fn get_default_backup_dir(state: State<'_, AppState>) -> Result<String, PwdBoxError> {
+    Ok(data_dir::default_backup_dir(&state.vault.data_dir()).to_string_lossy().to_string())
+}
+
+// Run a CLI subcommand when one was given; None means the app should open instead
+#[cfg(desktop)]
+pub fn run_cli() -> Option<std::process::ExitCode> {
+    cli::run()
+}
+
+// Entry point of the native host binary, which the browser starts for the extension
+#[cfg(desktop)]
+pub fn run_native_host() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    native_messaging::run_host(&data_dir::default_dir()?, &args)
 }
 
 // Main run function for both desktop and mobile platforms
 pub fn run() {
-    // Initialize services
-    let app_state = initialize_services().expect("Failed to initialize application services");
+    // Initialize services. A failure is shown once the app is up, instead of exiting
+    // before any window opens.
+    let mut builder = tauri::Builder::default();
+    let startup_error = match initialize_services(None) {
+        Ok(app_state) => {
+            builder = builder.manage(app_state);
+            None
+        }
+        Err(e) => Some(format!("{:#}", e)),
+    };
 
-    tauri::Builder::default()
-        .manage(app_state)
+    builder
+        .manage(StartupFailure(Mutex::new(startup_error)))
+        .plugin(
+            tauri_plugin_log::Builder::new()
+                .level(if cfg!(debug_assertions) { log::LevelFilter::Debug } else { log::LevelFilter::Info })
+                .build(),
+        )
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .setup(|app| {
+            let startup_error = app.state::<StartupFailure>().0.lock().ok().and_then(|failure| failure.clone());
+            match startup_error {
+                None => start_services(app.handle())?,
+                Some(error) => report_startup_failure(app.handle(), &error),
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             // User management
             is_app_setup,
@@ -213,25 +1696,172 @@ pub fn run() {
             login,
             get_security_questions,
             verify_recovery_answers,
+            practice_recovery,
+            get_recovery_status,
             reset_master_password,
+            reset_master_password_with_code,
+            generate_recovery_code,
+            change_security_questions,
+            get_password_hint,
+            set_password_hint,
+            setup_duress,
+            get_panic_wipe_threshold,
+            set_panic_wipe_threshold,
             change_master_password,
+            lock_vault,
+            is_session_active,
+            touch_activity,
             // Password management
             add_password,
+            add_passwords_batch,
             get_all_passwords,
             get_password,
+            copy_password_to_clipboard,
             update_password,
             delete_password,
+            delete_passwords,
+            replace_account_value,
+            get_software_suggestions,
+            normalize_software_names,
+            find_entries_for_url,
+            duplicate_password,
+            share_entry,
+            import_shared_entry,
+            find_duplicates,
+            merge_entries,
+            list_trash,
+            restore_password,
+            purge_trash,
+            list_password_policies,
+            set_password_policy,
+            delete_password_policy,
+            get_audit_log,
+            verify_audit_log,
+            check_integrity,
             search_passwords,
             get_password_count,
+            validate_master_key,
+            get_health_report,
+            audit_vault,
+            re_encrypt_vault,
+            check_breaches,
+            encrypt_metadata,
+            migrate_to_encrypted_db,
+            get_stats_history,
+            get_entries_grouped_by_domain,
+            set_entry_icon,
+            clear_entry_icon,
+            get_icon,
+            fetch_icon,
+            get_entry_qr,
+            add_attachment,
+            get_attachment,
+            list_attachments,
+            delete_attachment,
+            set_custom_field,
+            delete_custom_field,
+            get_entry_template,
+            get_stale_passwords,
+            save_entry_draft,
+            get_entry_draft,
+            discard_entry_draft,
+            toggle_favorite,
+            set_rotation_interval,
+            get_expiring_passwords,
+            add_tag_to_entry,
+            tag_passwords,
+            remove_tag_from_entry,
+            list_tags,
+            get_software_groups,
+            get_entries_by_tag,
+            delete_tag,
+            mark_entries_breached,
+            get_action_items,
+            dismiss_breach,
+            parse_clipboard_for_credentials,
+            get_vault_stats,
             // Export/Import
             export_data,
             import_data,
             preview_import,
             create_backup,
+            prepare_restore,
+            confirm_restore,
+            export_audit_log_csv,
+            export_csv,
+            import_csv,
+            preview_csv_import,
             validate_export_file,
             get_export_info,
+            list_backups,
+            cleanup_old_backups,
+            // Vault snapshots
+            list_snapshots,
+            restore_snapshot,
+            get_snapshot_interval_days,
+            set_snapshot_interval_days,
+            // Query console
+            #[cfg(feature = "query-console")]
+            run_readonly_query,
+            // Settings
+            get_locale,
+            set_locale,
+            get_vault_size_warning_threshold,
+            set_vault_size_warning_threshold,
+            get_login_lockout_minutes,
+            set_login_lockout_minutes,
+            get_encrypt_metadata,
+            get_kdf_params,
+            set_kdf_params,
+            benchmark_kdf,
+            get_scan_batch_size,
+            set_scan_batch_size,
+            get_password_max_age_days,
+            set_password_max_age_days,
+            get_breach_check_enabled,
+            set_breach_check_enabled,
+            get_favicon_fetch_enabled,
+            set_favicon_fetch_enabled,
+            get_favicon_source,
+            set_favicon_source,
+            #[cfg(desktop)]
+            get_lock_shortcut,
+            #[cfg(desktop)]
+            set_lock_shortcut,
+            get_clipboard_parsing_enabled,
+            set_clipboard_parsing_enabled,
+            get_draft_grace_minutes,
+            get_attachment_max_bytes,
+            set_attachment_max_bytes,
+            set_draft_grace_minutes,
+            get_reminder_settings,
+            set_reminder_settings,
+            get_auto_lock_timeout,
+            set_auto_lock_timeout,
+            get_lock_on_suspend,
+            set_lock_on_suspend,
+            get_lock_on_screen_lock,
+            set_lock_on_screen_lock,
+            get_native_host_extensions,
+            set_native_host_extensions,
+            #[cfg(desktop)]
+            install_native_host,
+            #[cfg(desktop)]
+            answer_native_credential_request,
+            enable_os_keychain_unlock,
+            disable_os_keychain_unlock,
+            unlock_with_os_keychain,
+            enable_pin_unlock,
+            disable_pin_unlock,
+            unlock_with_pin,
+            take_pending_link,
             // Utilities
+            get_capabilities,
+            get_startup_error,
+            retry_initialization,
             get_app_data_dir,
+            get_data_dir,
+            set_data_dir,
             get_default_backup_dir
         ])
         .run(tauri::generate_context!())
@@ -4,6 +4,11 @@
   windows_subsystem = "windows"
 )]
 
-fn main() {
-    pwdbox::run();
+fn main() -> std::process::ExitCode {
+    // `pwdbox <command>` runs headless in the terminal; see cli.rs
+    if let Some(exit_code) = pwdbox_lib::run_cli() {
+        return exit_code;
+    }
+    pwdbox_lib::run();
+    std::process::ExitCode::SUCCESS
 } 
\ No newline at end of file
@@ -0,0 +1,329 @@
+use crate::crypto::{CryptoService, SecretKey};
+use crate::database::Database;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+// Keys available to data migrations after a successful unlock
+pub struct MigrationContext {
+    pub kek: SecretKey,       // Derived from the master password
+    pub entry_key: SecretKey, // Key entries are encrypted with; migrations may replace it
+}
+
+// A data migration that runs on the first unlock after an upgrade.
+// `check` reports whether the data still needs it, so a migration whose changes were
+// committed but whose completion flag was not (app killed in between) is not re-applied.
+// `apply` must commit all of its changes in a single transaction.
+pub struct DataMigration {
+    pub id: &'static str,
+    pub check: fn(&Database, &MigrationContext) -> Result<bool>,
+    pub apply: fn(&Database, &mut MigrationContext) -> Result<()>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationProgress {
+    pub migration_id: String,
+    pub step: usize,
+    pub total: usize,
+    pub status: String, // "running", "completed" or "skipped"
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub applied: Vec<String>,
+    pub backup_path: Option<String>,
+}
+
+pub struct DataMigrationOrchestrator {
+    migrations: Vec<DataMigration>,
+}
+
+impl DataMigrationOrchestrator {
+    pub fn new() -> Self {
+        DataMigrationOrchestrator { migrations: Vec::new() }
+    }
+
+    // All data migrations shipped with the app, in the order they must run
+    pub fn with_default_migrations() -> Self {
+        let mut orchestrator = Self::new();
+        orchestrator.register(DataMigration {
+            id: "vault_key_wrapping",
+            check: vault_key_wrapping_needed,
+            apply: apply_vault_key_wrapping,
+        });
+        orchestrator.register(DataMigration {
+            id: "encrypt_entry_notes",
+            check: entry_notes_encryption_needed,
+            apply: apply_entry_notes_encryption,
+        });
+        orchestrator.register(DataMigration {
+            id: "score_password_strength",
+            check: password_strength_scoring_needed,
+            apply: apply_password_strength_scoring,
+        });
+        orchestrator
+    }
+
+    pub fn register(&mut self, migration: DataMigration) {
+        self.migrations.push(migration);
+    }
+
+    // Whether a migration has yet to run, which only a master password login can do
+    pub fn has_pending(&self, database: &Database) -> Result<bool> {
+        for migration in &self.migrations {
+            if !database.is_migration_completed(migration.id)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Run every pending migration in order, recording each as completed once it succeeds.
+    // A safety backup of the database is taken before the first migration that changes data.
+    pub fn run(
+        &self,
+        database: &Database,
+        context: &mut MigrationContext,
+        on_progress: &mut dyn FnMut(&MigrationProgress),
+    ) -> Result<MigrationReport> {
+        let mut report = MigrationReport::default();
+        let total = self.migrations.len();
+
+        for (index, migration) in self.migrations.iter().enumerate() {
+            if database.is_migration_completed(migration.id)? {
+                continue;
+            }
+
+            let mut progress = MigrationProgress {
+                migration_id: migration.id.to_string(),
+                step: index + 1,
+                total,
+                status: "running".to_string(),
+            };
+
+            if !(migration.check)(database, context)? {
+                database.mark_migration_completed(migration.id)?;
+                progress.status = "skipped".to_string();
+                on_progress(&progress);
+                continue;
+            }
+
+            if report.backup_path.is_none() {
+                report.backup_path = Some(Self::safety_backup(database)?);
+            }
+
+            on_progress(&progress);
+            (migration.apply)(database, context)
+                .map_err(|e| anyhow!("Data migration '{}' failed: {}", migration.id, e))?;
+            database.mark_migration_completed(migration.id)?;
+
+            progress.status = "completed".to_string();
+            on_progress(&progress);
+            report.applied.push(migration.id.to_string());
+        }
+
+        Ok(report)
+    }
+
+    // Copy the database next to itself before touching any data
+    fn safety_backup(database: &Database) -> Result<String> {
+        let backup_path = database.safety_copy("migration_backups", "pwdbox_pre_migration_")?;
+        Ok(backup_path.to_string_lossy().to_string())
+    }
+}
+
+// Vaults created before the vault key existed encrypt entries with the password-derived key
+fn vault_key_wrapping_needed(database: &Database, _context: &MigrationContext) -> Result<bool> {
+    let user_meta = database.get_user_meta()?
+        .ok_or_else(|| anyhow!("User not found"))?;
+    Ok(user_meta.wrapped_vault_key.is_none())
+}
+
+// Re-encrypt entries once under a fresh vault key and store it wrapped with the password-derived key
+fn apply_vault_key_wrapping(database: &Database, context: &mut MigrationContext) -> Result<()> {
+    let mut user_meta = database.get_user_meta()?
+        .ok_or_else(|| anyhow!("User not found"))?;
+    let vault_key = CryptoService::generate_vault_key();
+
+    let mut entries = Vec::new();
+    for mut entry in database.get_all_password_entries_including_trash()? {
+        // Entries the legacy key cannot read were already unreadable; leave them untouched
+        let Ok(password) = CryptoService::decrypt_password(&entry.encrypted_password, &entry.nonce, &context.entry_key, entry.entry_uid.as_deref()) else {
+            continue;
+        };
+        let notes = CryptoService::decrypt_notes(entry.notes.as_deref(), entry.notes_nonce.as_deref(), &context.entry_key, entry.entry_uid.as_deref())?;
+        let entry_uid = entry.entry_uid.get_or_insert_with(CryptoService::generate_entry_uid);
+        let (encrypted_password, nonce) = CryptoService::encrypt_password(&password, &vault_key, Some(entry_uid))?;
+        (entry.notes, entry.notes_nonce) = CryptoService::encrypt_notes(notes.as_deref(), &vault_key, Some(entry_uid))?;
+        entry.encrypted_password = encrypted_password;
+        entry.nonce = nonce;
+        entries.push(entry);
+    }
+
+    let (wrapped_vault_key, vault_key_nonce) = CryptoService::wrap_key(&vault_key, &context.kek)?;
+    user_meta.wrapped_vault_key = Some(wrapped_vault_key);
+    user_meta.vault_key_nonce = Some(vault_key_nonce);
+
+    database.migrate_to_vault_key(&user_meta, &entries)?;
+    context.entry_key = vault_key;
+    Ok(())
+}
+
+// Notes were stored in plaintext before they were encrypted alongside the password
+fn entry_notes_encryption_needed(database: &Database, _context: &MigrationContext) -> Result<bool> {
+    Ok(database
+        .get_all_password_entries_including_trash()?
+        .iter()
+        .any(|entry| entry.notes.is_some() && entry.notes_nonce.is_none()))
+}
+
+fn apply_entry_notes_encryption(database: &Database, context: &mut MigrationContext) -> Result<()> {
+    let mut entries = Vec::new();
+    for mut entry in database.get_all_password_entries_including_trash()? {
+        if entry.notes.is_none() || entry.notes_nonce.is_some() {
+            continue;
+        }
+        (entry.notes, entry.notes_nonce) = CryptoService::encrypt_notes(entry.notes.as_deref(), &context.entry_key, entry.entry_uid.as_deref())?;
+        entries.push(entry);
+    }
+    database.update_password_entries(&entries)
+}
+
+// Entries saved before strength scores were stored have none
+fn password_strength_scoring_needed(database: &Database, _context: &MigrationContext) -> Result<bool> {
+    Ok(database
+        .get_all_password_entries_including_trash()?
+        .iter()
+        .any(|entry| entry.strength_score.is_none()))
+}
+
+// Entries the key cannot read keep no score; lists leave them out of strength filters
+fn apply_password_strength_scoring(database: &Database, context: &mut MigrationContext) -> Result<()> {
+    let mut entries = Vec::new();
+    for mut entry in database.get_all_password_entries_including_trash()? {
+        if entry.strength_score.is_some() {
+            continue;
+        }
+        let Ok(password) = CryptoService::decrypt_password(&entry.encrypted_password, &entry.nonce, &context.entry_key, entry.entry_uid.as_deref()) else {
+            continue;
+        };
+        entry.strength_score = Some(CryptoService::password_strength(&password));
+        CryptoService::clear_sensitive_string(password);
+        entries.push(entry);
+    }
+    database.update_password_entries(&entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FIRST_APPLIED: AtomicUsize = AtomicUsize::new(0);
+    static SECOND_APPLIED: AtomicUsize = AtomicUsize::new(0);
+
+    fn context() -> MigrationContext {
+        MigrationContext { kek: SecretKey::new([1u8; 32]), entry_key: SecretKey::new([1u8; 32]) }
+    }
+
+    fn setting_missing(database: &Database, key: &str) -> Result<bool> {
+        Ok(database.get_setting(key)?.is_none())
+    }
+
+    fn first_check(database: &Database, _: &MigrationContext) -> Result<bool> {
+        setting_missing(database, "first")
+    }
+
+    fn first_apply(database: &Database, _: &mut MigrationContext) -> Result<()> {
+        FIRST_APPLIED.fetch_add(1, Ordering::SeqCst);
+        database.set_setting("first", "done")
+    }
+
+    fn second_check(database: &Database, _: &MigrationContext) -> Result<bool> {
+        setting_missing(database, "second")
+    }
+
+    fn second_apply(database: &Database, _: &mut MigrationContext) -> Result<()> {
+        SECOND_APPLIED.fetch_add(1, Ordering::SeqCst);
+        database.set_setting("second", "done")
+    }
+
+    // Simulates the app being killed while the second migration runs
+    fn second_apply_interrupted(_: &Database, _: &mut MigrationContext) -> Result<()> {
+        Err(anyhow!("interrupted"))
+    }
+
+    fn orchestrator(second: fn(&Database, &mut MigrationContext) -> Result<()>) -> DataMigrationOrchestrator {
+        let mut orchestrator = DataMigrationOrchestrator::new();
+        orchestrator.register(DataMigration { id: "first", check: first_check, apply: first_apply });
+        orchestrator.register(DataMigration { id: "second", check: second_check, apply: second });
+        orchestrator
+    }
+
+    #[test]
+    fn test_resumes_after_interruption_between_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::new(dir.path().join("pwdbox.db")).unwrap();
+
+        let result = orchestrator(second_apply_interrupted).run(&database, &mut context(), &mut |_| {});
+        assert!(result.is_err());
+        assert!(database.is_migration_completed("first").unwrap());
+        assert!(!database.is_migration_completed("second").unwrap());
+        assert_eq!(FIRST_APPLIED.load(Ordering::SeqCst), 1);
+
+        // The next unlock only runs what is still pending
+        let mut events = Vec::new();
+        let report = orchestrator(second_apply)
+            .run(&database, &mut context(), &mut |p| events.push((p.migration_id.clone(), p.status.clone())))
+            .unwrap();
+        assert_eq!(report.applied, vec!["second".to_string()]);
+        assert!(report.backup_path.is_some());
+        assert_eq!(FIRST_APPLIED.load(Ordering::SeqCst), 1);
+        assert_eq!(SECOND_APPLIED.load(Ordering::SeqCst), 1);
+        assert_eq!(events, vec![
+            ("second".to_string(), "running".to_string()),
+            ("second".to_string(), "completed".to_string()),
+        ]);
+
+        // Everything is recorded: further runs do nothing
+        let report = orchestrator(second_apply).run(&database, &mut context(), &mut |_| {}).unwrap();
+        assert!(report.applied.is_empty());
+        assert!(report.backup_path.is_none());
+    }
+
+    #[test]
+    fn test_committed_but_unrecorded_migration_is_not_reapplied() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::new(dir.path().join("pwdbox.db")).unwrap();
+
+        // The data change landed but the app died before recording completion
+        database.set_setting("first", "done").unwrap();
+        database.set_setting("second", "done").unwrap();
+
+        let mut events = Vec::new();
+        let mut orchestrator = DataMigrationOrchestrator::new();
+        orchestrator.register(DataMigration { id: "first", check: first_check, apply: second_apply_interrupted });
+        let report = orchestrator.run(&database, &mut context(), &mut |p| events.push(p.status.clone())).unwrap();
+
+        assert!(report.applied.is_empty());
+        assert!(report.backup_path.is_none());
+        assert_eq!(events, vec!["skipped".to_string()]);
+        assert!(database.is_migration_completed("first").unwrap());
+    }
+
+    #[test]
+    fn test_strength_scoring_fills_in_readable_entries() {
+        use crate::database::PasswordEntry;
+
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::new(dir.path().join("pwdbox.db")).unwrap();
+        let context = &mut context();
+        let (encrypted_password, nonce) = CryptoService::encrypt_password("password", &context.entry_key, None).unwrap();
+        database.insert_password_entry(&PasswordEntry { software: "mail".to_string(), encrypted_password, nonce, ..Default::default() }).unwrap();
+        database.insert_password_entry(&PasswordEntry { software: "lost".to_string(), encrypted_password: "AAAA".to_string(), nonce: CryptoService::generate_nonce(), ..Default::default() }).unwrap();
+        assert!(password_strength_scoring_needed(&database, context).unwrap());
+
+        apply_password_strength_scoring(&database, context).unwrap();
+        let scores: Vec<Option<u8>> = database.get_all_password_entries().unwrap().iter().map(|entry| entry.strength_score).collect();
+        assert_eq!(scores, vec![Some(0), None]);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::data_dir;
+use crate::error::PwdBoxError;
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+// Lets a browser extension ask PwdBox for credentials. The browser starts the native host
+// (bin/pwdbox-native-host.rs) and talks to it over stdio with the native messaging framing:
+// a 32-bit length in native byte order, then that many bytes of JSON.
+//
+// The host has no vault access of its own. It relays each request to the running app over
+// a loopback connection, so the app's session, the extension allowlist and the approval
+// prompt decide every answer, and nothing is answered while the app is closed or locked.
+// The app publishes its port and a random token in ENDPOINT_FILE when it starts; requests
+// without the token are dropped.
+pub const HOST_NAME: &str = "com.pwdbox.native_host";
+pub const HOST_BINARY: &str = "pwdbox-native-host";
+// Kept beside the data directory pointer (see data_dir.rs), which stays put when the vault moves
+pub const ENDPOINT_FILE: &str = "native_host.json";
+
+// Chrome refuses messages from the host over 1 MB; requests are far smaller still
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+// How long get_credential waits for the user to approve it
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(120);
+// Covers the user answering the approval prompt, with time to spare
+const RELAY_TIMEOUT: Duration = Duration::from_secs(150);
+// A connection that does not send its request within this is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostRequest {
+    Status,
+    QueryByUrl { url: String },
+    GetCredential { id: i64 },
+}
+
+// A request as relayed to the app: the extension it came from, taken from the arguments
+// the browser started the host with, and the app's token
+#[derive(Deserialize, Serialize)]
+struct RelayedRequest {
+    token: String,
+    extension_id: String,
+    request: HostRequest,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Endpoint {
+    port: u16,
+    token: String,
+}
+
+// How the app answers a relayed request, given the calling extension's id
+pub type RequestHandler = dyn Fn(&str, HostRequest) -> Result<Value, PwdBoxError> + Send + Sync;
+
+// Responses are {"ok": true, "result": ...} or {"ok": false, "error": {"code", "message"}},
+// with the request_id of the request when it had one
+fn success(result: Value) -> Value {
+    json!({"ok": true, "result": result})
+}
+
+fn failure(error: PwdBoxError) -> Value {
+    json!({"ok": false, "error": error})
+}
+
+// None once the browser has closed the pipe
+pub fn read_message(reader: &mut impl Read) -> Result<Option<Value>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length) {
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_LEN {
+        return Err(anyhow!("Message of {} bytes is too long", length));
+    }
+    let mut message = vec![0u8; length];
+    reader.read_exact(&mut message)?;
+    Ok(Some(serde_json::from_slice(&message)?))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let message = serde_json::to_vec(message)?;
+    if message.len() > MAX_MESSAGE_LEN {
+        return Err(anyhow!("Response of {} bytes is too long", message.len()));
+    }
+    writer.write_all(&(message.len() as u32).to_ne_bytes())?;
+    writer.write_all(&message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Chrome starts the host with the caller's origin (chrome-extension://<id>/), Firefox with
+// the manifest path and then the extension id
+pub fn caller_extension_id(args: &[String]) -> Option<String> {
+    if let Some(id) = args.iter().find_map(|arg| arg.strip_prefix("chrome-extension://")) {
+        return Some(id.trim_end_matches('/').to_string());
+    }
+    args.get(1).filter(|id| !id.is_empty()).cloned()
+}
+
+// The host's main loop: one response per message, until the browser closes stdin
+pub fn run_host(endpoint_dir: &Path, args: &[String]) -> Result<()> {
+    let extension_id = caller_extension_id(args);
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    while let Some(message) = read_message(&mut stdin)? {
+        let response = answer_message(endpoint_dir, extension_id.as_deref(), message);
+        write_message(&mut stdout, &response)?;
+    }
+    Ok(())
+}
+
+fn answer_message(endpoint_dir: &Path, extension_id: Option<&str>, message: Value) -> Value {
+    let request_id = message.get("request_id").cloned();
+    let mut response = match (serde_json::from_value::<HostRequest>(message), extension_id) {
+        (Err(e), _) => failure(PwdBoxError::validation(format!("Unknown request: {}", e))),
+        (Ok(_), None) => failure(PwdBoxError::validation("The browser did not say which extension is calling")),
+        (Ok(request), Some(extension_id)) => relay(endpoint_dir, extension_id, request).unwrap_or_else(failure),
+    };
+    if let Some(request_id) = request_id {
+        response["request_id"] = request_id;
+    }
+    response
+}
+
+// Pass a request to the app and return its response. Status is answered here when the app
+// is not running.
+fn relay(endpoint_dir: &Path, extension_id: &str, request: HostRequest) -> Result<Value, PwdBoxError> {
+    let connected = read_endpoint(endpoint_dir).and_then(|endpoint| {
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, endpoint.port))?;
+        Ok((stream, endpoint.token))
+    });
+    let (mut stream, token) = match connected {
+        Ok(connected) => connected,
+        Err(_) if request == HostRequest::Status => return Ok(success(json!({"running": false, "unlocked": false}))),
+        Err(_) => return Err(PwdBoxError::io("PwdBox is not running")),
+    };
+
+    let relayed = RelayedRequest { token, extension_id: extension_id.to_string(), request };
+    let exchange = (|| -> Result<Value> {
+        stream.set_read_timeout(Some(RELAY_TIMEOUT))?;
+        writeln!(stream, "{}", serde_json::to_string(&relayed)?)?;
+        let mut line = String::new();
+        BufReader::new(stream).take(MAX_MESSAGE_LEN as u64).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    })();
+    exchange.map_err(|e| PwdBoxError::io(format!("PwdBox did not answer: {}", e)))
+}
+
+fn read_endpoint(endpoint_dir: &Path) -> Result<Endpoint> {
+    Ok(serde_json::from_str(&fs::read_to_string(endpoint_dir.join(ENDPOINT_FILE))?)?)
+}
+
+// Readable by the owner only where the platform allows, and replaced atomically
+fn write_endpoint(endpoint_dir: &Path, endpoint: &Endpoint) -> Result<()> {
+    fs::create_dir_all(endpoint_dir)?;
+    let contents = serde_json::to_string(endpoint)?;
+    data_dir::write_atomically(&endpoint_dir.join(ENDPOINT_FILE), |file| file.write_all(contents.as_bytes()))?;
+    Ok(())
+}
+
+// Called by the app: listen on a loopback port, publish it with a fresh token, and answer
+// each connection's single request on its own thread
+pub fn serve(endpoint_dir: &Path, handler: Arc<RequestHandler>) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let mut token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    let endpoint = Endpoint { port: listener.local_addr()?.port(), token: general_purpose::URL_SAFE_NO_PAD.encode(token) };
+    write_endpoint(endpoint_dir, &endpoint)?;
+
+    let token = Arc::new(endpoint.token);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (handler, token) = (handler.clone(), token.clone());
+            thread::spawn(move || {
+                if let Err(e) = answer_connection(stream, &token, &*handler) {
+                    log::debug!("Dropped a native host connection: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn answer_connection(mut stream: TcpStream, token: &str, handler: &RequestHandler) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).take(MAX_MESSAGE_LEN as u64).read_line(&mut line)?;
+    let relayed: RelayedRequest = serde_json::from_str(&line)?;
+    // Compared as digests, so the time taken says nothing about the token
+    if Sha256::digest(relayed.token.as_bytes()) != Sha256::digest(token.as_bytes()) {
+        return Err(anyhow!("Wrong token"));
+    }
+    let response = match handler(&relayed.extension_id, relayed.request) {
+        Ok(result) => success(result),
+        Err(error) => failure(error),
+    };
+    writeln!(stream, "{}", response)?;
+    Ok(())
+}
+
+// get_credential requests waiting for the user, by request id. The frontend answers
+// through answer_native_credential_request; locking denies them all.
+#[derive(Default)]
+pub struct Approvals {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, mpsc::Sender<bool>>>,
+}
+
+impl Approvals {
+    pub fn request(&self) -> (u64, mpsc::Receiver<bool>) {
+        let request_id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(request_id, sender);
+        }
+        (request_id, receiver)
+    }
+
+    // False when the request is no longer waiting: answered, timed out or denied by a lock
+    pub fn answer(&self, request_id: u64, approved: bool) -> bool {
+        let sender = self.pending.lock().ok().and_then(|mut pending| pending.remove(&request_id));
+        sender.is_some_and(|sender| sender.send(approved).is_ok())
+    }
+
+    // Wait for the answer; no answer in time counts as denied
+    pub fn wait(&self, request_id: u64, receiver: mpsc::Receiver<bool>) -> bool {
+        let approved = receiver.recv_timeout(APPROVAL_TIMEOUT).unwrap_or(false);
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&request_id);
+        }
+        approved
+    }
+
+    // Dropping the senders wakes every waiting request with a denial
+    pub fn deny_all(&self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.clear();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Browser {
+    Chrome,
+    Chromium,
+    Firefox,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledManifest {
+    pub browser: Browser,
+    pub path: String,
+}
+
+// Chrome extension ids are 32 letters from a to p; anything else in the allowlist is taken
+// for a Firefox id (an email-like name or a {UUID})
+fn is_chrome_extension_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|byte| (b'a'..=b'p').contains(&byte))
+}
+
+pub fn manifest(browser: Browser, host_path: &Path, extension_ids: &[String]) -> Value {
+    let mut manifest = json!({
+        "name": HOST_NAME,
+        "description": "PwdBox password manager",
+        "path": host_path.to_string_lossy(),
+        "type": "stdio",
+    });
+    match browser {
+        Browser::Chrome | Browser::Chromium => {
+            let origins: Vec<String> = extension_ids
+                .iter()
+                .filter(|id| is_chrome_extension_id(id))
+                .map(|id| format!("chrome-extension://{}/", id))
+                .collect();
+            manifest["allowed_origins"] = json!(origins);
+        }
+        Browser::Firefox => {
+            let ids: Vec<&String> = extension_ids.iter().filter(|id| !is_chrome_extension_id(id)).collect();
+            manifest["allowed_extensions"] = json!(ids);
+        }
+    }
+    manifest
+}
+
+// Where each browser looks for host manifests. Windows browsers find them through the
+// registry instead, so the files go in the PwdBox directory there.
+fn manifest_path(browser: Browser, home: &Path, endpoint_dir: &Path) -> PathBuf {
+    let file_name = format!("{}.json", HOST_NAME);
+    if cfg!(target_os = "windows") {
+        return endpoint_dir.join("native-messaging").join(format!("{:?}", browser).to_lowercase()).join(file_name);
+    }
+    let dir = if cfg!(target_os = "macos") {
+        let support = home.join("Library").join("Application Support");
+        match browser {
+            Browser::Chrome => support.join("Google").join("Chrome").join("NativeMessagingHosts"),
+            Browser::Chromium => support.join("Chromium").join("NativeMessagingHosts"),
+            Browser::Firefox => support.join("Mozilla").join("NativeMessagingHosts"),
+        }
+    } else {
+        match browser {
+            Browser::Chrome => home.join(".config").join("google-chrome").join("NativeMessagingHosts"),
+            Browser::Chromium => home.join(".config").join("chromium").join("NativeMessagingHosts"),
+            Browser::Firefox => home.join(".mozilla").join("native-messaging-hosts"),
+        }
+    };
+    dir.join(file_name)
+}
+
+// Write the manifest for each browser, pointing at the host binary next to the app's
+// executable and allowing the extensions in the allowlist
+pub fn install(browsers: &[Browser], extension_ids: &[String], endpoint_dir: &Path) -> Result<Vec<InstalledManifest>> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+    let host_path = std::env::current_exe()?.with_file_name(format!("{}{}", HOST_BINARY, std::env::consts::EXE_SUFFIX));
+    let mut installed = Vec::new();
+    for &browser in browsers {
+        let path = manifest_path(browser, &home, endpoint_dir);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&manifest(browser, &host_path, extension_ids))?)?;
+        #[cfg(target_os = "windows")]
+        register_manifest(browser, &path)?;
+        installed.push(InstalledManifest { browser, path: path.to_string_lossy().to_string() });
+    }
+    Ok(installed)
+}
+
+#[cfg(target_os = "windows")]
+fn register_manifest(browser: Browser, manifest_path: &Path) -> Result<()> {
+    let vendor = match browser {
+        Browser::Chrome => "Google\\Chrome",
+        Browser::Chromium => "Chromium",
+        Browser::Firefox => "Mozilla",
+    };
+    let key = format!("HKCU\\Software\\{}\\NativeMessagingHosts\\{}", vendor, HOST_NAME);
+    let status = std::process::Command::new("reg")
+        .args(["add", &key, "/ve", "/t", "REG_SZ", "/d"])
+        .arg(manifest_path)
+        .arg("/f")
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("Could not register the native host for {:?}", browser));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_are_length_prefixed_json() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({"type": "status"})).unwrap();
+        assert_eq!(&buffer[..4], &17u32.to_ne_bytes());
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({"type": "status"})));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut too_long = ((MAX_MESSAGE_LEN + 1) as u32).to_ne_bytes().to_vec();
+        too_long.extend_from_slice(b"{}");
+        assert!(read_message(&mut too_long.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_requests_are_relayed_to_the_app_with_the_caller() {
+        let dir = tempfile::tempdir().unwrap();
+        let chrome_id = "a".repeat(32);
+        let args = [format!("chrome-extension://{}/", chrome_id)];
+        assert_eq!(caller_extension_id(&args).as_deref(), Some(chrome_id.as_str()));
+        let firefox_args = ["/path/to/manifest.json".to_string(), "pwdbox@example.com".to_string()];
+        assert_eq!(caller_extension_id(&firefox_args).as_deref(), Some("pwdbox@example.com"));
+
+        // Without the app only status is answered
+        let status = answer_message(dir.path(), Some(&chrome_id), json!({"type": "status", "request_id": 1}));
+        assert_eq!(status, json!({"ok": true, "result": {"running": false, "unlocked": false}, "request_id": 1}));
+        let query = json!({"type": "query_by_url", "url": "https://example.com"});
+        assert_eq!(answer_message(dir.path(), Some(&chrome_id), query.clone())["error"]["code"], "io");
+
+        serve(dir.path(), Arc::new(|extension_id: &str, request: HostRequest| match request {
+            HostRequest::QueryByUrl { url } => Ok(json!({"extension_id": extension_id, "url": url})),
+            _ => Err(PwdBoxError::validation("Not allowed")),
+        }))
+        .unwrap();
+        assert_eq!(
+            answer_message(dir.path(), Some(&chrome_id), query),
+            json!({"ok": true, "result": {"extension_id": chrome_id, "url": "https://example.com"}})
+        );
+        let refused = answer_message(dir.path(), Some(&chrome_id), json!({"type": "get_credential", "id": 1}));
+        assert_eq!(refused["error"], json!({"code": "validation", "message": "Not allowed"}));
+        assert_eq!(answer_message(dir.path(), Some(&chrome_id), json!({"type": "delete_all"}))["error"]["code"], "validation");
+
+        // A connection without the token gets nothing back
+        let endpoint = read_endpoint(dir.path()).unwrap();
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, endpoint.port)).unwrap();
+        let forged = RelayedRequest { token: "guess".to_string(), extension_id: chrome_id, request: HostRequest::Status };
+        writeln!(stream, "{}", serde_json::to_string(&forged).unwrap()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_approvals_are_answered_once_and_denied_by_lock() {
+        let approvals = Approvals::default();
+        let (first, receiver) = approvals.request();
+        assert!(approvals.answer(first, true));
+        assert!(approvals.wait(first, receiver));
+        assert!(!approvals.answer(first, true));
+
+        let (second, receiver) = approvals.request();
+        assert_ne!(first, second);
+        approvals.deny_all();
+        assert!(!approvals.answer(second, true));
+        assert!(!approvals.wait(second, receiver));
+    }
+
+    #[test]
+    fn test_manifests_allow_each_browser_its_own_ids() {
+        let ids = vec!["b".repeat(32), "pwdbox@example.com".to_string()];
+        let host = Path::new("/opt/pwdbox/pwdbox-native-host");
+        let chrome = manifest(Browser::Chrome, host, &ids);
+        assert_eq!(chrome["allowed_origins"], json!([format!("chrome-extension://{}/", "b".repeat(32))]));
+        assert!(chrome.get("allowed_extensions").is_none());
+        let firefox = manifest(Browser::Firefox, host, &ids);
+        assert_eq!(firefox["allowed_extensions"], json!(["pwdbox@example.com"]));
+        assert_eq!((&firefox["name"], &firefox["type"]), (&json!(HOST_NAME), &json!("stdio")));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::crypto::{CryptoService, SecretKey};
+use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+// Unlocking without typing the master password. Turning it on wraps the vault key under a
+// random secret; the secret goes into the OS keychain (Windows Credential Manager, the macOS
+// Keychain, the Secret Service on Linux) and the wrapped key into settings, so neither the
+// database nor the keychain alone opens the vault. Password login always keeps working.
+//
+// Items are stored as the keychain stores any other: open for as long as the OS session is.
+// Asking for Windows Hello or Touch ID on each read needs an access policy on the item,
+// which the keyring crate cannot set, so no biometric prompt is promised here.
+
+// Service name the keychain items are filed under
+pub const KEYCHAIN_SERVICE: &str = "PwdBox";
+
+// Where the secret lives: OsKeychain in builds with the os-keychain feature. Calls may wait
+// on the user (a keychain prompt), so callers make them with no lock held.
+pub trait SecretStore {
+    fn set(&self, account: &str, secret: &str) -> Result<()>;
+    // None when there is no such item
+    fn get(&self, account: &str) -> Result<Option<Zeroizing<String>>>;
+    // Removing an item that is already gone is not an error
+    fn delete(&self, account: &str) -> Result<()>;
+}
+
+// Kept in settings while keychain unlock is on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeychainUnlock {
+    pub account: String, // Keychain item holding the secret; new each time it is turned on
+    pub wrapped_vault_key: String,
+    pub vault_key_nonce: String,
+    pub generation: i64, // user_meta generation it was made under; a password change retires it
+}
+
+// A new keychain item name, `prefix` followed by random hex
+pub fn new_account(prefix: &str) -> String {
+    let mut id = [0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    format!("{}-{}", prefix, id.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+}
+
+// Wrap `vault_key` under a new secret and put the secret in `store`
+pub fn enable(vault_key: &[u8; 32], generation: i64, store: &dyn SecretStore) -> Result<KeychainUnlock> {
+    let secret = CryptoService::generate_vault_key();
+    let (wrapped_vault_key, vault_key_nonce) = CryptoService::wrap_key(vault_key, &secret)?;
+    let account = new_account("vault");
+
+    store.set(&account, &Zeroizing::new(general_purpose::STANDARD.encode(secret)))?;
+    Ok(KeychainUnlock { account, wrapped_vault_key, vault_key_nonce, generation })
+}
+
+// The vault key, or None when the keychain item is gone
+pub fn open(unlock: &KeychainUnlock, store: &dyn SecretStore) -> Result<Option<SecretKey>> {
+    let Some(secret) = store.get(&unlock.account)? else {
+        return Ok(None);
+    };
+    let secret = CryptoService::decode_key(&secret)?;
+    Ok(Some(CryptoService::unwrap_key(&unlock.wrapped_vault_key, &unlock.vault_key_nonce, &secret)?))
+}
+
+#[cfg(feature = "os-keychain")]
+pub struct OsKeychain;
+
+#[cfg(feature = "os-keychain")]
+impl SecretStore for OsKeychain {
+    fn set(&self, account: &str, secret: &str) -> Result<()> {
+        keyring::Entry::new(KEYCHAIN_SERVICE, account)?.set_password(secret)?;
+        Ok(())
+    }
+
+    fn get(&self, account: &str) -> Result<Option<Zeroizing<String>>> {
+        match keyring::Entry::new(KEYCHAIN_SERVICE, account)?.get_password() {
+            Ok(secret) => Ok(Some(Zeroizing::new(secret))),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        match keyring::Entry::new(KEYCHAIN_SERVICE, account)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// A keychain in memory, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    pub items: std::sync::Mutex<std::collections::HashMap<String, String>>,
+}
+
+#[cfg(test)]
+impl SecretStore for MemoryStore {
+    fn set(&self, account: &str, secret: &str) -> Result<()> {
+        self.items.lock().unwrap().insert(account.to_string(), secret.to_string());
+        Ok(())
+    }
+
+    fn get(&self, account: &str) -> Result<Option<Zeroizing<String>>> {
+        Ok(self.items.lock().unwrap().get(account).cloned().map(Zeroizing::new))
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        self.items.lock().unwrap().remove(account);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enable_and_open() {
+        let store = MemoryStore::default();
+        let vault_key = CryptoService::generate_vault_key();
+        let first = enable(&vault_key, 3, &store).unwrap();
+        let second = enable(&vault_key, 3, &store).unwrap();
+        assert_ne!(first.account, second.account);
+        assert_eq!(first.generation, 3);
+
+        assert_eq!(*open(&first, &store).unwrap().unwrap(), *vault_key);
+        store.delete(&first.account).unwrap();
+        assert!(open(&first, &store).unwrap().is_none());
+        assert_eq!(*open(&second, &store).unwrap().unwrap(), *vault_key);
+
+        // Another vault's secret does not unwrap this key
+        let other = enable(&CryptoService::generate_vault_key(), 0, &store).unwrap();
+        let swapped = KeychainUnlock { account: other.account, ..second };
+        assert!(open(&swapped, &store).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::crypto::CryptoService;
+use crate::database::{PasswordPolicy, PolicyMode};
+use serde::{Deserialize, Serialize};
+
+// Highest floor a policy may ask for
+pub const MAX_POLICY_ENTROPY_BITS: u32 = 256;
+
+// Passwords common enough to be tried first, compared lowercase with spaces removed. Only
+// ones of 8 characters or more: shorter ones fail the length rule of any master policy.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "password1", "password12", "password123", "password1234", "passw0rd", "p@ssw0rd",
+    "passwordpassword", "12345678", "123456789", "1234567890", "123456789012", "11111111",
+    "00000000", "88888888", "87654321", "abcd1234", "abcdefgh", "1q2w3e4r", "1q2w3e4r5t",
+    "1qaz2wsx", "1qaz2wsx3edc", "zaq12wsx", "qwertyui", "qwertyuiop", "qwerty123", "qwerty123456",
+    "asdfghjkl", "iloveyou", "iloveyou1", "sunshine", "football", "baseball", "princess",
+    "superman", "starwars", "whatever", "trustno1", "welcome1", "welcome123", "letmein1",
+    "letmein123", "changeme", "computer", "internet", "michelle", "jennifer", "liverpool",
+    "administrator", "masterpassword", "mypassword", "secretpassword", "correcthorsebatterystaple",
+];
+
+// What a new master password has to meet
+#[derive(Debug, Clone, Copy)]
+pub struct MasterPasswordPolicy {
+    pub min_length: usize, // In characters
+    pub min_entropy_bits: u32, // By CryptoService::guessable_entropy_bits
+    pub reject_common: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MasterPasswordRule {
+    MinLength,
+    Common,
+    MinEntropy,
+}
+
+// Returned (through anyhow) when a new master password falls short. The validation error
+// carries it as "policy", so the UI can name the rule and fill a strength meter.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MasterPasswordRejected {
+    #[serde(skip)]
+    pub field: &'static str, // The request field holding the password
+    pub rule: MasterPasswordRule,
+    pub entropy_bits: u32,
+    pub min_entropy_bits: u32,
+    pub min_length: usize,
+}
+
+impl std::fmt::Display for MasterPasswordRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.rule {
+            MasterPasswordRule::MinLength => write!(f, "The master password must be at least {} characters long", self.min_length),
+            MasterPasswordRule::Common => write!(f, "The master password is one of the most commonly used passwords"),
+            MasterPasswordRule::MinEntropy => write!(
+                f,
+                "The master password is too easy to guess: about {} bits of entropy, at least {} needed",
+                self.entropy_bits, self.min_entropy_bits
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MasterPasswordRejected {}
+
+pub fn is_common_password(password: &str) -> bool {
+    let compact: String = password.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect();
+    COMMON_PASSWORDS.contains(&compact.as_str())
+}
+
+impl MasterPasswordPolicy {
+    // The first rule `password` breaks, in the order length, common, entropy
+    pub fn check(&self, password: &str, field: &'static str) -> Result<(), MasterPasswordRejected> {
+        let entropy_bits = CryptoService::guessable_entropy_bits(password);
+        let rule = if password.chars().count() < self.min_length {
+            MasterPasswordRule::MinLength
+        } else if self.reject_common && is_common_password(password) {
+            MasterPasswordRule::Common
+        } else if entropy_bits < self.min_entropy_bits {
+            MasterPasswordRule::MinEntropy
+        } else {
+            return Ok(());
+        };
+        Err(MasterPasswordRejected {
+            field,
+            rule,
+            entropy_bits,
+            min_entropy_bits: self.min_entropy_bits,
+            min_length: self.min_length,
+        })
+    }
+}
+
+// Returned (through anyhow) when a strict policy refuses a save, and listed by the health
+// report for entries that fall short of any policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyViolation {
+    pub tag: String,
+    pub mode: PolicyMode,
+    pub min_entropy_bits: u32,
+    pub entropy_bits: u32,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Password policy \"{}\" requires at least {} bits of entropy, this password has about {}",
+            self.tag, self.min_entropy_bits, self.entropy_bits
+        )
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+// Character-pool estimate: length times log2 of the size of the character classes used.
+// Accurate for generated passwords; chosen ones (words, patterns) are overestimated.
+pub fn estimate_entropy_bits(password: &str) -> u32 {
+    let mut pool = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if password.chars().any(|c| c.is_ascii_punctuation() || c == ' ') {
+        pool += 33;
+    }
+    if !password.is_ascii() {
+        pool += 100;
+    }
+    if pool == 0 {
+        return 0;
+    }
+    (password.chars().count() as f64 * (pool as f64).log2()).floor() as u32
+}
+
+// Policies among `policies` that an entry with `tags` and `password` falls short of
+pub fn violations(password: &str, tags: &[String], policies: &[PasswordPolicy]) -> Vec<PolicyViolation> {
+    let entropy_bits = estimate_entropy_bits(password);
+    policies
+        .iter()
+        .filter(|policy| entropy_bits < policy.min_entropy_bits)
+        .filter(|policy| tags.iter().any(|tag| tag.to_lowercase() == policy.tag.to_lowercase()))
+        .map(|policy| PolicyViolation {
+            tag: policy.tag.clone(),
+            mode: policy.mode,
+            min_entropy_bits: policy.min_entropy_bits,
+            entropy_bits,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy_estimate_and_matching_policies() {
+        assert_eq!(estimate_entropy_bits(""), 0);
+        assert_eq!(estimate_entropy_bits("aaaaaaaa"), 37);
+        // 11 characters from [A-Za-z0-9] clear a 64-bit floor, 10 do not
+        assert_eq!(estimate_entropy_bits("aB3dE5gH9jK"), 65);
+        assert_eq!(estimate_entropy_bits("aB3dE5gH9j"), 59);
+
+        let policies = vec![
+            PasswordPolicy { tag: "Infra".to_string(), min_entropy_bits: 64, mode: PolicyMode::Strict },
+            PasswordPolicy { tag: "personal".to_string(), min_entropy_bits: 40, mode: PolicyMode::Advisory },
+        ];
+        let tags = vec!["infra".to_string()];
+        let found = violations("aB3dE5gH9j", &tags, &policies);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].tag.as_str(), found[0].mode), ("Infra", PolicyMode::Strict));
+        assert!(found[0].to_string().contains("\"Infra\""));
+
+        assert!(violations("aB3dE5gH9jK", &tags, &policies).is_empty());
+        assert!(violations("short", &["other".to_string()], &policies).is_empty());
+    }
+
+    #[test]
+    fn test_master_password_policy_boundaries_and_common_passwords() {
+        let policy = crate::user_service::MASTER_PASSWORD_POLICY;
+        let rule = |password: &str| policy.check(password, "master_password").err().map(|rejected| rejected.rule);
+
+        assert_eq!(rule("123"), Some(MasterPasswordRule::MinLength));
+        assert_eq!(rule("kT9#mQ2$vL7"), Some(MasterPasswordRule::MinLength));
+        assert_eq!(rule("kT9#mQ2$vL7p"), None);
+        assert_eq!(rule("qmzvtrxkwjfp"), None);
+
+        // Dictionary hits ignore case and spaces
+        assert!(is_common_password("Pass Word"));
+        assert_eq!(rule("Password1234"), Some(MasterPasswordRule::Common));
+        assert_eq!(rule("Correct Horse Battery Staple"), Some(MasterPasswordRule::Common));
+        assert_eq!(rule("correct horse battery stapler"), None);
+
+        // Long enough and not listed, but a run of one character is guessed at once
+        let rejected = policy.check("aaaaaaaaaaaaaaaa", "new_password").unwrap_err();
+        assert_eq!((rejected.rule, rejected.entropy_bits < policy.min_entropy_bits), (MasterPasswordRule::MinEntropy, true));
+        assert!(rejected.to_string().contains(&format!("about {} bits", rejected.entropy_bits)));
+
+        let error = serde_json::to_value(crate::error::PwdBoxError::from(anyhow::Error::new(rejected))).unwrap();
+        assert_eq!((&error["code"], &error["field"]), (&serde_json::json!("validation"), &serde_json::json!("new_password")));
+        assert_eq!(error["policy"]["rule"], "min_entropy");
+        assert_eq!(error["policy"]["min_length"], 12);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::crypto::{CryptoService, KdfParams, SecretKey};
+use crate::error::PwdBoxError;
+use crate::os_keychain::{self, SecretStore};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+// Unlocking with a short PIN once the vault has locked. Turning it on wraps the vault key
+// under a key derived with Argon2 from the PIN and a random pepper; the pepper goes into
+// the OS keychain and the wrapped key into settings. Six digits are quickly guessed offline,
+// which the pepper rules out for anyone holding only the database, and online, which the
+// attempt cap rules out: MAX_PIN_ATTEMPTS wrong PINs in a row delete the wrapped key and
+// the master password is needed again.
+
+pub const PIN_LENGTH: usize = 6;
+pub const MAX_PIN_ATTEMPTS: u32 = 5;
+
+// Kept in settings while PIN unlock is on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PinUnlock {
+    pub account: String, // Keychain item holding the pepper; new each time it is turned on
+    pub wrapped_vault_key: String,
+    pub vault_key_nonce: String,
+    pub salt: String,
+    pub kdf_params: KdfParams,
+    pub generation: i64, // user_meta generation it was made under; a password change retires it
+    #[serde(default)]
+    pub failed_attempts: u32, // Wrong PINs in a row
+}
+
+// What trying a PIN came to
+pub enum PinOutcome {
+    Opened(SecretKey),
+    WrongPin,
+    PepperMissing, // The keychain item is gone, so no PIN opens the key any more
+}
+
+pub fn validate_pin(pin: &str) -> Result<()> {
+    if pin.len() != PIN_LENGTH || !pin.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(PwdBoxError::invalid_field("pin", format!("The PIN must be {} digits", PIN_LENGTH)).into());
+    }
+    Ok(())
+}
+
+fn pin_key(pin: &str, pepper: &str, salt: &str, params: &KdfParams) -> Result<SecretKey> {
+    CryptoService::derive_key_from_password(&Zeroizing::new(format!("{}:{}", pin, pepper)), salt, params)
+}
+
+// Wrap `vault_key` under `pin` and a new pepper, and put the pepper in `store`
+pub fn enable(vault_key: &[u8; 32], generation: i64, pin: &str, params: &KdfParams, store: &dyn SecretStore) -> Result<PinUnlock> {
+    validate_pin(pin)?;
+    let pepper = Zeroizing::new(CryptoService::generate_salt());
+    let salt = CryptoService::generate_salt();
+    let key = pin_key(pin, &pepper, &salt, params)?;
+    let (wrapped_vault_key, vault_key_nonce) = CryptoService::wrap_key(vault_key, &key)?;
+    let account = os_keychain::new_account("pin");
+
+    store.set(&account, &pepper)?;
+    Ok(PinUnlock { account, wrapped_vault_key, vault_key_nonce, salt, kdf_params: *params, generation, failed_attempts: 0 })
+}
+
+// Try `pin` against the wrapped key. Counting the attempt is up to the caller.
+pub fn open(unlock: &PinUnlock, pin: &str, store: &dyn SecretStore) -> Result<PinOutcome> {
+    validate_pin(pin)?;
+    let Some(pepper) = store.get(&unlock.account)? else {
+        return Ok(PinOutcome::PepperMissing);
+    };
+    let key = pin_key(pin, &pepper, &unlock.salt, &unlock.kdf_params)?;
+    Ok(match CryptoService::unwrap_key(&unlock.wrapped_vault_key, &unlock.vault_key_nonce, &key) {
+        Ok(vault_key) => PinOutcome::Opened(vault_key),
+        Err(_) => PinOutcome::WrongPin,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os_keychain::MemoryStore;
+
+    #[test]
+    fn test_enable_and_open() {
+        let store = MemoryStore::default();
+        let vault_key = CryptoService::generate_vault_key();
+        let params = KdfParams::default();
+        for pin in ["12345", "1234567", "12345a", "١٢٣٤٥٦"] {
+            assert!(enable(&vault_key, 0, pin, &params, &store).is_err());
+        }
+        let unlock = enable(&vault_key, 2, "042917", &params, &store).unwrap();
+        assert_eq!((unlock.generation, unlock.failed_attempts), (2, 0));
+        assert!(!unlock.wrapped_vault_key.contains("042917"));
+
+        assert!(matches!(open(&unlock, "042917", &store).unwrap(), PinOutcome::Opened(key) if *key == *vault_key));
+        assert!(matches!(open(&unlock, "042918", &store).unwrap(), PinOutcome::WrongPin));
+        assert!(open(&unlock, "4291", &store).is_err());
+
+        // Without the pepper even the right PIN opens nothing
+        store.delete(&unlock.account).unwrap();
+        assert!(matches!(open(&unlock, "042917", &store).unwrap(), PinOutcome::PepperMissing));
+    }
+}
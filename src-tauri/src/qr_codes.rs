@@ -0,0 +1,133 @@
+use crate::error::PwdBoxError;
+use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose};
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use zeroize::Zeroizing;
+
+// QR codes for handing an entry to a phone: a Wi-Fi network to join, or a TOTP secret to
+// enrol in an authenticator app. They are rendered in memory and returned, never written out.
+
+// Rendered codes are at least this many pixels wide, quiet zone included
+const MIN_QR_SIZE: u32 = 256;
+
+// Characters the WIFI: format gives a meaning, escaped with a backslash inside values
+const WIFI_SPECIAL: &[char] = &['\\', ';', ',', ':', '"'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QrKind {
+    Wifi, // Network name from the account, password from the entry
+    Totp, // The entry's TOTP custom field, as an otpauth:// URI
+}
+
+#[derive(Debug, Serialize)]
+pub struct QrImage {
+    pub png: String, // Base64 encoded PNG
+    pub width: u32,
+    pub height: u32,
+}
+
+fn escape_wifi(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if WIFI_SPECIAL.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// "WIFI:T:WPA;S:<ssid>;P:<password>;;", or an open network when there is no password
+pub fn wifi_payload(ssid: &str, password: &str) -> Zeroizing<String> {
+    if password.is_empty() {
+        return Zeroizing::new(format!("WIFI:T:nopass;S:{};;", escape_wifi(ssid)));
+    }
+    Zeroizing::new(format!("WIFI:T:WPA;S:{};P:{};;", escape_wifi(ssid), escape_wifi(password)))
+}
+
+// Everything but unreserved characters, percent-encoded as UTF-8
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// A stored otpauth:// URI is used as it is; a bare base32 secret (spaces, padding and case
+// ignored) is wrapped in one labelled with the issuer and account
+pub fn otpauth_uri(secret: &str, issuer: &str, account: &str) -> Result<Zeroizing<String>> {
+    let secret = secret.trim();
+    if secret.to_ascii_lowercase().starts_with("otpauth://") {
+        return Ok(Zeroizing::new(secret.to_string()));
+    }
+
+    let normalized: Zeroizing<String> = Zeroizing::new(
+        secret.chars().filter(|c| !c.is_whitespace() && *c != '=' && *c != '-').map(|c| c.to_ascii_uppercase()).collect(),
+    );
+    if normalized.is_empty() || !normalized.chars().all(|c| matches!(c, 'A'..='Z' | '2'..='7')) {
+        return Err(PwdBoxError::invalid_field("secret", "TOTP secrets are base32 (letters A-Z and digits 2-7) or an otpauth:// URI").into());
+    }
+
+    let label = if issuer.is_empty() {
+        percent_encode(account)
+    } else {
+        format!("{}:{}", percent_encode(issuer), percent_encode(account))
+    };
+    let mut uri = format!("otpauth://totp/{}?secret={}", label, normalized.as_str());
+    if !issuer.is_empty() {
+        uri.push_str(&format!("&issuer={}", percent_encode(issuer)));
+    }
+    Ok(Zeroizing::new(uri))
+}
+
+pub fn render(payload: &str) -> Result<QrImage> {
+    let code = QrCode::new(payload.as_bytes())
+        .map_err(|e| PwdBoxError::validation(format!("Too much data for a QR code: {}", e)))?;
+    let image = code.render::<Luma<u8>>().min_dimensions(MIN_QR_SIZE, MIN_QR_SIZE).build();
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(QrImage {
+        png: general_purpose::STANDARD.encode(&png),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wifi_values_are_escaped() {
+        assert_eq!(wifi_payload("Home", "hunter22").as_str(), "WIFI:T:WPA;S:Home;P:hunter22;;");
+        assert_eq!(wifi_payload("Café;Guest:5G", "").as_str(), r"WIFI:T:nopass;S:Café\;Guest\:5G;;");
+        assert_eq!(wifi_payload(r#"a\b,"c""#, "p;a:s\\s").as_str(), r#"WIFI:T:WPA;S:a\\b\,\"c\";P:p\;a\:s\\s;;"#);
+    }
+
+    #[test]
+    fn test_otpauth_uris_and_rendering() {
+        let uri = otpauth_uri("jbsw y3dp ehpk 3pxp==", "ACME Co", "me@example.com").unwrap();
+        assert_eq!(uri.as_str(), "otpauth://totp/ACME%20Co:me%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co");
+        let stored = "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&period=60";
+        assert_eq!(otpauth_uri(stored, "Other", "bob").unwrap().as_str(), stored);
+        assert!(otpauth_uri("not base32!", "ACME", "me").is_err());
+        assert!(otpauth_uri("  ", "ACME", "me").is_err());
+
+        let image = render(&uri).unwrap();
+        assert!(image.width >= MIN_QR_SIZE && image.width == image.height);
+        let png = general_purpose::STANDARD.decode(&image.png).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (image.width, image.height));
+        assert!(render(&"x".repeat(8000)).is_err());
+    }
+}
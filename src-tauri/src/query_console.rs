@@ -0,0 +1,208 @@
+use crate::audit_log;
+use crate::database::Database;
+use anyhow::{Result, anyhow};
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use rusqlite::types::ValueRef;
+use rusqlite::{Batch, OpenFlags};
+use serde::{Deserialize, Serialize};
+
+// Rows returned beyond this are dropped and the result is marked truncated
+const MAX_ROWS: usize = 500;
+
+// Columns a console query may read. Everything else, including encrypted passwords,
+// notes, nonces, hashes, salts and wrapped keys, is rejected by the authorizer.
+const READABLE_COLUMNS: &[(&str, &[&str])] = &[
+    ("password_entries", &["id", "software", "account", "expires_at", "last_used_at", "password_changed_at", "breach_acknowledged_at", "created_at", "updated_at", "is_favorite", "deleted_at", "url", "url_host"]),
+    ("stats_history", &[
+        "recorded_on",
+        "recorded_at",
+        "entry_count",
+        "weak_count",
+        "reused_count",
+        "breached_count",
+        "average_password_age_days",
+    ]),
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+    pub truncated: bool,
+}
+
+pub struct QueryConsole {
+    database: Database,
+}
+
+impl QueryConsole {
+    pub fn new(database: Database) -> Self {
+        QueryConsole { database }
+    }
+
+    // Run a single SELECT over vault metadata on a separate read-only connection
+    pub fn run_readonly_query(&self, sql: &str) -> Result<QueryResult> {
+        audit_log::record(&self.database, audit_log::READONLY_QUERY, None, Some(sql), None)?;
+
+        let connection = self.database.open_connection(
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        connection.pragma_update(None, "query_only", true)?;
+        connection.authorizer(Some(authorize));
+
+        let mut batch = Batch::new(&connection, sql);
+        let mut stmt = batch
+            .next()
+            .map_err(|e| anyhow!("Query rejected: {}", e))?
+            .ok_or_else(|| anyhow!("Query is empty"))?;
+        if !matches!(batch.next(), Ok(None)) {
+            return Err(anyhow!("Query rejected: only a single statement is allowed"));
+        }
+        let columns: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+
+        let mut rows = Vec::new();
+        let mut truncated = false;
+        let mut result_rows = stmt.query([])?;
+        while let Some(row) = result_rows.next()? {
+            if rows.len() == MAX_ROWS {
+                truncated = true;
+                break;
+            }
+            let mut values = Vec::with_capacity(columns.len());
+            for index in 0..columns.len() {
+                values.push(json_value(row.get_ref(index)?));
+            }
+            rows.push(values);
+        }
+
+        Ok(QueryResult { columns, rows, truncated })
+    }
+}
+
+// Allow plain reads of whitelisted columns; deny every write, pragma, attach and schema change
+fn authorize(context: AuthContext<'_>) -> Authorization {
+    match context.action {
+        AuthAction::Select | AuthAction::Recursive => Authorization::Allow,
+        AuthAction::Read { table_name, column_name } => {
+            let readable = READABLE_COLUMNS.iter().any(|(table, columns)| {
+                // An empty column name is a row access without reading a column, e.g. count(*)
+                *table == table_name && (column_name.is_empty() || columns.contains(&column_name))
+            });
+            if readable { Authorization::Allow } else { Authorization::Deny }
+        }
+        AuthAction::Function { function_name } => {
+            if function_name.eq_ignore_ascii_case("load_extension") {
+                Authorization::Deny
+            } else {
+                Authorization::Allow
+            }
+        }
+        _ => Authorization::Deny,
+    }
+}
+
+fn json_value(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => serde_json::json!(i),
+        ValueRef::Real(f) => serde_json::json!(f),
+        ValueRef::Text(t) => serde_json::json!(String::from_utf8_lossy(t)),
+        ValueRef::Blob(b) => serde_json::json!(format!("<{} byte blob>", b.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoService;
+    use crate::database::PasswordEntry;
+
+    fn console(dir: &tempfile::TempDir, entries: usize) -> QueryConsole {
+        let database = Database::new(dir.path().join("pwdbox.db")).unwrap();
+        for index in 0..entries {
+            database.insert_password_entry(&PasswordEntry {
+                id: None,
+                software: format!("site{}.example.{}", index, if index % 2 == 0 { "com" } else { "org" }),
+                account: "me@example.com".to_string(),
+                encrypted_password: "c2VjcmV0LWNpcGhlcnRleHQ=".to_string(),
+                nonce: CryptoService::generate_nonce(),
+                ..Default::default()
+            }).unwrap();
+        }
+        QueryConsole::new(database)
+    }
+
+    #[test]
+    fn test_metadata_queries_return_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let console = console(&dir, 4);
+
+        let result = console.run_readonly_query(
+            "SELECT substr(software, instr(software, '.example.') + 9) AS tld, count(*) AS entries
+             FROM password_entries GROUP BY tld ORDER BY tld",
+        ).unwrap();
+        assert_eq!(result.columns, vec!["tld".to_string(), "entries".to_string()]);
+        assert_eq!(result.rows, vec![
+            vec![serde_json::json!("com"), serde_json::json!(2)],
+            vec![serde_json::json!("org"), serde_json::json!(2)],
+        ]);
+        assert!(!result.truncated);
+    }
+
+    #[test]
+    fn test_row_cap_truncates() {
+        let dir = tempfile::tempdir().unwrap();
+        let console = console(&dir, MAX_ROWS + 1);
+
+        let result = console.run_readonly_query("SELECT id FROM password_entries").unwrap();
+        assert_eq!(result.rows.len(), MAX_ROWS);
+        assert!(result.truncated);
+    }
+
+    #[test]
+    fn test_secret_columns_cannot_be_exfiltrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let console = console(&dir, 2);
+
+        for sql in [
+            "SELECT encrypted_password FROM password_entries",
+            "SELECT * FROM password_entries",
+            "SELECT nonce || '' FROM password_entries",
+            "SELECT length(encrypted_password) FROM password_entries",
+            "SELECT software FROM password_entries WHERE nonce LIKE 's%'",
+            "SELECT software FROM password_entries ORDER BY encrypted_password",
+            "SELECT (SELECT encrypted_password FROM password_entries LIMIT 1)",
+            "SELECT id FROM password_entries WHERE id IN (SELECT id FROM password_entries WHERE encrypted_password > '')",
+            "WITH leaked AS (SELECT nonce FROM password_entries) SELECT * FROM leaked",
+            "SELECT master_hash FROM user_meta",
+            "SELECT answer_salt1 FROM user_meta",
+            "SELECT wrapped_vault_key FROM user_meta",
+            "SELECT notes FROM password_entries",
+            "SELECT value FROM settings",
+            "SELECT sql FROM sqlite_master",
+        ] {
+            assert!(console.run_readonly_query(sql).is_err(), "query was allowed: {}", sql);
+        }
+    }
+
+    #[test]
+    fn test_writes_pragmas_and_attach_are_denied() {
+        let dir = tempfile::tempdir().unwrap();
+        let console = console(&dir, 1);
+
+        for sql in [
+            "DELETE FROM password_entries",
+            "UPDATE password_entries SET software = 'x'",
+            "INSERT INTO settings (key, value) VALUES ('a', 'b')",
+            "DROP TABLE password_entries",
+            "PRAGMA query_only = 0",
+            "PRAGMA table_info(password_entries)",
+            "ATTACH DATABASE 'other.db' AS other",
+            "SELECT load_extension('evil')",
+            "SELECT id FROM password_entries; DELETE FROM password_entries",
+        ] {
+            assert!(console.run_readonly_query(sql).is_err(), "query was allowed: {}", sql);
+        }
+        assert_eq!(console.database.count_password_entries().unwrap(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::capabilities::{self, Capability, CapabilityRegistry};
+use crate::database::Database;
+use crate::time_utils;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Local, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+const REMINDER_SETTINGS_KEY: &str = "expiry_reminders";
+const LAST_REMINDER_KEY: &str = "expiry_reminders_last_sent"; // Local date of the last notification
+
+// Filter the entry list opens with when a reminder is followed
+pub const EXPIRING_ENTRIES_LINK: &str = "entries?filter=expiring";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReminderSettings {
+    pub enabled: bool,
+    pub window_days: u32,
+    pub quiet_hours_start: Option<u32>, // Local hour (0-23); quiet hours may wrap past midnight
+    pub quiet_hours_end: Option<u32>,
+}
+
+impl Default for ReminderSettings {
+    fn default() -> Self {
+        ReminderSettings {
+            enabled: true,
+            window_days: 7,
+            quiet_hours_start: None,
+            quiet_hours_end: None,
+        }
+    }
+}
+
+impl ReminderSettings {
+    fn is_quiet_hour(&self, hour: u32) -> bool {
+        match (self.quiet_hours_start, self.quiet_hours_end) {
+            (Some(start), Some(end)) if start < end => hour >= start && hour < end,
+            (Some(start), Some(end)) if start > end => hour >= start || hour < end,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpiryReminder {
+    pub expiring_count: usize,
+    pub title: String,
+    pub body: String, // Never names entries: notifications can show on a locked screen
+    pub link: String,
+}
+
+pub struct ReminderService {
+    database: Database,
+}
+
+impl ReminderService {
+    pub fn new(database: Database) -> Self {
+        ReminderService { database }
+    }
+
+    pub fn get_settings(&self) -> Result<ReminderSettings> {
+        match self.database.get_setting(REMINDER_SETTINGS_KEY)? {
+            Some(value) => Ok(serde_json::from_str(&value)?),
+            None => Ok(ReminderSettings::default()),
+        }
+    }
+
+    // Notification permission is probed separately, where the app handle is available
+    pub fn register_capabilities(&self, registry: &mut CapabilityRegistry) -> Result<()> {
+        registry.register(capabilities::EXPIRY_REMINDERS, Capability::available(self.get_settings()?.enabled));
+        Ok(())
+    }
+
+    pub fn set_settings(&self, settings: ReminderSettings) -> Result<ReminderSettings> {
+        if settings.window_days == 0 || settings.window_days > 365 {
+            return Err(anyhow!("Reminder window must be between 1 and 365 days"));
+        }
+        if settings.quiet_hours_start.is_some() != settings.quiet_hours_end.is_some() {
+            return Err(anyhow!("Quiet hours need both a start and an end"));
+        }
+        if [settings.quiet_hours_start, settings.quiet_hours_end].iter().flatten().any(|hour| *hour > 23) {
+            return Err(anyhow!("Quiet hours must be between 0 and 23"));
+        }
+
+        self.database.set_setting(REMINDER_SETTINGS_KEY, &serde_json::to_string(&settings)?)?;
+        Ok(settings)
+    }
+
+    // Check for entries expiring within the configured window. Only reads plaintext
+    // metadata; callers must still skip the check while the vault is locked.
+    pub fn check_expiring(&self) -> Result<Option<ExpiryReminder>> {
+        let local = Local::now();
+        self.check_expiring_at(&local.with_timezone(&Utc), local.hour(), &local.format("%Y-%m-%d").to_string())
+    }
+
+    // Returns at most one reminder per local day. A check during quiet hours is
+    // deferred rather than consumed, so the next check after they end still notifies.
+    fn check_expiring_at(&self, now: &DateTime<Utc>, local_hour: u32, local_date: &str) -> Result<Option<ExpiryReminder>> {
+        let settings = self.get_settings()?;
+        if !settings.enabled || settings.is_quiet_hour(local_hour) {
+            return Ok(None);
+        }
+        if self.database.get_setting(LAST_REMINDER_KEY)?.as_deref() == Some(local_date) {
+            return Ok(None);
+        }
+
+        let cutoff = *now + Duration::days(settings.window_days as i64);
+        let expiring = self.database.get_entries_expiring_before(&time_utils::to_rfc3339(&cutoff))?;
+        if expiring.is_empty() {
+            return Ok(None);
+        }
+
+        self.database.set_setting(LAST_REMINDER_KEY, local_date)?;
+        let noun = if expiring.len() == 1 { "password" } else { "passwords" };
+        Ok(Some(ExpiryReminder {
+            expiring_count: expiring.len(),
+            title: "PwdBox".to_string(),
+            body: format!("{} {} expired or expiring within {} days", expiring.len(), noun, settings.window_days),
+            link: EXPIRING_ENTRIES_LINK.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoService;
+    use crate::database::PasswordEntry;
+
+    fn service(dir: &tempfile::TempDir) -> ReminderService {
+        ReminderService::new(Database::new(dir.path().join("pwdbox.db")).unwrap())
+    }
+
+    fn add_expiring(service: &ReminderService, expires_at: Option<&str>) {
+        service.database.insert_password_entry(&PasswordEntry {
+            id: None,
+            software: "mail".to_string(),
+            account: "me@example.com".to_string(),
+            encrypted_password: "Y2lwaGVydGV4dA==".to_string(),
+            nonce: CryptoService::generate_nonce(),
+            expires_at: expires_at.map(str::to_string),
+            ..Default::default()
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_reminds_once_per_day_within_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = service(&dir);
+        let now = time_utils::parse_rfc3339("2024-06-01T09:00:00Z").unwrap();
+
+        add_expiring(&service, Some("2024-06-05T00:00:00Z"));
+        add_expiring(&service, Some("2024-05-30T00:00:00Z")); // Already expired
+        add_expiring(&service, Some("2024-07-01T00:00:00Z")); // Outside the window
+        add_expiring(&service, None);
+
+        let reminder = service.check_expiring_at(&now, 9, "2024-06-01").unwrap().unwrap();
+        assert_eq!(reminder.expiring_count, 2);
+        assert_eq!(reminder.link, EXPIRING_ENTRIES_LINK);
+        assert!(!reminder.body.contains("mail"));
+
+        assert!(service.check_expiring_at(&now, 15, "2024-06-01").unwrap().is_none());
+        assert!(service.check_expiring_at(&now, 9, "2024-06-02").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_quiet_hours_defer_and_disable_skips() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = service(&dir);
+        let now = time_utils::parse_rfc3339("2024-06-01T23:00:00Z").unwrap();
+        add_expiring(&service, Some("2024-06-02T00:00:00Z"));
+
+        service.set_settings(ReminderSettings {
+            quiet_hours_start: Some(22),
+            quiet_hours_end: Some(7),
+            ..ReminderSettings::default()
+        }).unwrap();
+        assert!(service.check_expiring_at(&now, 23, "2024-06-01").unwrap().is_none());
+        assert!(service.check_expiring_at(&now, 6, "2024-06-01").unwrap().is_none());
+        assert!(service.check_expiring_at(&now, 7, "2024-06-01").unwrap().is_some());
+
+        service.set_settings(ReminderSettings { enabled: false, ..ReminderSettings::default() }).unwrap();
+        assert!(service.check_expiring_at(&now, 12, "2024-06-02").unwrap().is_none());
+
+        assert!(service.set_settings(ReminderSettings { window_days: 0, ..ReminderSettings::default() }).is_err());
+        assert!(service.set_settings(ReminderSettings { quiet_hours_start: Some(22), ..ReminderSettings::default() }).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::crypto::{CryptoService, SecretKey};
+use crate::database::Database;
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use rand::RngCore;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// Keys passed to the services are stamped with the user_meta generation they were issued
+// under ("g<generation>:<base64 key>"). Changing or resetting the master password and
+// replacing the vault from an import bump the generation, so a key still held by another
+// window stops working and that window has to log in again.
+//
+// The vault key itself stays in the backend: after login the frontend only gets an opaque
+// session token. Builds with the legacy-master-key-ipc feature still hand out the stamped
+// key and accept it back in command requests, for frontends not migrated yet.
+pub const LEGACY_KEY_IPC: bool = cfg!(feature = "legacy-master-key-ipc");
+
+// Returned (through anyhow) for a key issued under an older generation
+#[derive(Debug)]
+pub struct SessionInvalidated {
+    pub session_generation: i64,
+    pub current_generation: i64,
+}
+
+impl std::fmt::Display for SessionInvalidated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Session invalidated: the master password or vault changed since this session started, please log in again"
+        )
+    }
+}
+
+impl std::error::Error for SessionInvalidated {}
+
+// Returned for commands that arrive while the vault is locked. Commands turn it into a
+// PwdBoxError with the "locked" code, on which the frontend shows the login screen.
+#[derive(Debug)]
+pub struct VaultLocked;
+
+impl std::fmt::Display for VaultLocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "locked")
+    }
+}
+
+impl std::error::Error for VaultLocked {}
+
+// Returned when the frontend sends a master key to a build that no longer accepts one
+#[derive(Debug)]
+pub struct ExplicitKeyRefused;
+
+impl std::fmt::Display for ExplicitKeyRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Master keys are no longer accepted from the frontend; the backend holds the session key")
+    }
+}
+
+impl std::error::Error for ExplicitKeyRefused {}
+
+pub fn issue(vault_key: &[u8; 32], generation: i64) -> String {
+    format!("g{}:{}", generation, general_purpose::STANDARD.encode(vault_key))
+}
+
+// Split a stamped key into its generation and key. Unstamped keys predate sessions and
+// count as generation 0.
+pub fn parse_key(session_key: &str) -> Result<(i64, SecretKey)> {
+    // Base64 never contains ':', so a bare key cannot be mistaken for a stamped one
+    let (generation, key) = match session_key.strip_prefix('g').and_then(|rest| rest.split_once(':')) {
+        Some((generation, key)) => (generation.parse().map_err(|_| anyhow!("Invalid master key"))?, key),
+        None => (0, session_key),
+    };
+    Ok((generation, CryptoService::decode_key(key)?))
+}
+
+// Decode a stamped key, refusing keys from stale sessions
+pub fn open_key(database: &Database, session_key: &str) -> Result<SecretKey> {
+    let (session_generation, key) = parse_key(session_key)?;
+    let current_generation = database.get_user_meta_generation()?;
+    if session_generation != current_generation {
+        return Err(SessionInvalidated { session_generation, current_generation }.into());
+    }
+    Ok(key)
+}
+
+// The unlocked session held by the backend: the vault key from login, the token the
+// frontend knows the session by, and when the last command ran. Its mutex is a leaf: it
+// is only held for these few field updates, never while a command runs against the
+// vault, so it cannot take part in a deadlock.
+pub struct SessionManager {
+    session: Mutex<Session>,
+    // Told whether the vault is now unlocked each time that changes, after the session
+    // mutex is released; the tray icon follows the lock state through it
+    on_change: Mutex<Option<LockStateListener>>,
+}
+
+type LockStateListener = Box<dyn Fn(bool) + Send + Sync>;
+
+struct Session {
+    key: Option<SessionKey>,
+    last_activity: Instant,
+    idle_timeout: Duration,
+    // How many times a key was dropped, by a lock or by a newer login
+    cleared: u64,
+}
+
+struct SessionKey {
+    vault_key: SecretKey,
+    generation: i64,
+    token: String,
+}
+
+impl Session {
+    // Drop the key, which zeroes it. Returns whether there was one.
+    fn clear(&mut self) -> bool {
+        let cleared = self.key.take().is_some();
+        if cleared {
+            self.cleared += 1;
+        }
+        cleared
+    }
+}
+
+impl SessionManager {
+    pub fn new(idle_timeout: Duration) -> Self {
+        SessionManager {
+            session: Mutex::new(Session { key: None, last_activity: Instant::now(), idle_timeout, cleared: 0 }),
+            on_change: Mutex::new(None),
+        }
+    }
+
+    pub fn set_on_change(&self, on_change: impl Fn(bool) + Send + Sync + 'static) {
+        *self.on_change.lock().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(on_change));
+    }
+
+    fn notify(&self, unlocked: bool) {
+        if let Some(on_change) = &*self.on_change.lock().unwrap_or_else(PoisonError::into_inner) {
+            on_change(unlocked);
+        }
+    }
+
+    // Updates are single field writes, so a poisoned session is still consistent
+    fn session(&self) -> MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Start a session for a freshly authenticated stamped key. Returns the new session's
+    // token; a previous session is cleared and its token stops matching.
+    pub fn unlock(&self, session_key: &str) -> Result<String> {
+        let (generation, vault_key) = parse_key(session_key)?;
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        let token = general_purpose::URL_SAFE_NO_PAD.encode(token);
+
+        {
+            let mut session = self.session();
+            session.clear();
+            session.key = Some(SessionKey { vault_key, generation, token: token.clone() });
+            session.last_activity = Instant::now();
+        }
+        self.notify(true);
+        Ok(token)
+    }
+
+    // Zero and drop the key. Returns whether the vault was unlocked.
+    pub fn lock(&self) -> bool {
+        let locked = self.session().clear();
+        if locked {
+            self.notify(false);
+        }
+        locked
+    }
+
+    // Whether `token` belongs to the current, unexpired session
+    pub fn is_current(&self, token: &str, now: Instant) -> bool {
+        self.ensure_unlocked(now).is_ok()
+            && self.session().key.as_ref().is_some_and(|key| key.token == token)
+    }
+
+    // The key a command runs with, stamped as the services expect. A non-empty key from
+    // the frontend is used as is with LEGACY_KEY_IPC and refused without it; either way
+    // the vault must be unlocked, so a key kept by the frontend outlives no lock.
+    pub fn key_for_command(&self, explicit_key: &str, now: Instant) -> Result<String> {
+        self.ensure_unlocked(now)?;
+        if !explicit_key.is_empty() {
+            return if LEGACY_KEY_IPC { Ok(explicit_key.to_string()) } else { Err(ExplicitKeyRefused.into()) };
+        }
+        match &self.session().key {
+            Some(key) => Ok(issue(&key.vault_key, key.generation)),
+            None => Err(VaultLocked.into()),
+        }
+    }
+
+    // Changes whenever the session key is dropped. A command compares it before and after
+    // running to tell whether the vault locked meanwhile, even if it was unlocked again.
+    pub fn epoch(&self) -> u64 {
+        self.session().cleared
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.ensure_unlocked(Instant::now()).is_ok()
+    }
+
+    // Refuse once the key is gone or the idle timeout has run out, even if the idle
+    // timer has not fired yet
+    pub fn ensure_unlocked(&self, now: Instant) -> Result<(), VaultLocked> {
+        let session = self.session();
+        match session.key {
+            Some(_) if now.saturating_duration_since(session.last_activity) < session.idle_timeout => Ok(()),
+            _ => Err(VaultLocked),
+        }
+    }
+
+    // Restart the idle timeout after a successful command
+    pub fn touch(&self, now: Instant) -> Result<(), VaultLocked> {
+        self.ensure_unlocked(now)?;
+        self.session().last_activity = now;
+        Ok(())
+    }
+
+    pub fn set_idle_timeout(&self, idle_timeout: Duration) {
+        self.session().idle_timeout = idle_timeout;
+    }
+
+    // Called by the idle timer. Returns true when this call locked the vault.
+    pub fn lock_if_idle(&self, now: Instant) -> bool {
+        let locked = {
+            let mut session = self.session();
+            session.key.is_some() && now.saturating_duration_since(session.last_activity) >= session.idle_timeout && session.clear()
+        };
+        if locked {
+            self.notify(false);
+        }
+        locked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped_key() -> String {
+        issue(&CryptoService::generate_vault_key(), 3)
+    }
+
+    #[test]
+    fn test_idle_session_locks_and_activity_resets_timer() {
+        let state = SessionManager::new(Duration::from_secs(300));
+        let start = Instant::now();
+        assert!(state.ensure_unlocked(start).is_err());
+
+        state.unlock(&stamped_key()).unwrap();
+        assert!(state.touch(start + Duration::from_secs(200)).is_ok());
+        // 200s after the last command, 400s after login: still open
+        assert!(state.ensure_unlocked(start + Duration::from_secs(400)).is_ok());
+        assert!(!state.lock_if_idle(start + Duration::from_secs(400)));
+
+        // Past the timeout commands are refused before the timer fires, then it locks once
+        let idle = start + Duration::from_secs(500);
+        assert_eq!(state.ensure_unlocked(idle).unwrap_err().to_string(), "locked");
+        assert!(state.lock_if_idle(idle));
+        assert!(!state.lock_if_idle(idle));
+        assert!(state.touch(idle).is_err());
+
+        state.unlock(&stamped_key()).unwrap();
+        assert!(state.lock());
+        assert!(!state.is_unlocked());
+    }
+
+    #[test]
+    fn test_lock_state_changes_are_reported() {
+        let state = SessionManager::new(Duration::from_secs(300));
+        let changes = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let recorded = changes.clone();
+        state.set_on_change(move |unlocked| recorded.lock().unwrap().push(unlocked));
+
+        let start = Instant::now();
+        state.unlock(&stamped_key()).unwrap();
+        assert!(state.lock());
+        assert!(!state.lock());
+        state.unlock(&stamped_key()).unwrap();
+        assert!(state.lock_if_idle(start + Duration::from_secs(600)));
+        assert_eq!(*changes.lock().unwrap(), [true, false, true, false]);
+    }
+
+    #[test]
+    fn test_epoch_moves_on_lock_and_relogin() {
+        let state = SessionManager::new(Duration::from_secs(300));
+        state.unlock(&stamped_key()).unwrap();
+        let epoch = state.epoch();
+        assert!(state.touch(Instant::now()).is_ok());
+        assert_eq!(state.epoch(), epoch);
+
+        // Locked and unlocked again while a command ran: unlocked, but not the same session
+        assert!(state.lock());
+        state.unlock(&stamped_key()).unwrap();
+        assert!(state.is_unlocked());
+        assert_ne!(state.epoch(), epoch);
+
+        // So does a newer login replacing the session
+        let epoch = state.epoch();
+        state.unlock(&stamped_key()).unwrap();
+        assert_ne!(state.epoch(), epoch);
+    }
+
+    #[test]
+    fn test_session_hands_out_a_token_and_keeps_the_key() {
+        let state = SessionManager::new(Duration::from_secs(300));
+        let key = stamped_key();
+        let now = Instant::now();
+        assert_eq!(state.key_for_command("", now).unwrap_err().to_string(), "locked");
+
+        let token = state.unlock(&key).unwrap();
+        assert!(!token.contains(&key[3..]));
+        assert!(state.is_current(&token, now));
+        assert_eq!(state.key_for_command("", now).unwrap(), key);
+
+        // Logging in again starts a new session under a new token
+        let second = state.unlock(&key).unwrap();
+        assert_ne!(token, second);
+        assert!(!state.is_current(&token, now) && state.is_current(&second, now));
+
+        // A key sent by the frontend is only honoured by legacy builds
+        let explicit = state.key_for_command("g3:explicit", now);
+        assert_eq!(explicit.is_ok(), LEGACY_KEY_IPC);
+
+        assert!(state.lock());
+        assert!(!state.is_current(&second, now));
+        assert!(state.key_for_command("", now).is_err());
+        // Not even legacy builds take a key from the frontend while the vault is locked
+        assert_eq!(state.key_for_command("g3:explicit", now).unwrap_err().to_string(), "locked");
+        assert!(state.unlock("not a key").is_err());
+    }
+}
@@ -0,0 +1,519 @@
+use crate::capabilities::{self, Capability, CapabilityRegistry};
+use crate::crypto::KdfParams;
+use crate::database::Database;
+use crate::favicons;
+use crate::os_keychain::KeychainUnlock;
+use crate::pin_unlock::PinUnlock;
+use crate::time_utils;
+use anyhow::{Result, anyhow};
+
+const LOCALE_KEY: &str = "locale";
+const VAULT_SIZE_WARNING_KEY: &str = "vault_size_warning_threshold";
+const CLIPBOARD_PARSING_KEY: &str = "clipboard_parsing_enabled";
+const BREACH_CHECK_KEY: &str = "breach_check_enabled";
+const FAVICON_FETCH_KEY: &str = "favicon_fetch_enabled";
+const FAVICON_SOURCE_KEY: &str = "favicon_source";
+const DRAFT_GRACE_KEY: &str = "draft_grace_minutes";
+const AUTO_LOCK_KEY: &str = "auto_lock_minutes";
+const SCAN_BATCH_SIZE_KEY: &str = "scan_batch_size";
+const PASSWORD_MAX_AGE_KEY: &str = "password_max_age_days";
+const LOGIN_LOCKOUT_KEY: &str = "login_lockout_minutes";
+const KDF_PARAMS_KEY: &str = "kdf_params";
+const ENCRYPT_METADATA_KEY: &str = "encrypt_metadata";
+const LOCK_SHORTCUT_KEY: &str = "lock_shortcut";
+const LOCK_ON_SUSPEND_KEY: &str = "lock_on_suspend";
+const LOCK_ON_SCREEN_LOCK_KEY: &str = "lock_on_screen_lock";
+const NATIVE_HOST_EXTENSIONS_KEY: &str = "native_host_extensions";
+const LAST_BACKUP_KEY: &str = "last_backup_at";
+const ATTACHMENT_MAX_BYTES_KEY: &str = "attachment_max_bytes";
+const OS_KEYCHAIN_UNLOCK_KEY: &str = "os_keychain_unlock";
+const PIN_UNLOCK_KEY: &str = "pin_unlock";
+const PANIC_WIPE_KEY: &str = "panic_wipe_threshold";
+
+// What a user picks on the settings page, as opposed to state kept about this vault's own
+// data (metadata encryption, the last backup, keychain and PIN unlock, the native host allowlist)
+const PREFERENCE_KEYS: &[&str] = &[
+    LOCALE_KEY,
+    VAULT_SIZE_WARNING_KEY,
+    CLIPBOARD_PARSING_KEY,
+    BREACH_CHECK_KEY,
+    FAVICON_FETCH_KEY,
+    FAVICON_SOURCE_KEY,
+    DRAFT_GRACE_KEY,
+    AUTO_LOCK_KEY,
+    SCAN_BATCH_SIZE_KEY,
+    PASSWORD_MAX_AGE_KEY,
+    LOGIN_LOCKOUT_KEY,
+    KDF_PARAMS_KEY,
+    LOCK_SHORTCUT_KEY,
+    LOCK_ON_SUSPEND_KEY,
+    LOCK_ON_SCREEN_LOCK_KEY,
+    ATTACHMENT_MAX_BYTES_KEY,
+    PANIC_WIPE_KEY,
+];
+
+// List endpoints warn once the vault holds more entries than this
+pub const DEFAULT_VAULT_SIZE_WARNING: usize = 10_000;
+
+// Entry drafts untouched for this long are purged when the vault locks
+pub const DEFAULT_DRAFT_GRACE_MINUTES: u32 = 60;
+const MAX_DRAFT_GRACE_MINUTES: u32 = 7 * 24 * 60;
+
+// The vault locks itself after this long without a command
+pub const DEFAULT_AUTO_LOCK_MINUTES: u32 = 5;
+const MAX_AUTO_LOCK_MINUTES: u32 = 24 * 60;
+
+// Vault-wide passes (re-encryption, health report, search) load and decrypt this many entries at a time
+pub const DEFAULT_SCAN_BATCH_SIZE: usize = 500;
+const MAX_SCAN_BATCH_SIZE: usize = 10_000;
+
+// The vault audit reports passwords unchanged for longer than this
+pub const DEFAULT_PASSWORD_MAX_AGE_DAYS: u32 = 365;
+const MAX_PASSWORD_MAX_AGE_DAYS: u32 = 10 * 365;
+
+// Largest file add_attachment accepts. Attachments are read into memory whole and carried
+// in exports as base64, so the cap stays small.
+pub const DEFAULT_ATTACHMENT_MAX_BYTES: u64 = 1024 * 1024;
+const MAX_ATTACHMENT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+// Login and recovery are refused for this long once too many attempts in a row failed
+pub const DEFAULT_LOGIN_LOCKOUT_MINUTES: u32 = 5;
+const MAX_LOGIN_LOCKOUT_MINUTES: u32 = 24 * 60;
+
+// Range of failed unlock attempts in a row after which the vault wipes itself, when turned on
+pub const MIN_PANIC_WIPE_THRESHOLD: u32 = 5;
+const MAX_PANIC_WIPE_THRESHOLD: u32 = 1000;
+
+// Global shortcut that locks the vault from any application (desktop only)
+pub const DEFAULT_LOCK_SHORTCUT: &str = "CmdOrCtrl+Shift+L";
+const MAX_LOCK_SHORTCUT_LENGTH: usize = 64;
+
+// Longest browser extension id accepted into the native host allowlist
+const MAX_EXTENSION_ID_LENGTH: usize = 128;
+
+pub struct SettingsService {
+    database: Database,
+}
+
+impl SettingsService {
+    pub fn new(database: Database) -> Self {
+        SettingsService { database }
+    }
+
+    // Give `target` the same preferences, for a decoy vault that should look like this one
+    pub fn copy_preferences_to(&self, target: &Database) -> Result<()> {
+        for key in PREFERENCE_KEYS {
+            match self.database.get_setting(key)? {
+                Some(value) => target.set_setting(key, &value)?,
+                None => target.delete_setting(key)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Read the display locale from any service's database handle
+    pub fn locale_from(database: &Database) -> Result<String> {
+        Ok(database
+            .get_setting(LOCALE_KEY)?
+            .unwrap_or_else(|| time_utils::DEFAULT_LOCALE.to_string()))
+    }
+
+    // Locale used to render display timestamps in responses
+    pub fn get_locale(&self) -> Result<String> {
+        Self::locale_from(&self.database)
+    }
+
+    pub fn set_locale(&self, locale: &str) -> Result<String> {
+        let locale = locale.trim();
+        if locale.is_empty() || !locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow!("Invalid locale: {}", locale));
+        }
+
+        self.database.set_setting(LOCALE_KEY, locale)?;
+        Ok(locale.to_string())
+    }
+
+    // Entry count past which list endpoints add a performance warning
+    pub fn vault_size_warning_threshold_from(database: &Database) -> Result<usize> {
+        match database.get_setting(VAULT_SIZE_WARNING_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_VAULT_SIZE_WARNING),
+        }
+    }
+
+    pub fn get_vault_size_warning_threshold(&self) -> Result<usize> {
+        Self::vault_size_warning_threshold_from(&self.database)
+    }
+
+    pub fn set_vault_size_warning_threshold(&self, threshold: usize) -> Result<usize> {
+        if threshold == 0 {
+            return Err(anyhow!("Vault size warning threshold must be positive"));
+        }
+
+        self.database.set_setting(VAULT_SIZE_WARNING_KEY, &threshold.to_string())?;
+        Ok(threshold)
+    }
+
+    // Whether "create entry from clipboard" may read the clipboard at all; on by default
+    pub fn get_clipboard_parsing_enabled(&self) -> Result<bool> {
+        Ok(self.database.get_setting(CLIPBOARD_PARSING_KEY)?.as_deref() != Some("false"))
+    }
+
+    pub fn set_clipboard_parsing_enabled(&self, enabled: bool) -> Result<bool> {
+        self.database.set_setting(CLIPBOARD_PARSING_KEY, &enabled.to_string())?;
+        Ok(enabled)
+    }
+
+    // Off unless turned on, like every feature that goes online
+    pub fn get_breach_check_enabled(&self) -> Result<bool> {
+        Ok(self.database.get_setting(BREACH_CHECK_KEY)?.as_deref() == Some("true"))
+    }
+
+    pub fn set_breach_check_enabled(&self, enabled: bool) -> Result<bool> {
+        self.database.set_setting(BREACH_CHECK_KEY, &enabled.to_string())?;
+        Ok(enabled)
+    }
+
+    // Off unless turned on, since fetching an icon tells the source which hosts are in the vault
+    pub fn get_favicon_fetch_enabled(&self) -> Result<bool> {
+        Ok(self.database.get_setting(FAVICON_FETCH_KEY)?.as_deref() == Some("true"))
+    }
+
+    // Turning fetching off also drops the icons fetched so far
+    pub fn set_favicon_fetch_enabled(&self, enabled: bool) -> Result<bool> {
+        self.database.set_setting(FAVICON_FETCH_KEY, &enabled.to_string())?;
+        if !enabled {
+            self.database.clear_host_icons()?;
+        }
+        Ok(enabled)
+    }
+
+    pub fn get_favicon_source(&self) -> Result<String> {
+        Ok(self.database.get_setting(FAVICON_SOURCE_KEY)?.unwrap_or_else(|| favicons::DEFAULT_FAVICON_SOURCE.to_string()))
+    }
+
+    // An https:// URL with "{host}" where the entry's host goes. Icons cached from the old
+    // source are dropped so the new one is asked.
+    pub fn set_favicon_source(&self, source: &str) -> Result<String> {
+        let source = source.trim();
+        favicons::validate_source(source)?;
+        if self.get_favicon_source()? != source {
+            self.database.set_setting(FAVICON_SOURCE_KEY, source)?;
+            self.database.clear_host_icons()?;
+        }
+        Ok(source.to_string())
+    }
+
+    pub fn draft_grace_minutes_from(database: &Database) -> Result<u32> {
+        match database.get_setting(DRAFT_GRACE_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_DRAFT_GRACE_MINUTES),
+        }
+    }
+
+    pub fn get_draft_grace_minutes(&self) -> Result<u32> {
+        Self::draft_grace_minutes_from(&self.database)
+    }
+
+    // 0 purges every draft on lock
+    pub fn set_draft_grace_minutes(&self, minutes: u32) -> Result<u32> {
+        if minutes > MAX_DRAFT_GRACE_MINUTES {
+            return Err(anyhow!("Draft grace period cannot exceed 7 days"));
+        }
+
+        self.database.set_setting(DRAFT_GRACE_KEY, &minutes.to_string())?;
+        Ok(minutes)
+    }
+
+    pub fn get_auto_lock_minutes(&self) -> Result<u32> {
+        match self.database.get_setting(AUTO_LOCK_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_AUTO_LOCK_MINUTES),
+        }
+    }
+
+    pub fn set_auto_lock_minutes(&self, minutes: u32) -> Result<u32> {
+        if minutes == 0 || minutes > MAX_AUTO_LOCK_MINUTES {
+            return Err(anyhow!("Auto-lock timeout must be between 1 and {} minutes", MAX_AUTO_LOCK_MINUTES));
+        }
+
+        self.database.set_setting(AUTO_LOCK_KEY, &minutes.to_string())?;
+        Ok(minutes)
+    }
+
+    pub fn scan_batch_size_from(database: &Database) -> Result<usize> {
+        match database.get_setting(SCAN_BATCH_SIZE_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_SCAN_BATCH_SIZE),
+        }
+    }
+
+    pub fn get_scan_batch_size(&self) -> Result<usize> {
+        Self::scan_batch_size_from(&self.database)
+    }
+
+    pub fn set_scan_batch_size(&self, batch_size: usize) -> Result<usize> {
+        if batch_size == 0 || batch_size > MAX_SCAN_BATCH_SIZE {
+            return Err(anyhow!("Scan batch size must be between 1 and {}", MAX_SCAN_BATCH_SIZE));
+        }
+
+        self.database.set_setting(SCAN_BATCH_SIZE_KEY, &batch_size.to_string())?;
+        Ok(batch_size)
+    }
+
+    pub fn password_max_age_days_from(database: &Database) -> Result<u32> {
+        match database.get_setting(PASSWORD_MAX_AGE_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_PASSWORD_MAX_AGE_DAYS),
+        }
+    }
+
+    pub fn get_password_max_age_days(&self) -> Result<u32> {
+        Self::password_max_age_days_from(&self.database)
+    }
+
+    pub fn set_password_max_age_days(&self, days: u32) -> Result<u32> {
+        if days == 0 || days > MAX_PASSWORD_MAX_AGE_DAYS {
+            return Err(anyhow!("Password age limit must be between 1 and {} days", MAX_PASSWORD_MAX_AGE_DAYS));
+        }
+
+        self.database.set_setting(PASSWORD_MAX_AGE_KEY, &days.to_string())?;
+        Ok(days)
+    }
+
+    pub fn attachment_max_bytes_from(database: &Database) -> Result<u64> {
+        match database.get_setting(ATTACHMENT_MAX_BYTES_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_ATTACHMENT_MAX_BYTES),
+        }
+    }
+
+    pub fn get_attachment_max_bytes(&self) -> Result<u64> {
+        Self::attachment_max_bytes_from(&self.database)
+    }
+
+    // Only applies to files added from now on
+    pub fn set_attachment_max_bytes(&self, bytes: u64) -> Result<u64> {
+        if bytes == 0 || bytes > MAX_ATTACHMENT_MAX_BYTES {
+            return Err(anyhow!("Attachment size limit must be between 1 byte and {} MiB", MAX_ATTACHMENT_MAX_BYTES / (1024 * 1024)));
+        }
+
+        self.database.set_setting(ATTACHMENT_MAX_BYTES_KEY, &bytes.to_string())?;
+        Ok(bytes)
+    }
+
+    pub fn login_lockout_minutes_from(database: &Database) -> Result<u32> {
+        match database.get_setting(LOGIN_LOCKOUT_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_LOGIN_LOCKOUT_MINUTES),
+        }
+    }
+
+    pub fn get_login_lockout_minutes(&self) -> Result<u32> {
+        Self::login_lockout_minutes_from(&self.database)
+    }
+
+    pub fn set_login_lockout_minutes(&self, minutes: u32) -> Result<u32> {
+        if minutes == 0 || minutes > MAX_LOGIN_LOCKOUT_MINUTES {
+            return Err(anyhow!("Login lockout must be between 1 and {} minutes", MAX_LOGIN_LOCKOUT_MINUTES));
+        }
+
+        self.database.set_setting(LOGIN_LOCKOUT_KEY, &minutes.to_string())?;
+        Ok(minutes)
+    }
+
+    // Failed unlock attempts in a row that wipe the vault; None (the default) when the
+    // panic wipe is off. See VaultCoordinator::set_panic_wipe_threshold.
+    pub fn panic_wipe_threshold_from(database: &Database) -> Result<Option<u32>> {
+        match database.get_setting(PANIC_WIPE_KEY)? {
+            Some(value) => Ok(Some(value.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_panic_wipe_threshold(&self) -> Result<Option<u32>> {
+        Self::panic_wipe_threshold_from(&self.database)
+    }
+
+    // Only records the threshold; VaultCoordinator::set_panic_wipe_threshold checks the master password first
+    pub fn set_panic_wipe_threshold(&self, threshold: Option<u32>) -> Result<Option<u32>> {
+        match threshold {
+            Some(threshold) => {
+                if !(MIN_PANIC_WIPE_THRESHOLD..=MAX_PANIC_WIPE_THRESHOLD).contains(&threshold) {
+                    return Err(anyhow!(
+                        "The panic wipe threshold must be between {} and {} failed attempts",
+                        MIN_PANIC_WIPE_THRESHOLD,
+                        MAX_PANIC_WIPE_THRESHOLD
+                    ));
+                }
+                self.database.set_setting(PANIC_WIPE_KEY, &threshold.to_string())?;
+            }
+            None => self.database.delete_setting(PANIC_WIPE_KEY)?,
+        }
+        Ok(threshold)
+    }
+
+    // Argon2 parameters new hashes and keys are made with; older ones are upgraded on login
+    pub fn kdf_params_from(database: &Database) -> Result<KdfParams> {
+        match database.get_setting(KDF_PARAMS_KEY)? {
+            Some(value) => Ok(serde_json::from_str(&value)?),
+            None => Ok(KdfParams::default()),
+        }
+    }
+
+    pub fn get_kdf_params(&self) -> Result<KdfParams> {
+        Self::kdf_params_from(&self.database)
+    }
+
+    // Only records the target; VaultCoordinator::set_kdf_params also re-hashes the master password
+    pub fn set_kdf_params(&self, params: KdfParams) -> Result<KdfParams> {
+        params.validate()?;
+
+        self.database.set_setting(KDF_PARAMS_KEY, &serde_json::to_string(&params)?)?;
+        Ok(params)
+    }
+
+    // Whether entries are saved with their software and account encrypted. Turned on, for
+    // good, by PasswordService::encrypt_metadata or by importing a backup made with it on.
+    pub fn encrypt_metadata_from(database: &Database) -> Result<bool> {
+        Ok(database.get_setting(ENCRYPT_METADATA_KEY)?.as_deref() == Some("true"))
+    }
+
+    pub fn enable_encrypt_metadata(database: &Database) -> Result<()> {
+        database.set_setting(ENCRYPT_METADATA_KEY, "true")
+    }
+
+    pub fn get_encrypt_metadata(&self) -> Result<bool> {
+        Self::encrypt_metadata_from(&self.database)
+    }
+
+    // When ExportService::create_backup last wrote a backup (UTC RFC3339), None if never
+    pub fn last_backup_at_from(database: &Database) -> Result<Option<String>> {
+        database.get_setting(LAST_BACKUP_KEY)
+    }
+
+    pub fn record_backup(database: &Database, at: &str) -> Result<()> {
+        database.set_setting(LAST_BACKUP_KEY, at)
+    }
+
+    // An empty accelerator turns the shortcut off
+    pub fn get_lock_shortcut(&self) -> Result<String> {
+        Ok(self
+            .database
+            .get_setting(LOCK_SHORTCUT_KEY)?
+            .unwrap_or_else(|| DEFAULT_LOCK_SHORTCUT.to_string()))
+    }
+
+    // Only the length is checked here; whether the accelerator parses and is free is
+    // found out when it is registered
+    pub fn set_lock_shortcut(&self, accelerator: &str) -> Result<String> {
+        let accelerator = accelerator.trim();
+        if accelerator.len() > MAX_LOCK_SHORTCUT_LENGTH {
+            return Err(anyhow!("Shortcut cannot be longer than {} characters", MAX_LOCK_SHORTCUT_LENGTH));
+        }
+
+        self.database.set_setting(LOCK_SHORTCUT_KEY, accelerator)?;
+        Ok(accelerator.to_string())
+    }
+
+    // Lock the vault when the machine suspends; on by default
+    pub fn get_lock_on_suspend(&self) -> Result<bool> {
+        Ok(self.database.get_setting(LOCK_ON_SUSPEND_KEY)?.as_deref() != Some("false"))
+    }
+
+    pub fn set_lock_on_suspend(&self, enabled: bool) -> Result<bool> {
+        self.database.set_setting(LOCK_ON_SUSPEND_KEY, &enabled.to_string())?;
+        Ok(enabled)
+    }
+
+    // Lock the vault when the OS session locks; on by default, where that can be detected
+    pub fn get_lock_on_screen_lock(&self) -> Result<bool> {
+        Ok(self.database.get_setting(LOCK_ON_SCREEN_LOCK_KEY)?.as_deref() != Some("false"))
+    }
+
+    pub fn set_lock_on_screen_lock(&self, enabled: bool) -> Result<bool> {
+        self.database.set_setting(LOCK_ON_SCREEN_LOCK_KEY, &enabled.to_string())?;
+        Ok(enabled)
+    }
+
+    // The wrapped vault key for unlocking with the OS keychain, None while that is off.
+    // Only VaultCoordinator changes it, since the keychain item has to change along with it.
+    pub fn get_os_keychain_unlock(&self) -> Result<Option<KeychainUnlock>> {
+        match self.database.get_setting(OS_KEYCHAIN_UNLOCK_KEY)? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_os_keychain_unlock(&self, unlock: Option<&KeychainUnlock>) -> Result<()> {
+        match unlock {
+            Some(unlock) => self.database.set_setting(OS_KEYCHAIN_UNLOCK_KEY, &serde_json::to_string(unlock)?),
+            None => self.database.delete_setting(OS_KEYCHAIN_UNLOCK_KEY),
+        }
+    }
+
+    // The PIN-wrapped vault key and its failed attempts, None while PIN unlock is off. Only
+    // VaultCoordinator changes it, like the keychain unlock above.
+    pub fn get_pin_unlock(&self) -> Result<Option<PinUnlock>> {
+        match self.database.get_setting(PIN_UNLOCK_KEY)? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_pin_unlock(&self, unlock: Option<&PinUnlock>) -> Result<()> {
+        match unlock {
+            Some(unlock) => self.database.set_setting(PIN_UNLOCK_KEY, &serde_json::to_string(unlock)?),
+            None => self.database.delete_setting(PIN_UNLOCK_KEY),
+        }
+    }
+
+    // Browser extensions the native host answers, as a JSON list of ids. Empty (the
+    // default) turns the native host off.
+    pub fn get_native_host_extensions(&self) -> Result<Vec<String>> {
+        match self.database.get_setting(NATIVE_HOST_EXTENSIONS_KEY)? {
+            Some(value) => Ok(serde_json::from_str(&value)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Chrome ids are 32 letters, Firefox ids an email-like name or a {UUID}; anything with
+    // other characters cannot be either
+    pub fn set_native_host_extensions(&self, extension_ids: &[String]) -> Result<Vec<String>> {
+        let mut cleaned: Vec<String> = Vec::new();
+        for id in extension_ids.iter().map(|id| id.trim()).filter(|id| !id.is_empty()) {
+            let valid = id.len() <= MAX_EXTENSION_ID_LENGTH
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || "@._-{}".contains(c));
+            if !valid {
+                return Err(anyhow!("Not a browser extension id: {}", id));
+            }
+            if !cleaned.iter().any(|existing| existing == id) {
+                cleaned.push(id.to_string());
+            }
+        }
+
+        self.database.set_setting(NATIVE_HOST_EXTENSIONS_KEY, &serde_json::to_string(&cleaned)?)?;
+        Ok(cleaned)
+    }
+
+    pub fn register_capabilities(&self, registry: &mut CapabilityRegistry) -> Result<()> {
+        registry.register(capabilities::CLIPBOARD_PARSING, Capability::available(self.get_clipboard_parsing_enabled()?));
+        registry.register(capabilities::BREACH_CHECK, Capability::available(self.get_breach_check_enabled()?));
+        registry.register(capabilities::FAVICONS, Capability::available(self.get_favicon_fetch_enabled()?));
+        if cfg!(target_os = "linux") {
+            registry.register(capabilities::LOCK_ON_SCREEN_LOCK, Capability::available(self.get_lock_on_screen_lock()?));
+        } else {
+            registry.register(capabilities::LOCK_ON_SCREEN_LOCK, Capability::unavailable("Screen locks are not detected on this platform"));
+        }
+        if cfg!(feature = "os-keychain") {
+            registry.register(capabilities::OS_KEYCHAIN_UNLOCK, Capability::available(self.get_os_keychain_unlock()?.is_some()));
+            registry.register(capabilities::PIN_UNLOCK, Capability::available(self.get_pin_unlock()?.is_some()));
+        }
+        if cfg!(desktop) {
+            registry.register(capabilities::NATIVE_MESSAGING, Capability::available(!self.get_native_host_extensions()?.is_empty()));
+        } else {
+            registry.register(capabilities::NATIVE_MESSAGING, Capability::unavailable("Browsers cannot reach apps on this platform"));
+        }
+        Ok(())
+    }
+}
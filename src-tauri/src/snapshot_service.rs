@@ -0,0 +1,282 @@
+use crate::crypto::CryptoService;
+use crate::database::{Database, PasswordEntry};
+use crate::session;
+use crate::time_utils;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+const SNAPSHOT_INTERVAL_KEY: &str = "snapshot_interval_days";
+const DEFAULT_SNAPSHOT_INTERVAL_DAYS: u32 = 7;
+
+// Snapshots kept in the ring; older ones are deleted after each new snapshot
+pub const SNAPSHOTS_KEPT: usize = 4;
+
+// Snapshot files use their own prefix so user backup retention (cleanup_old_backups,
+// which only matches "pwdbox_backup_") never touches them
+const SNAPSHOT_PREFIX: &str = "pwdbox_snapshot_";
+const SNAPSHOT_EXTENSION: &str = ".snap";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub file_name: String,
+    pub created_at: String,
+    pub entry_count: usize,
+}
+
+// On-disk layout: plaintext header for listing, entries encrypted with the vault key
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    created_at: String,
+    entry_count: usize,
+    fingerprint: String,
+    nonce: String,
+    data: String,
+}
+
+// Automatic local safety net, independent of user backups: at most one encrypted
+// snapshot per interval, taken only when the vault changed since the last one.
+pub struct SnapshotService {
+    database: Database,
+    snapshots_dir: PathBuf,
+}
+
+impl SnapshotService {
+    pub fn new(database: Database, snapshots_dir: PathBuf) -> Self {
+        SnapshotService { database, snapshots_dir }
+    }
+
+    pub fn get_interval_days(&self) -> Result<u32> {
+        match self.database.get_setting(SNAPSHOT_INTERVAL_KEY)? {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(DEFAULT_SNAPSHOT_INTERVAL_DAYS),
+        }
+    }
+
+    pub fn set_interval_days(&self, days: u32) -> Result<u32> {
+        if days == 0 || days > 365 {
+            return Err(anyhow!("Snapshot interval must be between 1 and 365 days"));
+        }
+
+        self.database.set_setting(SNAPSHOT_INTERVAL_KEY, &days.to_string())?;
+        Ok(days)
+    }
+
+    // Snapshots in the ring, newest first
+    pub fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        Ok(self.read_snapshots()?.into_iter().map(|(info, _)| info).collect())
+    }
+
+    // Called after unlocking, when the vault key is at hand
+    pub fn take_snapshot_if_due(&self, vault_key: &str) -> Result<Option<SnapshotInfo>> {
+        self.take_snapshot_if_due_at(&time_utils::now(), &*session::open_key(&self.database, vault_key)?)
+    }
+
+    fn take_snapshot_if_due_at(&self, now: &DateTime<Utc>, vault_key: &[u8; 32]) -> Result<Option<SnapshotInfo>> {
+        let entries = self.current_entries()?;
+        let fingerprint = vault_fingerprint(&entries)?;
+
+        let snapshots = self.read_snapshots()?;
+        if let Some((latest, latest_fingerprint)) = snapshots.first() {
+            let interval = Duration::days(self.get_interval_days()? as i64);
+            let due = time_utils::parse_rfc3339(&latest.created_at).map_or(true, |taken| *now - taken >= interval);
+            if !due || *latest_fingerprint == fingerprint {
+                return Ok(None);
+            }
+        }
+
+        let nonce = CryptoService::generate_nonce();
+        let snapshot = SnapshotFile {
+            version: 1,
+            created_at: time_utils::to_rfc3339(now),
+            entry_count: entries.len(),
+            fingerprint,
+            data: CryptoService::encrypt_data(&serde_json::to_string(&entries)?, vault_key, &nonce)?,
+            nonce,
+        };
+        let file_name = format!("{}{}{}", SNAPSHOT_PREFIX, time_utils::file_stamp(now), SNAPSHOT_EXTENSION);
+        fs::create_dir_all(&self.snapshots_dir)?;
+        fs::write(self.snapshots_dir.join(&file_name), serde_json::to_string(&snapshot)?)?;
+
+        // Trim the ring; the snapshot just written is the newest
+        for (old, _) in self.read_snapshots()?.iter().skip(SNAPSHOTS_KEPT) {
+            fs::remove_file(self.snapshots_dir.join(&old.file_name))?;
+        }
+
+        Ok(Some(SnapshotInfo {
+            file_name,
+            created_at: snapshot.created_at,
+            entry_count: snapshot.entry_count,
+        }))
+    }
+
+    // Replace every entry with the snapshot's. The user meta (and with it the current
+    // master password) is kept, so only snapshots taken with this vault key can be restored.
+    pub fn restore_snapshot(&self, file_name: &str, vault_key: &str) -> Result<usize> {
+        if !is_snapshot_file_name(file_name) {
+            return Err(anyhow!("Unknown snapshot: {}", file_name));
+        }
+        let path = self.snapshots_dir.join(file_name);
+        if !path.is_file() {
+            return Err(anyhow!("Unknown snapshot: {}", file_name));
+        }
+
+        let snapshot: SnapshotFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let vault_key = session::open_key(&self.database, vault_key)?;
+        let data = CryptoService::decrypt_data(&snapshot.data, &vault_key, &snapshot.nonce)
+            .map_err(|_| anyhow!("Snapshot was taken with a different vault key"))?;
+        let entries: Vec<PasswordEntry> = serde_json::from_str(&data)?;
+
+        self.database.replace_password_entries(&entries)?;
+        Ok(entries.len())
+    }
+
+    // Entries (trash included) with their icons and tags, as a snapshot stores them
+    fn current_entries(&self) -> Result<Vec<PasswordEntry>> {
+        let mut entries = self.database.get_all_password_entries_including_trash()?;
+        let mut icons = self.database.get_entry_icons()?;
+        let mut tags = self.database.get_entry_tags()?;
+        for entry in &mut entries {
+            if let Some(id) = entry.id {
+                entry.icon = icons.remove(&id);
+                entry.tags = tags.remove(&id).unwrap_or_default();
+            }
+        }
+        Ok(entries)
+    }
+
+    // Snapshot headers and fingerprints, newest first. Unreadable files are skipped.
+    fn read_snapshots(&self) -> Result<Vec<(SnapshotInfo, String)>> {
+        if !self.snapshots_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut snapshots = Vec::new();
+        for dir_entry in fs::read_dir(&self.snapshots_dir)? {
+            let path = dir_entry?.path();
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()).filter(|n| is_snapshot_file_name(n)) else {
+                continue;
+            };
+            let Some(snapshot) = fs::read_to_string(&path).ok()
+                .and_then(|text| serde_json::from_str::<SnapshotFile>(&text).ok())
+            else {
+                continue;
+            };
+            snapshots.push((
+                SnapshotInfo {
+                    file_name: file_name.to_string(),
+                    created_at: snapshot.created_at,
+                    entry_count: snapshot.entry_count,
+                },
+                snapshot.fingerprint,
+            ));
+        }
+        // RFC3339 UTC timestamps sort chronologically as text
+        snapshots.sort_by(|a, b| b.0.created_at.cmp(&a.0.created_at));
+        Ok(snapshots)
+    }
+}
+
+fn is_snapshot_file_name(file_name: &str) -> bool {
+    file_name.starts_with(SNAPSHOT_PREFIX)
+        && file_name.ends_with(SNAPSHOT_EXTENSION)
+        && !file_name.contains(['/', '\\'])
+        && !file_name.contains("..")
+}
+
+// Content hash of the vault's entries. Usage tracking is left out: revealing a
+// password is not a change worth a new snapshot.
+fn vault_fingerprint(entries: &[PasswordEntry]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        let entry = PasswordEntry { last_used_at: None, ..entry.clone() };
+        hasher.update(serde_json::to_vec(&entry)?);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(dir: &tempfile::TempDir) -> SnapshotService {
+        SnapshotService::new(
+            Database::new(dir.path().join("pwdbox.db")).unwrap(),
+            dir.path().join("snapshots"),
+        )
+    }
+
+    fn add(service: &SnapshotService, software: &str, key: &[u8; 32]) -> i64 {
+        let (encrypted_password, nonce) = CryptoService::encrypt_password("secret", key, None).unwrap();
+        service.database.insert_password_entry(&PasswordEntry {
+            software: software.to_string(),
+            account: "me@example.com".to_string(),
+            encrypted_password,
+            nonce,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn at(days: i64) -> DateTime<Utc> {
+        time_utils::parse_rfc3339("2024-01-01T12:00:00Z").unwrap() + Duration::days(days)
+    }
+
+    #[test]
+    fn test_snapshots_are_weekly_skip_unchanged_and_keep_four() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = service(&dir);
+        let key = CryptoService::generate_vault_key();
+        add(&service, "mail", &key);
+
+        assert!(service.take_snapshot_if_due_at(&at(0), &key).unwrap().is_some());
+        // Not due yet, even though the vault changed
+        add(&service, "bank", &key);
+        assert!(service.take_snapshot_if_due_at(&at(3), &key).unwrap().is_none());
+        assert!(service.take_snapshot_if_due_at(&at(7), &key).unwrap().is_some());
+        // Due, but nothing changed; usage tracking does not count as a change
+        service.database.touch_password_entry(1, "2024-01-09T00:00:00Z").unwrap();
+        assert!(service.take_snapshot_if_due_at(&at(14), &key).unwrap().is_none());
+
+        for week in 3..8 {
+            add(&service, &format!("site{}", week), &key);
+            assert!(service.take_snapshot_if_due_at(&at(week * 7), &key).unwrap().is_some());
+        }
+        let snapshots = service.list_snapshots().unwrap();
+        assert_eq!(snapshots.len(), SNAPSHOTS_KEPT);
+        assert_eq!(snapshots[0].created_at, time_utils::to_rfc3339(&at(49)));
+        assert_eq!(snapshots[0].entry_count, 7);
+
+        // A user backup sitting in the same directory is neither listed nor removed
+        fs::write(dir.path().join("snapshots").join("pwdbox_backup_20240101_000000.enc"), "backup").unwrap();
+        assert_eq!(service.list_snapshots().unwrap().len(), SNAPSHOTS_KEPT);
+    }
+
+    #[test]
+    fn test_restore_replaces_entries_and_needs_the_vault_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = service(&dir);
+        let key = CryptoService::generate_vault_key();
+        let encoded_key = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &key);
+        let mail = add(&service, "mail", &key);
+        service.database.add_tag_to_entry(mail, "work").unwrap();
+        let snapshot = service.take_snapshot_if_due_at(&at(0), &key).unwrap().unwrap();
+
+        service.database.delete_password_entry(mail).unwrap();
+        add(&service, "bank", &key);
+
+        let other_key = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, CryptoService::generate_vault_key());
+        assert!(service.restore_snapshot(&snapshot.file_name, &other_key).is_err());
+        assert!(service.restore_snapshot("../pwdbox.db", &encoded_key).is_err());
+
+        assert_eq!(service.restore_snapshot(&snapshot.file_name, &encoded_key).unwrap(), 1);
+        let entries = service.database.get_all_password_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].software, "mail");
+        assert_eq!(CryptoService::decrypt_password(&entries[0].encrypted_password, &entries[0].nonce, &key, entries[0].entry_uid.as_deref()).unwrap(), "secret");
+        assert_eq!(service.database.get_tags_for_entry(entries[0].id.unwrap()).unwrap(), vec!["work"]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// OS events after which the vault should not stay open. lib.rs locks on them unless the
+// matching setting (lock_on_suspend, lock_on_screen_lock) is off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemEvent {
+    Suspend,
+    ScreenLocked,
+}
+
+// How often the wall clock is read to notice the machine slept
+const SLEEP_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// How far the wall clock may run ahead of a check interval before that counts as sleep
+const SLEEP_TOLERANCE: Duration = Duration::from_secs(10);
+
+// Start the watchers; each runs on its own thread for the life of the app.
+//
+// Sleep is noticed everywhere by the wall clock jumping ahead between two checks, as the
+// thread does not run while the machine sleeps. That lock comes on resume, before anyone
+// can use the window. On Linux logind also announces sleep before it happens, and the
+// screen saver reports screen locks over D-Bus; other platforms have no screen lock event
+// we can listen to without a native event loop.
+pub fn watch(on_event: impl Fn(SystemEvent) + Send + Sync + 'static) {
+    let on_event: Arc<dyn Fn(SystemEvent) + Send + Sync> = Arc::new(on_event);
+    let handler = on_event.clone();
+    thread::spawn(move || watch_wall_clock(&*handler));
+    #[cfg(target_os = "linux")]
+    linux::watch(on_event);
+}
+
+fn watch_wall_clock(on_event: &(dyn Fn(SystemEvent) + Send + Sync)) {
+    let mut last_check = SystemTime::now();
+    loop {
+        thread::sleep(SLEEP_CHECK_INTERVAL);
+        let now = SystemTime::now();
+        if slept_between(last_check, now) {
+            on_event(SystemEvent::Suspend);
+        }
+        last_check = now;
+    }
+}
+
+// A clock set forward by hand looks the same as sleep; locking then is harmless
+fn slept_between(last_check: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(last_check)
+        .is_ok_and(|elapsed| elapsed > SLEEP_CHECK_INTERVAL + SLEEP_TOLERANCE)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SystemEvent;
+    use std::sync::Arc;
+    use std::thread;
+    use zbus::blocking::{Connection, Proxy};
+
+    type Handler = Arc<dyn Fn(SystemEvent) + Send + Sync>;
+
+    // GNOME has its own screen saver interface; KDE, Xfce and most others the freedesktop one
+    const SCREEN_SAVERS: [(&str, &str, &str); 2] = [
+        ("org.gnome.ScreenSaver", "/org/gnome/ScreenSaver", "org.gnome.ScreenSaver"),
+        ("org.freedesktop.ScreenSaver", "/org/freedesktop/ScreenSaver", "org.freedesktop.ScreenSaver"),
+    ];
+
+    // A missing bus or service only means that event is not reported; it is logged once
+    pub fn watch(on_event: Handler) {
+        let handler = on_event.clone();
+        thread::spawn(move || {
+            if let Err(e) = watch_sleep(&handler) {
+                log::info!("Not watching logind for sleep: {}", e);
+            }
+        });
+        for (service, path, interface) in SCREEN_SAVERS {
+            let handler = on_event.clone();
+            thread::spawn(move || {
+                if let Err(e) = watch_screen_saver(service, path, interface, &handler) {
+                    log::info!("Not watching {} for screen locks: {}", service, e);
+                }
+            });
+        }
+    }
+
+    // PrepareForSleep(true) comes just before the machine sleeps, false after it wakes
+    fn watch_sleep(on_event: &Handler) -> zbus::Result<()> {
+        let connection = Connection::system()?;
+        let proxy = Proxy::new(&connection, "org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager")?;
+        for signal in proxy.receive_signal("PrepareForSleep")? {
+            if signal.body().deserialize::<bool>()? {
+                on_event(SystemEvent::Suspend);
+            }
+        }
+        Ok(())
+    }
+
+    // ActiveChanged(true) when the screen saver, and with it the lock screen, comes on
+    fn watch_screen_saver(service: &str, path: &str, interface: &str, on_event: &Handler) -> zbus::Result<()> {
+        let connection = Connection::session()?;
+        let proxy = Proxy::new(&connection, service, path, interface)?;
+        for signal in proxy.receive_signal("ActiveChanged")? {
+            if signal.body().deserialize::<bool>()? {
+                on_event(SystemEvent::ScreenLocked);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wall_clock_jump_counts_as_sleep() {
+        let start = SystemTime::now();
+        assert!(!slept_between(start, start + SLEEP_CHECK_INTERVAL));
+        assert!(!slept_between(start, start + SLEEP_CHECK_INTERVAL + Duration::from_secs(3)));
+        assert!(slept_between(start, start + Duration::from_secs(3600)));
+        // The clock going back (NTP, a manual change) is not sleep
+        assert!(!slept_between(start, start - Duration::from_secs(3600)));
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Local, SecondsFormat, TimeZone, Utc};
+use anyhow::{Result, anyhow};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// Current time in UTC
+pub fn now() -> DateTime<Utc> {
+    Utc::now()
+}
+
+// Canonical storage format: UTC RFC3339 with second precision ("2024-03-10T06:30:00Z")
+pub fn to_rfc3339(value: &DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn now_rfc3339() -> String {
+    to_rfc3339(&now())
+}
+
+// Parse any RFC3339 timestamp into UTC
+pub fn parse_rfc3339(value: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| anyhow!("Invalid timestamp '{}': {}", value, e))
+}
+
+pub fn from_unix_secs(secs: i64) -> Option<DateTime<Utc>> {
+    DateTime::<Utc>::from_timestamp(secs, 0)
+}
+
+pub fn days_ago(days: i64) -> DateTime<Utc> {
+    now() - chrono::Duration::days(days)
+}
+
+// UTC calendar day, used to bucket records per day
+pub fn utc_date(value: &DateTime<Utc>) -> String {
+    value.format("%Y-%m-%d").to_string()
+}
+
+// Compact stamp for generated file names
+pub fn file_stamp(value: &DateTime<Utc>) -> String {
+    value.format("%Y%m%d_%H%M%S").to_string()
+}
+
+// Render a timestamp for display in the machine's local time zone
+pub fn format_for_locale(value: &DateTime<Utc>, locale: &str) -> String {
+    format_in_zone(value, &Local, locale)
+}
+
+// Render a stored RFC3339 timestamp for display, passing unparseable values through unchanged
+pub fn display_rfc3339(value: &str, locale: &str) -> String {
+    match parse_rfc3339(value) {
+        Ok(dt) => format_for_locale(&dt, locale),
+        Err(_) => value.to_string(),
+    }
+}
+
+// Render a timestamp in the given zone. The UTC offset is always included so the
+// repeated hour at a DST fall-back transition stays unambiguous. Locales we have no
+// pattern for, and locales requesting a non-Gregorian calendar, fall back to ISO 8601.
+pub fn format_in_zone<Tz: TimeZone>(value: &DateTime<Utc>, zone: &Tz, locale: &str) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let local = value.with_timezone(zone);
+    let pattern = match locale_language(locale) {
+        Some("en") => "%b %-d, %Y %H:%M (UTC%:z)",
+        Some("zh") => "%Y年%-m月%-d日 %H:%M (UTC%:z)",
+        _ => "%Y-%m-%d %H:%M (UTC%:z)",
+    };
+    local.format(pattern).to_string()
+}
+
+// Primary language of a BCP 47 tag, or None when the tag asks for a non-Gregorian calendar
+fn locale_language(locale: &str) -> Option<&'static str> {
+    let normalized = locale.trim().to_ascii_lowercase().replace('_', "-");
+    if let Some((_, extension)) = normalized.split_once("-u-") {
+        let mut keys = extension.split('-');
+        while let Some(key) = keys.next() {
+            if key == "ca" && keys.next() != Some("gregory") {
+                return None;
+            }
+        }
+    }
+    match normalized.split('-').next() {
+        Some("en") => Some("en"),
+        Some("zh") => Some("zh"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn test_rfc3339_round_trip() {
+        let value = parse_rfc3339("2024-03-10T01:30:00-05:00").unwrap();
+        assert_eq!(to_rfc3339(&value), "2024-03-10T06:30:00Z");
+        assert_eq!(parse_rfc3339(&to_rfc3339(&value)).unwrap(), value);
+        assert!(parse_rfc3339("yesterday").is_err());
+        assert_eq!(to_rfc3339(&from_unix_secs(0).unwrap()), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_dst_fall_back_hour_is_unambiguous() {
+        // 2024-11-03 in New York: 01:30 happens twice, first at -04:00 and then at -05:00
+        let edt = FixedOffset::west_opt(4 * 3600).unwrap();
+        let est = FixedOffset::west_opt(5 * 3600).unwrap();
+        let first = parse_rfc3339("2024-11-03T05:30:00Z").unwrap();
+        let second = parse_rfc3339("2024-11-03T06:30:00Z").unwrap();
+
+        let first_display = format_in_zone(&first, &edt, "en-US");
+        let second_display = format_in_zone(&second, &est, "en-US");
+        assert_eq!(first_display, "Nov 3, 2024 01:30 (UTC-04:00)");
+        assert_eq!(second_display, "Nov 3, 2024 01:30 (UTC-05:00)");
+        assert_ne!(first_display, second_display);
+    }
+
+    #[test]
+    fn test_dst_spring_forward_crosses_date_boundary() {
+        // 2024-03-31 in Berlin: clocks jump from 02:00 to 03:00
+        let cest = FixedOffset::east_opt(2 * 3600).unwrap();
+        let value = parse_rfc3339("2024-03-31T01:00:00Z").unwrap();
+        assert_eq!(format_in_zone(&value, &cest, "zh-CN"), "2024年3月31日 03:00 (UTC+02:00)");
+
+        let cst = FixedOffset::east_opt(8 * 3600).unwrap();
+        let late = parse_rfc3339("2024-03-31T20:00:00Z").unwrap();
+        assert_eq!(format_in_zone(&late, &cst, "zh_TW"), "2024年4月1日 04:00 (UTC+08:00)");
+    }
+
+    #[test]
+    fn test_unsupported_and_non_gregorian_locales_fall_back_to_iso() {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let value = parse_rfc3339("2024-03-10T06:30:00Z").unwrap();
+        let iso = "2024-03-10 06:30 (UTC+00:00)";
+
+        assert_eq!(format_in_zone(&value, &utc, "th-TH-u-ca-buddhist"), iso);
+        assert_eq!(format_in_zone(&value, &utc, "en-US-u-ca-islamic"), iso);
+        assert_eq!(format_in_zone(&value, &utc, "fa-IR"), iso);
+        assert_eq!(format_in_zone(&value, &utc, ""), iso);
+        assert_eq!(format_in_zone(&value, &utc, "en-GB-u-ca-gregory"), "Mar 10, 2024 06:30 (UTC+00:00)");
+    }
+
+    #[test]
+    fn test_display_passes_through_unparseable_values() {
+        assert_eq!(display_rfc3339("unknown", DEFAULT_LOCALE), "unknown");
+    }
+}
@@ -1,17 +1,63 @@
-use crate::database::{Database, UserMeta};
-use crate::crypto::CryptoService;
+use crate::audit_log;
+use crate::database::{Database, LegacyQuestions, LoginStats, SecurityQuestionRecord, UserMeta};
+use crate::crypto::{CryptoService, KdfParams, SecretKey};
+use crate::error::PwdBoxError;
+use crate::session;
+use crate::migrations::{DataMigrationOrchestrator, MigrationContext, MigrationProgress};
+use crate::password_policy::MasterPasswordPolicy;
+use crate::settings_service::SettingsService;
+use crate::time_utils;
 use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use unicode_normalization::UnicodeNormalization;
+use zeroize::{Zeroize, Zeroizing};
+
+// What setup, password changes and resets ask of a new master password
+pub const MASTER_PASSWORD_POLICY: MasterPasswordPolicy = MasterPasswordPolicy { min_length: 12, min_entropy_bits: 50, reject_common: true };
+
+// Test builds, and builds with relaxed-master-password, take the short throwaway passwords
+// of fixtures and dev vaults. Common passwords are still refused.
+#[cfg(any(test, feature = "relaxed-master-password"))]
+const BUILD_MASTER_PASSWORD_POLICY: MasterPasswordPolicy = MasterPasswordPolicy { min_length: 8, min_entropy_bits: 0, reject_common: true };
+#[cfg(not(any(test, feature = "relaxed-master-password")))]
+const BUILD_MASTER_PASSWORD_POLICY: MasterPasswordPolicy = MASTER_PASSWORD_POLICY;
+
+// How many security questions a vault may have
+pub const MIN_SECURITY_QUESTIONS: usize = 2;
+pub const MAX_SECURITY_QUESTIONS: usize = 5;
+
+// Longest master password hint accepted, in characters
+pub const MAX_PASSWORD_HINT_CHARS: usize = 200;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SetupRequest {
     pub master_password: String,
+    // (question, answer) pairs. Older frontends send exactly three in the fixed fields
+    // below instead, which are used when this is empty.
+    #[serde(default)]
+    pub questions: Vec<(String, String)>,
+    #[serde(default)]
     pub question1: String,
+    #[serde(default)]
     pub answer1: String,
+    #[serde(default)]
     pub question2: String,
+    #[serde(default)]
     pub answer2: String,
+    #[serde(default)]
     pub question3: String,
+    #[serde(default)]
     pub answer3: String,
+    // Encrypt the database file itself; needs a build with the sqlcipher feature
+    #[serde(default)]
+    pub encrypt_database: bool,
+    // Also make a recovery code, returned once in AuthResponse::recovery_code
+    #[serde(default)]
+    pub create_recovery_code: bool,
+    #[serde(default)]
+    pub password_hint: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,26 +70,272 @@ pub struct SecurityQuestion {
     pub question: String,
 }
 
+// Answers go in `answers`, in question order; answer1-3 are what older frontends send
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RecoveryRequest {
+    #[serde(default)]
+    pub answers: Vec<String>,
+    #[serde(default)]
     pub answer1: String,
+    #[serde(default)]
     pub answer2: String,
+    #[serde(default)]
     pub answer3: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetWithCodeRequest {
+    pub recovery_code: String,
+    pub new_master_password: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResetPasswordRequest {
     pub new_master_password: String,
+    #[serde(default)]
+    pub answers: Vec<String>, // As in RecoveryRequest
+    #[serde(default)]
     pub answer1: String,
+    #[serde(default)]
     pub answer2: String,
+    #[serde(default)]
     pub answer3: String,
+    #[serde(default)]
+    pub wipe_entries: bool, // Entries encrypted with the old key cannot survive a reset
+}
+
+// The secrets in requests are zeroed once the request has been handled and dropped
+impl Drop for SetupRequest {
+    fn drop(&mut self) {
+        self.master_password.zeroize();
+        self.questions.zeroize();
+        self.answer1.zeroize();
+        self.answer2.zeroize();
+        self.answer3.zeroize();
+    }
+}
+
+impl Drop for LoginRequest {
+    fn drop(&mut self) {
+        self.master_password.zeroize();
+    }
+}
+
+impl Drop for RecoveryRequest {
+    fn drop(&mut self) {
+        self.answers.zeroize();
+        self.answer1.zeroize();
+        self.answer2.zeroize();
+        self.answer3.zeroize();
+    }
+}
+
+impl Drop for ResetWithCodeRequest {
+    fn drop(&mut self) {
+        self.recovery_code.zeroize();
+        self.new_master_password.zeroize();
+    }
+}
+
+impl Drop for ResetPasswordRequest {
+    fn drop(&mut self) {
+        self.new_master_password.zeroize();
+        self.answers.zeroize();
+        self.answer1.zeroize();
+        self.answer2.zeroize();
+        self.answer3.zeroize();
+    }
+}
+
+impl SetupRequest {
+    fn question_pairs(&self) -> Vec<(&str, &str)> {
+        if !self.questions.is_empty() {
+            return self.questions.iter().map(|(question, answer)| (question.as_str(), answer.as_str())).collect();
+        }
+        vec![
+            (self.question1.as_str(), self.answer1.as_str()),
+            (self.question2.as_str(), self.answer2.as_str()),
+            (self.question3.as_str(), self.answer3.as_str()),
+        ]
+    }
+}
+
+// Answers as vaults with answers_normalized hash and compare them: trimmed, each run of
+// whitespace made one space, NFC and lowercase, so "Fluffy " at setup matches "fluffy" later
+pub fn normalize_answer(answer: &str) -> Zeroizing<String> {
+    let collapsed = Zeroizing::new(answer.split_whitespace().collect::<Vec<_>>().join(" "));
+    let composed = Zeroizing::new(collapsed.nfc().collect::<String>());
+    Zeroizing::new(composed.to_lowercase())
+}
+
+fn answers_or_legacy<'a>(answers: &'a [String], legacy: [&'a String; 3]) -> Vec<&'a str> {
+    if answers.is_empty() {
+        legacy.into_iter().map(String::as_str).collect()
+    } else {
+        answers.iter().map(String::as_str).collect()
+    }
+}
+
+impl RecoveryRequest {
+    fn answers(&self) -> Vec<&str> {
+        answers_or_legacy(&self.answers, [&self.answer1, &self.answer2, &self.answer3])
+    }
+}
+
+impl ResetPasswordRequest {
+    fn answers(&self) -> Vec<&str> {
+        answers_or_legacy(&self.answers, [&self.answer1, &self.answer2, &self.answer3])
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub success: bool,
     pub message: String,
-    pub master_key: Option<String>, // Base64 encoded key for frontend storage (temporary)
+    // Stamped vault key. Kept by the backend session and only passed on to the frontend
+    // with session::LEGACY_KEY_IPC.
+    pub master_key: Option<String>,
+    pub entries_at_risk: Option<usize>, // Set when a reset would make existing entries unreadable
+    #[serde(default)]
+    pub session_token: Option<String>, // Opaque id of the backend session, set once it starts
+    #[serde(default)]
+    pub retry_after_secs: Option<u64>, // Set when the attempt was refused by the login throttle
+    #[serde(default)]
+    pub recovery_code: Option<String>, // Only from setup with create_recovery_code; shown once, never stored
+    #[serde(default)]
+    pub previous_login: Option<LoginStats>, // Set by a successful login: the stats as they were before it
+}
+
+// From generate_recovery_code. The code is only ever returned here, once.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodeResponse {
+    pub success: bool,
+    pub message: String,
+    pub recovery_code: Option<String>,
+}
+
+impl Drop for RecoveryCodeResponse {
+    fn drop(&mut self) {
+        self.recovery_code.zeroize();
+    }
+}
+
+// Failed logins (and recovery attempts, which share the counter) allowed in a row before
+// each further attempt is delayed, doubling from 2 seconds
+pub const FREE_LOGIN_ATTEMPTS: u32 = 3;
+// Failed attempts in a row after which every attempt waits out the configured lockout
+pub const LOCKOUT_LOGIN_ATTEMPTS: u32 = 10;
+
+// Returned (through anyhow) by answer checks refused by the login throttle
+#[derive(Debug)]
+pub struct LoginThrottledError {
+    pub retry_after_secs: u64,
+}
+
+impl std::fmt::Display for LoginThrottledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Too many failed attempts, try again in {} seconds", self.retry_after_secs)
+    }
+}
+
+impl std::error::Error for LoginThrottledError {}
+
+// Recovery factors a drill can report as failed: answer_factor for each answer, and
+pub const FACTOR_RECOVERY_KEY: &str = "recovery_key"; // The answers no longer open the recovery copy of the vault key
+
+// "answer1" for the first question's answer, and so on
+pub fn answer_factor(position: usize) -> String {
+    format!("answer{}", position)
+}
+
+// The recovery status warns once the last passed drill is older than this
+pub const RECOVERY_DRILL_WARNING_DAYS: i64 = 365;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryDrillResult {
+    pub success: bool,
+    pub message: String,
+    pub failed_factors: Vec<String>, // Empty when the drill passed
+    pub verified_at: Option<String>, // Last passed drill, this one included
+    #[serde(default)]
+    pub retry_after_secs: Option<u64>, // Set when the drill was refused by the login throttle
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryStatus {
+    pub verified_at: Option<String>,
+    pub days_since_verified: Option<i64>,
+    pub warning: Option<String>,
+    #[serde(default)]
+    pub has_recovery_code: bool,
+}
+
+impl RecoveryCodeResponse {
+    pub fn failure(message: &str) -> Self {
+        RecoveryCodeResponse { success: false, message: message.to_string(), recovery_code: None }
+    }
+}
+
+impl AuthResponse {
+    pub fn failure(message: &str) -> Self {
+        AuthResponse {
+            success: false,
+            message: message.to_string(),
+            master_key: None,
+            entries_at_risk: None,
+            session_token: None,
+            retry_after_secs: None,
+            recovery_code: None,
+            previous_login: None,
+        }
+    }
+
+    fn throttled(retry_after_secs: u64) -> Self {
+        AuthResponse {
+            retry_after_secs: Some(retry_after_secs),
+            ..Self::failure(&LoginThrottledError { retry_after_secs }.to_string())
+        }
+    }
+
+    // The key goes out stamped with the session generation; see session.rs
+    fn success(message: &str, master_key: &[u8; 32], generation: i64) -> Self {
+        AuthResponse {
+            success: true,
+            message: message.to_string(),
+            master_key: Some(session::issue(master_key, generation)),
+            entries_at_risk: None,
+            session_token: None,
+            retry_after_secs: None,
+            recovery_code: None,
+            previous_login: None,
+        }
+    }
+}
+
+// The keys a correct master password opens
+pub struct UnlockedKeys {
+    kek: SecretKey,       // Derived from the password; wraps the vault key
+    entry_key: SecretKey, // What entries are encrypted with: the vault key, or the kek on legacy vaults
+}
+
+// User meta with its Argon2 work done, waiting to be written, and the vault key it wraps
+pub struct PreparedUserMeta {
+    user_meta: UserMeta,
+    vault_key: SecretKey,
+    recovery_code: Option<Zeroizing<String>>, // Set when a new recovery code was made for it
+}
+
+// A duress password with its Argon2 work done, and the decoy vault it opens
+pub struct PreparedDuress {
+    duress_hash: String,
+    duress_salt: String,
+    decoy: PreparedUserMeta,
+}
+
+// First step of a staged login; see VaultCoordinator::login_with_progress
+pub enum LoginStart {
+    Throttled(AuthResponse),
+    Verify(Box<UserMeta>), // Check the password against this, then finish_login
 }
 
 pub struct UserService {
@@ -60,223 +352,1009 @@ impl UserService {
         self.database.user_exists()
     }
 
+    // Refuse a new master password the build's policy does not accept. `field` is the
+    // request field it came in, for the validation error.
+    fn check_master_password(master_password: &str, field: &'static str) -> Result<()> {
+        BUILD_MASTER_PASSWORD_POLICY.check(master_password, field)?;
+        Ok(())
+    }
+
+    // A new master password must not be the duress password, which opens the decoy vault instead
+    fn check_not_duress(user_meta: &UserMeta, master_password: &str, field: &'static str) -> Result<()> {
+        if Self::duress_matches(user_meta, master_password)? {
+            return Err(PwdBoxError::invalid_field(field, "The master password must differ from the duress password").into());
+        }
+        Ok(())
+    }
+
+    // Whether a hint gives the master password away: the same text, or part of it, ignoring case
+    fn hint_reveals(hint: &str, master_password: &str) -> bool {
+        Zeroizing::new(master_password.to_lowercase()).contains(&hint.to_lowercase())
+    }
+
+    // A hint as it is stored: trimmed, None when blank. Refused when it is too long or
+    // gives the master password away.
+    pub fn validate_password_hint(hint: &str, master_password: &str) -> Result<Option<String>> {
+        let hint = hint.trim();
+        if hint.is_empty() {
+            return Ok(None);
+        }
+        if hint.chars().count() > MAX_PASSWORD_HINT_CHARS {
+            return Err(PwdBoxError::invalid_field(
+                "password_hint",
+                format!("Password hints are at most {} characters", MAX_PASSWORD_HINT_CHARS),
+            ).into());
+        }
+        if Self::hint_reveals(hint, master_password) {
+            return Err(PwdBoxError::invalid_field("password_hint", "The hint must not contain the master password or be part of it").into());
+        }
+        Ok(Some(hint.to_string()))
+    }
+
+    // Secret used to derive the recovery key-encryption key from the answers, in question order
+    fn recovery_secret(answers: &[Zeroizing<String>]) -> Zeroizing<String> {
+        let answers: Vec<&str> = answers.iter().map(|answer| answer.as_str()).collect();
+        Zeroizing::new(answers.join("\n"))
+    }
+
+    // The answers as the vault hashed them: normalized, or exactly as typed on vaults whose
+    // answers were last saved before normalization
+    fn stored_answers<'a>(normalized: bool, answers: impl IntoIterator<Item = &'a str>) -> Vec<Zeroizing<String>> {
+        answers
+            .into_iter()
+            .map(|answer| if normalized { normalize_answer(answer) } else { Zeroizing::new(answer.to_string()) })
+            .collect()
+    }
+
+    fn validate_questions(questions: &[(&str, &str)]) -> Result<()> {
+        if !(MIN_SECURITY_QUESTIONS..=MAX_SECURITY_QUESTIONS).contains(&questions.len()) {
+            return Err(PwdBoxError::invalid_field(
+                "questions",
+                format!("Choose between {} and {} security questions", MIN_SECURITY_QUESTIONS, MAX_SECURITY_QUESTIONS),
+            ).into());
+        }
+        let mut seen = HashSet::new();
+        for (question, answer) in questions {
+            if question.trim().is_empty() || answer.trim().is_empty() {
+                return Err(PwdBoxError::invalid_field("questions", "Every security question needs an answer").into());
+            }
+            if !seen.insert(question.trim().to_lowercase()) {
+                return Err(PwdBoxError::invalid_field("questions", "Security questions must all be different").into());
+            }
+        }
+        Ok(())
+    }
+
+    fn hash_questions(questions: &[(&str, &str)], answers: &[Zeroizing<String>], params: &KdfParams) -> Result<Vec<SecurityQuestionRecord>> {
+        questions
+            .iter()
+            .zip(answers)
+            .map(|((question, _), answer)| {
+                let salt = CryptoService::generate_salt();
+                Ok(SecurityQuestionRecord {
+                    question: question.trim().to_string(),
+                    answer_hash: CryptoService::hash_password(answer, &salt, params)?,
+                    salt,
+                })
+            })
+            .collect()
+    }
+
+    // Answers must come one per stored question
+    fn expect_answers(user_meta: &UserMeta, answers: &[&str]) -> Result<()> {
+        let expected = user_meta.security_questions.len();
+        if expected < MIN_SECURITY_QUESTIONS {
+            return Err(anyhow!("Incomplete security questions setup"));
+        }
+        if answers.len() != expected {
+            return Err(PwdBoxError::invalid_field("answers", format!("Expected {} answers, one per security question", expected)).into());
+        }
+        Ok(())
+    }
+
+    // Hash the new master password and wrap the vault key under a key derived from it. The
+    // canary is encrypted afresh under the vault key each time.
+    fn set_master_password(user_meta: &mut UserMeta, master_password: &str, vault_key: &[u8; 32], params: &KdfParams) -> Result<()> {
+        let master_salt = CryptoService::generate_salt();
+        let master_hash = CryptoService::hash_password(master_password, &master_salt, params)?;
+        let kek = CryptoService::derive_key_from_password(master_password, &master_salt, params)?;
+        let (wrapped_vault_key, vault_key_nonce) = CryptoService::wrap_key(vault_key, &kek)?;
+        let (canary_ciphertext, canary_nonce) = CryptoService::encrypt_canary(vault_key)?;
+
+        user_meta.master_hash = master_hash;
+        user_meta.master_salt = master_salt;
+        user_meta.wrapped_vault_key = Some(wrapped_vault_key);
+        user_meta.vault_key_nonce = Some(vault_key_nonce);
+        user_meta.kdf_params = *params;
+        user_meta.canary_ciphertext = Some(canary_ciphertext);
+        user_meta.canary_nonce = Some(canary_nonce);
+        // A hint saved for the old password must not give the new one away
+        if user_meta.password_hint.as_deref().is_some_and(|hint| Self::hint_reveals(hint, master_password)) {
+            user_meta.password_hint = None;
+        }
+        Ok(())
+    }
+
+    // Wrap the vault key under a key derived from the security answers
+    fn set_recovery_wrap(user_meta: &mut UserMeta, recovery_secret: &str, vault_key: &[u8; 32], params: &KdfParams) -> Result<()> {
+        let recovery_key_salt = CryptoService::generate_salt();
+        let recovery_kek = CryptoService::derive_key_from_password(recovery_secret, &recovery_key_salt, params)?;
+        let (wrapped_vault_key, vault_key_nonce) = CryptoService::wrap_key(vault_key, &recovery_kek)?;
+
+        user_meta.recovery_wrapped_vault_key = Some(wrapped_vault_key);
+        user_meta.recovery_vault_key_nonce = Some(vault_key_nonce);
+        user_meta.recovery_key_salt = Some(recovery_key_salt);
+        user_meta.recovery_kdf_params = *params;
+        user_meta.recovery_verified_at = None; // Earlier drills tested the replaced wrap
+        Ok(())
+    }
+
+    // Wrap the vault key under a fresh recovery code, replacing any earlier code, and return
+    // the code. Only its verifier is kept.
+    fn set_recovery_code(user_meta: &mut UserMeta, vault_key: &[u8; 32]) -> Result<Zeroizing<String>> {
+        let code = CryptoService::generate_recovery_code();
+        let salt = CryptoService::generate_salt();
+        let kek = CryptoService::recovery_code_kek(&code, &salt)?;
+        let (wrapped_vault_key, vault_key_nonce) = CryptoService::wrap_key(vault_key, &kek)?;
+
+        user_meta.recovery_code_verifier = Some(CryptoService::recovery_code_verifier(&code, &salt)?);
+        user_meta.recovery_code_salt = Some(salt);
+        user_meta.recovery_code_wrapped_vault_key = Some(wrapped_vault_key);
+        user_meta.recovery_code_vault_key_nonce = Some(vault_key_nonce);
+        Ok(code)
+    }
+
+    fn clear_recovery_code(user_meta: &mut UserMeta) {
+        user_meta.recovery_code_salt = None;
+        user_meta.recovery_code_verifier = None;
+        user_meta.recovery_code_wrapped_vault_key = None;
+        user_meta.recovery_code_vault_key_nonce = None;
+    }
+
+    // Argon2 parameters new hashes and keys should be made with
+    pub fn target_kdf_params(&self) -> Result<KdfParams> {
+        SettingsService::kdf_params_from(&self.database)
+    }
+
     // Set up the app with master password and security questions
     pub fn setup_app(&self, request: SetupRequest) -> Result<AuthResponse> {
         // Check if app is already set up
         if self.is_app_setup()? {
-            return Ok(AuthResponse {
-                success: false,
-                message: "App is already set up".to_string(),
-                master_key: None,
-            });
+            return Ok(AuthResponse::failure("App is already set up"));
         }
+        self.commit_setup(Self::prepare_setup(request, &self.target_kdf_params()?)?)
+    }
 
-        // Generate salt for master password
-        let master_salt = CryptoService::generate_salt();
-        let master_hash = CryptoService::hash_password(&request.master_password, &master_salt)?;
-
-        // Generate salts and hash security question answers
-        let answer_salt1 = CryptoService::generate_salt();
-        let answer_salt2 = CryptoService::generate_salt();
-        let answer_salt3 = CryptoService::generate_salt();
-
-        let answer1_hash = CryptoService::hash_password(&request.answer1, &answer_salt1)?;
-        let answer2_hash = CryptoService::hash_password(&request.answer2, &answer_salt2)?;
-        let answer3_hash = CryptoService::hash_password(&request.answer3, &answer_salt3)?;
+    // The Argon2 half of setup: hash the answers and master password and wrap a fresh vault
+    // key. Touches no service state, so it runs without any lock held.
+    pub fn prepare_setup(request: SetupRequest, params: &KdfParams) -> Result<PreparedUserMeta> {
+        Self::check_master_password(&request.master_password, "master_password")?;
+        let password_hint = match &request.password_hint {
+            Some(hint) => Self::validate_password_hint(hint, &request.master_password)?,
+            None => None,
+        };
+        let questions = request.question_pairs();
+        Self::validate_questions(&questions)?;
+        let answers = Self::stored_answers(true, questions.iter().map(|(_, answer)| *answer));
 
-        // Create user meta
-        let user_meta = UserMeta {
+        // Create user meta, with the security question answers hashed
+        let mut user_meta = UserMeta {
             id: None,
-            master_hash,
-            master_salt: master_salt.clone(),
-            question1: Some(request.question1),
-            answer1_hash: Some(answer1_hash),
-            answer_salt1: Some(answer_salt1),
-            question2: Some(request.question2),
-            answer2_hash: Some(answer2_hash),
-            answer_salt2: Some(answer_salt2),
-            question3: Some(request.question3),
-            answer3_hash: Some(answer3_hash),
-            answer_salt3: Some(answer_salt3),
+            master_hash: String::new(),
+            master_salt: String::new(),
+            security_questions: Self::hash_questions(&questions, &answers, params)?,
+            legacy_questions: LegacyQuestions::default(),
+            wrapped_vault_key: None,
+            vault_key_nonce: None,
+            recovery_wrapped_vault_key: None,
+            recovery_vault_key_nonce: None,
+            recovery_key_salt: None,
+            generation: 0,
+            recovery_verified_at: None,
+            kdf_params: *params,
+            recovery_kdf_params: *params,
+            canary_ciphertext: None,
+            canary_nonce: None,
+            recovery_code_salt: None,
+            recovery_code_verifier: None,
+            recovery_code_wrapped_vault_key: None,
+            recovery_code_vault_key_nonce: None,
+            answers_normalized: true,
+            password_hint,
+            duress_hash: None,
+            duress_salt: None,
         };
 
-        // Save to database
-        self.database.insert_user_meta(&user_meta)?;
+        // Generate the vault key and wrap it for both the master password and recovery
+        let vault_key = CryptoService::generate_vault_key();
+        Self::set_master_password(&mut user_meta, &request.master_password, &vault_key, params)?;
+        Self::set_recovery_wrap(&mut user_meta, &Self::recovery_secret(&answers), &vault_key, params)?;
+        let recovery_code = if request.create_recovery_code {
+            Some(Self::set_recovery_code(&mut user_meta, &vault_key)?)
+        } else {
+            None
+        };
+
+        Ok(PreparedUserMeta { user_meta, vault_key, recovery_code })
+    }
+
+    // Save prepared setup unless another setup finished first
+    pub fn commit_setup(&self, prepared: PreparedUserMeta) -> Result<AuthResponse> {
+        if self.is_app_setup()? {
+            return Ok(AuthResponse::failure("App is already set up"));
+        }
 
-        // Derive master key for immediate use
-        let master_key = CryptoService::derive_key_from_password(&request.master_password, &master_salt)?;
-        let master_key_b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, master_key);
+        // Save to database
+        self.database.insert_user_meta(&prepared.user_meta)?;
 
         Ok(AuthResponse {
-            success: true,
-            message: "App setup completed successfully".to_string(),
-            master_key: Some(master_key_b64),
+            recovery_code: prepared.recovery_code.as_ref().map(|code| code.to_string()),
+            ..AuthResponse::success("App setup completed successfully", &prepared.vault_key, prepared.user_meta.generation)
         })
     }
 
+    // Verify the master password against user meta and recover the key its entries are
+    // encrypted with: the unwrapped vault key, or the password-derived key for legacy vaults.
+    // Returns None if the password is wrong.
+    pub fn recover_entry_key(user_meta: &UserMeta, master_password: &str) -> Result<Option<SecretKey>> {
+        Ok(Self::verify_master_password(user_meta, master_password)?.map(|keys| keys.entry_key))
+    }
+
+    // Check the master password and open its keys, or None if it is wrong. All Argon2 and
+    // no service state, so callers run it without holding any lock.
+    pub fn verify_master_password(user_meta: &UserMeta, master_password: &str) -> Result<Option<UnlockedKeys>> {
+        if !CryptoService::verify_password(master_password, &user_meta.master_hash)? {
+            return Ok(None);
+        }
+
+        let kek = CryptoService::derive_key_from_password(master_password, &user_meta.master_salt, &user_meta.kdf_params)?;
+        let entry_key = match (&user_meta.wrapped_vault_key, &user_meta.vault_key_nonce) {
+            (Some(wrapped_vault_key), Some(vault_key_nonce)) => CryptoService::unwrap_key(wrapped_vault_key, vault_key_nonce, &kek)?,
+            _ => kek.clone(),
+        };
+        Ok(Some(UnlockedKeys { kek, entry_key }))
+    }
+
+    // Whether `password` is the duress password; false when none is set
+    pub fn duress_matches(user_meta: &UserMeta, password: &str) -> Result<bool> {
+        match &user_meta.duress_hash {
+            Some(duress_hash) => CryptoService::verify_password(password, duress_hash),
+            None => Ok(false),
+        }
+    }
+
+    // Hash a duress password, returning the hash and its salt
+    pub fn hash_duress(duress_password: &str, params: &KdfParams) -> Result<(String, String)> {
+        let duress_salt = CryptoService::generate_salt();
+        Ok((CryptoService::hash_password(duress_password, &duress_salt, params)?, duress_salt))
+    }
+
+    // The Argon2 half of setting a duress password: check the master password against
+    // `user_meta`, hash the duress password and set up the decoy vault it opens. The decoy
+    // has the duress password for its master password and the same security questions, with
+    // answers nobody knows, and a recovery code if this vault has one, so its recovery status
+    // reads the same. None if the master password is wrong.
+    pub fn prepare_duress(
+        user_meta: &UserMeta,
+        master_password: &str,
+        duress_password: &str,
+        params: &KdfParams,
+    ) -> Result<Option<PreparedDuress>> {
+        Self::check_master_password(duress_password, "duress_password")?;
+        if !CryptoService::verify_password(master_password, &user_meta.master_hash)? {
+            return Ok(None);
+        }
+        if duress_password == master_password {
+            return Err(PwdBoxError::invalid_field("duress_password", "The duress password must differ from the master password").into());
+        }
+
+        let (duress_hash, duress_salt) = Self::hash_duress(duress_password, params)?;
+        let questions = user_meta
+            .security_questions
+            .iter()
+            .map(|record| (record.question.clone(), CryptoService::generate_salt()))
+            .collect();
+        let mut decoy = Self::prepare_setup(
+            SetupRequest {
+                master_password: duress_password.to_string(),
+                questions,
+                question1: String::new(),
+                answer1: String::new(),
+                question2: String::new(),
+                answer2: String::new(),
+                question3: String::new(),
+                answer3: String::new(),
+                encrypt_database: false,
+                create_recovery_code: user_meta.recovery_code_verifier.is_some(),
+                password_hint: None,
+            },
+            params,
+        )?;
+        decoy.user_meta.password_hint = user_meta.password_hint.clone();
+        Ok(Some(PreparedDuress { duress_hash, duress_salt, decoy }))
+    }
+
+    // Set up the decoy vault in this, a new and empty database
+    pub fn commit_decoy_setup(&self, prepared: &PreparedDuress) -> Result<()> {
+        self.database.insert_user_meta(&prepared.decoy.user_meta)
+    }
+
+    // Save a prepared duress password. False if the master password changed since
+    // `verified_against` was read, in which case it has to be prepared again.
+    pub fn commit_duress(&self, verified_against: &UserMeta, prepared: &PreparedDuress) -> Result<bool> {
+        if self.current_if_unchanged(verified_against)?.is_none() {
+            return Ok(false);
+        }
+        self.database.set_duress(Some(&prepared.duress_hash), Some(&prepared.duress_salt))?;
+        Ok(true)
+    }
+
+    pub fn user_meta(&self) -> Result<UserMeta> {
+        self.database.get_user_meta()?
+            .ok_or_else(|| anyhow!("User not found. Please set up the app first."))
+    }
+
+    // The stored user meta, or None if its password changed since `verified_against` was read
+    fn current_if_unchanged(&self, verified_against: &UserMeta) -> Result<Option<UserMeta>> {
+        let current = self.user_meta()?;
+        let unchanged = current.master_hash == verified_against.master_hash && current.generation == verified_against.generation;
+        Ok(unchanged.then_some(current))
+    }
+
+    // How long `failed_attempts` failures in a row make the next attempt wait
+    fn throttle_delay_secs(failed_attempts: u32, lockout_secs: u64) -> u64 {
+        if failed_attempts >= LOCKOUT_LOGIN_ATTEMPTS {
+            lockout_secs
+        } else if failed_attempts >= FREE_LOGIN_ATTEMPTS {
+            1 << (failed_attempts - FREE_LOGIN_ATTEMPTS + 1)
+        } else {
+            0
+        }
+    }
+
+    // Seconds until another login or recovery attempt is allowed, None if it is now
+    fn retry_after(&self, now: DateTime<Utc>) -> Result<Option<u64>> {
+        let (failed_attempts, last_attempt_at) = self.database.get_login_attempts()?;
+        let Some(last_attempt_at) = last_attempt_at.and_then(|value| time_utils::parse_rfc3339(&value).ok()) else {
+            return Ok(None);
+        };
+
+        let lockout_secs = SettingsService::login_lockout_minutes_from(&self.database)? as u64 * 60;
+        let delay = Self::throttle_delay_secs(failed_attempts, lockout_secs);
+        let elapsed = (now - last_attempt_at).num_seconds().max(0) as u64;
+        Ok((elapsed < delay).then(|| delay - elapsed))
+    }
+
+    // Fail with LoginThrottledError while the throttle holds attempts back
+    fn ensure_not_throttled(&self) -> Result<()> {
+        match self.retry_after(time_utils::now())? {
+            Some(retry_after_secs) => Err(LoginThrottledError { retry_after_secs }.into()),
+            None => Ok(()),
+        }
+    }
+
+    // Whether failed attempts in a row have reached the panic wipe threshold, when it is on
+    pub fn panic_wipe_due(&self) -> Result<bool> {
+        let Some(threshold) = SettingsService::panic_wipe_threshold_from(&self.database)? else {
+            return Ok(false);
+        };
+        Ok(self.database.get_login_attempts()?.0 >= threshold)
+    }
+
+    // A success clears the failure streak; a failure extends it
+    fn record_attempt(&self, succeeded: bool) -> Result<()> {
+        if succeeded {
+            self.database.reset_login_attempts()
+        } else {
+            self.database.record_failed_login(&time_utils::now_rfc3339()).map(|_| ())
+        }
+    }
+
     // Login with master password
     pub fn login(&self, request: LoginRequest) -> Result<AuthResponse> {
-        // Get user meta from database
-        let user_meta = self.database.get_user_meta()?
-            .ok_or_else(|| anyhow!("User not found. Please set up the app first."))?;
+        self.login_with_progress(request, &mut |_| {})
+    }
 
-        // Verify master password
-        if !CryptoService::verify_password(&request.master_password, &user_meta.master_hash)? {
-            return Ok(AuthResponse {
-                success: false,
-                message: "Invalid master password".to_string(),
-                master_key: None,
-            });
+    // Login, reporting the progress of any first-unlock data migrations. Runs the staged
+    // login in one go; VaultCoordinator drives the stages itself to hash without locks.
+    pub fn login_with_progress(&self, request: LoginRequest, on_progress: &mut dyn FnMut(&MigrationProgress)) -> Result<AuthResponse> {
+        loop {
+            let user_meta = match self.start_login()? {
+                LoginStart::Throttled(response) => return Ok(response),
+                LoginStart::Verify(user_meta) => *user_meta,
+            };
+            let keys = Self::verify_master_password(&user_meta, &request.master_password)?;
+            if let Some(response) = self.finish_login(&user_meta, keys, on_progress)? {
+                if response.success {
+                    if let Err(e) = self.upgrade_kdf(&request.master_password) {
+                        log::warn!("Failed to upgrade the master password KDF: {}", e);
+                    }
+                }
+                return Ok(response);
+            }
         }
+    }
 
-        // Derive master key
-        let master_key = CryptoService::derive_key_from_password(&request.master_password, &user_meta.master_salt)?;
-        let master_key_b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, master_key);
+    // Re-hash the master password if it was made with other than the target parameters.
+    // Run after a successful login, the only time the password is at hand.
+    pub fn upgrade_kdf(&self, master_password: &str) -> Result<()> {
+        loop {
+            let user_meta = self.user_meta()?;
+            let params = self.target_kdf_params()?;
+            if user_meta.kdf_params == params {
+                return Ok(());
+            }
+            // Changed by another login in the meantime
+            let Some(prepared) = Self::prepare_kdf_change(&user_meta, master_password, &params)? else {
+                return Ok(());
+            };
+            if self.commit_kdf_change(&user_meta, prepared)? {
+                return Ok(());
+            }
+        }
+    }
 
-        Ok(AuthResponse {
-            success: true,
-            message: "Login successful".to_string(),
-            master_key: Some(master_key_b64),
-        })
+    // Throttled after repeated failures; a throttled attempt never checks the password
+    pub fn start_login(&self) -> Result<LoginStart> {
+        if let Some(retry_after_secs) = self.retry_after(time_utils::now())? {
+            return Ok(LoginStart::Throttled(AuthResponse::throttled(retry_after_secs)));
+        }
+        // Counted as a failure until finish_login settles it, so logins checked at the same
+        // time each see the others, and one that never finishes stays counted
+        self.database.record_failed_login(&time_utils::now_rfc3339())?;
+        Ok(LoginStart::Verify(Box::new(self.user_meta()?)))
     }
 
-    // Get security questions for password recovery
-    pub fn get_security_questions(&self) -> Result<Vec<SecurityQuestion>> {
-        let user_meta = self.database.get_user_meta()?
-            .ok_or_else(|| anyhow!("User not found"))?;
+    // Record the outcome of a checked password and, when it was right, run pending data
+    // migrations. None if the master password changed since `verified_against` was read,
+    // in which case the password has to be checked again.
+    pub fn finish_login(
+        &self,
+        verified_against: &UserMeta,
+        keys: Option<UnlockedKeys>,
+        on_progress: &mut dyn FnMut(&MigrationProgress),
+    ) -> Result<Option<AuthResponse>> {
+        // Checked again from start_login, which counts it anew
+        if self.current_if_unchanged(verified_against)?.is_none() {
+            self.database.uncount_failed_login()?;
+            return Ok(None);
+        }
 
-        let mut questions = Vec::new();
+        // A failure was counted by start_login already
+        if keys.is_some() {
+            self.database.reset_login_attempts()?;
+        }
+        let Some(UnlockedKeys { kek, entry_key }) = keys else {
+            self.database.record_login_failure(&time_utils::now_rfc3339())?;
+            audit_log::record(&self.database, audit_log::LOGIN_FAILED, None, None, None)?;
+            return Ok(Some(AuthResponse::failure("Invalid master password")));
+        };
 
-        if let Some(q1) = user_meta.question1 {
-            questions.push(SecurityQuestion { question: q1 });
+        let mut context = MigrationContext { kek, entry_key };
+        DataMigrationOrchestrator::with_default_migrations().run(&self.database, &mut context, on_progress)?;
+        let vault_key = context.entry_key;
+        // Vaults set up before the canary get theirs on the first login
+        if self.user_meta()?.canary_ciphertext.is_none() {
+            let (canary_ciphertext, canary_nonce) = CryptoService::encrypt_canary(&vault_key)?;
+            self.database.set_canary(&canary_ciphertext, &canary_nonce)?;
         }
-        if let Some(q2) = user_meta.question2 {
-            questions.push(SecurityQuestion { question: q2 });
+
+        audit_log::record(&self.database, audit_log::LOGIN, None, None, Some(&vault_key))?;
+        let previous_login = self.database.record_login_success(&time_utils::now_rfc3339())?;
+        Ok(Some(AuthResponse {
+            previous_login: Some(previous_login),
+            ..AuthResponse::success("Login successful", &vault_key, self.database.get_user_meta_generation()?)
+        }))
+    }
+
+    // Whether `vault_key` still opens this vault under `generation`: neither the password
+    // nor the vault was replaced since it was issued
+    pub fn opens_vault(&self, vault_key: &[u8; 32], generation: i64) -> Result<bool> {
+        let user_meta = self.user_meta()?;
+        let (Some(canary_ciphertext), Some(canary_nonce)) = (&user_meta.canary_ciphertext, &user_meta.canary_nonce) else {
+            return Ok(false);
+        };
+        Ok(user_meta.generation == generation && CryptoService::verify_canary(canary_ciphertext, canary_nonce, vault_key))
+    }
+
+    // Log in with a vault key read back from the OS keychain, counted like a password login.
+    // None if the key no longer opens this vault, in which case keychain unlock should be
+    // turned off. Migrations need the password-derived key, so while one is pending the
+    // password has to be typed once.
+    pub fn finish_os_keychain_unlock(&self, vault_key: &SecretKey, generation: i64) -> Result<Option<AuthResponse>> {
+        self.finish_stored_key_unlock(vault_key, generation, "os keychain", "Unlocked with the OS keychain")
+    }
+
+    // As finish_os_keychain_unlock, for a vault key the PIN unwrapped
+    pub fn finish_pin_unlock(&self, vault_key: &SecretKey, generation: i64) -> Result<Option<AuthResponse>> {
+        self.finish_stored_key_unlock(vault_key, generation, "pin", "Unlocked with the PIN")
+    }
+
+    fn finish_stored_key_unlock(&self, vault_key: &SecretKey, generation: i64, detail: &str, message: &str) -> Result<Option<AuthResponse>> {
+        if !self.opens_vault(vault_key, generation)? {
+            return Ok(None);
         }
-        if let Some(q3) = user_meta.question3 {
-            questions.push(SecurityQuestion { question: q3 });
+        if DataMigrationOrchestrator::with_default_migrations().has_pending(&self.database)? {
+            return Ok(Some(AuthResponse::failure("This update needs the master password once. Log in with it to continue.")));
         }
 
-        if questions.len() != 3 {
+        audit_log::record(&self.database, audit_log::LOGIN, None, Some(detail), Some(vault_key))?;
+        let previous_login = self.database.record_login_success(&time_utils::now_rfc3339())?;
+        Ok(Some(AuthResponse {
+            previous_login: Some(previous_login),
+            ..AuthResponse::success(message, vault_key, generation)
+        }))
+    }
+
+    // Get security questions for password recovery
+    pub fn get_security_questions(&self) -> Result<Vec<SecurityQuestion>> {
+        let user_meta = self.database.get_user_meta()?
+            .ok_or_else(|| anyhow!("User not found"))?;
+
+        if user_meta.security_questions.len() < MIN_SECURITY_QUESTIONS {
             return Err(anyhow!("Incomplete security questions setup"));
         }
 
-        Ok(questions)
+        Ok(user_meta.security_questions.into_iter().map(|stored| SecurityQuestion { question: stored.question }).collect())
     }
 
-    // Verify security question answers for password recovery
+    // Verify security question answers for password recovery. Shares the login throttle
+    // and fails with LoginThrottledError while it holds attempts back.
     pub fn verify_recovery_answers(&self, request: RecoveryRequest) -> Result<bool> {
+        self.verify_answers(&request.answers())
+    }
+
+    fn verify_answers(&self, answers: &[&str]) -> Result<bool> {
+        self.ensure_not_throttled()?;
         let user_meta = self.database.get_user_meta()?
             .ok_or_else(|| anyhow!("User not found"))?;
+        Self::expect_answers(&user_meta, answers)?;
+        let answers = Self::stored_answers(user_meta.answers_normalized, answers.iter().copied());
 
-        // Verify all three answers
-        let answer1_valid = match (&user_meta.answer1_hash, &user_meta.answer_salt1) {
-            (Some(hash), Some(_salt)) => {
-                CryptoService::verify_password(&request.answer1, hash)?
-            }
-            _ => false,
-        };
+        // Every answer is checked, so a wrong first answer takes as long as a wrong last one
+        let mut valid = true;
+        for (stored, answer) in user_meta.security_questions.iter().zip(&answers) {
+            valid &= CryptoService::verify_password(answer, &stored.answer_hash)?;
+        }
+        self.record_attempt(valid)?;
+        Ok(valid)
+    }
 
-        let answer2_valid = match (&user_meta.answer2_hash, &user_meta.answer_salt2) {
-            (Some(hash), Some(_salt)) => {
-                CryptoService::verify_password(&request.answer2, hash)?
+    // Run the recovery path against the stored material without resetting anything: each
+    // answer is checked on its own so a failed drill names the factor to fix, then the answers
+    // must open the recovery copy of the vault key. That key is cleared at once and never
+    // reaches a session. A passed drill is timestamped in user meta.
+    pub fn practice_recovery(&self, request: RecoveryRequest) -> Result<RecoveryDrillResult> {
+        let user_meta = self.database.get_user_meta()?
+            .ok_or_else(|| anyhow!("User not found"))?;
+        // Naming the wrong answer would otherwise make this a faster oracle than recovery itself
+        if let Some(retry_after_secs) = self.retry_after(time_utils::now())? {
+            return Ok(RecoveryDrillResult {
+                success: false,
+                message: LoginThrottledError { retry_after_secs }.to_string(),
+                failed_factors: Vec::new(),
+                verified_at: user_meta.recovery_verified_at,
+                retry_after_secs: Some(retry_after_secs),
+            });
+        }
+
+        let answers = request.answers();
+        Self::expect_answers(&user_meta, &answers)?;
+        let answers = Self::stored_answers(user_meta.answers_normalized, answers);
+        let mut failed_factors = Vec::new();
+        for (index, (stored, answer)) in user_meta.security_questions.iter().zip(&answers).enumerate() {
+            if !CryptoService::verify_password(answer, &stored.answer_hash)? {
+                failed_factors.push(answer_factor(index + 1));
             }
-            _ => false,
-        };
+        }
+
+        self.record_attempt(failed_factors.is_empty())?;
 
-        let answer3_valid = match (&user_meta.answer3_hash, &user_meta.answer_salt3) {
-            (Some(hash), Some(_salt)) => {
-                CryptoService::verify_password(&request.answer3, hash)?
+        // Only meaningful once the answers match; a wrong answer cannot open the wrap anyway
+        if failed_factors.is_empty() {
+            let opened = match (&user_meta.recovery_wrapped_vault_key, &user_meta.recovery_vault_key_nonce, &user_meta.recovery_key_salt) {
+                (Some(wrapped_vault_key), Some(vault_key_nonce), Some(recovery_key_salt)) => {
+                    let recovery_secret = Self::recovery_secret(&answers);
+                    let recovery_kek = CryptoService::derive_key_from_password(&recovery_secret, recovery_key_salt, &user_meta.recovery_kdf_params)?;
+                    // The unwrapped key is zeroed as it is dropped here
+                    CryptoService::unwrap_key(wrapped_vault_key, vault_key_nonce, &recovery_kek).is_ok()
+                }
+                _ => false,
+            };
+            if !opened {
+                failed_factors.push(FACTOR_RECOVERY_KEY.to_string());
             }
-            _ => false,
+        }
+
+        if !failed_factors.is_empty() {
+            audit_log::record(&self.database, audit_log::RECOVERY_DRILL, None, Some(&format!("failed: {}", failed_factors.join(", "))), None)?;
+            let message = if failed_factors == [FACTOR_RECOVERY_KEY] {
+                "The answers are correct but do not open a recovery copy of the vault key; \
+                 a reset would have to delete your entries"
+                    .to_string()
+            } else {
+                format!("Recovery drill failed: {} did not match", failed_factors.join(", "))
+            };
+            return Ok(RecoveryDrillResult {
+                success: false,
+                message,
+                failed_factors,
+                verified_at: user_meta.recovery_verified_at,
+                retry_after_secs: None,
+            });
+        }
+
+        let verified_at = time_utils::now_rfc3339();
+        self.database.set_recovery_verified_at(&verified_at)?;
+        audit_log::record(&self.database, audit_log::RECOVERY_DRILL, None, Some("passed"), None)?;
+        Ok(RecoveryDrillResult {
+            success: true,
+            message: "Recovery drill passed".to_string(),
+            failed_factors,
+            verified_at: Some(verified_at),
+            retry_after_secs: None,
+        })
+    }
+
+    // When recovery was last shown to work, with a warning if never or too long ago
+    pub fn get_recovery_status(&self) -> Result<RecoveryStatus> {
+        let user_meta = self.database.get_user_meta()?
+            .ok_or_else(|| anyhow!("User not found"))?;
+
+        let days_since_verified = user_meta.recovery_verified_at
+            .as_deref()
+            .and_then(|verified_at| time_utils::parse_rfc3339(verified_at).ok())
+            .map(|verified_at| (time_utils::now() - verified_at).num_days());
+        let warning = match days_since_verified {
+            None => Some("Recovery never verified".to_string()),
+            Some(days) if days > RECOVERY_DRILL_WARNING_DAYS => Some(format!("Recovery last verified {} days ago", days)),
+            Some(_) => None,
         };
 
-        Ok(answer1_valid && answer2_valid && answer3_valid)
+        Ok(RecoveryStatus {
+            verified_at: user_meta.recovery_verified_at,
+            days_since_verified,
+            warning,
+            has_recovery_code: user_meta.recovery_code_verifier.is_some(),
+        })
     }
 
-    // Reset master password using security questions
+    // Reset master password using security questions.
+    // The answers unwrap the recovery copy of the vault key, so entries stay readable.
+    // Legacy vaults without a recovery wrap cannot recover their entries, which are
+    // then only dropped on explicit request.
     pub fn reset_master_password(&self, request: ResetPasswordRequest) -> Result<AuthResponse> {
-        // First verify the security answers
-        let recovery_request = RecoveryRequest {
-            answer1: request.answer1,
-            answer2: request.answer2,
-            answer3: request.answer3,
-        };
+        // A refused password should not cost a recovery attempt, so it is checked first
+        Self::check_master_password(&request.new_master_password, "new_master_password")?;
 
-        if !self.verify_recovery_answers(recovery_request)? {
-            return Ok(AuthResponse {
-                success: false,
-                message: "Invalid security answers".to_string(),
-                master_key: None,
-            });
+        // Then verify the security answers
+        let answers = request.answers();
+        if let Some(retry_after_secs) = self.retry_after(time_utils::now())? {
+            return Ok(AuthResponse::throttled(retry_after_secs));
+        }
+        if !self.verify_answers(&answers)? {
+            return Ok(AuthResponse::failure("Invalid security answers"));
         }
 
         // Get current user meta
         let mut user_meta = self.database.get_user_meta()?
             .ok_or_else(|| anyhow!("User not found"))?;
+        Self::check_not_duress(&user_meta, &request.new_master_password, "new_master_password")?;
+        let recovery_secret = Self::recovery_secret(&Self::stored_answers(user_meta.answers_normalized, answers));
+        let params = self.target_kdf_params()?;
+
+        if let (Some(wrapped_vault_key), Some(vault_key_nonce), Some(recovery_key_salt)) = (
+            &user_meta.recovery_wrapped_vault_key,
+            &user_meta.recovery_vault_key_nonce,
+            &user_meta.recovery_key_salt,
+        ) {
+            let recovery_kek = CryptoService::derive_key_from_password(&recovery_secret, recovery_key_salt, &user_meta.recovery_kdf_params)?;
+            let vault_key = CryptoService::unwrap_key(wrapped_vault_key, vault_key_nonce, &recovery_kek)?;
+
+            // Re-wrap the vault key under the new master password
+            Self::set_master_password(&mut user_meta, &request.new_master_password, &vault_key, &params)?;
+            // The answers are only at hand now, so bring the recovery wrap up to the target too.
+            // Same answers and vault key, so an earlier drill still holds.
+            if user_meta.recovery_kdf_params != params {
+                let recovery_verified_at = user_meta.recovery_verified_at.take();
+                Self::set_recovery_wrap(&mut user_meta, &recovery_secret, &vault_key, &params)?;
+                user_meta.recovery_verified_at = recovery_verified_at;
+            }
+            user_meta.generation += 1;
+            self.database.insert_user_meta(&user_meta)?;
+
+            return Ok(AuthResponse::success("Master password reset successfully", &vault_key, user_meta.generation));
+        }
+
+        let entry_count = self.database.count_password_entries()?;
+        if entry_count > 0 && !request.wipe_entries {
+            return Ok(AuthResponse {
+                entries_at_risk: Some(entry_count),
+                ..AuthResponse::failure(&format!(
+                    "{} password entries are encrypted with the old master password and cannot be recovered. \
+                     Confirm the reset with wipe_entries to delete them.",
+                    entry_count
+                ))
+            });
+        }
+
+        // Start over with a fresh vault key, recoverable from now on
+        let vault_key = CryptoService::generate_vault_key();
+        Self::set_master_password(&mut user_meta, &request.new_master_password, &vault_key, &params)?;
+        Self::set_recovery_wrap(&mut user_meta, &recovery_secret, &vault_key, &params)?;
+        // A recovery code would still open the discarded key
+        Self::clear_recovery_code(&mut user_meta);
+        user_meta.generation += 1;
+
+        // Save updated user meta, dropping the unreadable entries alongside it
+        let wiped_count = self.database.reset_user_meta_and_wipe_entries(&user_meta)?;
 
-        // Generate new salt and hash for the new master password
-        let new_master_salt = CryptoService::generate_salt();
-        let new_master_hash = CryptoService::hash_password(&request.new_master_password, &new_master_salt)?;
+        let message = if wiped_count > 0 {
+            format!("Master password reset successfully. {} password entries were deleted.", wiped_count)
+        } else {
+            "Master password reset successfully".to_string()
+        };
+        Ok(AuthResponse::success(&message, &vault_key, user_meta.generation))
+    }
+
+    // Reset the master password with the recovery code. The code unwraps the vault key, so
+    // entries stay readable. Codes are one-time: the one used is retired and
+    // generate_recovery_code has to make another. Shares the login throttle.
+    pub fn reset_master_password_with_code(&self, request: ResetWithCodeRequest) -> Result<AuthResponse> {
+        Self::check_master_password(&request.new_master_password, "new_master_password")?;
+        if let Some(retry_after_secs) = self.retry_after(time_utils::now())? {
+            return Ok(AuthResponse::throttled(retry_after_secs));
+        }
+        let mut user_meta = self.user_meta()?;
+        let (Some(salt), Some(verifier), Some(wrapped_vault_key), Some(vault_key_nonce)) = (
+            &user_meta.recovery_code_salt,
+            &user_meta.recovery_code_verifier,
+            &user_meta.recovery_code_wrapped_vault_key,
+            &user_meta.recovery_code_vault_key_nonce,
+        ) else {
+            return Ok(AuthResponse::failure("No recovery code has been set up"));
+        };
 
-        // Update user meta with new master password
-        user_meta.master_hash = new_master_hash;
-        user_meta.master_salt = new_master_salt.clone();
+        let valid = CryptoService::verify_recovery_code(&request.recovery_code, salt, verifier);
+        self.record_attempt(valid)?;
+        if !valid {
+            audit_log::record(&self.database, audit_log::LOGIN_FAILED, None, Some("recovery code"), None)?;
+            return Ok(AuthResponse::failure("Invalid recovery code"));
+        }
+        Self::check_not_duress(&user_meta, &request.new_master_password, "new_master_password")?;
+        let kek = CryptoService::recovery_code_kek(&request.recovery_code, salt)?;
+        let vault_key = CryptoService::unwrap_key(wrapped_vault_key, vault_key_nonce, &kek)?;
 
-        // Save updated user meta
+        Self::set_master_password(&mut user_meta, &request.new_master_password, &vault_key, &self.target_kdf_params()?)?;
+        Self::clear_recovery_code(&mut user_meta);
+        user_meta.generation += 1;
         self.database.insert_user_meta(&user_meta)?;
+        audit_log::record(&self.database, audit_log::RECOVERY_CODE_USED, None, None, Some(&vault_key))?;
 
-        // Derive new master key
-        let master_key = CryptoService::derive_key_from_password(&request.new_master_password, &new_master_salt)?;
-        let master_key_b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, master_key);
+        Ok(AuthResponse::success(
+            "Master password reset successfully. The recovery code has been used up; generate a new one.",
+            &vault_key,
+            user_meta.generation,
+        ))
+    }
 
-        Ok(AuthResponse {
+    // Make a new recovery code, replacing any earlier one (requires the master password)
+    pub fn generate_recovery_code(&self, master_password: &str) -> Result<RecoveryCodeResponse> {
+        loop {
+            let user_meta = self.user_meta()?;
+            let Some(prepared) = Self::prepare_recovery_code(&user_meta, master_password)? else {
+                return Ok(RecoveryCodeResponse::failure("Master password is incorrect"));
+            };
+            if let Some(response) = self.commit_recovery_code(&user_meta, prepared)? {
+                return Ok(response);
+            }
+        }
+    }
+
+    // The Argon2 half of generate_recovery_code: check the master password against
+    // `user_meta` and wrap its vault key under a new code. None if the password is wrong.
+    pub fn prepare_recovery_code(user_meta: &UserMeta, master_password: &str) -> Result<Option<PreparedUserMeta>> {
+        let Some(keys) = Self::verify_master_password(user_meta, master_password)? else {
+            return Ok(None);
+        };
+
+        let mut user_meta = user_meta.clone();
+        let recovery_code = Self::set_recovery_code(&mut user_meta, &keys.entry_key)?;
+        Ok(Some(PreparedUserMeta { user_meta, vault_key: keys.entry_key, recovery_code: Some(recovery_code) }))
+    }
+
+    // Save a prepared recovery code. None if the master password changed since
+    // `verified_against` was read, in which case the code has to be prepared again.
+    pub fn commit_recovery_code(&self, verified_against: &UserMeta, prepared: PreparedUserMeta) -> Result<Option<RecoveryCodeResponse>> {
+        let Some(current) = self.current_if_unchanged(verified_against)? else {
+            return Ok(None);
+        };
+
+        let user_meta = UserMeta {
+            recovery_verified_at: current.recovery_verified_at,
+            password_hint: current.password_hint,
+            duress_hash: current.duress_hash,
+            duress_salt: current.duress_salt,
+            ..prepared.user_meta
+        };
+        self.database.insert_user_meta(&user_meta)?;
+        audit_log::record(&self.database, audit_log::RECOVERY_CODE_CREATED, None, None, Some(&prepared.vault_key))?;
+        Ok(Some(RecoveryCodeResponse {
             success: true,
-            message: "Master password reset successfully".to_string(),
-            master_key: Some(master_key_b64),
-        })
+            message: "Recovery code created. Print it or write it down now; it will not be shown again.".to_string(),
+            recovery_code: prepared.recovery_code.as_ref().map(|code| code.to_string()),
+        }))
     }
 
-    // Change master password (requires current password)
-    pub fn change_master_password(&self, current_password: &str, new_password: &str) -> Result<AuthResponse> {
-        // Verify current password first
-        let login_request = LoginRequest {
-            master_password: current_password.to_string(),
+    // Replace the security questions and answers (requires the master password). The
+    // recovery copy of the vault key is re-wrapped under the new answers, so the recovery
+    // status asks for a new drill.
+    pub fn change_security_questions(&self, master_password: &str, questions: &[(String, String)]) -> Result<RecoveryStatus> {
+        loop {
+            let user_meta = self.user_meta()?;
+            let params = self.target_kdf_params()?;
+            let Some(prepared) = Self::prepare_question_change(&user_meta, master_password, questions, &params)? else {
+                return Err(anyhow!("Master password is incorrect"));
+            };
+            if self.commit_question_change(&user_meta, prepared)? {
+                return self.get_recovery_status();
+            }
+        }
+    }
+
+    // The Argon2 half of a question change: check the master password against `user_meta`,
+    // hash the new answers and wrap its vault key under them. None if the password is wrong.
+    pub fn prepare_question_change(
+        user_meta: &UserMeta,
+        master_password: &str,
+        questions: &[(String, String)],
+        params: &KdfParams,
+    ) -> Result<Option<PreparedUserMeta>> {
+        let questions: Vec<(&str, &str)> = questions.iter().map(|(question, answer)| (question.as_str(), answer.as_str())).collect();
+        Self::validate_questions(&questions)?;
+        let Some(keys) = Self::verify_master_password(user_meta, master_password)? else {
+            return Ok(None);
         };
 
-        let auth_result = self.login(login_request)?;
-        if !auth_result.success {
-            return Ok(AuthResponse {
-                success: false,
-                message: "Current password is incorrect".to_string(),
-                master_key: None,
-            });
+        // Saving the answers again is what moves an older vault over to normalized answers
+        let answers = Self::stored_answers(true, questions.iter().map(|(_, answer)| *answer));
+        let mut user_meta = user_meta.clone();
+        user_meta.security_questions = Self::hash_questions(&questions, &answers, params)?;
+        user_meta.answers_normalized = true;
+        Self::set_recovery_wrap(&mut user_meta, &Self::recovery_secret(&answers), &keys.entry_key, params)?;
+        Ok(Some(PreparedUserMeta { user_meta, vault_key: keys.entry_key, recovery_code: None }))
+    }
+
+    // Save a prepared question change, replacing the whole set at once. False if the master
+    // password changed since `verified_against` was read, in which case the change has to be
+    // prepared again.
+    pub fn commit_question_change(&self, verified_against: &UserMeta, prepared: PreparedUserMeta) -> Result<bool> {
+        let Some(current) = self.current_if_unchanged(verified_against)? else {
+            return Ok(false);
+        };
+
+        let user_meta = UserMeta {
+            password_hint: current.password_hint,
+            duress_hash: current.duress_hash,
+            duress_salt: current.duress_salt,
+            ..prepared.user_meta
+        };
+        self.database.insert_user_meta(&user_meta)?;
+        let detail = format!("{} questions", user_meta.security_questions.len());
+        audit_log::record(&self.database, audit_log::SECURITY_QUESTIONS_CHANGED, None, Some(&detail), Some(&prepared.vault_key))?;
+        Ok(true)
+    }
+
+    // The hint, for the login screen. lib.rs only hands it out after a failed login.
+    pub fn get_password_hint(&self) -> Result<Option<String>> {
+        Ok(self.database.get_user_meta()?.and_then(|user_meta| user_meta.password_hint))
+    }
+
+    // Save or, with a blank hint, remove the hint (requires the master password)
+    pub fn set_password_hint(&self, master_password: &str, hint: &str) -> Result<Option<String>> {
+        let hint = Self::validate_password_hint(hint, master_password)?;
+        loop {
+            let user_meta = self.user_meta()?;
+            if !CryptoService::verify_password(master_password, &user_meta.master_hash)? {
+                return Err(anyhow!("Master password is incorrect"));
+            }
+            if self.commit_password_hint(&user_meta, hint.as_deref())? {
+                return Ok(hint);
+            }
         }
+    }
 
-        // Get current user meta
-        let mut user_meta = self.database.get_user_meta()?
-            .ok_or_else(|| anyhow!("User not found"))?;
+    // Save a prepared hint. False if the master password changed since `verified_against`
+    // was read, since the hint was only checked against the old one.
+    pub fn commit_password_hint(&self, verified_against: &UserMeta, hint: Option<&str>) -> Result<bool> {
+        if self.current_if_unchanged(verified_against)?.is_none() {
+            return Ok(false);
+        }
+        self.database.set_password_hint(hint)?;
+        Ok(true)
+    }
 
-        // Generate new salt and hash for the new master password
-        let new_master_salt = CryptoService::generate_salt();
-        let new_master_hash = CryptoService::hash_password(new_password, &new_master_salt)?;
+    // Change master password (requires current password).
+    // Only the vault key is re-wrapped; entries keep their encryption.
+    pub fn change_master_password(&self, current_password: &str, new_password: &str) -> Result<AuthResponse> {
+        loop {
+            let user_meta = self.user_meta()?;
+            let params = self.target_kdf_params()?;
+            let Some(prepared) = Self::prepare_password_change(&user_meta, current_password, new_password, &params)? else {
+                return Ok(AuthResponse::failure("Current password is incorrect"));
+            };
+            if let Some(response) = self.commit_password_change(&user_meta, prepared)? {
+                return Ok(response);
+            }
+        }
+    }
 
-        // Update user meta with new master password
-        user_meta.master_hash = new_master_hash;
-        user_meta.master_salt = new_master_salt.clone();
+    // The Argon2 half of a password change: check the current password against `user_meta`
+    // and re-wrap its vault key under the new one. None if the current password is wrong.
+    // The new password is hashed with the target `params`.
+    pub fn prepare_password_change(
+        user_meta: &UserMeta,
+        current_password: &str,
+        new_password: &str,
+        params: &KdfParams,
+    ) -> Result<Option<PreparedUserMeta>> {
+        Self::check_master_password(new_password, "new_password")?;
+        let Some(keys) = Self::verify_master_password(user_meta, current_password)? else {
+            return Ok(None);
+        };
+        Self::check_not_duress(user_meta, new_password, "new_password")?;
+
+        // Sessions opened with the old password end once this is saved
+        let mut user_meta = user_meta.clone();
+        Self::set_master_password(&mut user_meta, new_password, &keys.entry_key, params)?;
+        user_meta.generation += 1;
+        Ok(Some(PreparedUserMeta { user_meta, vault_key: keys.entry_key, recovery_code: None }))
+    }
 
-        // Save updated user meta
+    // Save a prepared change. None if the master password changed since `verified_against`
+    // was read, in which case the change has to be prepared again.
+    pub fn commit_password_change(&self, verified_against: &UserMeta, prepared: PreparedUserMeta) -> Result<Option<AuthResponse>> {
+        let Some(current) = self.current_if_unchanged(verified_against)? else {
+            return Ok(None);
+        };
+
+        // A recovery drill may have passed in the meantime
+        let user_meta = UserMeta { recovery_verified_at: current.recovery_verified_at, ..prepared.user_meta };
         self.database.insert_user_meta(&user_meta)?;
+        Ok(Some(AuthResponse::success("Master password changed successfully", &prepared.vault_key, user_meta.generation)))
+    }
 
-        // Derive new master key
-        let master_key = CryptoService::derive_key_from_password(new_password, &new_master_salt)?;
-        let master_key_b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, master_key);
+    // The Argon2 half of a KDF parameter change: check the master password against
+    // `user_meta`, then hash it and re-wrap the vault key under `params`. The password is
+    // unchanged, so sessions stay valid. None if the password is wrong.
+    pub fn prepare_kdf_change(user_meta: &UserMeta, master_password: &str, params: &KdfParams) -> Result<Option<PreparedUserMeta>> {
+        let Some(keys) = Self::verify_master_password(user_meta, master_password)? else {
+            return Ok(None);
+        };
 
-        Ok(AuthResponse {
-            success: true,
-            message: "Master password changed successfully".to_string(),
-            master_key: Some(master_key_b64),
-        })
+        let mut user_meta = user_meta.clone();
+        Self::set_master_password(&mut user_meta, master_password, &keys.entry_key, params)?;
+        Ok(Some(PreparedUserMeta { user_meta, vault_key: keys.entry_key, recovery_code: None }))
+    }
+
+    // Save a prepared KDF change. False if the master password changed since
+    // `verified_against` was read, in which case the change has to be prepared again.
+    pub fn commit_kdf_change(&self, verified_against: &UserMeta, prepared: PreparedUserMeta) -> Result<bool> {
+        let Some(current) = self.current_if_unchanged(verified_against)? else {
+            return Ok(false);
+        };
+
+        let user_meta = UserMeta {
+            recovery_verified_at: current.recovery_verified_at,
+            password_hint: current.password_hint,
+            duress_hash: current.duress_hash,
+            duress_salt: current.duress_salt,
+            ..prepared.user_meta
+        };
+        self.database.insert_user_meta(&user_meta)?;
+        let detail = format!("{} -> {}", verified_against.kdf_params, user_meta.kdf_params);
+        audit_log::record(&self.database, audit_log::KDF_UPGRADED, None, Some(&detail), Some(&prepared.vault_key))?;
+        Ok(true)
     }
 
     // Logout (for clearing sensitive data from memory)
@@ -285,4 +1363,624 @@ impl UserService {
         // For now, this is mainly a placeholder for frontend state management
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{EntryType, SecretKind};
+    use crate::password_policy::MasterPasswordRule;
+    use crate::events::EventEmitter;
+    use crate::password_service::{AddPasswordRequest, DecryptPasswordRequest, PasswordService};
+    use std::path::Path;
+
+    fn setup_request() -> SetupRequest {
+        SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "First pet?".to_string(),
+            answer1: "fluffy".to_string(),
+            question2: "Birth city?".to_string(),
+            answer2: "paris".to_string(),
+            question3: "Favourite colour?".to_string(),
+            answer3: "green".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }
+    }
+
+    fn reset_request(wipe_entries: bool) -> ResetPasswordRequest {
+        ResetPasswordRequest {
+            new_master_password: "new_master".to_string(),
+            answers: Vec::new(),
+            answer1: "fluffy".to_string(),
+            answer2: "paris".to_string(),
+            answer3: "green".to_string(),
+            wipe_entries,
+        }
+    }
+
+    fn services(path: &Path) -> (UserService, PasswordService) {
+        (
+            UserService::new(Database::new(path.to_path_buf()).unwrap()),
+            PasswordService::new(Database::new(path.to_path_buf()).unwrap(), EventEmitter::default()),
+        )
+    }
+
+    fn add_entry(password_service: &PasswordService, master_key: &str) -> i64 {
+        let response = password_service.add_password(AddPasswordRequest {
+            software: "mail".to_string(),
+            account: "me@example.com".to_string(),
+            password: "hunter2".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.to_string(),
+        }).unwrap();
+        response.data.unwrap()["id"].as_i64().unwrap()
+    }
+
+    // Recreate the pre-vault-key layout: entries encrypted with the password-derived key
+    fn make_legacy_vault(user_service: &UserService, password_service: &PasswordService) -> i64 {
+        // Three questions in the old flat fields, as exports from before security_questions have them
+        let hashed = |answer: &str| {
+            let salt = CryptoService::generate_salt();
+            (Some(CryptoService::hash_password(answer, &salt, &KdfParams::default()).unwrap()), Some(salt))
+        };
+        let (answer1_hash, answer_salt1) = hashed("fluffy");
+        let (answer2_hash, answer_salt2) = hashed("paris");
+        let (answer3_hash, answer_salt3) = hashed("green");
+
+        let master_salt = CryptoService::generate_salt();
+        let user_meta = UserMeta {
+            id: None,
+            master_hash: CryptoService::hash_password("original_master", &master_salt, &KdfParams::default()).unwrap(),
+            master_salt: master_salt.clone(),
+            security_questions: Vec::new(),
+            legacy_questions: LegacyQuestions {
+                question1: Some("First pet?".to_string()),
+                answer1_hash,
+                answer_salt1,
+                question2: Some("Birth city?".to_string()),
+                answer2_hash,
+                answer_salt2,
+                question3: Some("Favourite colour?".to_string()),
+                answer3_hash,
+                answer_salt3,
+            },
+            wrapped_vault_key: None,
+            vault_key_nonce: None,
+            recovery_wrapped_vault_key: None,
+            recovery_vault_key_nonce: None,
+            recovery_key_salt: None,
+            generation: 0,
+            recovery_verified_at: None,
+            kdf_params: KdfParams::default(),
+            recovery_kdf_params: KdfParams::default(),
+            canary_ciphertext: None,
+            canary_nonce: None,
+            recovery_code_salt: None,
+            recovery_code_verifier: None,
+            recovery_code_wrapped_vault_key: None,
+            recovery_code_vault_key_nonce: None,
+            answers_normalized: false,
+            password_hint: None,
+            duress_hash: None,
+            duress_salt: None,
+        };
+        user_service.database.insert_user_meta(&user_meta).unwrap();
+
+        let legacy_key = CryptoService::derive_key_from_password("original_master", &master_salt, &KdfParams::default()).unwrap();
+        let legacy_key_b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, legacy_key);
+        add_entry(password_service, &legacy_key_b64)
+    }
+
+    fn reveal(password_service: &PasswordService, id: i64, master_key: String) -> String {
+        let entry = password_service.get_password(DecryptPasswordRequest { id, master_key, skip_usage_tracking: false }).unwrap();
+        entry.data.unwrap()["password"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_reset_keeps_entries_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+
+        let master_key = user_service.setup_app(setup_request()).unwrap().master_key.unwrap();
+        let id = add_entry(&password_service, &master_key);
+
+        let response = user_service.reset_master_password(reset_request(false)).unwrap();
+        assert!(response.success);
+        // Same vault key, issued to a new session generation
+        assert_eq!(response.master_key.unwrap().split_once(':').unwrap().1, master_key.split_once(':').unwrap().1);
+
+        let login = user_service.login(LoginRequest { master_password: "new_master".to_string() }).unwrap();
+        assert_eq!(reveal(&password_service, id, login.master_key.unwrap()), "hunter2");
+        assert!(!user_service.login(LoginRequest { master_password: "original_master".to_string() }).unwrap().success);
+    }
+
+    #[test]
+    fn test_recovery_code_resets_keep_entries_readable_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let mut request = setup_request();
+        request.create_recovery_code = true;
+        let setup = user_service.setup_app(request).unwrap();
+        let master_key = setup.master_key.clone().unwrap();
+        let id = add_entry(&password_service, &master_key);
+
+        // Only the verifier is stored, never the code
+        let setup_code = setup.recovery_code.clone().unwrap();
+        let stored = serde_json::to_string(&user_service.user_meta().unwrap()).unwrap();
+        assert!(!stored.contains(&setup_code) && !stored.contains(&setup_code.replace('-', "")));
+        assert!(user_service.get_recovery_status().unwrap().has_recovery_code);
+
+        // A new code replaces the one from setup
+        assert!(!user_service.generate_recovery_code("wrong_master").unwrap().success);
+        let code = user_service.generate_recovery_code("original_master").unwrap().recovery_code.clone().unwrap();
+        assert_ne!(code, setup_code);
+        let reset = |recovery_code: &str| user_service.reset_master_password_with_code(ResetWithCodeRequest {
+            recovery_code: recovery_code.to_string(),
+            new_master_password: "new_master".to_string(),
+        }).unwrap();
+        assert!(!reset(&setup_code).success);
+
+        let response = reset(&code.to_uppercase());
+        assert!(response.success, "{}", response.message);
+        assert_eq!(response.master_key.unwrap().split_once(':').unwrap().1, master_key.split_once(':').unwrap().1);
+        let login = user_service.login(LoginRequest { master_password: "new_master".to_string() }).unwrap();
+        assert_eq!(reveal(&password_service, id, login.master_key.unwrap()), "hunter2");
+
+        // The code is used up
+        assert!(!user_service.get_recovery_status().unwrap().has_recovery_code);
+        assert_eq!(reset(&code).message, "No recovery code has been set up");
+    }
+
+    #[test]
+    fn test_recovery_drill_names_failed_factor_and_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let master_key = user_service.setup_app(setup_request()).unwrap().master_key.unwrap();
+        assert_eq!(user_service.get_recovery_status().unwrap().warning.as_deref(), Some("Recovery never verified"));
+
+        let answers = |answer2: &str| RecoveryRequest {
+            answers: Vec::new(),
+            answer1: "fluffy".to_string(),
+            answer2: answer2.to_string(),
+            answer3: "green".to_string(),
+        };
+        let failed = user_service.practice_recovery(answers("london")).unwrap();
+        assert!(!failed.success);
+        assert_eq!(failed.failed_factors, vec![answer_factor(2)]);
+        assert!(failed.verified_at.is_none());
+
+        let generation = user_service.database.get_user_meta_generation().unwrap();
+        let passed = user_service.practice_recovery(answers("paris")).unwrap();
+        assert!(passed.success && passed.failed_factors.is_empty());
+        assert_eq!(user_service.get_recovery_status().unwrap().warning, None);
+
+        // The master password and open sessions are untouched
+        assert_eq!(user_service.database.get_user_meta_generation().unwrap(), generation);
+        assert!(user_service.login(LoginRequest { master_password: "original_master".to_string() }).unwrap().success);
+        add_entry(&password_service, &master_key);
+
+        let long_ago = time_utils::to_rfc3339(&time_utils::days_ago(400));
+        user_service.database.set_recovery_verified_at(&long_ago).unwrap();
+        let status = user_service.get_recovery_status().unwrap();
+        assert_eq!((status.days_since_verified, status.warning.as_deref()), (Some(400), Some("Recovery last verified 400 days ago")));
+
+        // A reset re-wraps nothing for recovery, so the drill stays valid
+        assert!(user_service.reset_master_password(reset_request(false)).unwrap().success);
+        assert_eq!(user_service.get_recovery_status().unwrap().verified_at, Some(long_ago));
+    }
+
+    #[test]
+    fn test_recovery_drill_flags_vault_without_recovery_wrap() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        make_legacy_vault(&user_service, &password_service);
+
+        let drill = user_service.practice_recovery(RecoveryRequest {
+            answers: Vec::new(),
+            answer1: "fluffy".to_string(),
+            answer2: "paris".to_string(),
+            answer3: "green".to_string(),
+        }).unwrap();
+        assert!(!drill.success);
+        assert_eq!(drill.failed_factors, vec![FACTOR_RECOVERY_KEY]);
+        assert!(user_service.get_recovery_status().unwrap().warning.is_some());
+    }
+
+    fn pairs(questions: &[(&str, &str)]) -> Vec<(String, String)> {
+        questions.iter().map(|(question, answer)| (question.to_string(), answer.to_string())).collect()
+    }
+
+    fn answers(answers: &[&str]) -> RecoveryRequest {
+        RecoveryRequest {
+            answers: answers.iter().map(|answer| answer.to_string()).collect(),
+            answer1: String::new(),
+            answer2: String::new(),
+            answer3: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_security_questions_vary_in_count_and_are_replaced_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let mut request = setup_request();
+        request.questions = pairs(&[("First pet?", "fluffy"), ("Birth city?", "paris")]);
+        let master_key = user_service.setup_app(request).unwrap().master_key.unwrap();
+        let id = add_entry(&password_service, &master_key);
+
+        assert_eq!(user_service.get_security_questions().unwrap().len(), 2);
+        assert!(user_service.verify_recovery_answers(answers(&["fluffy", "paris"])).unwrap());
+        let error = PwdBoxError::from(user_service.verify_recovery_answers(answers(&["fluffy", "paris", "green"])).unwrap_err());
+        assert!(matches!(error, PwdBoxError::Validation { field: Some(ref field), .. } if field == "answers"));
+        assert!(user_service.practice_recovery(answers(&["fluffy", "paris"])).unwrap().success);
+
+        // Refused changes leave the questions as they were
+        let four = pairs(&[("Street?", "elm"), ("School?", "st mary"), ("Car?", "beetle"), ("Team?", "ajax")]);
+        assert!(user_service.change_security_questions("wrong_master", &four).is_err());
+        for invalid in [
+            pairs(&[("Street?", "elm")]),
+            pairs(&[("A?", "a"), ("B?", "b"), ("C?", "c"), ("D?", "d"), ("E?", "e"), ("F?", "f")]),
+            pairs(&[("Street?", "elm"), (" street? ", "oak")]),
+            pairs(&[("Street?", "elm"), ("School?", "")]),
+        ] {
+            let error = PwdBoxError::from(user_service.change_security_questions("original_master", &invalid).unwrap_err());
+            assert!(matches!(error, PwdBoxError::Validation { .. }), "{:?}", invalid);
+        }
+        assert!(user_service.verify_recovery_answers(answers(&["fluffy", "paris"])).unwrap());
+
+        let status = user_service.change_security_questions("original_master", &four).unwrap();
+        assert!(status.verified_at.is_none() && status.warning.is_some());
+        let questions: Vec<String> = user_service.get_security_questions().unwrap().into_iter().map(|q| q.question).collect();
+        assert_eq!(questions, ["Street?", "School?", "Car?", "Team?"]);
+        let failed = user_service.practice_recovery(answers(&["elm", "st mary", "golf", "ajax"])).unwrap();
+        assert_eq!(failed.failed_factors, vec![answer_factor(3)]);
+
+        // The new answers open the recovery copy of the same vault key
+        let mut request = reset_request(false);
+        request.answers = vec!["elm".to_string(), "st mary".to_string(), "beetle".to_string(), "ajax".to_string()];
+        let response = user_service.reset_master_password(request).unwrap();
+        assert!(response.success);
+        assert_eq!(reveal(&password_service, id, response.master_key.unwrap()), "hunter2");
+    }
+
+    #[test]
+    fn test_answers_are_normalized_unless_the_vault_predates_it() {
+        assert_eq!(normalize_answer("  Fluffy \t Cat\n").as_str(), "fluffy cat");
+        assert_eq!(normalize_answer("Cafe\u{301}").as_str(), "caf\u{e9}");
+        assert_eq!(normalize_answer("\u{ff30}\u{ff21}\u{ff32}\u{ff29}\u{ff33}").as_str(), "\u{ff50}\u{ff41}\u{ff52}\u{ff49}\u{ff53}");
+
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let mut request = setup_request();
+        // Full-width "PARIS", and "Cafe" with a combining acute accent
+        request.questions = pairs(&[("First pet?", "Fluffy "), ("Birth city?", "\u{ff30}\u{ff21}\u{ff32}\u{ff29}\u{ff33}"), ("Cafe?", "Cafe\u{301}")]);
+        let master_key = user_service.setup_app(request).unwrap().master_key.unwrap();
+        let id = add_entry(&password_service, &master_key);
+        assert!(user_service.user_meta().unwrap().answers_normalized);
+
+        assert!(user_service.verify_recovery_answers(answers(&["fluffy", "\u{ff50}\u{ff41}\u{ff52}\u{ff49}\u{ff53}", "CAF\u{c9}"])).unwrap());
+        // NFC leaves full-width letters alone; they are not folded to ASCII
+        assert!(!user_service.verify_recovery_answers(answers(&["fluffy", "paris", "caf\u{e9}"])).unwrap());
+        let response = user_service.reset_master_password(ResetPasswordRequest {
+            new_master_password: "new_master".to_string(),
+            answers: vec!["  FLUFFY".to_string(), "\u{ff30}\u{ff41}\u{ff52}\u{ff49}\u{ff53} ".to_string(), "caf\u{e9}".to_string()],
+            answer1: String::new(),
+            answer2: String::new(),
+            answer3: String::new(),
+            wipe_entries: false,
+        }).unwrap();
+        assert!(response.success);
+        assert_eq!(reveal(&password_service, id, response.master_key.unwrap()), "hunter2");
+
+        // A vault from before normalization compares exactly until its answers are saved again
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        make_legacy_vault(&user_service, &password_service);
+        assert!(!user_service.verify_recovery_answers(answers(&["Fluffy", "paris", "green "])).unwrap());
+        assert!(user_service.verify_recovery_answers(answers(&["fluffy", "paris", "green"])).unwrap());
+
+        user_service.change_security_questions("original_master", &pairs(&[("First pet?", "Fluffy"), ("Birth city?", "Paris")])).unwrap();
+        assert!(user_service.user_meta().unwrap().answers_normalized);
+        assert!(user_service.verify_recovery_answers(answers(&["fluffy ", "PARIS"])).unwrap());
+    }
+
+    #[test]
+    fn test_new_master_passwords_must_meet_the_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let rejected = |result: Result<AuthResponse>| match PwdBoxError::from(result.unwrap_err()) {
+            PwdBoxError::Validation { field, policy: Some(policy), .. } => (field.unwrap(), policy.rule),
+            other => panic!("not a policy error: {:?}", other),
+        };
+
+        let mut request = setup_request();
+        request.master_password = "123".to_string();
+        assert_eq!(rejected(user_service.setup_app(request)), ("master_password".to_string(), MasterPasswordRule::MinLength));
+        let mut request = setup_request();
+        request.master_password = "password123".to_string();
+        assert_eq!(rejected(user_service.setup_app(request)), ("master_password".to_string(), MasterPasswordRule::Common));
+        assert!(!user_service.is_app_setup().unwrap());
+
+        let master_key = user_service.setup_app(setup_request()).unwrap().master_key.unwrap();
+        let id = add_entry(&password_service, &master_key);
+        assert_eq!(rejected(user_service.change_master_password("original_master", "iloveyou")), ("new_password".to_string(), MasterPasswordRule::Common));
+
+        // Refused before the answers are checked, so no recovery attempt is spent
+        for _ in 0..FREE_LOGIN_ATTEMPTS + 1 {
+            let mut request = reset_request(false);
+            request.new_master_password = "short".to_string();
+            assert_eq!(rejected(user_service.reset_master_password(request)), ("new_master_password".to_string(), MasterPasswordRule::MinLength));
+        }
+        let response = user_service.reset_master_password(reset_request(false)).unwrap();
+        assert!(response.success);
+        assert_eq!(reveal(&password_service, id, response.master_key.unwrap()), "hunter2");
+    }
+
+    #[test]
+    fn test_legacy_question_layouts_are_moved_to_security_questions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pwdbox.db");
+        let (user_service, password_service) = services(&path);
+        let master_key = user_service.setup_app(setup_request()).unwrap().master_key.unwrap();
+        let id = add_entry(&password_service, &master_key);
+        let user_meta = user_service.user_meta().unwrap();
+        assert_eq!(user_meta.security_questions.len(), 3);
+
+        // An export from before security_questions has three flat fields per question instead
+        let mut exported = serde_json::to_value(&user_meta).unwrap();
+        assert!(exported.get("question1").is_none());
+        let fields = exported.as_object_mut().unwrap();
+        fields.remove("security_questions");
+        for (index, stored) in user_meta.security_questions.iter().enumerate() {
+            let position = index + 1;
+            fields.insert(format!("question{}", position), stored.question.clone().into());
+            fields.insert(format!("answer{}_hash", position), stored.answer_hash.clone().into());
+            fields.insert(format!("answer_salt{}", position), stored.salt.clone().into());
+        }
+        let imported: UserMeta = serde_json::from_value(exported).unwrap();
+        assert!(imported.security_questions.is_empty());
+        assert_eq!(imported.questions(), user_meta.security_questions);
+        user_service.database.insert_user_meta(&imported).unwrap();
+        assert_eq!(user_service.user_meta().unwrap().security_questions, user_meta.security_questions);
+
+        // A database from then kept them in user_meta columns, moved over when it is opened
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection.execute("DELETE FROM security_questions", []).unwrap();
+        for (index, stored) in user_meta.security_questions.iter().enumerate() {
+            connection.execute(
+                &format!("UPDATE user_meta SET question{0} = ?1, answer{0}_hash = ?2, answer_salt{0} = ?3", index + 1),
+                rusqlite::params![stored.question, stored.answer_hash, stored.salt],
+            ).unwrap();
+        }
+        let (user_service, password_service) = services(&path);
+        assert_eq!(user_service.user_meta().unwrap().security_questions, user_meta.security_questions);
+        let legacy_left: i64 = connection.query_row("SELECT COUNT(*) FROM user_meta WHERE question1 IS NOT NULL OR answer3_hash IS NOT NULL", [], |row| row.get(0)).unwrap();
+        assert_eq!(legacy_left, 0);
+
+        // Frontends that send answer1-3 still recover
+        let response = user_service.reset_master_password(reset_request(false)).unwrap();
+        assert!(response.success);
+        assert_eq!(reveal(&password_service, id, response.master_key.unwrap()), "hunter2");
+    }
+
+    #[test]
+    fn test_failed_attempts_throttle_login_and_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pwdbox.db");
+        let (user_service, _) = services(&path);
+        user_service.setup_app(setup_request()).unwrap();
+        let login = |password: &str| user_service.login(LoginRequest { master_password: password.to_string() }).unwrap();
+        let fail_at = |count: u32, secs_ago: i64| {
+            user_service.database.reset_login_attempts().unwrap();
+            let at = time_utils::to_rfc3339(&(time_utils::now() - chrono::Duration::seconds(secs_ago)));
+            for _ in 0..count {
+                user_service.database.record_failed_login(&at).unwrap();
+            }
+        };
+
+        assert_eq!(UserService::throttle_delay_secs(2, 300), 0);
+        assert_eq!(UserService::throttle_delay_secs(3, 300), 2);
+        assert_eq!(UserService::throttle_delay_secs(9, 300), 128);
+        assert_eq!(UserService::throttle_delay_secs(10, 300), 300);
+
+        // Throttled attempts are refused before the password is even checked
+        fail_at(3, 0);
+        let throttled = login("original_master");
+        assert!(!throttled.success);
+        assert!(throttled.retry_after_secs.is_some_and(|secs| secs <= 2));
+        assert_eq!(user_service.database.get_login_attempts().unwrap().0, 3);
+
+        // Once the delay has passed a wrong password extends the streak
+        fail_at(3, 10);
+        assert!(login("wrong").retry_after_secs.is_none());
+        assert_eq!(user_service.database.get_login_attempts().unwrap().0, 4);
+        assert!(login("original_master").retry_after_secs.is_some());
+
+        // Recovery answers share the throttle
+        let answers = || RecoveryRequest {
+            answers: Vec::new(),
+            answer1: "fluffy".to_string(),
+            answer2: "paris".to_string(),
+            answer3: "green".to_string(),
+        };
+        let error = user_service.verify_recovery_answers(answers()).unwrap_err();
+        assert!(error.downcast_ref::<LoginThrottledError>().is_some());
+        assert!(user_service.practice_recovery(answers()).unwrap().retry_after_secs.is_some());
+        assert!(user_service.reset_master_password(reset_request(false)).unwrap().retry_after_secs.is_some());
+
+        // Ten failures lock out for the configured time, and a success clears the streak
+        fail_at(10, 60);
+        assert!(login("original_master").retry_after_secs.is_some_and(|secs| secs > 200));
+        SettingsService::new(Database::new(path.clone()).unwrap()).set_login_lockout_minutes(1).unwrap();
+        assert!(login("original_master").success);
+        assert_eq!(user_service.database.get_login_attempts().unwrap(), (0, None));
+    }
+
+    #[test]
+    fn test_change_master_password_keeps_entries_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+
+        let master_key = user_service.setup_app(setup_request()).unwrap().master_key.unwrap();
+        let id = add_entry(&password_service, &master_key);
+
+        assert!(!user_service.change_master_password("wrong", "new_master").unwrap().success);
+        let response = user_service.change_master_password("original_master", "new_master").unwrap();
+        assert!(response.success);
+
+        let login = user_service.login(LoginRequest { master_password: "new_master".to_string() }).unwrap();
+        assert_eq!(login.master_key.as_deref().unwrap().split_once(':').unwrap().1, master_key.split_once(':').unwrap().1);
+        assert_eq!(reveal(&password_service, id, login.master_key.unwrap()), "hunter2");
+    }
+
+    #[test]
+    fn test_canary_catches_a_wrong_key_on_an_empty_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let master_key = user_service.setup_app(setup_request()).unwrap().master_key.unwrap();
+        let canary = || user_service.database.get_user_meta().unwrap().unwrap().canary_ciphertext.unwrap();
+        let other_key = |master_key: &str| format!(
+            "{}:{}",
+            master_key.split_once(':').unwrap().0,
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, CryptoService::generate_vault_key()),
+        );
+
+        assert!(password_service.validate_master_key(&master_key).unwrap());
+        assert!(!password_service.validate_master_key(&other_key(&master_key)).unwrap());
+
+        // Re-encrypted on every password change and reset, still under the same vault key
+        let before = canary();
+        let changed = user_service.change_master_password("original_master", "new_master").unwrap().master_key.unwrap();
+        assert_ne!(canary(), before);
+        assert!(password_service.validate_master_key(&changed).unwrap());
+        assert!(!password_service.validate_master_key(&other_key(&changed)).unwrap());
+
+        let before = canary();
+        let reset = user_service.reset_master_password(reset_request(false)).unwrap().master_key.unwrap();
+        assert_ne!(canary(), before);
+        assert!(password_service.validate_master_key(&reset).unwrap());
+    }
+
+    #[test]
+    fn test_login_migrates_legacy_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let id = make_legacy_vault(&user_service, &password_service);
+
+        // Until then keys are checked against the first entry
+        assert!(!password_service.validate_master_key(&format!("g0:{}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, [7u8; 32]))).unwrap());
+        let login = user_service.login(LoginRequest { master_password: "original_master".to_string() }).unwrap();
+        assert!(user_service.database.get_user_meta().unwrap().unwrap().wrapped_vault_key.is_some());
+        // The first login adds the canary
+        assert!(user_service.database.get_user_meta().unwrap().unwrap().canary_ciphertext.is_some());
+        assert!(password_service.validate_master_key(login.master_key.as_deref().unwrap()).unwrap());
+        assert_eq!(reveal(&password_service, id, login.master_key.clone().unwrap()), "hunter2");
+
+        // Subsequent logins unwrap the same vault key
+        let again = user_service.login(LoginRequest { master_password: "original_master".to_string() }).unwrap();
+        assert_eq!(again.master_key, login.master_key);
+    }
+
+    #[test]
+    fn test_legacy_reset_refuses_to_orphan_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        let id = make_legacy_vault(&user_service, &password_service);
+
+        let response = user_service.reset_master_password(reset_request(false)).unwrap();
+        assert!(!response.success);
+        assert_eq!(response.entries_at_risk, Some(1));
+
+        // Nothing changed: the old password still unlocks the entry
+        let login = user_service.login(LoginRequest { master_password: "original_master".to_string() }).unwrap();
+        assert_eq!(reveal(&password_service, id, login.master_key.unwrap()), "hunter2");
+    }
+
+    #[test]
+    fn test_legacy_reset_with_wipe_clears_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, password_service) = services(&dir.path().join("pwdbox.db"));
+        make_legacy_vault(&user_service, &password_service);
+
+        let response = user_service.reset_master_password(reset_request(true)).unwrap();
+        assert!(response.success);
+        assert_eq!(password_service.get_password_count().unwrap().data.unwrap()["count"], 0);
+
+        // The vault is usable again under the new password
+        let new_key = response.master_key.unwrap();
+        let id = add_entry(&password_service, &new_key);
+        assert_eq!(reveal(&password_service, id, new_key), "hunter2");
+        assert!(!user_service.login(LoginRequest { master_password: "original_master".to_string() }).unwrap().success);
+    }
+
+    #[test]
+    fn test_password_hint_is_validated_and_kept_across_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, _) = services(&dir.path().join("pwdbox.db"));
+        let hint_field = |result: Result<AuthResponse>| match PwdBoxError::from(result.unwrap_err()) {
+            PwdBoxError::Validation { field, .. } => field.unwrap(),
+            other => panic!("not a validation error: {:?}", other),
+        };
+
+        for hint in ["original_master", "ORIGINAL", "x".repeat(MAX_PASSWORD_HINT_CHARS + 1).as_str()] {
+            let mut request = setup_request();
+            request.password_hint = Some(hint.to_string());
+            assert_eq!(hint_field(user_service.setup_app(request)), "password_hint");
+        }
+        let mut request = setup_request();
+        request.password_hint = Some("  first pet, then where  ".to_string());
+        user_service.setup_app(request).unwrap();
+        assert_eq!(user_service.get_password_hint().unwrap().as_deref(), Some("first pet, then where"));
+
+        assert!(user_service.set_password_hint("wrong_master", "anything").is_err());
+        assert!(user_service.set_password_hint("original_master", "master").is_err());
+        assert_eq!(user_service.set_password_hint("original_master", "  ").unwrap(), None);
+        assert_eq!(user_service.get_password_hint().unwrap(), None);
+
+        // Rewriting the row for a recovery code keeps it; a new password it gives away drops it
+        user_service.set_password_hint("original_master", "rhymes with shoe").unwrap();
+        assert!(user_service.generate_recovery_code("original_master").unwrap().success);
+        assert_eq!(user_service.get_password_hint().unwrap().as_deref(), Some("rhymes with shoe"));
+        assert!(user_service.change_master_password("original_master", "changed_master").unwrap().success);
+        assert_eq!(user_service.get_password_hint().unwrap().as_deref(), Some("rhymes with shoe"));
+        user_service.set_password_hint("changed_master", "again").unwrap();
+        assert!(user_service.change_master_password("changed_master", "changed_again").unwrap().success);
+        assert_eq!(user_service.get_password_hint().unwrap(), None);
+    }
+
+    #[test]
+    fn test_login_stats_report_the_previous_login_and_reset_on_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let (user_service, _) = services(&dir.path().join("pwdbox.db"));
+        user_service.setup_app(setup_request()).unwrap();
+        let login = |master_password: &str| user_service.login(LoginRequest { master_password: master_password.to_string() }).unwrap();
+
+        assert!(!login("wrong_master").success);
+        // Recovery attempts share the throttle but are not logins
+        assert!(!user_service.verify_recovery_answers(answers(&["wrong", "wrong", "wrong"])).unwrap());
+        let stats = user_service.database.get_login_stats().unwrap();
+        assert_eq!(stats.failed_since_last_login, 1);
+        assert!(stats.last_login_at.is_none() && stats.last_failed_at.is_some());
+
+        let first = login("original_master");
+        assert_eq!(first.previous_login, Some(stats.clone()));
+        let after_first = user_service.database.get_login_stats().unwrap();
+        assert_eq!(after_first.failed_since_last_login, 0);
+        assert_eq!(after_first.last_failed_at, stats.last_failed_at);
+        assert!(after_first.last_login_at.is_some());
+
+        // A failure alone does not reset the count
+        assert!(!login("wrong_master").success);
+        let second = login("original_master").previous_login.unwrap();
+        assert_eq!((second.last_login_at, second.failed_since_last_login), (after_first.last_login_at, 1));
+        assert_eq!(user_service.database.get_login_stats().unwrap().failed_since_last_login, 0);
+    }
+}
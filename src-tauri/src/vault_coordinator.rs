@@ -0,0 +1,2159 @@
+use crate::breach_check::{self, BreachCheckReport, RangeCache};
+use crate::capabilities::{self, Capability, CapabilityRegistry};
+use crate::crypto::{CryptoService, KdfBenchmark, KdfParams, SecretKey};
+use crate::database::{Database, ExportData, HostIcon};
+use crate::database_key;
+use crate::error::PwdBoxError;
+use crate::events::{EventEmitter, EventSink};
+use crate::favicons::{self, FetchedIcon};
+use crate::export_service::{ExportService, ImportRequest, ImportResponse, RestoreResponse};
+use crate::migrations::MigrationProgress;
+use crate::os_keychain::{self, SecretStore};
+use crate::pin_unlock::{self, PinOutcome, PinUnlock};
+use crate::password_service::PasswordService;
+#[cfg(feature = "query-console")]
+use crate::query_console::QueryConsole;
+use crate::reminder_service::ReminderService;
+use crate::session::{self, SessionInvalidated};
+use crate::settings_service::SettingsService;
+use crate::snapshot_service::SnapshotService;
+use crate::time_utils;
+use crate::user_service::{AuthResponse, LoginRequest, LoginStart, PreparedDuress, RecoveryCodeResponse, RecoveryStatus, ResetPasswordRequest, ResetWithCodeRequest, SetupRequest, UserService};
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Owns every service and is the only place that takes more than one lock.
+//
+// Lock order, outermost first:
+//   1. vault            shared by single-service commands, exclusive for vault-wide operations
+//   2. user_service
+//   3. password_service
+//   4. export_service
+//   5. settings_service
+//   6. reminder_service
+//   7. snapshot_service
+//   8. query_console
+//
+// Single-service commands take the vault lock shared and then their one service lock.
+// Vault-wide operations (setup, login migrations, master password change/reset, import,
+// backup and snapshot restore, database encryption, data directory changes)
+// take the vault lock exclusively, so no other command is mid-flight while they run and
+// they may then take any service locks they need, in the order above.
+//
+// Setup, login, master password changes and KDF parameter changes do their Argon2 work with no lock held and
+// only take the locks to read user meta and to write the result, so a slow derivation
+// never holds up other commands.
+//
+// Every service holds a clone of one Database and so shares its single connection, which
+// is keyed when the database file is encrypted (see database_key). The password and export
+// services likewise share one EventEmitter for their change events.
+//
+// A login with the duress password binds the services to the decoy database instead, until
+// the vault locks or the next login; see setup_duress. Nothing a command returns tells the
+// two apart, so commands that work on files of the real vault (encrypting the database,
+// changing the data directory) are turned down while the decoy is bound.
+pub struct VaultCoordinator {
+    vault: RwLock<()>,
+    // Only changed by change_data_dir, with the vault lock held exclusively
+    app_data_dir: RwLock<PathBuf>,
+    // The vault's own database, also while the services are bound to the decoy. Changed
+    // with the vault lock held exclusively, like the binding.
+    database: RwLock<Database>,
+    decoy_bound: AtomicBool,
+    events: EventEmitter,
+    user_service: Mutex<UserService>,
+    password_service: Mutex<PasswordService>,
+    export_service: Mutex<ExportService>,
+    settings_service: Mutex<SettingsService>,
+    reminder_service: Mutex<ReminderService>,
+    snapshot_service: Mutex<SnapshotService>,
+    #[cfg(feature = "query-console")]
+    query_console: Mutex<QueryConsole>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| anyhow!("Service state is unavailable after an earlier failure"))
+}
+
+impl VaultCoordinator {
+    // Open the vault database in `app_data_dir` once and hand every service a clone of it
+    pub fn open(app_data_dir: &Path) -> Result<Self> {
+        let database = open_database(app_data_dir, database_key(app_data_dir)?.as_ref())?;
+        let events = EventEmitter::default();
+        Ok(VaultCoordinator {
+            vault: RwLock::new(()),
+            app_data_dir: RwLock::new(app_data_dir.to_path_buf()),
+            database: RwLock::new(database.clone()),
+            decoy_bound: AtomicBool::new(false),
+            user_service: Mutex::new(UserService::new(database.clone())),
+            password_service: Mutex::new(PasswordService::new(database.clone(), events.clone())),
+            export_service: Mutex::new(ExportService::new(database.clone(), events.clone())),
+            settings_service: Mutex::new(SettingsService::new(database.clone())),
+            reminder_service: Mutex::new(ReminderService::new(database.clone())),
+            snapshot_service: Mutex::new(SnapshotService::new(database.clone(), app_data_dir.join("snapshots"))),
+            #[cfg(feature = "query-console")]
+            query_console: Mutex::new(QueryConsole::new(database)),
+            events,
+        })
+    }
+
+    // The directory the vault database and its companions (key file, snapshots) live in
+    pub fn data_dir(&self) -> PathBuf {
+        self.app_data_dir.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    // Where change events go from now on; the app sets its handle here once it has one
+    pub fn set_event_sink(&self, sink: Arc<dyn EventSink>) {
+        self.events.set_sink(sink);
+    }
+
+    fn shared(&self) -> Result<RwLockReadGuard<'_, ()>> {
+        self.vault.read().map_err(|_| anyhow!("Vault state is unavailable after an earlier failure"))
+    }
+
+    fn exclusive(&self) -> Result<RwLockWriteGuard<'_, ()>> {
+        self.vault.write().map_err(|_| anyhow!("Vault state is unavailable after an earlier failure"))
+    }
+
+    // Single-service access
+
+    pub fn users<T>(&self, f: impl FnOnce(&UserService) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.user_service)?;
+        f(&service)
+    }
+
+    pub fn passwords<T>(&self, f: impl FnOnce(&PasswordService) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.password_service)?;
+        f(&service)
+    }
+
+    pub fn exports<T>(&self, f: impl FnOnce(&ExportService) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.export_service)?;
+        f(&service)
+    }
+
+    pub fn settings<T>(&self, f: impl FnOnce(&SettingsService) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.settings_service)?;
+        f(&service)
+    }
+
+    pub fn reminders<T>(&self, f: impl FnOnce(&ReminderService) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.reminder_service)?;
+        f(&service)
+    }
+
+    pub fn snapshots<T>(&self, f: impl FnOnce(&SnapshotService) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.snapshot_service)?;
+        f(&service)
+    }
+
+    #[cfg(feature = "query-console")]
+    pub fn query_console<T>(&self, f: impl FnOnce(&QueryConsole) -> Result<T>) -> Result<T> {
+        let _vault = self.shared()?;
+        let service = lock(&self.query_console)?;
+        f(&service)
+    }
+
+    // Optional features of this build, each registered by the module that owns it
+    pub fn capabilities(&self) -> Result<CapabilityRegistry> {
+        let mut registry = CapabilityRegistry::default();
+        registry.register_build_features();
+        if cfg!(feature = "sqlcipher") {
+            registry.register(capabilities::ENCRYPTED_DATABASE, Capability::available(self.is_database_encrypted()));
+        }
+        self.settings(|settings_service| settings_service.register_capabilities(&mut registry))?;
+        self.reminders(|reminder_service| reminder_service.register_capabilities(&mut registry))?;
+        Ok(registry)
+    }
+
+    // Look every password up against Have I Been Pwned and flag the breached entries. The
+    // lookups run with no lock held; the password service is only locked to read the
+    // digests and to flag what was found.
+    pub fn check_breaches(&self, master_key: &str, fetch: &mut dyn FnMut(&str) -> Result<String>) -> Result<BreachCheckReport> {
+        if !self.settings(|settings_service| settings_service.get_breach_check_enabled())? {
+            return Err(anyhow!("Breach checks are turned off in settings"));
+        }
+        let (digests, unreadable_count) = self.passwords(|password_service| password_service.breach_digests(master_key))?;
+        let cache = RangeCache::new(self.data_dir().join("breach_ranges"));
+        let report = breach_check::check(&digests, unreadable_count, &cache, &time_utils::now(), fetch);
+
+        let breached: Vec<i64> = report.breaches.keys().copied().collect();
+        if !breached.is_empty() {
+            self.passwords(|password_service| password_service.mark_entries_breached(&breached))?;
+        }
+        Ok(report)
+    }
+
+    // Fetch the favicon for an entry's host, or reuse the one cached for it. Like the breach
+    // check, the download runs with no lock held. A host without a usable icon is not an
+    // error: the result carries no icon and the miss is cached too.
+    pub fn fetch_icon(&self, entry_id: i64, fetch: &mut dyn FnMut(&str) -> Result<Vec<u8>>) -> Result<FetchedIcon> {
+        let (enabled, source) = self.settings(|settings_service| {
+            Ok((settings_service.get_favicon_fetch_enabled()?, settings_service.get_favicon_source()?))
+        })?;
+        if !enabled {
+            return Err(anyhow!("Icon fetching is turned off in settings"));
+        }
+        let host = self
+            .passwords(|password_service| password_service.entry_url_host(entry_id))?
+            .ok_or_else(|| PwdBoxError::not_found("Password entry not found"))?;
+        let Some(host) = host else {
+            return Ok(FetchedIcon { id: entry_id, host: None, icon: None });
+        };
+
+        let now = time_utils::now();
+        let cached = self.passwords(|password_service| password_service.cached_favicon(&host))?;
+        let icon = match cached.filter(|cached| favicons::is_fresh(cached, &now)) {
+            Some(cached) => cached.icon,
+            None => {
+                let cached = HostIcon { icon: favicons::download(&source, &host, fetch), fetched_at: time_utils::to_rfc3339(&now) };
+                self.passwords(|password_service| password_service.store_favicon(entry_id, &host, &cached))?;
+                cached.icon
+            }
+        };
+        Ok(FetchedIcon { id: entry_id, host: Some(host), icon })
+    }
+
+    // Vault-wide operations
+
+    pub fn setup_app(&self, request: SetupRequest) -> Result<AuthResponse> {
+        let params = self.users(|user_service| {
+            if user_service.is_app_setup()? {
+                return Ok(None);
+            }
+            user_service.target_kdf_params().map(Some)
+        })?;
+        let Some(params) = params else {
+            return Ok(AuthResponse::failure("App is already set up"));
+        };
+        if request.encrypt_database && !cfg!(feature = "sqlcipher") {
+            return Err(anyhow!("This build cannot encrypt the vault database"));
+        }
+        let encrypt_database = request.encrypt_database;
+        let prepared = UserService::prepare_setup(request, &params)?;
+
+        let _vault = self.exclusive()?;
+        // Encrypted before the first secret is written, so none ever reaches a plaintext file
+        if encrypt_database && !self.is_database_encrypted() {
+            self.encrypt_database()?;
+        }
+        lock(&self.user_service)?.commit_setup(prepared)
+    }
+
+    // Login may run data migrations that rewrite every entry. Every login starts from the
+    // real vault; a wrong password that is the duress password opens the decoy instead and
+    // is not counted as a failure here. Checking it is one more Argon2 hash on a failed
+    // login when a duress password is set, so the timing of a login is not hidden.
+    pub fn login_with_progress(&self, request: LoginRequest, on_progress: &mut dyn FnMut(&MigrationProgress)) -> Result<AuthResponse> {
+        self.leave_decoy()?;
+        loop {
+            let user_meta = match self.users(|user_service| user_service.start_login())? {
+                LoginStart::Throttled(response) => return Ok(response),
+                LoginStart::Verify(user_meta) => *user_meta,
+            };
+            let keys = UserService::verify_master_password(&user_meta, &request.master_password)?;
+            let decoy_response = match keys {
+                None if UserService::duress_matches(&user_meta, &request.master_password)? => {
+                    self.login_decoy(&request.master_password, on_progress)?
+                }
+                _ => None,
+            };
+
+            let response = match decoy_response {
+                Some(response) => {
+                    // The real vault does not count the duress password
+                    self.real_database().uncount_failed_login()?;
+                    Some(response)
+                }
+                None => {
+                    let _vault = self.exclusive()?;
+                    let response = lock(&self.user_service)?.finish_login(&user_meta, keys, on_progress)?;
+                    // Answered like any other wrong password, so nothing shows the vault is gone
+                    if response.as_ref().is_some_and(|response| !response.success) && lock(&self.user_service)?.panic_wipe_due()? {
+                        self.panic_wipe()?;
+                    }
+                    response
+                }
+            };
+            if let Some(response) = response {
+                // The login stands even if the upgrade fails; the next one tries again
+                if response.success {
+                    if let Err(e) = self.upgrade_kdf(&request.master_password) {
+                        log::warn!("Failed to upgrade the master password KDF: {}", e);
+                    }
+                }
+                return Ok(response);
+            }
+        }
+    }
+
+    // Log in to the decoy vault with the duress password, counted there like any login, and
+    // bind the services to it if that succeeds. None when there is no decoy database.
+    fn login_decoy(&self, duress_password: &str, on_progress: &mut dyn FnMut(&MigrationProgress)) -> Result<Option<AuthResponse>> {
+        let Some(decoy) = open_decoy(&self.data_dir())? else {
+            return Ok(None);
+        };
+        let user_service = UserService::new(decoy.clone());
+        loop {
+            let user_meta = match user_service.start_login()? {
+                LoginStart::Throttled(response) => return Ok(Some(response)),
+                LoginStart::Verify(user_meta) => *user_meta,
+            };
+            let keys = UserService::verify_master_password(&user_meta, duress_password)?;
+
+            let _vault = self.exclusive()?;
+            if let Some(response) = user_service.finish_login(&user_meta, keys, on_progress)? {
+                if response.success {
+                    self.bind(decoy, self.data_dir().join(DECOY_SNAPSHOTS_DIR))?;
+                    self.decoy_bound.store(true, Ordering::SeqCst);
+                }
+                return Ok(Some(response));
+            }
+        }
+    }
+
+    // Bind the services back to the real vault after a decoy session. The app calls this
+    // when the vault locks, so the locked screen never shows the decoy.
+    pub fn leave_decoy(&self) -> Result<()> {
+        let _vault = self.exclusive()?;
+        if self.decoy_bound.swap(false, Ordering::SeqCst) {
+            self.bind(self.real_database(), self.data_dir().join("snapshots"))?;
+        }
+        Ok(())
+    }
+
+    fn real_database(&self) -> Database {
+        self.database.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    // Commands that would touch the real vault's files are refused in a decoy session with
+    // an error that does not say why
+    fn ensure_not_decoy(&self) -> Result<()> {
+        if self.decoy_bound.load(Ordering::SeqCst) {
+            return Err(anyhow!("This cannot be done right now; lock the vault and try again"));
+        }
+        Ok(())
+    }
+
+    // Overwrite and delete the vault: its database, key file and snapshots, and the decoy,
+    // which nothing could open any more. The services are left on a new, empty database, as
+    // before setup. Callers hold the vault lock exclusively.
+    fn panic_wipe(&self) -> Result<()> {
+        let app_data_dir = self.data_dir();
+        self.reopen_all(Database::new(PathBuf::from(":memory:"))?)?;
+        shred_database_files(&app_data_dir.join(DATABASE_FILE))?;
+        shred_file(&app_data_dir.join(database_key::MARKER_FILE))?;
+        shred_dir(&app_data_dir.join("snapshots"))?;
+        shred_database_files(&app_data_dir.join(DECOY_FILE))?;
+        shred_dir(&app_data_dir.join(DECOY_SNAPSHOTS_DIR))?;
+        self.reopen_all(open_database(&app_data_dir, None)?)
+    }
+
+    // Wipe the vault after `threshold` failed unlock attempts in a row, or never with None.
+    // Attempts are counted in the database as the login throttle counts them, recovery
+    // attempts included, so a restart does not reset them. Any change takes the master
+    // password, checked with no lock held. Returns the threshold as saved.
+    pub fn set_panic_wipe_threshold(&self, master_password: &str, threshold: Option<u32>) -> Result<Option<u32>> {
+        let user_meta = self.users(|user_service| user_service.user_meta())?;
+        if !CryptoService::verify_password(master_password, &user_meta.master_hash)? {
+            return Err(anyhow!("Master password is incorrect"));
+        }
+        self.settings(|settings_service| settings_service.set_panic_wipe_threshold(threshold))
+    }
+
+    // Re-hash the master password if it was made with other than the target parameters
+    fn upgrade_kdf(&self, master_password: &str) -> Result<()> {
+        loop {
+            let (user_meta, params) = self.users(|user_service| Ok((user_service.user_meta()?, user_service.target_kdf_params()?)))?;
+            if user_meta.kdf_params == params {
+                return Ok(());
+            }
+            // Changed by another login in the meantime
+            let Some(prepared) = UserService::prepare_kdf_change(&user_meta, master_password, &params)? else {
+                return Ok(());
+            };
+
+            let _vault = self.exclusive()?;
+            if lock(&self.user_service)?.commit_kdf_change(&user_meta, prepared)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Make `params` the target and re-hash the master password and re-wrap the vault key
+    // under them. Export files and new answer hashes use the target from then on; the
+    // recovery wrap follows on the next reset, when the answers are at hand.
+    pub fn set_kdf_params(&self, master_password: &str, params: KdfParams) -> Result<KdfParams> {
+        params.validate()?;
+        loop {
+            let user_meta = self.users(|user_service| user_service.user_meta())?;
+            let Some(prepared) = UserService::prepare_kdf_change(&user_meta, master_password, &params)? else {
+                return Err(anyhow!("Master password is incorrect"));
+            };
+
+            let _vault = self.exclusive()?;
+            if lock(&self.user_service)?.commit_kdf_change(&user_meta, prepared)? {
+                return lock(&self.settings_service)?.set_kdf_params(params);
+            }
+        }
+    }
+
+    // Benchmark Argon2 without any lock held. Before setup the recommendation becomes the
+    // target straight away, so setup hashes with it; afterwards applying it re-hashes the
+    // master password, which set_kdf_params does.
+    pub fn benchmark_kdf(&self, target_ms: u64) -> Result<KdfBenchmark> {
+        let benchmark = CryptoService::benchmark_kdf(target_ms)?;
+
+        let _vault = self.exclusive()?;
+        let user_service = lock(&self.user_service)?;
+        if !user_service.is_app_setup()? {
+            lock(&self.settings_service)?.set_kdf_params(benchmark.recommended)?;
+        }
+        Ok(benchmark)
+    }
+
+    // In a decoy session the password changed is the duress password, so the real vault's
+    // copy of it follows and the next duress login still opens the decoy
+    pub fn change_master_password(&self, current_password: &str, new_password: &str) -> Result<AuthResponse> {
+        loop {
+            let (user_meta, params) = self.users(|user_service| Ok((user_service.user_meta()?, user_service.target_kdf_params()?)))?;
+            let Some(prepared) = UserService::prepare_password_change(&user_meta, current_password, new_password, &params)? else {
+                return Ok(AuthResponse::failure("Current password is incorrect"));
+            };
+            let duress = if self.decoy_bound.load(Ordering::SeqCst) {
+                Some(UserService::hash_duress(new_password, &params)?)
+            } else {
+                None
+            };
+
+            let _vault = self.exclusive()?;
+            if let Some(response) = lock(&self.user_service)?.commit_password_change(&user_meta, prepared)? {
+                if let Some((duress_hash, duress_salt)) = duress.filter(|_| self.decoy_bound.load(Ordering::SeqCst)) {
+                    self.real_database().set_duress(Some(&duress_hash), Some(&duress_salt))?;
+                }
+                return Ok(response);
+            }
+        }
+    }
+
+    pub fn reset_master_password(&self, request: ResetPasswordRequest) -> Result<AuthResponse> {
+        let _vault = self.exclusive()?;
+        lock(&self.user_service)?.reset_master_password(request)
+    }
+
+    pub fn reset_master_password_with_code(&self, request: ResetWithCodeRequest) -> Result<AuthResponse> {
+        let _vault = self.exclusive()?;
+        lock(&self.user_service)?.reset_master_password_with_code(request)
+    }
+
+    // Checking the master password is the slow part, so like a password change it runs
+    // with no lock held
+    pub fn generate_recovery_code(&self, master_password: &str) -> Result<RecoveryCodeResponse> {
+        loop {
+            let user_meta = self.users(|user_service| user_service.user_meta())?;
+            let Some(prepared) = UserService::prepare_recovery_code(&user_meta, master_password)? else {
+                return Ok(RecoveryCodeResponse::failure("Master password is incorrect"));
+            };
+
+            let _vault = self.exclusive()?;
+            if let Some(response) = lock(&self.user_service)?.commit_recovery_code(&user_meta, prepared)? {
+                return Ok(response);
+            }
+        }
+    }
+
+    // Replace the security questions; see UserService::change_security_questions
+    pub fn change_security_questions(&self, master_password: &str, questions: &[(String, String)]) -> Result<RecoveryStatus> {
+        loop {
+            let (user_meta, params) = self.users(|user_service| Ok((user_service.user_meta()?, user_service.target_kdf_params()?)))?;
+            let Some(prepared) = UserService::prepare_question_change(&user_meta, master_password, questions, &params)? else {
+                return Err(anyhow!("Master password is incorrect"));
+            };
+
+            let _vault = self.exclusive()?;
+            let user_service = lock(&self.user_service)?;
+            if user_service.commit_question_change(&user_meta, prepared)? {
+                return user_service.get_recovery_status();
+            }
+        }
+    }
+
+    // The password check runs unlocked; the hint itself was validated before any hashing
+    pub fn set_password_hint(&self, master_password: &str, hint: &str) -> Result<Option<String>> {
+        let hint = UserService::validate_password_hint(hint, master_password)?;
+        loop {
+            let user_meta = self.users(|user_service| user_service.user_meta())?;
+            if !CryptoService::verify_password(master_password, &user_meta.master_hash)? {
+                return Err(anyhow!("Master password is incorrect"));
+            }
+
+            let _vault = self.exclusive()?;
+            if lock(&self.user_service)?.commit_password_hint(&user_meta, hint.as_deref())? {
+                return Ok(hint);
+            }
+        }
+    }
+
+    // Set the duress password. Logging in with it opens the decoy vault in decoy.db: a vault
+    // of its own, empty at first, with the duress password as its master password and the
+    // same security questions and preferences as this one. Setting it again starts the decoy
+    // over. In a decoy session only the decoy's own record changes, which no login reads, so
+    // the call looks the same there but the real vault and the decoy are left alone.
+    //
+    // Keychain unlock opens the real vault without any password, so it should stay off on a
+    // vault with a duress password.
+    pub fn setup_duress(&self, master_password: &str, duress_password: &str) -> Result<()> {
+        loop {
+            let (user_meta, params) = self.users(|user_service| Ok((user_service.user_meta()?, user_service.target_kdf_params()?)))?;
+            let Some(prepared) = UserService::prepare_duress(&user_meta, master_password, duress_password, &params)? else {
+                return Err(anyhow!("Master password is incorrect"));
+            };
+
+            let _vault = self.exclusive()?;
+            if !self.decoy_bound.load(Ordering::SeqCst) {
+                self.create_decoy(&prepared)?;
+            }
+            if lock(&self.user_service)?.commit_duress(&user_meta, &prepared)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Replace the decoy database with a new one holding only the prepared decoy, keyed like
+    // the vault's. Callers hold the vault lock exclusively.
+    fn create_decoy(&self, prepared: &PreparedDuress) -> Result<()> {
+        let app_data_dir = self.data_dir();
+        let path = app_data_dir.join(DECOY_FILE);
+        remove_database_files(&path)?;
+        remove_dir_if_exists(&app_data_dir.join(DECOY_SNAPSHOTS_DIR))?;
+        let decoy = match database_key(&app_data_dir)? {
+            Some(key) => Database::new_encrypted(path, &key)?,
+            None => Database::new(path)?,
+        };
+        lock(&self.settings_service)?.copy_preferences_to(&decoy)?;
+        UserService::new(decoy).commit_decoy_setup(prepared)
+    }
+
+    // Keychain unlock. The keychain may prompt the user, so it is only used with no lock held.
+
+    // The generation and vault key of a session key that still opens the vault
+    fn session_vault_key(&self, master_key: &str) -> Result<(i64, SecretKey)> {
+        let (generation, vault_key) = session::parse_key(master_key)?;
+        if !self.users(|user_service| user_service.opens_vault(&vault_key, generation))? {
+            let current_generation = self.users(|user_service| Ok(user_service.user_meta()?.generation))?;
+            return Err(SessionInvalidated { session_generation: generation, current_generation }.into());
+        }
+        Ok((generation, vault_key))
+    }
+
+    // Keep the session's vault key in the OS keychain, replacing any earlier item
+    pub fn enable_os_keychain_unlock(&self, master_key: &str, store: &dyn SecretStore) -> Result<()> {
+        let (generation, vault_key) = self.session_vault_key(master_key)?;
+        let unlock = os_keychain::enable(&vault_key, generation, store)?;
+
+        let saved = self.settings(|settings_service| {
+            let previous = settings_service.get_os_keychain_unlock()?;
+            settings_service.set_os_keychain_unlock(Some(&unlock))?;
+            Ok(previous)
+        });
+        match saved {
+            Ok(Some(previous)) => store.delete(&previous.account),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                let _ = store.delete(&unlock.account);
+                Err(e)
+            }
+        }
+    }
+
+    // Forget the wrapped key first, so keychain unlock is off even if the item cannot be deleted
+    pub fn disable_os_keychain_unlock(&self, store: &dyn SecretStore) -> Result<()> {
+        let previous = self.settings(|settings_service| {
+            let previous = settings_service.get_os_keychain_unlock()?;
+            settings_service.set_os_keychain_unlock(None)?;
+            Ok(previous)
+        })?;
+        match previous {
+            Some(previous) => store.delete(&previous.account),
+            None => Ok(()),
+        }
+    }
+
+    // Log in with the vault key kept in the keychain. A key the password or vault has
+    // changed under since, or a keychain item that is gone, turns keychain unlock off.
+    pub fn unlock_with_os_keychain(&self, store: &dyn SecretStore) -> Result<AuthResponse> {
+        let Some(unlock) = self.settings(|settings_service| settings_service.get_os_keychain_unlock())? else {
+            return Err(PwdBoxError::validation("Keychain unlock is not turned on").into());
+        };
+        let response = match os_keychain::open(&unlock, store)? {
+            Some(vault_key) => self.users(|user_service| user_service.finish_os_keychain_unlock(&vault_key, unlock.generation))?,
+            None => None,
+        };
+        if let Some(response) = response {
+            return Ok(response);
+        }
+        self.disable_os_keychain_unlock(store)?;
+        Ok(AuthResponse::failure("Keychain unlock no longer matches this vault and was turned off. Log in with the master password."))
+    }
+
+    // PIN unlock; see pin_unlock.rs. The keychain and the Argon2 work are only used with no
+    // lock held, as for keychain unlock.
+
+    // Wrap the session's vault key under `pin`, replacing any earlier PIN
+    pub fn enable_pin_unlock(&self, master_key: &str, pin: &str, store: &dyn SecretStore) -> Result<()> {
+        pin_unlock::validate_pin(pin)?;
+        let (generation, vault_key) = self.session_vault_key(master_key)?;
+        let params = self.users(|user_service| user_service.target_kdf_params())?;
+        let unlock = pin_unlock::enable(&vault_key, generation, pin, &params, store)?;
+
+        let saved = self.settings(|settings_service| {
+            let previous = settings_service.get_pin_unlock()?;
+            settings_service.set_pin_unlock(Some(&unlock))?;
+            Ok(previous)
+        });
+        match saved {
+            Ok(Some(previous)) => store.delete(&previous.account),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                let _ = store.delete(&unlock.account);
+                Err(e)
+            }
+        }
+    }
+
+    // Forget the wrapped key first, so PIN unlock is off even if the pepper cannot be deleted
+    pub fn disable_pin_unlock(&self, store: &dyn SecretStore) -> Result<()> {
+        let previous = self.settings(|settings_service| {
+            let previous = settings_service.get_pin_unlock()?;
+            settings_service.set_pin_unlock(None)?;
+            Ok(previous)
+        })?;
+        match previous {
+            Some(previous) => store.delete(&previous.account),
+            None => Ok(()),
+        }
+    }
+
+    // Log in with the PIN. Each attempt is counted in settings before the PIN is checked, so
+    // concurrent attempts cannot get past the cap and a restart does not reset the count; a
+    // right PIN clears it again, and the last allowed wrong one turns PIN unlock off. So do a
+    // key the password or vault has changed under since, and a pepper gone from the keychain.
+    pub fn unlock_with_pin(&self, pin: &str, store: &dyn SecretStore) -> Result<AuthResponse> {
+        let reserved = self.settings(|settings_service| {
+            let Some(mut unlock) = settings_service.get_pin_unlock()? else {
+                return Err(PwdBoxError::validation("PIN unlock is not turned on").into());
+            };
+            if unlock.failed_attempts >= pin_unlock::MAX_PIN_ATTEMPTS {
+                return Ok(None);
+            }
+            unlock.failed_attempts += 1;
+            settings_service.set_pin_unlock(Some(&unlock))?;
+            Ok(Some(unlock))
+        })?;
+        // The attempts left were all taken by others still being checked
+        let Some(unlock) = reserved else {
+            return Ok(AuthResponse::failure("Too many wrong PINs. Log in with the master password."));
+        };
+
+        match pin_unlock::open(&unlock, pin, store)? {
+            PinOutcome::Opened(vault_key) => {
+                self.reset_pin_attempts(&unlock.account)?;
+                if let Some(response) = self.users(|user_service| user_service.finish_pin_unlock(&vault_key, unlock.generation))? {
+                    return Ok(response);
+                }
+            }
+            PinOutcome::WrongPin if unlock.failed_attempts >= pin_unlock::MAX_PIN_ATTEMPTS => {
+                self.disable_pin_unlock(store)?;
+                return Ok(AuthResponse::failure("Too many wrong PINs. PIN unlock was turned off; log in with the master password."));
+            }
+            PinOutcome::WrongPin => {
+                return Ok(AuthResponse::failure(&format!(
+                    "Wrong PIN. Attempts left before PIN unlock turns off: {}",
+                    pin_unlock::MAX_PIN_ATTEMPTS - unlock.failed_attempts
+                )));
+            }
+            PinOutcome::PepperMissing => {}
+        }
+        self.disable_pin_unlock(store)?;
+        Ok(AuthResponse::failure("PIN unlock no longer matches this vault and was turned off. Log in with the master password."))
+    }
+
+    // Clear the failed attempts of the PIN unlock kept for `account`, unless it was turned
+    // off or replaced in the meantime
+    fn reset_pin_attempts(&self, account: &str) -> Result<()> {
+        self.settings(|settings_service| {
+            let Some(unlock) = settings_service.get_pin_unlock()?.filter(|unlock| unlock.account == account) else {
+                return Ok(());
+            };
+            settings_service.set_pin_unlock(Some(&PinUnlock { failed_attempts: 0, ..unlock }))
+        })
+    }
+
+    pub fn import_data(&self, request: ImportRequest) -> Result<ImportResponse> {
+        let _vault = self.exclusive()?;
+        lock(&self.export_service)?.import_data(request)
+    }
+
+    pub fn confirm_restore(&self, export_data: &ExportData) -> Result<RestoreResponse> {
+        let _vault = self.exclusive()?;
+        lock(&self.export_service)?.confirm_restore(export_data)
+    }
+
+    pub fn restore_snapshot(&self, file_name: &str, vault_key: &str) -> Result<usize> {
+        let _vault = self.exclusive()?;
+        lock(&self.snapshot_service)?.restore_snapshot(file_name, vault_key)
+    }
+
+    pub fn is_database_encrypted(&self) -> bool {
+        self.data_dir().join(database_key::MARKER_FILE).exists()
+    }
+
+    // Move an existing plaintext vault database to an encrypted one
+    pub fn migrate_to_encrypted_db(&self, master_key: &str) -> Result<()> {
+        if !self.passwords(|password_service| password_service.validate_master_key(master_key))? {
+            return Err(PwdBoxError::invalid_key("Invalid master key").into());
+        }
+        let _vault = self.exclusive()?;
+        self.encrypt_database()
+    }
+
+    // Copy the database into a new SQLCipher file, then swap it in for the plaintext one.
+    // Callers hold the vault lock exclusively. A decoy database made before stays plaintext
+    // until the duress password is set again.
+    fn encrypt_database(&self) -> Result<()> {
+        self.ensure_not_decoy()?;
+        if !cfg!(feature = "sqlcipher") {
+            return Err(anyhow!("This build cannot encrypt the vault database"));
+        }
+        if self.is_database_encrypted() {
+            return Err(anyhow!("The vault database is already encrypted"));
+        }
+
+        let app_data_dir = self.data_dir();
+        let db_path = app_data_dir.join(DATABASE_FILE);
+        let encrypted_path = app_data_dir.join(ENCRYPTING_FILE);
+        if encrypted_path.exists() {
+            fs::remove_file(&encrypted_path)?;
+        }
+        let key = CryptoService::generate_vault_key();
+
+        Database::new(db_path.clone())?.export_encrypted_to(&encrypted_path, &key)?;
+        // Opening it checks the key and the copy before anything depends on them
+        drop(Database::new_encrypted(encrypted_path.clone(), &key)?);
+        database_key::write(&app_data_dir, &key)?;
+
+        // Nothing may hold the plaintext file open while it is replaced
+        self.reopen_all(Database::new(PathBuf::from(":memory:"))?)?;
+        if let Err(e) = fs::rename(&encrypted_path, &db_path) {
+            // Still on the plaintext file
+            database_key::remove(&app_data_dir)?;
+            self.reopen_all(Database::new(db_path)?)?;
+            return Err(anyhow!("Failed to replace the vault database: {}", e));
+        }
+        self.reopen_all(Database::new_encrypted(db_path, &key)?)
+    }
+
+    // Move the vault to `new_dir`, or with `move_existing` false start using the vault
+    // found there (a new one if there is none). A directory already holding a vault is
+    // refused unless `overwrite` is set: the moved vault then replaces it, and without
+    // moving, that vault is opened in place of the current one.
+    //
+    // A moved database is copied, checked to open with the same row counts, and only then
+    // put in place; `switch` runs next to record the new directory, and the old files are
+    // removed last (safety copies from restores and migrations stay behind). A failure
+    // before `switch` leaves the vault where it was.
+    pub fn change_data_dir(
+        &self,
+        new_dir: &Path,
+        move_existing: bool,
+        overwrite: bool,
+        switch: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let _vault = self.exclusive()?;
+        self.ensure_not_decoy()?;
+        let old_dir = self.data_dir();
+        fs::create_dir_all(new_dir)?;
+        if fs::canonicalize(new_dir)? == fs::canonicalize(&old_dir)? {
+            return Ok(());
+        }
+        if new_dir.join(DATABASE_FILE).exists() && !overwrite {
+            return Err(PwdBoxError::invalid_field(
+                "new_path",
+                format!("{} already holds a different vault", new_dir.display()),
+            )
+            .into());
+        }
+
+        if move_existing {
+            let key = database_key(&old_dir)?;
+            let source = open_database(&old_dir, key.as_ref())?;
+            let moving_path = new_dir.join(MOVING_FILE);
+            remove_database_files(&moving_path)?;
+            source.backup_to(&moving_path)?;
+            let copy = match &key {
+                Some(key) => Database::new_encrypted(moving_path.clone(), key)?,
+                None => Database::new(moving_path.clone())?,
+            };
+            if copy.row_counts()? != source.row_counts()? {
+                drop(copy);
+                remove_database_files(&moving_path)?;
+                return Err(anyhow!("The copy of the vault in {} does not match the original", new_dir.display()));
+            }
+            drop((copy, source));
+
+            remove_database_files(&new_dir.join(DATABASE_FILE))?;
+            match &key {
+                Some(key) => database_key::write(new_dir, key)?,
+                None => database_key::remove(new_dir)?,
+            }
+            fs::rename(&moving_path, new_dir.join(DATABASE_FILE))?;
+            copy_dir(&old_dir.join("snapshots"), &new_dir.join("snapshots"))?;
+
+            // The decoy goes along, keyed as before, or the duress password would stop working
+            remove_database_files(&new_dir.join(DECOY_FILE))?;
+            remove_dir_if_exists(&new_dir.join(DECOY_SNAPSHOTS_DIR))?;
+            if let Some(decoy) = open_decoy(&old_dir)? {
+                decoy.backup_to(&new_dir.join(DECOY_FILE))?;
+                copy_dir(&old_dir.join(DECOY_SNAPSHOTS_DIR), &new_dir.join(DECOY_SNAPSHOTS_DIR))?;
+            }
+        }
+
+        switch()?;
+        let database = open_database(new_dir, database_key(new_dir)?.as_ref())?;
+        *self.app_data_dir.write().unwrap_or_else(PoisonError::into_inner) = new_dir.to_path_buf();
+        self.reopen_all(database)?;
+
+        if move_existing {
+            let removed = remove_database_files(&old_dir.join(DATABASE_FILE))
+                .and_then(|()| database_key::remove(&old_dir))
+                .and_then(|()| remove_dir_if_exists(&old_dir.join("snapshots")))
+                .and_then(|()| remove_database_files(&old_dir.join(DECOY_FILE)))
+                .and_then(|()| remove_dir_if_exists(&old_dir.join(DECOY_SNAPSHOTS_DIR)));
+            if let Err(e) = removed {
+                log::warn!("Failed to remove the vault from {} after moving it: {}", old_dir.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    // Hand every service `database` in place of the one it has, as the vault's own database.
+    // Callers hold the vault lock exclusively, so no command is using the services meanwhile.
+    fn reopen_all(&self, database: Database) -> Result<()> {
+        *self.database.write().unwrap_or_else(PoisonError::into_inner) = database.clone();
+        self.decoy_bound.store(false, Ordering::SeqCst);
+        self.bind(database, self.data_dir().join("snapshots"))
+    }
+
+    // Point every service at `database`, keeping its snapshots in `snapshots_dir`. Callers
+    // hold the vault lock exclusively.
+    fn bind(&self, database: Database, snapshots_dir: PathBuf) -> Result<()> {
+        *lock(&self.user_service)? = UserService::new(database.clone());
+        *lock(&self.password_service)? = PasswordService::new(database.clone(), self.events.clone());
+        *lock(&self.export_service)? = ExportService::new(database.clone(), self.events.clone());
+        *lock(&self.settings_service)? = SettingsService::new(database.clone());
+        *lock(&self.reminder_service)? = ReminderService::new(database.clone());
+        #[cfg(feature = "query-console")]
+        {
+            *lock(&self.query_console)? = QueryConsole::new(database.clone());
+        }
+        *lock(&self.snapshot_service)? = SnapshotService::new(database, snapshots_dir);
+        Ok(())
+    }
+}
+
+const DATABASE_FILE: &str = "pwdbox.db";
+// The decoy vault a duress login opens, and its snapshots
+const DECOY_FILE: &str = "decoy.db";
+const DECOY_SNAPSHOTS_DIR: &str = "decoy_snapshots";
+// The encrypted copy while it is being written
+const ENCRYPTING_FILE: &str = "pwdbox.db.encrypting";
+// The copy in the new directory while a moved vault is checked
+const MOVING_FILE: &str = "pwdbox.db.moving";
+
+// A database file with its WAL and shared memory files, any of which may be missing
+fn remove_database_files(db_path: &Path) -> Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Overwrite a file with zeros, then remove it; nothing when it is missing. SSDs and
+// copy-on-write filesystems may keep the old blocks anyway, so an encrypted database,
+// whose key file goes the same way, is what makes a wipe thorough.
+fn shred_file(path: &Path) -> Result<()> {
+    let mut file = match fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let zeros = [0u8; 64 * 1024];
+    let mut remaining = file.metadata()?.len();
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+// As remove_database_files, overwriting each file first
+fn shred_database_files(db_path: &Path) -> Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        shred_file(Path::new(&path))?;
+    }
+    Ok(())
+}
+
+// The files directly in `dir`, then `dir` itself; nothing when it is missing
+fn shred_dir(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            shred_file(&entry.path())?;
+        }
+    }
+    remove_dir_if_exists(dir)
+}
+
+// The files directly in `from` (snapshots keep no subdirectories); nothing when it is missing
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_dir_if_exists(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// The database key, if the database file is encrypted. A key file next to a plaintext
+// database is left over from a migration that stopped before the swap and is dropped.
+fn database_key(app_data_dir: &Path) -> Result<Option<SecretKey>> {
+    let key = database_key::read(app_data_dir)?;
+    if key.is_some() && Database::is_plaintext_file(&app_data_dir.join(DATABASE_FILE)) {
+        log::warn!("Ignoring the database key file left by an interrupted encryption");
+        database_key::remove(app_data_dir)?;
+        return Ok(None);
+    }
+    Ok(key)
+}
+
+// The decoy database, None if there is none. It is keyed like the vault's unless it was
+// made before the vault was encrypted.
+fn open_decoy(app_data_dir: &Path) -> Result<Option<Database>> {
+    let path = app_data_dir.join(DECOY_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let decoy = match database_key(app_data_dir)? {
+        Some(key) if !Database::is_plaintext_file(&path) => Database::new_encrypted(path, &key)?,
+        _ => Database::new(path)?,
+    };
+    Ok(Some(decoy))
+}
+
+fn open_database(app_data_dir: &Path, key: Option<&SecretKey>) -> Result<Database> {
+    let db_path = app_data_dir.join(DATABASE_FILE);
+    match key {
+        Some(key) => Database::new_encrypted(db_path, key),
+        None => Database::new(db_path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{EntryType, SecretKind};
+    use crate::export_service::ExportRequest;
+    use crate::password_service::{AddPasswordRequest, GetPasswordsRequest};
+    use crate::os_keychain::MemoryStore;
+    use crate::session::SessionInvalidated;
+    use crate::user_service::{FREE_LOGIN_ATTEMPTS, LOCKOUT_LOGIN_ATTEMPTS};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_concurrent_logins_reads_and_password_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = Arc::new(VaultCoordinator::open(dir.path()).unwrap());
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        for index in 0..20 {
+            vault.passwords(|service| service.add_password(AddPasswordRequest {
+                software: format!("site{}", index),
+                account: "me@example.com".to_string(),
+                password: format!("password-{}", index),
+                notes: None,
+                expires_at: None,
+                tags: Vec::new(),
+                url: None,
+                entry_type: EntryType::Login,
+                secret_kind: SecretKind::Password,
+                master_key: master_key.clone(),
+            })).unwrap();
+        }
+
+        let (done, finished) = mpsc::channel();
+        let mut workers = Vec::new();
+        for worker in 0..8 {
+            let (vault, master_key, done) = (vault.clone(), master_key.clone(), done.clone());
+            workers.push(thread::spawn(move || {
+                for _ in 0..5 {
+                    match worker % 4 {
+                        0 => {
+                            // Either password may be current depending on when the change lands
+                            let login = vault.login_with_progress(
+                                LoginRequest { master_password: "original_master".to_string() },
+                                &mut |_| {},
+                            ).unwrap();
+                            if let Some(key) = login.master_key {
+                                assert_eq!(key.split_once(':').unwrap().1, master_key.split_once(':').unwrap().1);
+                            }
+                        }
+                        _ => {
+                            // Once the change lands this session's key is refused
+                            match vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+                                master_key: master_key.clone(),
+                                ..Default::default()
+                            })) {
+                                Ok(list) => assert_eq!(list.data.unwrap()["total_count"], 20),
+                                Err(e) => assert!(e.downcast_ref::<SessionInvalidated>().is_some(), "{}", e),
+                            }
+                        }
+                    }
+                }
+                done.send(()).unwrap();
+            }));
+        }
+        {
+            let (vault, done) = (vault.clone(), done.clone());
+            workers.push(thread::spawn(move || {
+                assert!(vault.change_master_password("original_master", "changed_master").unwrap().success);
+                done.send(()).unwrap();
+            }));
+        }
+        drop(done);
+
+        for _ in 0..workers.len() {
+            finished.recv_timeout(Duration::from_secs(120)).expect("workers deadlocked");
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // Logins that lost the race to the change failed and may have tripped the login throttle
+        Database::new(dir.path().join("pwdbox.db")).unwrap().reset_login_attempts().unwrap();
+
+        // The change committed whole: the new password unlocks the same vault key
+        let login = vault.login_with_progress(
+            LoginRequest { master_password: "changed_master".to_string() },
+            &mut |_| {},
+        ).unwrap();
+        assert_eq!(login.master_key.unwrap().split_once(':').unwrap().1, master_key.split_once(':').unwrap().1);
+        assert!(!vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap().success);
+    }
+
+    #[test]
+    fn test_login_hashes_without_holding_up_other_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = Arc::new(VaultCoordinator::open(dir.path()).unwrap());
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        let login = |vault: &VaultCoordinator| vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap();
+
+        // Argon2 makes up nearly all of a login
+        let started = Instant::now();
+        assert!(login(&vault).success);
+        let login_time = started.elapsed();
+
+        let hashing = {
+            let vault = vault.clone();
+            thread::spawn(move || login(&vault))
+        };
+        thread::sleep(login_time / 4);
+
+        // Commands issued mid-derivation run straight away instead of queueing behind it
+        let started = Instant::now();
+        vault.settings(|service| service.get_locale()).unwrap();
+        vault.passwords(|service| service.get_password_count()).unwrap();
+        assert!(started.elapsed() < login_time / 4, "waited {:?} of a {:?} login", started.elapsed(), login_time);
+        assert!(!hashing.is_finished());
+        assert!(hashing.join().unwrap().success);
+    }
+
+    #[test]
+    fn test_concurrent_logins_share_the_throttle() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        let login = |master_password: &str| vault.login_with_progress(
+            LoginRequest { master_password: master_password.to_string() },
+            &mut |_| {},
+        ).unwrap();
+
+        let concurrent_logins = |count: usize| -> Vec<AuthResponse> {
+            let barrier = std::sync::Barrier::new(count);
+            thread::scope(|scope| {
+                let threads: Vec<_> = (0..count)
+                    .map(|_| scope.spawn(|| {
+                        barrier.wait();
+                        login("wrong_master")
+                    }))
+                    .collect();
+                threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+            })
+        };
+        let checked = |responses: &[AuthResponse]| responses.iter().filter(|response| response.retry_after_secs.is_none()).count();
+
+        // Twice the free attempts at once: only the free ones get a password checked
+        let responses = concurrent_logins(2 * FREE_LOGIN_ATTEMPTS as usize);
+        assert!(responses.iter().all(|response| !response.success));
+        assert_eq!(checked(&responses), FREE_LOGIN_ATTEMPTS as usize);
+
+        // One attempt short of the lockout, long ago: a batch gets the one attempt, and the
+        // right password after it is locked out like any other
+        rusqlite::Connection::open(dir.path().join(DATABASE_FILE)).unwrap()
+            .execute(
+                "UPDATE login_attempts SET count = ?1, last_attempt_at = '2000-01-01T00:00:00Z' WHERE id = 1",
+                [LOCKOUT_LOGIN_ATTEMPTS - 1],
+            )
+            .unwrap();
+        assert_eq!(checked(&concurrent_logins(4)), 1);
+        let locked_out = login("original_master");
+        assert!(!locked_out.success && locked_out.retry_after_secs.is_some());
+    }
+
+    #[test]
+    fn test_password_change_invalidates_other_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        let login = |master_password: &str| vault.login_with_progress(
+            LoginRequest { master_password: master_password.to_string() },
+            &mut |_| {},
+        ).unwrap().master_key.unwrap();
+        let add = |master_key: &str| vault.passwords(|service| service.add_password(AddPasswordRequest {
+            software: "site".to_string(),
+            account: "me@example.com".to_string(),
+            password: "password".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.to_string(),
+        }));
+
+        // Two windows are logged in; the first changes the master password
+        let _first = login("original_master");
+        let second = login("original_master");
+        assert!(vault.change_master_password("original_master", "changed_master").unwrap().success);
+
+        let error = add(&second).unwrap_err();
+        assert!(error.downcast_ref::<SessionInvalidated>().is_some(), "{}", error);
+        assert!(vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+            master_key: second.clone(),
+            ..Default::default()
+        })).is_err());
+
+        // Logging in again gives the second window a working key
+        let renewed = login("changed_master");
+        assert!(renewed.starts_with("g1:"));
+        assert!(add(&renewed).unwrap().success);
+    }
+
+    #[test]
+    fn test_os_keychain_unlock_is_retired_by_a_password_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        let login = |master_password: &str| vault.login_with_progress(
+            LoginRequest { master_password: master_password.to_string() },
+            &mut |_| {},
+        ).unwrap().master_key.unwrap();
+        let store = MemoryStore::default();
+        assert!(vault.unlock_with_os_keychain(&store).is_err());
+
+        // Turning it on again replaces the item
+        let master_key = login("original_master");
+        vault.enable_os_keychain_unlock(&master_key, &store).unwrap();
+        vault.enable_os_keychain_unlock(&master_key, &store).unwrap();
+        assert_eq!(store.items.lock().unwrap().len(), 1);
+        let unlocked = vault.unlock_with_os_keychain(&store).unwrap();
+        assert!(unlocked.success);
+        assert_eq!(unlocked.master_key.as_deref(), Some(master_key.as_str()));
+        assert!(unlocked.previous_login.unwrap().last_login_at.is_some());
+
+        // A password changed elsewhere (the CLI, another window) turns it off on the next try
+        assert!(vault.change_master_password("original_master", "changed_master").unwrap().success);
+        let refused = vault.unlock_with_os_keychain(&store).unwrap();
+        assert!(!refused.success && refused.master_key.is_none());
+        assert!(store.items.lock().unwrap().is_empty());
+        assert!(vault.settings(|service| service.get_os_keychain_unlock()).unwrap().is_none());
+        let error = vault.enable_os_keychain_unlock(&master_key, &store).unwrap_err();
+        assert!(error.downcast_ref::<SessionInvalidated>().is_some(), "{}", error);
+
+        vault.enable_os_keychain_unlock(&login("changed_master"), &store).unwrap();
+        vault.disable_os_keychain_unlock(&store).unwrap();
+        assert!(store.items.lock().unwrap().is_empty());
+        assert!(vault.unlock_with_os_keychain(&store).is_err());
+    }
+
+    #[test]
+    fn test_pin_unlock_is_capped_and_retired_by_a_password_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        let master_key = vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap().master_key.unwrap();
+        let store = MemoryStore::default();
+        assert!(vault.unlock_with_pin("246810", &store).is_err());
+        let refused = PwdBoxError::from(vault.enable_pin_unlock(&master_key, "24681", &store).unwrap_err());
+        assert!(matches!(refused, PwdBoxError::Validation { field: Some(ref field), .. } if field == "pin"));
+
+        vault.enable_pin_unlock(&master_key, "246810", &store).unwrap();
+        let unlocked = vault.unlock_with_pin("246810", &store).unwrap();
+        assert!(unlocked.success);
+        assert_eq!(unlocked.master_key.as_deref(), Some(master_key.as_str()));
+
+        // Wrong PINs are counted in the database, so a restart keeps the count
+        for _ in 0..4 {
+            assert!(!vault.unlock_with_pin("135790", &store).unwrap().success);
+        }
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        assert_eq!(vault.settings(|service| service.get_pin_unlock()).unwrap().unwrap().failed_attempts, 4);
+        assert!(vault.unlock_with_pin("246810", &store).unwrap().success);
+        assert_eq!(vault.settings(|service| service.get_pin_unlock()).unwrap().unwrap().failed_attempts, 0);
+
+        // The fifth wrong PIN in a row deletes the wrapped key and the pepper
+        for _ in 0..4 {
+            assert!(!vault.unlock_with_pin("135790", &store).unwrap().success);
+        }
+        let last = vault.unlock_with_pin("135790", &store).unwrap();
+        assert!(!last.success && last.message.starts_with("Too many wrong PINs"));
+        assert!(store.items.lock().unwrap().is_empty());
+        assert!(vault.unlock_with_pin("246810", &store).is_err());
+
+        // A new master password leaves the PIN nothing to open
+        vault.enable_pin_unlock(&master_key, "246810", &store).unwrap();
+        assert!(vault.change_master_password("original_master", "changed_master").unwrap().success);
+        let refused = vault.unlock_with_pin("246810", &store).unwrap();
+        assert!(!refused.success && refused.master_key.is_none());
+        assert!(store.items.lock().unwrap().is_empty());
+        assert_eq!(vault.settings(|service| service.get_pin_unlock()).unwrap(), None);
+    }
+
+    // Counts the pepper lookups, one for each PIN actually checked
+    #[derive(Default)]
+    struct CountingStore {
+        store: MemoryStore,
+        gets: std::sync::atomic::AtomicUsize,
+    }
+
+    impl SecretStore for CountingStore {
+        fn set(&self, account: &str, secret: &str) -> Result<()> {
+            self.store.set(account, secret)
+        }
+
+        fn get(&self, account: &str) -> Result<Option<zeroize::Zeroizing<String>>> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.store.get(account)
+        }
+
+        fn delete(&self, account: &str) -> Result<()> {
+            self.store.delete(account)
+        }
+    }
+
+    #[test]
+    fn test_concurrent_wrong_pins_stay_within_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        let master_key = vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap().master_key.unwrap();
+        let store = CountingStore::default();
+        vault.enable_pin_unlock(&master_key, "246810", &store).unwrap();
+
+        // Twice the cap at once, as from as many unlock_with_pin commands
+        let attempts = 2 * pin_unlock::MAX_PIN_ATTEMPTS as usize;
+        let barrier = std::sync::Barrier::new(attempts);
+        let responses: Vec<AuthResponse> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..attempts)
+                .map(|_| scope.spawn(|| {
+                    barrier.wait();
+                    vault.unlock_with_pin("135790", &store).unwrap()
+                }))
+                .collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+        assert!(responses.iter().all(|response| !response.success));
+        assert_eq!(store.gets.load(Ordering::SeqCst), pin_unlock::MAX_PIN_ATTEMPTS as usize);
+        assert!(store.store.items.lock().unwrap().is_empty());
+        assert!(vault.unlock_with_pin("246810", &store).is_err());
+    }
+
+    #[test]
+    fn test_duress_password_opens_a_decoy_vault_until_the_vault_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: Some("the usual".to_string()),
+        }).unwrap();
+        let login = |master_password: &str| vault.login_with_progress(
+            LoginRequest { master_password: master_password.to_string() },
+            &mut |_| {},
+        ).unwrap();
+        let add = |master_key: &str, software: &str| vault.passwords(|service| service.add_password(AddPasswordRequest {
+            software: software.to_string(),
+            account: "me@example.com".to_string(),
+            password: "hunter22".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.to_string(),
+        })).unwrap();
+        let entry_count = || vault.passwords(|service| service.get_password_count()).unwrap().data.unwrap()["count"].clone();
+        let export = |master_key: &str, name: &str| {
+            let file_path = dir.path().join(name).to_string_lossy().to_string();
+            let exported = vault.exports(|service| service.export_data(ExportRequest {
+                export_passphrase: "passphrase".to_string(),
+                file_path: file_path.clone(),
+                include_stats_history: true,
+                include_icons: false,
+                include_attachments: false,
+                include_trash: true,
+                include_audit_log: true,
+                entry_ids: None,
+                tag: None,
+                include_user_meta: true,
+                master_key: Some(master_key.to_string()),
+            })).unwrap();
+            assert!(exported.success, "{}", exported.message);
+            vault.exports(|service| service.prepare_restore(&file_path, "passphrase", Instant::now())).unwrap().1
+        };
+
+        let real = login("original_master");
+        let real_key = real.master_key.clone().unwrap();
+        add(&real_key, "bank");
+        assert!(vault.setup_duress("wrong_master", "duress_secret").is_err());
+        let refused = PwdBoxError::from(vault.setup_duress("original_master", "original_master").unwrap_err());
+        assert!(matches!(refused, PwdBoxError::Validation { field: Some(ref field), .. } if field == "duress_password"));
+        vault.setup_duress("original_master", "duress_secret").unwrap();
+        assert!(dir.path().join("decoy.db").exists());
+
+        // The duress login reads like any other and lands in the decoy
+        let decoy = login("duress_secret");
+        assert!(decoy.success);
+        assert_eq!(decoy.message, real.message);
+        let decoy_key = decoy.master_key.clone().unwrap();
+        assert_eq!(entry_count(), 0);
+        add(&decoy_key, "news");
+        assert_eq!(entry_count(), 1);
+        assert!(!vault.passwords(|service| service.validate_master_key(&real_key)).unwrap());
+        assert_eq!(vault.users(|service| service.get_password_hint()).unwrap().as_deref(), Some("the usual"));
+        assert_eq!(vault.users(|service| service.get_security_questions()).unwrap().len(), 3);
+
+        // Stats, the audit log and exports only know the decoy
+        let stats = vault.passwords(|service| service.get_vault_stats(Some(&decoy_key))).unwrap();
+        assert_eq!(stats.data.unwrap()["entry_count"], 1);
+        let audit = vault.passwords(|service| service.get_audit_log(&decoy_key, 0, 100)).unwrap();
+        assert_eq!(audit.iter().filter(|record| record.action == crate::audit_log::LOGIN).count(), 1);
+        assert_eq!(export(&decoy_key, "decoy.pwdbox").entry_count, 1);
+
+        // Files of the real vault are out of reach
+        assert!(vault.change_data_dir(&dir.path().join("elsewhere"), true, false, || Ok(())).is_err());
+
+        // Locking goes back to the real vault, untouched by the decoy session
+        vault.leave_decoy().unwrap();
+        assert_eq!(entry_count(), 1);
+        let real = login("original_master");
+        assert_eq!(export(&real.master_key.unwrap(), "real.pwdbox").entry_count, 1);
+        assert_eq!(real.previous_login.unwrap().failed_since_last_login, 0);
+
+        // The duress password cannot become the master password
+        let refused = PwdBoxError::from(vault.change_master_password("original_master", "duress_secret").unwrap_err());
+        assert!(matches!(refused, PwdBoxError::Validation { field: Some(ref field), .. } if field == "new_password"));
+
+        // Changing the password in a decoy session changes the duress password
+        login("duress_secret");
+        assert!(vault.change_master_password("duress_secret", "duress_changed").unwrap().success);
+        assert!(!login("duress_secret").success);
+        let decoy = login("duress_changed");
+        assert!(decoy.success);
+        assert_eq!(entry_count(), 1);
+        assert!(login("original_master").success);
+        assert_eq!(entry_count(), 1);
+    }
+
+    #[test]
+    fn test_panic_wipe_after_too_many_failed_logins() {
+        let dir = tempfile::tempdir().unwrap();
+        let login = |vault: &VaultCoordinator, master_password: &str| vault.login_with_progress(
+            LoginRequest { master_password: master_password.to_string() },
+            &mut |_| {},
+        ).unwrap();
+        {
+            let vault = VaultCoordinator::open(dir.path()).unwrap();
+            vault.setup_app(SetupRequest {
+                master_password: "original_master".to_string(),
+                questions: Vec::new(),
+                question1: "q1".to_string(),
+                answer1: "a1".to_string(),
+                question2: "q2".to_string(),
+                answer2: "a2".to_string(),
+                question3: "q3".to_string(),
+                answer3: "a3".to_string(),
+                encrypt_database: false,
+                create_recovery_code: false,
+                password_hint: None,
+            }).unwrap();
+            assert!(vault.set_panic_wipe_threshold("wrong_master", Some(5)).is_err());
+            assert!(vault.set_panic_wipe_threshold("original_master", Some(2)).is_err());
+            assert_eq!(vault.set_panic_wipe_threshold("original_master", Some(5)).unwrap(), Some(5));
+            assert_eq!(vault.settings(|service| service.get_panic_wipe_threshold()).unwrap(), Some(5));
+
+            // Below the threshold a failure is only a failure, and a success starts the count over
+            assert!(!login(&vault, "wrong_master").success);
+            assert!(vault.users(|service| service.is_app_setup()).unwrap());
+            assert!(login(&vault, "original_master").success);
+        }
+        fs::create_dir_all(dir.path().join("snapshots")).unwrap();
+        fs::write(dir.path().join("snapshots").join("snapshot.pwdbox"), b"snapshot").unwrap();
+        fs::write(dir.path().join("decoy.db"), b"decoy").unwrap();
+        // Failures from before a restart, long enough ago that the throttle lets the next one through
+        let database = Database::new(dir.path().join("pwdbox.db")).unwrap();
+        for _ in 0..4 {
+            database.record_failed_login("2000-01-01T00:00:00Z").unwrap();
+        }
+        drop(database);
+
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let failed = login(&vault, "wrong_master");
+        assert!(!failed.success);
+        assert_eq!(failed.message, "Invalid master password");
+        assert!(!vault.users(|service| service.is_app_setup()).unwrap());
+        assert!(!dir.path().join("snapshots").exists());
+        assert!(!dir.path().join("decoy.db").exists());
+
+        // Reopened, it is a vault waiting for setup
+        drop(vault);
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        assert!(!vault.users(|service| service.is_app_setup()).unwrap());
+        assert_eq!(vault.settings(|service| service.get_panic_wipe_threshold()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_breach_check_is_opt_in_and_flags_breached_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        for (software, password) in [("forum", "password"), ("bank", "a-long-unique-passphrase")] {
+            vault.passwords(|service| service.add_password(AddPasswordRequest {
+                software: software.to_string(),
+                account: "me@example.com".to_string(),
+                password: password.to_string(),
+                notes: None,
+                expires_at: None,
+                tags: Vec::new(),
+                url: None,
+                entry_type: EntryType::Login,
+                secret_kind: SecretKind::Password,
+                master_key: master_key.clone(),
+            })).unwrap();
+        }
+        let digest = breach_check::password_digest("password");
+        let mut fetch = |prefix: &str| -> Result<String> {
+            Ok(if digest.starts_with(prefix) { format!("{}:42", &digest[5..]) } else { String::new() })
+        };
+
+        assert!(vault.check_breaches(&master_key, &mut fetch).is_err());
+        vault.settings(|settings_service| settings_service.set_breach_check_enabled(true)).unwrap();
+        let report = vault.check_breaches(&master_key, &mut fetch).unwrap();
+        assert_eq!((report.checked_count, report.breaches.values().copied().collect::<Vec<_>>()), (2, vec![42]));
+        assert!(dir.path().join("breach_ranges").is_dir());
+
+        let items = vault.passwords(|service| service.get_action_items(&master_key)).unwrap().data.unwrap();
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!((&items[0]["software"], items[0]["entry_id"].as_i64()), (&serde_json::json!("forum"), report.breaches.keys().next().copied()));
+    }
+
+    #[test]
+    fn test_icon_fetch_is_opt_in_cached_per_host_and_degrades() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        let mut ids = Vec::new();
+        for (software, url) in [("work", Some("https://github.com/login")), ("personal", Some("github.com")), ("down", Some("https://broken.example")), ("local", None)] {
+            let response = vault.passwords(|service| service.add_password(AddPasswordRequest {
+                software: software.to_string(),
+                account: "me@example.com".to_string(),
+                password: "a-long-unique-passphrase".to_string(),
+                notes: None,
+                expires_at: None,
+                tags: Vec::new(),
+                url: url.map(str::to_string),
+                entry_type: EntryType::Login,
+                secret_kind: SecretKind::Password,
+                master_key: master_key.clone(),
+            })).unwrap();
+            ids.push(response.data.unwrap()["id"].as_i64().unwrap());
+        }
+
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgba8(16, 16).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let requested = std::cell::RefCell::new(Vec::new());
+        let mut fetch = |url: &str| -> Result<Vec<u8>> {
+            requested.borrow_mut().push(url.to_string());
+            if url.contains("broken") { Err(anyhow!("connection refused")) } else { Ok(png.clone()) }
+        };
+
+        assert!(vault.fetch_icon(ids[0], &mut fetch).is_err());
+        vault.settings(|settings_service| settings_service.set_favicon_fetch_enabled(true)).unwrap();
+
+        // Both GitHub entries share one download
+        let first = vault.fetch_icon(ids[0], &mut fetch).unwrap();
+        let icon = first.icon.unwrap();
+        assert_eq!((first.host.as_deref(), icon.mime_type.as_str()), (Some("github.com"), "image/png"));
+        assert_eq!(vault.fetch_icon(ids[1], &mut fetch).unwrap().icon.as_ref(), Some(&icon));
+
+        // Failures and entries without a URL come back without an icon, not as errors
+        assert_eq!(vault.fetch_icon(ids[2], &mut fetch).unwrap().icon, None);
+        assert_eq!(vault.fetch_icon(ids[2], &mut fetch).unwrap().icon, None);
+        assert_eq!(vault.fetch_icon(ids[3], &mut fetch).unwrap().host, None);
+        assert!(vault.fetch_icon(9999, &mut fetch).is_err());
+        assert_eq!(*requested.borrow(), vec!["https://github.com/favicon.ico", "https://broken.example/favicon.ico"]);
+
+        let stored = vault.passwords(|service| service.get_icon(ids[1])).unwrap().data.unwrap();
+        assert_eq!(stored["icon"]["content_hash"].as_str(), Some(icon.content_hash.as_str()));
+        let listed = vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+            master_key: master_key.clone(),
+            ..Default::default()
+        })).unwrap().data.unwrap();
+        let with_icons = listed["entries"].as_array().unwrap().iter().filter(|entry| !entry["icon"].is_null()).count();
+        assert_eq!(with_icons, 2);
+
+        // Turning fetching off forgets the cached icons
+        vault.settings(|settings_service| settings_service.set_favicon_fetch_enabled(false)).unwrap();
+        assert!(vault.passwords(|service| service.get_icon(ids[0])).unwrap().data.unwrap()["icon"].is_null());
+    }
+
+    // Keeps every event instead of sending it to a window
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<(String, serde_json::Value)>>);
+
+    impl EventSink for RecordingSink {
+        fn emit(&self, event: &str, payload: serde_json::Value) {
+            self.0.lock().unwrap().push((event.to_string(), payload));
+        }
+    }
+
+    impl RecordingSink {
+        fn take(&self) -> Vec<(String, serde_json::Value)> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_service_changes_emit_events() {
+        use crate::events;
+        use crate::export_service::ImportMode;
+        use crate::password_service::{AddPasswordItem, DeletePasswordRequest, UpdatePasswordRequest};
+        use serde_json::json;
+
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        vault.set_event_sink(sink.clone());
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        assert!(sink.take().is_empty());
+        let event = |name: &str, payload: serde_json::Value| vec![(name.to_string(), payload)];
+
+        let id = vault.passwords(|service| service.add_password(AddPasswordRequest {
+            software: "mail".to_string(),
+            account: "me@example.com".to_string(),
+            password: "first-password".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.clone(),
+        })).unwrap().data.unwrap()["id"].as_i64().unwrap();
+        assert_eq!(sink.take(), event(events::ENTRY_ADDED, json!({"id": id})));
+
+        vault.passwords(|service| service.update_password(UpdatePasswordRequest {
+            id,
+            software: "mail".to_string(),
+            account: "me@example.com".to_string(),
+            password: "second-password".to_string(),
+            notes: None,
+            expires_at: None,
+            url: None,
+            secret_kind: None,
+            master_key: master_key.clone(),
+        })).unwrap();
+        vault.passwords(|service| service.toggle_favorite(id)).unwrap();
+        vault.passwords(|service| service.add_tag_to_entry(id, "Work")).unwrap();
+        assert_eq!(sink.take(), vec![(events::ENTRY_UPDATED.to_string(), json!({"id": id})); 3]);
+
+        // One event for the whole batch, listing the entries actually changed
+        let items = (0..3).map(|index| AddPasswordItem {
+            software: format!("site-{}", index),
+            account: "me@example.com".to_string(),
+            password: "batch-password".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+        }).collect();
+        let added = vault.passwords(|service| service.add_passwords_batch(items, &master_key)).unwrap();
+        let batch_ids: Vec<i64> = added.results.iter().filter_map(|result| result.id).collect();
+        assert_eq!(sink.take(), event(events::ENTRIES_CHANGED, json!({"ids": batch_ids})));
+        vault.passwords(|service| service.tag_passwords(&[batch_ids[0], 9999], "Shared")).unwrap();
+        assert_eq!(sink.take(), event(events::ENTRIES_CHANGED, json!({"ids": [batch_ids[0]]})));
+        vault.passwords(|service| service.delete_passwords(&batch_ids[1..])).unwrap();
+        assert_eq!(sink.take(), event(events::ENTRIES_CHANGED, json!({"ids": &batch_ids[1..]})));
+
+        vault.passwords(|service| service.delete_password(DeletePasswordRequest { id })).unwrap();
+        assert_eq!(sink.take(), event(events::ENTRY_DELETED, json!({"id": id})));
+        // Nothing happened, so nothing is sent
+        vault.passwords(|service| service.delete_password(DeletePasswordRequest { id })).unwrap();
+        vault.passwords(|service| service.delete_passwords(&[id])).unwrap();
+        assert!(sink.take().is_empty());
+
+        let backup_path = dir.path().join("backup.enc").to_string_lossy().to_string();
+        vault.exports(|service| service.create_backup("passphrase", Some(&backup_path), dir.path())).unwrap();
+        assert_eq!(sink.take(), event(events::BACKUP_CREATED, json!({"file_path": backup_path})));
+        let stats = vault.passwords(|service| service.get_vault_stats(None)).unwrap().data.unwrap();
+        assert!(stats["last_backup_at"].is_string());
+        let imported = vault.import_data(ImportRequest {
+            import_passphrase: "passphrase".to_string(),
+            file_path: backup_path,
+            mode: ImportMode::Replace,
+            overwrite_duplicates: false,
+            source_master_password: None,
+            target_master_key: None,
+            session_key: None,
+        }).unwrap();
+        assert!(imported.success, "{}", imported.message);
+        assert_eq!(sink.take(), event(events::VAULT_IMPORTED, json!({"mode": "replace", "imported_entries_count": 4})));
+    }
+
+    #[test]
+    fn test_kdf_benchmark_sets_the_target_only_before_setup() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let target = || vault.settings(|service| service.get_kdf_params()).unwrap();
+
+        let benchmark = vault.benchmark_kdf(100).unwrap();
+        assert_eq!(target(), benchmark.recommended);
+
+        // Setup hashes with the recommendation
+        vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap();
+        assert_eq!(vault.users(|service| service.user_meta()).unwrap().kdf_params, benchmark.recommended);
+
+        // Afterwards it is only a recommendation until set_kdf_params applies it
+        vault.settings(|service| service.set_kdf_params(KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 })).unwrap();
+        vault.benchmark_kdf(100).unwrap();
+        assert_eq!(target(), KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 });
+    }
+
+    #[test]
+    fn test_kdf_params_change_and_upgrade_on_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        let stored_params = || vault.users(|service| service.user_meta()).unwrap().kdf_params;
+        assert_eq!(stored_params(), KdfParams::default());
+
+        let cheaper = KdfParams { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+        assert!(vault.set_kdf_params("wrong_master", cheaper).is_err());
+        assert!(vault.set_kdf_params("original_master", KdfParams { m_cost: 1024, ..cheaper }).is_err());
+        assert_eq!(stored_params(), KdfParams::default());
+
+        // Re-hashed and re-wrapped in place: the open session and its vault key keep working
+        assert_eq!(vault.set_kdf_params("original_master", cheaper).unwrap(), cheaper);
+        assert_eq!(stored_params(), cheaper);
+        assert!(vault.users(|service| service.user_meta()).unwrap().master_hash.contains("m=8192,t=1,p=1"));
+        assert_eq!(vault.settings(|service| service.get_kdf_params()).unwrap(), cheaper);
+        assert!(vault.passwords(|service| service.get_password_count()).is_ok());
+        assert!(vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+            master_key: master_key.clone(),
+            ..Default::default()
+        })).is_ok());
+
+        // A login under other than the target parameters is upgraded to them
+        let target = KdfParams { t_cost: 2, ..cheaper };
+        vault.settings(|service| service.set_kdf_params(target)).unwrap();
+        let login = vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap();
+        assert!(login.success);
+        assert_eq!(login.master_key.unwrap(), master_key);
+        assert_eq!(stored_params(), target);
+        assert!(vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap().success);
+    }
+
+    #[test]
+    fn test_interleaved_adds_reads_and_exports_share_the_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = Arc::new(VaultCoordinator::open(dir.path()).unwrap());
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+
+        let mut workers = Vec::new();
+        for worker in 0..10 {
+            let (vault, master_key, export_dir) = (vault.clone(), master_key.clone(), dir.path().to_path_buf());
+            workers.push(thread::spawn(move || {
+                for round in 0..10 {
+                    match worker % 5 {
+                        0 | 1 => {
+                            let added = vault.passwords(|service| service.add_password(AddPasswordRequest {
+                                software: format!("site-{}-{}", worker, round),
+                                account: "me@example.com".to_string(),
+                                password: "password".to_string(),
+                                notes: None,
+                                expires_at: None,
+                                tags: Vec::new(),
+                                url: None,
+                                entry_type: EntryType::Login,
+                                secret_kind: SecretKind::Password,
+                                master_key: master_key.clone(),
+                            })).unwrap();
+                            assert!(added.success, "{}", added.message);
+                        }
+                        2 | 3 => {
+                            vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+                                master_key: master_key.clone(),
+                                ..Default::default()
+                            })).unwrap();
+                            vault.settings(|service| service.get_locale()).unwrap();
+                        }
+                        // Argon2 makes exports slow, so only a few
+                        _ if round < 2 => {
+                            let exported = vault.exports(|service| service.export_data(ExportRequest {
+                                export_passphrase: "passphrase".to_string(),
+                                file_path: export_dir.join(format!("export-{}-{}.pwdbox", worker, round)).to_string_lossy().to_string(),
+                                include_stats_history: true,
+                                include_icons: false,
+                                include_attachments: false,
+                                include_trash: true,
+                                include_audit_log: true,
+                                entry_ids: None,
+                                tag: None,
+                                include_user_meta: true,
+                                master_key: None,
+                            })).unwrap();
+                            assert!(exported.success, "{}", exported.message);
+                        }
+                        _ => {}
+                    }
+                }
+            }));
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(vault.passwords(|service| service.get_password_count()).unwrap().data.unwrap()["count"], 40);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_migrate_to_encrypted_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        vault.passwords(|service| service.add_password(AddPasswordRequest {
+            software: "Example".to_string(),
+            account: "me@example.com".to_string(),
+            password: "password".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.clone(),
+        })).unwrap();
+        let db_path = dir.path().join("pwdbox.db");
+        assert!(Database::is_plaintext_file(&db_path));
+        assert!(!vault.capabilities().unwrap().get(capabilities::ENCRYPTED_DATABASE).unwrap().enabled);
+
+        assert!(vault.migrate_to_encrypted_db("not a key").is_err());
+        vault.migrate_to_encrypted_db(&master_key).unwrap();
+
+        let contents = fs::read(&db_path).unwrap();
+        for plaintext in ["SQLite format 3", "me@example.com", "Example", "password_entries", "CREATE TABLE"] {
+            assert!(!contents.windows(plaintext.len()).any(|window| window == plaintext.as_bytes()), "found {:?}", plaintext);
+        }
+        assert!(Database::new(db_path.clone()).is_err());
+        // So are the copies taken before data migrations
+        let key = database_key::read(dir.path()).unwrap().unwrap();
+        let backup_path = dir.path().join("backup.db");
+        Database::new_encrypted(db_path.clone(), &key).unwrap().backup_to(&backup_path).unwrap();
+        assert!(!Database::is_plaintext_file(&backup_path));
+        assert!(Database::new_encrypted(backup_path, &key).is_ok());
+        assert!(vault.capabilities().unwrap().get(capabilities::ENCRYPTED_DATABASE).unwrap().enabled);
+        assert!(vault.migrate_to_encrypted_db(&master_key).is_err());
+
+        // Every service was reopened on the encrypted file
+        let list = vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+            master_key: master_key.clone(),
+            ..Default::default()
+        })).unwrap();
+        assert_eq!(list.data.unwrap()["total_count"], 1);
+        vault.settings(|service| service.set_login_lockout_minutes(5)).unwrap();
+
+        // As is a fresh start
+        drop(vault);
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        assert!(vault.login_with_progress(
+            LoginRequest { master_password: "original_master".to_string() },
+            &mut |_| {},
+        ).unwrap().success);
+        assert_eq!(vault.settings(|service| service.get_login_lockout_minutes()).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_change_data_dir_moves_or_switches_the_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_dir = dir.path().join("old");
+        fs::create_dir_all(&old_dir).unwrap();
+        let vault = VaultCoordinator::open(&old_dir).unwrap();
+        let master_key = vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: false,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().master_key.unwrap();
+        vault.passwords(|service| service.add_password(AddPasswordRequest {
+            software: "Example".to_string(),
+            account: "me@example.com".to_string(),
+            password: "password".to_string(),
+            notes: None,
+            expires_at: None,
+            tags: Vec::new(),
+            url: None,
+            entry_type: EntryType::Login,
+            secret_kind: SecretKind::Password,
+            master_key: master_key.clone(),
+        })).unwrap();
+        fs::create_dir_all(old_dir.join("snapshots")).unwrap();
+        fs::write(old_dir.join("snapshots").join("snapshot.json"), "{}").unwrap();
+        let entry_count = |vault: &VaultCoordinator| {
+            vault.passwords(|service| service.get_all_passwords(GetPasswordsRequest {
+                master_key: master_key.clone(),
+                ..Default::default()
+            })).unwrap().data.unwrap()["total_count"].clone()
+        };
+
+        // A directory holding another vault is refused before anything is copied
+        let occupied = dir.path().join("occupied");
+        fs::create_dir_all(&occupied).unwrap();
+        drop(VaultCoordinator::open(&occupied).unwrap());
+        let refused = vault.change_data_dir(&occupied, true, false, || panic!("switched")).unwrap_err();
+        assert!(matches!(PwdBoxError::from(refused), PwdBoxError::Validation { .. }));
+
+        // Nothing moves when the new directory cannot be recorded
+        let failed = dir.path().join("failed");
+        assert!(vault.change_data_dir(&failed, true, false, || Err(anyhow!("read-only"))).is_err());
+        assert_eq!(vault.data_dir(), old_dir);
+        assert!(old_dir.join(DATABASE_FILE).exists());
+
+        let new_dir = dir.path().join("new");
+        let mut switched = false;
+        vault.change_data_dir(&new_dir, true, false, || {
+            switched = true;
+            Ok(())
+        }).unwrap();
+        assert!(switched);
+        assert_eq!(vault.data_dir(), new_dir);
+        assert_eq!(entry_count(&vault), 1);
+        assert!(new_dir.join("snapshots").join("snapshot.json").exists());
+        assert!(!old_dir.join(DATABASE_FILE).exists() && !old_dir.join("snapshots").exists());
+
+        // With overwrite the moved vault replaces the one already there
+        vault.change_data_dir(&occupied, true, true, || Ok(())).unwrap();
+        assert_eq!(entry_count(&vault), 1);
+        drop(vault);
+        assert_eq!(entry_count(&VaultCoordinator::open(&occupied).unwrap()), 1);
+
+        // Without moving, the vault in the new directory is used
+        let vault = VaultCoordinator::open(&occupied).unwrap();
+        vault.change_data_dir(&dir.path().join("fresh"), false, false, || Ok(())).unwrap();
+        assert!(!vault.users(|service| service.is_app_setup()).unwrap());
+        assert!(occupied.join(DATABASE_FILE).exists());
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_setup_can_encrypt_the_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = VaultCoordinator::open(dir.path()).unwrap();
+        assert!(vault.setup_app(SetupRequest {
+            master_password: "original_master".to_string(),
+            questions: Vec::new(),
+            question1: "q1".to_string(),
+            answer1: "a1".to_string(),
+            question2: "q2".to_string(),
+            answer2: "a2".to_string(),
+            question3: "q3".to_string(),
+            answer3: "a3".to_string(),
+            encrypt_database: true,
+            create_recovery_code: false,
+            password_hint: None,
+        }).unwrap().success);
+        assert!(!Database::is_plaintext_file(&dir.path().join("pwdbox.db")));
+        assert!(vault.users(|service| service.is_app_setup()).unwrap());
+
+        // A key file beside a plaintext database is left from an interrupted migration
+        let other = tempfile::tempdir().unwrap();
+        drop(VaultCoordinator::open(other.path()).unwrap());
+        fs::copy(dir.path().join(database_key::MARKER_FILE), other.path().join(database_key::MARKER_FILE)).unwrap();
+        let vault = VaultCoordinator::open(other.path()).unwrap();
+        assert!(!vault.is_database_encrypted());
+        assert!(!vault.users(|service| service.is_app_setup()).unwrap());
+    }
+}
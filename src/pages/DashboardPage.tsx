@@ -5,7 +5,7 @@ import { LanguageSwitcher } from '../components/LanguageSwitcher';
 import { passwordApi, exportApi, handleApiCall } from '../utils/api';
 import Button from '../components/Button';
 import Input from '../components/Input';
-import type { PasswordEntry, PasswordFormData } from '../types';
+import type { PasswordEntry, PasswordFormData, PasswordPage } from '../types';
 // import { save } from '@tauri-apps/plugin-dialog';
 
 const DashboardPage: React.FC = () => {
@@ -41,7 +41,7 @@ const DashboardPage: React.FC = () => {
       );
 
       if (response.success && response.data) {
-        setPasswords(response.data);
+        setPasswords((response.data as PasswordPage).entries);
       }
     } catch (error) {
       console.error('Failed to load passwords:', error);
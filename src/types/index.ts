@@ -1,12 +1,20 @@
 // User Management Types
 export interface SetupRequest {
   master_password: string;
-  question1: string;
-  answer1: string;
-  question2: string;
-  answer2: string;
-  question3: string;
-  answer3: string;
+  // [question, answer] pairs, 2 to 5; when left out, the three fixed fields below are used
+  questions?: [string, string][];
+  question1?: string;
+  answer1?: string;
+  question2?: string;
+  answer2?: string;
+  question3?: string;
+  answer3?: string;
+  // Encrypt the database file too; only when the encrypted_database capability is available
+  encrypt_database?: boolean;
+  // Also make a recovery code, returned once in AuthResponse.recovery_code
+  create_recovery_code?: boolean;
+  // Not the master password or part of it; shown at login only after a failed attempt
+  password_hint?: string;
 }
 
 export interface LoginRequest {
@@ -17,32 +25,132 @@ export interface SecurityQuestion {
   question: string;
 }
 
+// One answer per security question, in order; answer1-3 are still accepted instead
 export interface RecoveryRequest {
-  answer1: string;
-  answer2: string;
-  answer3: string;
+  answers?: string[];
+  answer1?: string;
+  answer2?: string;
+  answer3?: string;
 }
 
 export interface ResetPasswordRequest {
   new_master_password: string;
-  answer1: string;
-  answer2: string;
-  answer3: string;
+  answers?: string[];
+  answer1?: string;
+  answer2?: string;
+  answer3?: string;
+}
+
+export type RecoveryFactor = 'answer1' | 'answer2' | 'answer3' | 'answer4' | 'answer5' | 'recovery_key';
+
+export interface RecoveryDrillResult {
+  success: boolean;
+  message: string;
+  failed_factors: RecoveryFactor[];
+  verified_at?: string;
+  retry_after_secs?: number;
+}
+
+export interface RecoveryStatus {
+  verified_at?: string;
+  days_since_verified?: number;
+  warning?: string;
+  has_recovery_code: boolean;
+}
+
+// From generate_recovery_code. Show the code once for the user to print; it cannot be
+// retrieved again, and a reset with it uses it up.
+export interface RecoveryCodeResponse {
+  success: boolean;
+  message: string;
+  recovery_code?: string;
 }
 
+export interface ResetWithCodeRequest {
+  recovery_code: string;
+  new_master_password: string;
+}
+
+// The backend keeps the vault key for the session and returns an opaque
+// session_token. master_key is only returned, and only accepted back in requests,
+// by builds with the legacy-master-key-ipc feature; omit it from new code.
 export interface AuthResponse {
   success: boolean;
   message: string;
   master_key?: string;
+  session_token?: string;
+  // Set when repeated failures throttled the attempt; retry once it has passed
+  retry_after_secs?: number;
+  // Only from setup with create_recovery_code
+  recovery_code?: string;
+  // Only from a successful login: the login history as it was before this one
+  previous_login?: LoginStats;
+}
+
+// Master password logins only; recovery attempts are not counted
+export interface LoginStats {
+  last_login_at?: string;
+  last_failed_at?: string;
+  failed_since_last_login: number;
+}
+
+// Argon2id cost parameters (memory in KiB). Set with set_kdf_params, which needs the
+// master password; logins made with older parameters are upgraded to these.
+export interface KdfParams {
+  m_cost: number;
+  t_cost: number;
+  p_cost: number;
+}
+
+export interface KdfTiming {
+  params: KdfParams;
+  elapsed_ms: number;
+}
+
+// From benchmark_kdf. Before setup the recommendation is already the target; after
+// setup pass it to set_kdf_params to apply it.
+export interface KdfBenchmark {
+  target_ms: number;
+  recommended: KdfParams;
+  timings: KdfTiming[];
 }
 
 // Password Management Types
+// Logins need a password, secure notes their notes and identities a name (in account);
+// card details go in custom fields. Unset means login.
+export type EntryType = 'login' | 'secure_note' | 'card' | 'identity';
+
+// A hint for showing the password: keys and tokens read better in monospace. Passwords
+// may span several lines, up to 64 KiB. Unset means password.
+export type SecretKind = 'password' | 'ssh_key' | 'api_token';
+
 export interface AddPasswordRequest {
   software: string;
   account: string;
   password: string;
-  master_key: string;
+  master_key?: string;
   notes?: string;
+  tags?: string[];
+  url?: string;
+  entry_type?: EntryType;
+  secret_kind?: SecretKind;
+}
+
+// One entry for add_passwords_batch, which writes all accepted items in one transaction
+export type AddPasswordItem = Omit<AddPasswordRequest, 'master_key'> & { expires_at?: string };
+
+// By position in the request: id when added, error when refused
+export interface BatchItemResult {
+  index: number;
+  id?: number;
+  error?: string;
+  warnings?: string[];
+}
+
+export interface BatchAddResponse {
+  added_count: number;
+  failed_count: number;
+  results: BatchItemResult[];
 }
 
 export interface UpdatePasswordRequest {
@@ -50,22 +158,59 @@ export interface UpdatePasswordRequest {
   software: string;
   account: string;
   password: string;
-  master_key: string;
+  master_key?: string;
   notes?: string;
+  url?: string;
+  // Unset keeps the stored kind
+  secret_kind?: SecretKind;
+}
+
+export interface ReplaceAccountRequest {
+  old_value: string;
+  new_value: string;
+  match_mode?: 'contains' | 'exact';
+  dry_run?: boolean;
+  master_key?: string;
+}
+
+export interface SaveEntryDraftRequest {
+  entry_id?: number;
+  payload: unknown;
+  master_key?: string;
 }
 
 export interface DeletePasswordRequest {
   id: number;
 }
 
+export type PasswordSortColumn = 'software' | 'account' | 'created_at' | 'updated_at' | 'expires_at';
+
 export interface GetPasswordsRequest {
-  master_key: string;
+  master_key?: string;
   search_query?: string;
+  offset?: number;
+  limit?: number;
+  sort_by?: PasswordSortColumn;
+  sort_dir?: 'asc' | 'desc';
+  match_mode?: 'contains' | 'exact';
+  tag?: string;
+  // Only entries with this software name, ignoring case; see get_software_groups
+  software?: string;
+  favorites_only?: boolean;
+  // Only entries scoring at most this, e.g. 1 for the weak ones
+  max_strength?: PasswordStrength;
+  // Also match search_query against the decrypted passwords (case-sensitive); off unless set
+  match_passwords?: boolean;
+}
+
+export interface PasswordPage {
+  total_count: number;
+  entries: PasswordEntry[];
 }
 
 export interface DecryptPasswordRequest {
   id: number;
-  master_key: string;
+  master_key?: string;
 }
 
 export interface PasswordEntry {
@@ -75,7 +220,32 @@ export interface PasswordEntry {
   password?: string;
   notes?: string;
   created_at?: string;
-}
+  updated_at?: string;
+  expires_at?: string;
+  // expires_at has passed
+  expired?: boolean;
+  // Set with set_rotation_interval; a new password moves expires_at this many days on
+  rotation_days?: number;
+  entry_type?: EntryType;
+  secret_kind?: SecretKind;
+  // Values only come with get_password; lists leave them out, CVVs included
+  custom_fields?: CustomFieldValue[];
+  is_favorite?: boolean;
+  deleted_at?: string;
+  url?: string;
+  tags?: string[];
+  // Unset until the entry has been scored
+  strength?: PasswordStrength;
+}
+
+// Payload of the `passwords-expiring` event sent when the vault is unlocked
+export interface PasswordsExpiring {
+  count: number;
+  within_days: number;
+}
+
+// 0 (trivial) to 4 (strong), computed when the password is saved
+export type PasswordStrength = 0 | 1 | 2 | 3 | 4;
 
 export interface PasswordResponse {
   success: boolean;
@@ -83,10 +253,170 @@ export interface PasswordResponse {
   data?: any;
 }
 
+// From audit_vault, which emits `audit-progress` ({ batch, processed, total }) as it goes.
+// Entries appear by id only; max_age_days is set with set_password_max_age_days.
+export interface VaultAuditReport {
+  entry_count: number;
+  weak_count: number;
+  weak_entry_ids: number[];
+  reused_count: number;
+  reused_groups: number[][];
+  old_count: number;
+  old_entry_ids: number[];
+  max_age_days: number;
+  unreadable_entry_ids: number[];
+}
+
+// From get_software_suggestions: up to 20 names in use, most used first. Spellings that
+// differ only in case are listed separately; normalize_software_names takes a map from the
+// canonical name to the entry ids to rename.
+export interface SoftwareSuggestion {
+  software: string;
+  count: number;
+}
+
+export type CustomFieldKind =
+  | 'text'
+  | 'hidden'
+  | 'card_number'
+  | 'card_expiry'
+  | 'cvv'
+  | 'cardholder'
+  | 'name'
+  | 'address'
+  | 'phone'
+  | 'totp';
+
+export interface CustomFieldValue {
+  id: number;
+  label: string;
+  value?: string;
+  field_kind: CustomFieldKind;
+  position: number;
+}
+
+// For set_custom_field. Without id a field is added, last unless position is given.
+export interface SetCustomFieldRequest {
+  entry_id: number;
+  id?: number;
+  label: string;
+  value: string;
+  field_kind?: CustomFieldKind;
+  position?: number;
+  master_key?: string;
+}
+
+// From get_entry_template: the fields to offer for a new card or identity
+export interface TemplateField {
+  label: string;
+  field_kind: CustomFieldKind;
+}
+
+// One entry of list_attachments' data. Contents only leave the vault through
+// get_attachment, which decrypts them to a file.
+export interface AttachmentInfo {
+  id: number;
+  entry_id: number;
+  filename: string;
+  size: number;
+  added_at: string;
+}
+
+// From share_entry's data. blob is a single line of text for import_shared_entry on the
+// receiving side, which refuses it from expires_at on.
+export interface SharedEntryBlob {
+  blob: string;
+  created_at: string;
+  expires_at: string | null;
+}
+
+// From get_vault_stats. strength is only filled in when a master_key is passed; an empty
+// string uses the session's.
+export interface VaultStats {
+  entry_count: number;
+  warning_threshold: number;
+  max_entries: number;
+  warnings: string[];
+  favorite_count: number;
+  trash_count: number;
+  tags: { name: string; entry_count: number }[];
+  strength: { by_score: [number, number, number, number, number]; unscored: number } | null;
+  oldest_entry_at: string | null;
+  newest_entry_at: string | null;
+  database_size_bytes: number;
+  last_backup_at: string | null;
+}
+
+// From get_software_groups, most entries first. Names differing only in case form one
+// group, spelled as its most recently updated entry has it.
+export interface SoftwareGroup {
+  software: string;
+  entry_count: number;
+  last_updated_at?: string;
+}
+
+export type SoftwareNameMapping = Record<string, number[]>;
+
+// From find_duplicates. Groups hold at least two entries, in id order; pass one id to keep
+// and the rest to merge_entries.
+export interface DuplicateReport {
+  // Same software and account, ignoring case and surrounding spaces
+  same_name: PasswordEntry[][];
+  same_password: PasswordEntry[][];
+  unreadable_count: number;
+}
+
+// From check_breaches, once set_breach_check_enabled(true). Only the first five characters
+// of each password's SHA-1 are sent. network_unavailable means some entries went unchecked.
+export type BreachCheckStatus = 'complete' | 'network_unavailable';
+
+export interface BreachCheckReport {
+  status: BreachCheckStatus;
+  message: string;
+  checked_count: number;
+  unchecked_count: number;
+  unreadable_count: number;
+  // Entry id -> times seen in breaches
+  breaches: Record<number, number>;
+}
+
+// An entry's icon: uploaded with set_entry_icon, or a favicon fetched for its URL host
+export interface EntryIcon {
+  mime_type: string;
+  data: string; // Base64
+  content_hash: string;
+}
+
+// From fetch_icon, once set_favicon_fetch_enabled(true). host is null for entries without a
+// URL; icon is null when the host had no usable icon.
+export interface FetchedIcon {
+  id: number;
+  host: string | null;
+  icon: EntryIcon | null;
+}
+
+// get_entry_qr: 'wifi' joins the network named by the account, 'totp' enrols the entry's
+// TOTP custom field in an authenticator app
+export type QrKind = 'wifi' | 'totp';
+
+export interface QrImage {
+  png: string; // Base64
+  width: number;
+  height: number;
+}
+
 // Export/Import Types
 export interface ExportRequest {
   export_passphrase: string;
   file_path: string;
+  include_trash?: boolean;
+  include_audit_log?: boolean;
+  // Narrow the export to these entries and/or this tag
+  entry_ids?: number[];
+  tag?: string;
+  // Defaults to true. Without it the file holds no master hash or security questions and
+  // always merges into the importing vault; the audit log is left out.
+  include_user_meta?: boolean;
 }
 
 export interface ImportRequest {
@@ -94,16 +424,252 @@ export interface ImportRequest {
   file_path: string;
 }
 
+export interface OperationTimings {
+  file_io_ms: number;
+  kdf_ms: number;
+  crypto_ms: number;
+  db_ms: number;
+  total_ms: number;
+  rows: number;
+  rows_per_sec: number;
+  peak_memory_bytes: number;
+}
+
 export interface ExportResponse {
   success: boolean;
   message: string;
   file_path?: string;
+  timings?: OperationTimings;
+  // export_csv only: entries left out because they would not decrypt
+  failed_count?: number;
 }
 
 export interface ImportResponse {
   success: boolean;
   message: string;
   imported_entries_count?: number;
+  audit_events_imported?: number;
+  timings?: OperationTimings;
+  // import_csv only: rows that were left out
+  row_errors?: CsvRowError[];
+  // import_data only: entries left out because they could never be decrypted
+  malformed_entries?: CorruptEntry[];
+  // import_data only: whether the file failed its integrity check or the passphrase was wrong
+  file_status?: ExportFileStatus;
+}
+
+// An entry whose ciphertext is not base64 or whose nonce is not 12 bytes; from import_data
+// (id in the backup) and check_integrity
+export interface CorruptEntry {
+  id: number;
+  defect: string;
+}
+
+// corrupted also covers files that are no PwdBox export
+export type ExportFileStatus = 'valid' | 'wrong_passphrase' | 'corrupted';
+
+// From list_backups. Files that are no export or would not open carry the reason in error;
+// entry_count is only read when a passphrase is given.
+export interface BackupListing {
+  file_name: string;
+  file_path: string;
+  file_size?: number;
+  created_at?: string;
+  created_at_display?: string;
+  is_pwdbox_export: boolean;
+  format_version?: number;
+  entry_count?: number;
+  error?: string;
+}
+
+// From prepare_restore. Nothing changes until confirm_restore is called with the token,
+// which then replaces the vault (master password included) with the backup's.
+export interface RestoreSummary {
+  token: string;
+  expires_in_secs: number;
+  created_at?: string;
+  entry_count: number;
+  trash_count: number;
+  audit_events?: number;
+  metadata_encrypted: boolean;
+}
+
+export interface RestoreResponse {
+  success: boolean;
+  message: string;
+  restored_entries_count: number;
+  audit_events_restored?: number;
+  // Copy of the database from just before the restore
+  pre_restore_backup: string;
+}
+
+// What import_csv does with a row whose software and account are already in the vault
+export type CsvImportMode = 'skip' | 'update';
+
+// line counts from 1 and includes the header
+export interface CsvRowError {
+  line: number;
+  message: string;
+}
+
+export type CsvField = 'software' | 'url' | 'account' | 'password' | 'notes' | 'tags' | 'favorite' | 'ignore';
+
+export type CsvPreset = 'browser' | 'lastpass' | '1password';
+
+// Without a preset or columns the preset is detected from the header. columns maps
+// source column names to fields, overriding the preset for those fields.
+export interface CsvImportOptions {
+  preset?: CsvPreset;
+  columns?: Record<string, CsvField>;
+  strict?: boolean;
+}
+
+// Capability Types
+export interface Capability {
+  available: boolean;
+  enabled: boolean;
+  reason_if_unavailable?: string;
+}
+
+export type Capabilities = Record<string, Capability>;
+
+// From get_lock_shortcut and set_lock_shortcut (desktop only, see the lock_shortcut
+// capability). The default is CmdOrCtrl+Shift+L; an empty accelerator turns it off. error
+// says why registration failed, e.g. another application already uses the accelerator.
+export interface LockShortcutStatus {
+  accelerator: string;
+  registered: boolean;
+  error?: string;
+}
+
+// From get_data_dir and set_data_dir. 'environment' means PWDBOX_DATA_DIR chose the
+// directory and 'portable' that PwdBox runs in portable mode (portable.flag or --portable
+// next to the executable); set_data_dir is refused in both cases.
+export type DataDirSource = 'default' | 'pointer' | 'environment' | 'portable';
+
+export interface DataDirInfo {
+  path: string;
+  source: DataDirSource;
+  default_path: string;
+}
+
+// Browser extension native host (desktop only, see the native_messaging capability).
+// install_native_host writes the manifest for each browser and returns where.
+export type Browser = 'chrome' | 'chromium' | 'firefox';
+
+export interface InstalledManifest {
+  browser: Browser;
+  path: string;
+}
+
+// Payload of the native-credential-request event: an allowed extension asks for entry id's
+// password. Answer with answer_native_credential_request; unanswered requests are denied
+// after two minutes or when the vault locks.
+export interface NativeCredentialRequest {
+  request_id: number;
+  extension_id: string;
+  id: number;
+}
+
+export type PolicyMode = 'advisory' | 'strict';
+
+export interface PasswordPolicy {
+  tag: string;
+  min_entropy_bits: number;
+  mode: PolicyMode;
+}
+
+export interface AuditRecord {
+  id: number;
+  occurred_at: string;
+  action: string;
+  entry_id?: number;
+  detail?: string;
+  segment_id?: number;
+  source_id?: number;
+}
+
+// One independently chained part of the log: the local log (no segment_id) or a
+// segment brought in by an import
+export interface AuditSegmentVerification {
+  segment_id?: number;
+  imported_at?: string;
+  first_id?: number;
+  last_id?: number;
+  checked_count: number;
+  valid: boolean;
+  first_invalid_id?: number;
+}
+
+export interface AuditLogVerification {
+  valid: boolean;
+  checked_count: number;
+  pending_count: number;
+  first_invalid_id?: number;
+  message: string;
+  segments: AuditSegmentVerification[];
+}
+
+// What a failed command rejects with. Branch on code, which stays the same across
+// releases; message is for display. field names the request field a validation error is about.
+export type PwdBoxErrorCode =
+  | 'not_found'
+  | 'invalid_key'
+  | 'locked'
+  | 'crypto'
+  | 'database'
+  | 'io'
+  | 'validation'
+  | 'internal';
+
+export type MasterPasswordRule = 'min_length' | 'common' | 'min_entropy';
+
+// Set on validation errors for a new master password the policy refused
+export interface MasterPasswordRejected {
+  rule: MasterPasswordRule;
+  entropy_bits: number;
+  min_entropy_bits: number;
+  min_length: number;
+}
+
+export interface PwdBoxError {
+  code: PwdBoxErrorCode;
+  message: string;
+  field?: string;
+  policy?: MasterPasswordRejected;
+}
+
+// Error code returned by commands after the vault auto-locked or lock_vault ran; a
+// `vault-locked` event is emitted when that happens
+export const VAULT_LOCKED_ERROR: PwdBoxErrorCode = 'locked';
+
+// Change events, so open lists can update without polling get_all_passwords. Batch
+// operations send a single entries-changed rather than an event per entry.
+// entry-added, entry-updated, entry-deleted
+export interface EntryChangedEvent {
+  id: number;
+}
+
+// entries-changed
+export interface EntriesChangedEvent {
+  ids: number[];
+}
+
+// vault-imported
+export interface VaultImportedEvent {
+  mode: 'replace' | 'merge' | 'csv' | 'restore';
+  imported_entries_count: number;
+}
+
+// backup-created
+export interface BackupCreatedEvent {
+  file_path: string;
+}
+
+// reencrypt-progress, after each batch of re_encrypt_vault
+export interface ReencryptProgressEvent {
+  done: number;
+  total: number;
 }
 
 // App State Types
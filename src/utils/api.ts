@@ -16,6 +16,9 @@ import type {
   ImportRequest,
   ExportResponse,
   ImportResponse,
+  ExportFileStatus,
+  DataDirInfo,
+  PwdBoxError,
 } from '../types';
 
 // User Management API
@@ -104,7 +107,7 @@ export const exportApi = {
     });
   },
 
-  async validateExportFile(filePath: string, passphrase: string): Promise<boolean> {
+  async validateExportFile(filePath: string, passphrase: string): Promise<ExportFileStatus> {
     return await invoke('validate_export_file', { filePath, passphrase });
   },
 
@@ -122,6 +125,24 @@ export const utilityApi = {
   async getDefaultBackupDir(): Promise<string> {
     return await invoke('get_default_backup_dir');
   },
+
+  async getDataDir(): Promise<DataDirInfo> {
+    return await invoke('get_data_dir');
+  },
+
+  // overwrite is needed when the new directory already holds a vault
+  async setDataDir(newPath: string, moveExisting: boolean, overwrite?: boolean): Promise<DataDirInfo> {
+    return await invoke('set_data_dir', { newPath, moveExisting, overwrite: overwrite ?? null });
+  },
+
+  // Set when the backend could not open the vault at launch
+  async getStartupError(): Promise<string | null> {
+    return await invoke('get_startup_error');
+  },
+
+  async retryInitialization(path?: string): Promise<void> {
+    return await invoke('retry_initialization', { path: path || null });
+  },
 };
 
 // Error handling utility
@@ -132,6 +153,11 @@ export class ApiError extends Error {
   }
 }
 
+// Commands reject with a PwdBoxError object rather than a string
+function isPwdBoxError(error: unknown): error is PwdBoxError {
+  return typeof error === 'object' && error !== null && 'code' in error && 'message' in error;
+}
+
 // Wrapper function to handle API errors consistently
 export async function handleApiCall<T>(apiCall: () => Promise<T>): Promise<T> {
   try {
@@ -140,6 +166,8 @@ export async function handleApiCall<T>(apiCall: () => Promise<T>): Promise<T> {
     console.error('API call failed:', error);
     if (typeof error === 'string') {
       throw new ApiError(error);
+    } else if (isPwdBoxError(error)) {
+      throw new ApiError(error.message, error.code);
     } else if (error instanceof Error) {
       throw new ApiError(error.message);
     } else {